@@ -1,6 +1,8 @@
 use enumflags2::bitflags;
 
-use crate::error::{ElfHeaderParseError, ProgramHeaderParseError, SectionHeaderParseError};
+use crate::error::{
+    ElfHeaderParseError, ProgramHeaderParseError, SectionHeaderParseError, SymbolParseError,
+};
 
 #[repr(u8)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -30,7 +32,7 @@ impl TryFrom<u8> for Endianess {
 }
 
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum AbiType {
     SystemV = 0x00,
     HpUx = 0x01,
@@ -81,7 +83,7 @@ impl TryFrom<u8> for AbiType {
 }
 
 #[repr(u16)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ObjectType {
     None = 0x00,
     Rel = 0x01,
@@ -111,6 +113,19 @@ impl TryFrom<u16> for ObjectType {
     }
 }
 
+impl From<ObjectType> for u16 {
+    fn from(value: ObjectType) -> Self {
+        match value {
+            ObjectType::None => 0x00,
+            ObjectType::Rel => 0x01,
+            ObjectType::Exec => 0x02,
+            ObjectType::Dyn => 0x03,
+            ObjectType::Core => 0x04,
+            ObjectType::Os(value) | ObjectType::Proc(value) => value,
+        }
+    }
+}
+
 #[repr(u16)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ASI {
@@ -263,7 +278,7 @@ impl TryFrom<u16> for ASI {
 }
 
 #[repr(u32)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ProgramType {
     Null = 0x00000000,
     Load = 0x00000001,
@@ -293,6 +308,19 @@ impl TryFrom<u32> for ProgramType {
     }
 }
 
+impl From<ProgramType> for u32 {
+    fn from(value: ProgramType) -> Self {
+        match value {
+            ProgramType::Null => 0x00000000,
+            ProgramType::Load => 0x00000001,
+            ProgramType::Dynamic => 0x00000002,
+            ProgramType::Interp => 0x00000003,
+            ProgramType::Note => 0x00000004,
+            ProgramType::Os(value) | ProgramType::Proc(value) => value,
+        }
+    }
+}
+
 #[repr(u32)]
 #[bitflags]
 #[derive(Clone, Copy, Debug)]
@@ -371,3 +399,65 @@ pub enum SectionFlags {
     Group = 0x200,
     Tls = 0x400,
 }
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    NoType = 0,
+    Object = 1,
+    Func = 2,
+    Section = 3,
+    File = 4,
+    Common = 5,
+    Tls = 6,
+    /// range 10..=12
+    Os(u8) = 10,
+    /// range 13..=15
+    Proc(u8) = 13,
+}
+
+impl TryFrom<u8> for SymbolType {
+    type Error = SymbolParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SymbolType::NoType),
+            1 => Ok(SymbolType::Object),
+            2 => Ok(SymbolType::Func),
+            3 => Ok(SymbolType::Section),
+            4 => Ok(SymbolType::File),
+            5 => Ok(SymbolType::Common),
+            6 => Ok(SymbolType::Tls),
+            10..=12 => Ok(SymbolType::Os(value)),
+            13..=15 => Ok(SymbolType::Proc(value)),
+            _ => Err(SymbolParseError::InvalidSymbolType(value)),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolBinding {
+    Local = 0,
+    Global = 1,
+    Weak = 2,
+    /// range 10..=12
+    Os(u8) = 10,
+    /// range 13..=15
+    Proc(u8) = 13,
+}
+
+impl TryFrom<u8> for SymbolBinding {
+    type Error = SymbolParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SymbolBinding::Local),
+            1 => Ok(SymbolBinding::Global),
+            2 => Ok(SymbolBinding::Weak),
+            10..=12 => Ok(SymbolBinding::Os(value)),
+            13..=15 => Ok(SymbolBinding::Proc(value)),
+            _ => Err(SymbolParseError::InvalidBinding(value)),
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! Call frame information from `.debug_frame` and `.eh_frame`, which describes for every address
+//! in a function where its caller's registers and return address are saved.
+
+use std::collections::HashMap;
+
+use crate::{error::DwarfParseError, Address};
+
+use super::{reader::Reader, DwarfSections};
+
+/// The size of addresses when a unit does not give one, elf_load only reads 64 bit elfs
+const ADDRESS_SIZE: u8 = 8;
+
+const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_RESTORE: u8 = 0xc0;
+const DW_CFA_NOP: u8 = 0x00;
+const DW_CFA_SET_LOC: u8 = 0x01;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_OFFSET_EXTENDED: u8 = 0x05;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+const DW_CFA_UNDEFINED: u8 = 0x07;
+const DW_CFA_SAME_VALUE: u8 = 0x08;
+const DW_CFA_REGISTER: u8 = 0x09;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+const DW_CFA_DEF_CFA_EXPRESSION: u8 = 0x0f;
+const DW_CFA_EXPRESSION: u8 = 0x10;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+const DW_CFA_DEF_CFA_SF: u8 = 0x12;
+const DW_CFA_DEF_CFA_OFFSET_SF: u8 = 0x13;
+const DW_CFA_VAL_OFFSET: u8 = 0x14;
+const DW_CFA_VAL_OFFSET_SF: u8 = 0x15;
+const DW_CFA_VAL_EXPRESSION: u8 = 0x16;
+const DW_CFA_GNU_ARGS_SIZE: u8 = 0x2e;
+const DW_CFA_GNU_NEGATIVE_OFFSET_EXTENDED: u8 = 0x2f;
+
+const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_ULEB128: u8 = 0x01;
+const DW_EH_PE_UDATA2: u8 = 0x02;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_UDATA8: u8 = 0x04;
+const DW_EH_PE_SLEB128: u8 = 0x09;
+const DW_EH_PE_SDATA2: u8 = 0x0a;
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+const DW_EH_PE_SDATA8: u8 = 0x0c;
+const DW_EH_PE_PCREL: u8 = 0x10;
+
+/// How to find the canonical frame address, the stack pointer before the call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfaRule {
+    RegisterOffset(u16, i64),
+    /// A dwarf expression, which is not supported
+    Unsupported,
+}
+
+/// Where the caller's value of a register is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterRule {
+    /// The register can not be recovered, for the return address this means there is no caller
+    Undefined,
+    SameValue,
+    /// Saved at an offset from the cfa
+    Offset(i64),
+    /// The value is the cfa plus an offset
+    ValOffset(i64),
+    /// Saved in another register
+    Register(u16),
+    /// A dwarf expression, which is not supported
+    Unsupported,
+}
+
+/// The rules for unwinding a frame at one address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameRules {
+    pub cfa: CfaRule,
+    /// The register that holds the return address
+    pub return_address: u16,
+    registers: Vec<(u16, RegisterRule)>,
+}
+
+impl FrameRules {
+    /// The rule for a register, registers without one keep their value
+    pub fn register(&self, register: u16) -> RegisterRule {
+        self.registers
+            .iter()
+            .find(|(r, _)| *r == register)
+            .map_or(RegisterRule::SameValue, |(_, rule)| *rule)
+    }
+
+    fn set(&mut self, register: u16, rule: RegisterRule) {
+        match self.registers.iter_mut().find(|(r, _)| *r == register) {
+            Some((_, r)) => *r = rule,
+            None => self.registers.push((register, rule)),
+        }
+    }
+}
+
+/// A common information entry, shared by the frame descriptions of a unit.
+#[derive(Debug, Clone)]
+struct Cie {
+    code_alignment: u64,
+    data_alignment: i64,
+    return_address: u16,
+    /// The encoding of addresses in `.eh_frame` frame descriptions
+    encoding: u8,
+    /// Frame descriptions have augmentation data
+    augmented: bool,
+    instructions: Vec<u8>,
+}
+
+/// A frame description entry, the rules for the addresses of one function.
+#[derive(Debug, Clone)]
+struct Fde {
+    start: u64,
+    end: u64,
+    cie: usize,
+    instructions: Vec<u8>,
+}
+
+/// The call frame information of an elf, see
+/// [`Elf::call_frame_info()`](crate::Elf::call_frame_info()).
+#[derive(Debug, Clone, Default)]
+pub struct CallFrameInfo {
+    cies: Vec<Cie>,
+    /// Sorted by start address
+    fdes: Vec<Fde>,
+}
+
+impl CallFrameInfo {
+    pub fn parse(sections: &DwarfSections) -> Result<Self, DwarfParseError> {
+        let mut info = Self::default();
+        info.section(sections.debug_frame, None)?;
+        info.section(sections.eh_frame, Some(sections.eh_frame_address))?;
+        // The sort is stable, so functions described in both sections use .eh_frame
+        info.fdes.sort_by_key(|f| f.start);
+        Ok(info)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fdes.is_empty()
+    }
+
+    /// Read `.debug_frame`, or `.eh_frame` if its address is given
+    fn section(&mut self, section: &[u8], eh_frame: Option<u64>) -> Result<(), DwarfParseError> {
+        // The cies by their offset into the section
+        let mut cies = HashMap::new();
+        let mut reader = Reader::new(section);
+        while !reader.is_empty() {
+            let offset = reader.position() as u64;
+            let (length, dwarf64) = reader.initial_length()?;
+            // A zero length entry ends .eh_frame
+            if length == 0 {
+                if eh_frame.is_some() {
+                    break;
+                }
+                continue;
+            }
+            let id_position = reader.position() as u64;
+            let mut entry = reader.split(length)?;
+
+            let id = match eh_frame {
+                Some(_) => entry.u32()?.into(),
+                None => entry.offset(dwarf64)?,
+            };
+            let is_cie = match eh_frame {
+                Some(_) => id == 0,
+                None if dwarf64 => id == u64::MAX,
+                None => id == 0xFFFF_FFFF,
+            };
+
+            if is_cie {
+                self.cies.push(cie(&mut entry, eh_frame.is_some())?);
+                cies.insert(offset, self.cies.len() - 1);
+                continue;
+            }
+
+            // In .eh_frame the cie pointer is relative to itself
+            let cie_offset = match eh_frame {
+                Some(_) => id_position.wrapping_sub(id),
+                None => id,
+            };
+            let cie = *cies
+                .get(&cie_offset)
+                .ok_or(DwarfParseError::InvalidCiePointer(cie_offset))?;
+            let encoding = self.cies[cie].encoding;
+
+            let field = eh_frame.map(|address| address.wrapping_add(id_position).wrapping_add(4));
+            let start = pointer(&mut entry, encoding, field)?;
+            // The range is a plain number, even for pc relative encodings
+            let range = pointer(&mut entry, encoding & 0x0f, None)?;
+            if self.cies[cie].augmented {
+                let len = entry.uleb()?;
+                entry.skip(len)?;
+            }
+
+            self.fdes.push(Fde {
+                start,
+                end: start.wrapping_add(range),
+                cie,
+                instructions: entry.take(entry.remaining())?.to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The rules to unwind the frame of the function at `addr`, `None` if no frame description
+    /// covers it or its instructions are invalid.
+    pub fn rules(&self, addr: Address) -> Option<FrameRules> {
+        let end = self.fdes.partition_point(|f| f.start <= addr.0);
+        let fde = self.fdes[..end]
+            .iter()
+            .rev()
+            .find(|f| (f.start..f.end).contains(&addr.0))?;
+        let cie = &self.cies[fde.cie];
+
+        let mut initial = FrameRules {
+            cfa: CfaRule::Unsupported,
+            return_address: cie.return_address,
+            registers: vec![],
+        };
+        let mut machine = Machine {
+            cie,
+            location: fde.start,
+            target: addr.0,
+            stack: vec![],
+        };
+        machine
+            .execute(&cie.instructions, &mut initial, None)
+            .ok()?;
+        let mut rules = initial.clone();
+        machine
+            .execute(&fde.instructions, &mut rules, Some(&initial))
+            .ok()?;
+        Some(rules)
+    }
+}
+
+fn cie(entry: &mut Reader, eh_frame: bool) -> Result<Cie, DwarfParseError> {
+    let version = entry.u8()?;
+    if !matches!(version, 1 | 3 | 4) {
+        return Err(DwarfParseError::UnsupportedVersion(version.into()));
+    }
+    let augmentation = entry.str()?;
+    if version == 4 {
+        let address_size = entry.u8()?;
+        if address_size != ADDRESS_SIZE {
+            return Err(DwarfParseError::InvalidAddressSize(address_size));
+        }
+        // The segment selector size
+        entry.u8()?;
+    }
+    let code_alignment = entry.uleb()?;
+    let data_alignment = entry.sleb()?;
+    let return_address = if version == 1 {
+        entry.u8()?.into()
+    } else {
+        entry.uleb()? as u16
+    };
+
+    let mut encoding = DW_EH_PE_ABSPTR;
+    let augmented = augmentation.starts_with('z');
+    if augmented {
+        let len = entry.uleb()?;
+        let mut data = entry.split(len)?;
+        for c in augmentation.chars().skip(1) {
+            match c {
+                'R' => encoding = data.u8()?,
+                'L' => {
+                    data.u8()?;
+                }
+                'P' => {
+                    let encoding = data.u8()?;
+                    pointer(&mut data, encoding & 0x0f, None)?;
+                }
+                // Signal frames, which need no special handling here
+                'S' => {}
+                // The rest of the data is not understood, but its length is known
+                _ => break,
+            }
+        }
+    } else if eh_frame && !augmentation.is_empty() {
+        // Without a length the instructions can not be found
+        return Err(DwarfParseError::UnsupportedVersion(version.into()));
+    }
+
+    Ok(Cie {
+        code_alignment,
+        data_alignment,
+        return_address,
+        encoding,
+        augmented,
+        instructions: entry.take(entry.remaining())?.to_vec(),
+    })
+}
+
+/// Read an `.eh_frame` pointer, `field` is the address of the pointer for pc relative encodings
+fn pointer(reader: &mut Reader, encoding: u8, field: Option<u64>) -> Result<u64, DwarfParseError> {
+    if encoding == DW_EH_PE_OMIT {
+        return Ok(0);
+    }
+    let value = match encoding & 0x0f {
+        DW_EH_PE_ABSPTR => reader.uint(ADDRESS_SIZE.into())?,
+        DW_EH_PE_ULEB128 => reader.uleb()?,
+        DW_EH_PE_UDATA2 => reader.u16()?.into(),
+        DW_EH_PE_UDATA4 => reader.u32()?.into(),
+        DW_EH_PE_UDATA8 => reader.u64()?,
+        DW_EH_PE_SLEB128 => reader.sleb()? as u64,
+        DW_EH_PE_SDATA2 => reader.u16()? as i16 as u64,
+        DW_EH_PE_SDATA4 => reader.u32()? as i32 as u64,
+        DW_EH_PE_SDATA8 => reader.u64()?,
+        _ => return Err(DwarfParseError::UnsupportedPointerEncoding(encoding)),
+    };
+    match (encoding & 0x70, field) {
+        (0, _) => Ok(value),
+        (DW_EH_PE_PCREL, Some(field)) => Ok(field.wrapping_add(value)),
+        _ => Err(DwarfParseError::UnsupportedPointerEncoding(encoding)),
+    }
+}
+
+/// Executes call frame instructions up to an address.
+struct Machine<'a> {
+    cie: &'a Cie,
+    location: u64,
+    target: u64,
+    /// Rules saved with `DW_CFA_remember_state`
+    stack: Vec<FrameRules>,
+}
+
+impl Machine<'_> {
+    /// Apply the instructions to `rules` until the location passes the target. `initial` are the
+    /// rules after the cie's instructions, which `DW_CFA_restore` goes back to.
+    fn execute(
+        &mut self,
+        instructions: &[u8],
+        rules: &mut FrameRules,
+        initial: Option<&FrameRules>,
+    ) -> Result<(), DwarfParseError> {
+        let mut reader = Reader::new(instructions);
+        let data_alignment = self.cie.data_alignment;
+        let restore = |rules: &mut FrameRules, register| {
+            let rule = initial.map_or(RegisterRule::SameValue, |i| i.register(register));
+            rules.set(register, rule);
+        };
+
+        while !reader.is_empty() {
+            let op = reader.u8()?;
+            let (op, low) = match op & 0xc0 {
+                0 => (op, 0),
+                high => (high, op & 0x3f),
+            };
+            let location = match op {
+                DW_CFA_SET_LOC => Some(reader.uint(ADDRESS_SIZE.into())?),
+                DW_CFA_ADVANCE_LOC => Some(u64::from(low)),
+                DW_CFA_ADVANCE_LOC1 => Some(reader.u8()?.into()),
+                DW_CFA_ADVANCE_LOC2 => Some(reader.u16()?.into()),
+                DW_CFA_ADVANCE_LOC4 => Some(reader.u32()?.into()),
+                _ => None,
+            }
+            .map(|delta| match op {
+                DW_CFA_SET_LOC => delta,
+                _ => self
+                    .location
+                    .wrapping_add(delta.wrapping_mul(self.cie.code_alignment)),
+            });
+            if let Some(location) = location {
+                // The rules for the target are complete once the next row starts after it
+                if location > self.target {
+                    return Ok(());
+                }
+                self.location = location;
+                continue;
+            }
+
+            match op {
+                DW_CFA_NOP => {}
+                DW_CFA_OFFSET => {
+                    let offset = (reader.uleb()? as i64).wrapping_mul(data_alignment);
+                    rules.set(low.into(), RegisterRule::Offset(offset));
+                }
+                DW_CFA_RESTORE => restore(rules, low.into()),
+                DW_CFA_OFFSET_EXTENDED => {
+                    let register = reader.uleb()? as u16;
+                    let offset = (reader.uleb()? as i64).wrapping_mul(data_alignment);
+                    rules.set(register, RegisterRule::Offset(offset));
+                }
+                DW_CFA_OFFSET_EXTENDED_SF => {
+                    let register = reader.uleb()? as u16;
+                    let offset = reader.sleb()?.wrapping_mul(data_alignment);
+                    rules.set(register, RegisterRule::Offset(offset));
+                }
+                DW_CFA_GNU_NEGATIVE_OFFSET_EXTENDED => {
+                    let register = reader.uleb()? as u16;
+                    let offset = (reader.uleb()? as i64)
+                        .wrapping_neg()
+                        .wrapping_mul(data_alignment);
+                    rules.set(register, RegisterRule::Offset(offset));
+                }
+                DW_CFA_RESTORE_EXTENDED => {
+                    let register = reader.uleb()? as u16;
+                    restore(rules, register);
+                }
+                DW_CFA_UNDEFINED => rules.set(reader.uleb()? as u16, RegisterRule::Undefined),
+                DW_CFA_SAME_VALUE => rules.set(reader.uleb()? as u16, RegisterRule::SameValue),
+                DW_CFA_REGISTER => {
+                    let register = reader.uleb()? as u16;
+                    let other = reader.uleb()? as u16;
+                    rules.set(register, RegisterRule::Register(other));
+                }
+                DW_CFA_REMEMBER_STATE => self.stack.push(rules.clone()),
+                DW_CFA_RESTORE_STATE => {
+                    // The cfa is not part of the saved state
+                    let cfa = rules.cfa;
+                    *rules = self.stack.pop().ok_or(DwarfParseError::UnexpectedEnd)?;
+                    rules.cfa = cfa;
+                }
+                DW_CFA_DEF_CFA => {
+                    let register = reader.uleb()? as u16;
+                    let offset = reader.uleb()? as i64;
+                    rules.cfa = CfaRule::RegisterOffset(register, offset);
+                }
+                DW_CFA_DEF_CFA_SF => {
+                    let register = reader.uleb()? as u16;
+                    let offset = reader.sleb()?.wrapping_mul(data_alignment);
+                    rules.cfa = CfaRule::RegisterOffset(register, offset);
+                }
+                DW_CFA_DEF_CFA_REGISTER => {
+                    let register = reader.uleb()? as u16;
+                    rules.cfa = match rules.cfa {
+                        CfaRule::RegisterOffset(_, offset) => {
+                            CfaRule::RegisterOffset(register, offset)
+                        }
+                        CfaRule::Unsupported => CfaRule::Unsupported,
+                    };
+                }
+                DW_CFA_DEF_CFA_OFFSET | DW_CFA_DEF_CFA_OFFSET_SF => {
+                    let offset = if op == DW_CFA_DEF_CFA_OFFSET {
+                        reader.uleb()? as i64
+                    } else {
+                        reader.sleb()?.wrapping_mul(data_alignment)
+                    };
+                    if let CfaRule::RegisterOffset(register, _) = rules.cfa {
+                        rules.cfa = CfaRule::RegisterOffset(register, offset);
+                    }
+                }
+                DW_CFA_DEF_CFA_EXPRESSION => {
+                    let len = reader.uleb()?;
+                    reader.skip(len)?;
+                    rules.cfa = CfaRule::Unsupported;
+                }
+                DW_CFA_EXPRESSION | DW_CFA_VAL_EXPRESSION => {
+                    let register = reader.uleb()? as u16;
+                    let len = reader.uleb()?;
+                    reader.skip(len)?;
+                    rules.set(register, RegisterRule::Unsupported);
+                }
+                DW_CFA_VAL_OFFSET => {
+                    let register = reader.uleb()? as u16;
+                    let offset = (reader.uleb()? as i64).wrapping_mul(data_alignment);
+                    rules.set(register, RegisterRule::ValOffset(offset));
+                }
+                DW_CFA_VAL_OFFSET_SF => {
+                    let register = reader.uleb()? as u16;
+                    let offset = reader.sleb()?.wrapping_mul(data_alignment);
+                    rules.set(register, RegisterRule::ValOffset(offset));
+                }
+                DW_CFA_GNU_ARGS_SIZE => {
+                    reader.uleb()?;
+                }
+                op => return Err(DwarfParseError::UnsupportedInstruction(op)),
+            }
+        }
+        Ok(())
+    }
+}
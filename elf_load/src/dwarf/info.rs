@@ -0,0 +1,862 @@
+//! The `.debug_info` section, which describes the functions, variables and types of a program.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{error::DwarfParseError, Address};
+
+use super::{read_form, reader::Reader, DwarfSections, FormValue, Format};
+
+const DW_TAG_ARRAY_TYPE: u64 = 0x01;
+const DW_TAG_CLASS_TYPE: u64 = 0x02;
+const DW_TAG_ENUMERATION_TYPE: u64 = 0x04;
+const DW_TAG_FORMAL_PARAMETER: u64 = 0x05;
+const DW_TAG_LEXICAL_BLOCK: u64 = 0x0b;
+const DW_TAG_MEMBER: u64 = 0x0d;
+const DW_TAG_POINTER_TYPE: u64 = 0x0f;
+const DW_TAG_REFERENCE_TYPE: u64 = 0x10;
+const DW_TAG_COMPILE_UNIT: u64 = 0x11;
+const DW_TAG_STRUCTURE_TYPE: u64 = 0x13;
+const DW_TAG_SUBROUTINE_TYPE: u64 = 0x15;
+const DW_TAG_TYPEDEF: u64 = 0x16;
+const DW_TAG_UNION_TYPE: u64 = 0x17;
+const DW_TAG_SUBRANGE_TYPE: u64 = 0x21;
+const DW_TAG_BASE_TYPE: u64 = 0x24;
+const DW_TAG_CONST_TYPE: u64 = 0x26;
+const DW_TAG_ENUMERATOR: u64 = 0x28;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_TAG_VARIABLE: u64 = 0x34;
+const DW_TAG_VOLATILE_TYPE: u64 = 0x35;
+const DW_TAG_RESTRICT_TYPE: u64 = 0x37;
+const DW_TAG_ATOMIC_TYPE: u64 = 0x47;
+
+const DW_AT_LOCATION: u64 = 0x02;
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_BYTE_SIZE: u64 = 0x0b;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_CONST_VALUE: u64 = 0x1c;
+const DW_AT_UPPER_BOUND: u64 = 0x2f;
+const DW_AT_ABSTRACT_ORIGIN: u64 = 0x31;
+const DW_AT_COUNT: u64 = 0x37;
+const DW_AT_DATA_MEMBER_LOCATION: u64 = 0x38;
+const DW_AT_ENCODING: u64 = 0x3e;
+const DW_AT_FRAME_BASE: u64 = 0x40;
+const DW_AT_SPECIFICATION: u64 = 0x47;
+const DW_AT_TYPE: u64 = 0x49;
+const DW_AT_STR_OFFSETS_BASE: u64 = 0x72;
+const DW_AT_ADDR_BASE: u64 = 0x73;
+
+const DW_FORM_IMPLICIT_CONST: u64 = 0x21;
+
+const DW_UT_TYPE: u8 = 0x02;
+const DW_UT_SKELETON: u8 = 0x04;
+const DW_UT_SPLIT_COMPILE: u8 = 0x05;
+const DW_UT_SPLIT_TYPE: u8 = 0x06;
+
+const DW_OP_ADDR: u8 = 0x03;
+const DW_OP_PLUS_UCONST: u8 = 0x23;
+const DW_OP_REG0: u8 = 0x50;
+const DW_OP_REG31: u8 = 0x6f;
+const DW_OP_BREG0: u8 = 0x70;
+const DW_OP_BREG31: u8 = 0x8f;
+const DW_OP_REGX: u8 = 0x90;
+const DW_OP_FBREG: u8 = 0x91;
+const DW_OP_BREGX: u8 = 0x92;
+const DW_OP_CALL_FRAME_CFA: u8 = 0x9c;
+const DW_OP_ADDRX: u8 = 0xa1;
+
+/// The base type encodings, see [`Type::Base`]
+pub const DW_ATE_BOOLEAN: u8 = 0x02;
+pub const DW_ATE_FLOAT: u8 = 0x04;
+pub const DW_ATE_SIGNED: u8 = 0x05;
+pub const DW_ATE_SIGNED_CHAR: u8 = 0x06;
+pub const DW_ATE_UNSIGNED: u8 = 0x07;
+pub const DW_ATE_UNSIGNED_CHAR: u8 = 0x08;
+
+/// The index of a type in [`DebugInfo::types`]
+pub type TypeId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    /// Integers, floats, booleans and characters, `encoding` is one of the `DW_ATE_*` constants
+    Base {
+        name: String,
+        size: u64,
+        encoding: u8,
+    },
+    /// A pointer to `target`, `None` for `void *`
+    Pointer {
+        target: Option<TypeId>,
+        size: u64,
+    },
+    /// A struct, class or union, the members of a union all have offset 0
+    Struct {
+        name: Option<String>,
+        size: u64,
+        members: Vec<Member>,
+        union: bool,
+    },
+    /// An array of `count` elements, multidimensional arrays are arrays of arrays. The count is
+    /// `None` for arrays of unknown size, like flexible array members.
+    Array {
+        element: Option<TypeId>,
+        count: Option<u64>,
+    },
+    Enum {
+        name: Option<String>,
+        size: u64,
+        values: Vec<(String, i64)>,
+    },
+    Typedef {
+        name: String,
+        target: Option<TypeId>,
+    },
+    /// A `const`, `volatile`, `restrict` or `_Atomic` `target`, which does not change its value
+    Qualified {
+        qualifier: &'static str,
+        target: Option<TypeId>,
+    },
+    Function,
+    /// A type that is referred to but not described, or described in a way that is not
+    /// understood
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// `None` for anonymous structs and unions, whose members are accessed as if they were
+    /// members of the outer struct
+    pub name: Option<String>,
+    pub ty: Option<TypeId>,
+    pub offset: u64,
+}
+
+/// Where the value of a variable is, only the simple locations compilers use without
+/// optimizations are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// The variable has no location, because it was optimized out or is only declared here
+    None,
+    Address(Address),
+    /// A dwarf register number, 0 to 31 are the integer and 32 to 63 the float registers
+    Register(u16),
+    /// At an offset from the address in a register
+    RegisterOffset(u16, i64),
+    /// At an offset from the frame base of the function
+    FrameOffset(i64),
+    /// The canonical frame address, the stack pointer before the call, only used as frame base
+    Cfa,
+    /// Location lists and expressions that are not understood
+    Unsupported,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub ty: Option<TypeId>,
+    pub location: Location,
+}
+
+/// A local variable or parameter of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Local {
+    pub variable: Variable,
+    pub parameter: bool,
+    /// The addresses of the block the local is declared in, `None` if that is the whole function
+    pub scope: Option<(Address, Address)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: Option<String>,
+    pub low_pc: Address,
+    /// The first address after the function
+    pub high_pc: Address,
+    pub frame_base: Location,
+    pub locals: Vec<Local>,
+}
+
+impl Function {
+    pub fn contains(&self, addr: Address) -> bool {
+        (self.low_pc.0..self.high_pc.0).contains(&addr.0)
+    }
+}
+
+/// The functions, global variables and types of all compilation units.
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub types: Vec<Type>,
+    pub globals: Vec<Variable>,
+    pub functions: Vec<Function>,
+}
+
+impl DebugInfo {
+    pub fn parse(sections: &DwarfSections) -> Result<Self, DwarfParseError> {
+        let mut builder = Builder::default();
+        let mut tables = HashMap::new();
+        let mut reader = Reader::new(sections.debug_info);
+        while !reader.is_empty() {
+            builder.unit(&mut reader, sections, &mut tables)?;
+        }
+        Ok(builder.finish())
+    }
+
+    pub fn ty(&self, id: TypeId) -> &Type {
+        self.types.get(id).unwrap_or(&Type::Unknown)
+    }
+
+    /// The type behind typedefs and qualifiers, `None` for `void`
+    pub fn resolve(&self, mut id: Option<TypeId>) -> Option<TypeId> {
+        // Bounded in case of a cycle
+        for _ in 0..64 {
+            match self.ty(id?) {
+                Type::Typedef { target, .. } | Type::Qualified { target, .. } => id = *target,
+                _ => return id,
+            }
+        }
+        None
+    }
+
+    /// The size of a value of the type in bytes, `None` for `void` and arrays of unknown size
+    pub fn size_of(&self, id: Option<TypeId>) -> Option<u64> {
+        match self.ty(self.resolve(id)?) {
+            Type::Base { size, .. }
+            | Type::Pointer { size, .. }
+            | Type::Struct { size, .. }
+            | Type::Enum { size, .. } => Some(*size),
+            Type::Array { element, count } => Some(self.size_of(*element)? * (*count)?),
+            _ => None,
+        }
+    }
+
+    /// The type as it would be written in C
+    pub fn type_name(&self, id: Option<TypeId>) -> String {
+        let Some(id) = id else {
+            return "void".to_string();
+        };
+        match self.ty(id) {
+            Type::Base { name, .. } | Type::Typedef { name, .. } => name.clone(),
+            Type::Pointer { target, .. } => format!("{} *", self.type_name(*target)),
+            Type::Struct { name, union, .. } => format!(
+                "{} {}",
+                if *union { "union" } else { "struct" },
+                name.as_deref().unwrap_or("<anonymous>")
+            ),
+            Type::Array { .. } => {
+                // The dimensions of arrays of arrays follow the innermost element type
+                let mut dimensions = String::new();
+                let mut element = Some(id);
+                while let Some(Type::Array {
+                    element: inner,
+                    count,
+                }) = element.map(|e| self.ty(e))
+                {
+                    match count {
+                        Some(count) => dimensions += &format!("[{}]", count),
+                        None => dimensions += "[]",
+                    }
+                    element = *inner;
+                }
+                format!("{} {}", self.type_name(element), dimensions)
+            }
+            Type::Enum { name, .. } => {
+                format!("enum {}", name.as_deref().unwrap_or("<anonymous>"))
+            }
+            // Qualified arrays are arrays of qualified elements
+            Type::Qualified { target, .. }
+                if matches!(target.map(|t| self.ty(t)), Some(Type::Array { .. })) =>
+            {
+                self.type_name(*target)
+            }
+            Type::Qualified { qualifier, target } => {
+                format!("{} {}", qualifier, self.type_name(*target))
+            }
+            Type::Function => "<function>".to_string(),
+            Type::Unknown => "<unknown>".to_string(),
+        }
+    }
+
+    /// The innermost function with code at `addr`
+    pub fn function(&self, addr: Address) -> Option<&Function> {
+        self.functions
+            .iter()
+            .filter(|f| f.contains(addr))
+            .min_by_key(|f| f.high_pc.0 - f.low_pc.0)
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Variable> {
+        self.globals.iter().find(|v| v.name == name)
+    }
+
+    /// The variable called `name` as seen from code at `pc`, the innermost local in scope or
+    /// otherwise a global. The function is returned with locals, as their location can depend
+    /// on its frame base.
+    pub fn variable(&self, name: &str, pc: Address) -> Option<(&Variable, Option<&Function>)> {
+        if let Some(function) = self.function(pc) {
+            // Inner blocks come after the blocks they are in
+            let local = function.locals.iter().rev().find(|l| {
+                l.variable.name == name
+                    && l.scope
+                        .is_none_or(|(low, high)| (low.0..high.0).contains(&pc.0))
+            });
+            if let Some(local) = local {
+                return Some((&local.variable, Some(function)));
+            }
+        }
+        self.global(name).map(|v| (v, None))
+    }
+}
+
+struct Abbreviation {
+    tag: u64,
+    children: bool,
+    /// The name, form and for `DW_FORM_implicit_const` the value of the attributes
+    attributes: Vec<(u64, u64, i64)>,
+}
+
+fn abbreviations(
+    section: &[u8],
+    offset: u64,
+) -> Result<HashMap<u64, Abbreviation>, DwarfParseError> {
+    let mut reader = Reader::at(section, offset)?;
+    let mut table = HashMap::new();
+    loop {
+        let code = reader.uleb()?;
+        if code == 0 {
+            return Ok(table);
+        }
+        let tag = reader.uleb()?;
+        let children = reader.u8()? != 0;
+        let mut attributes = vec![];
+        loop {
+            let name = reader.uleb()?;
+            let form = reader.uleb()?;
+            if name == 0 && form == 0 {
+                break;
+            }
+            let value = if form == DW_FORM_IMPLICIT_CONST {
+                reader.sleb()?
+            } else {
+                0
+            };
+            attributes.push((name, form, value));
+        }
+        table.insert(
+            code,
+            Abbreviation {
+                tag,
+                children,
+                attributes,
+            },
+        );
+    }
+}
+
+/// A compilation unit, needed to resolve references, addresses and strings.
+struct Unit<'a> {
+    offset: u64,
+    format: Format,
+    sections: DwarfSections<'a>,
+    str_offsets_base: u64,
+    addr_base: u64,
+}
+
+impl<'a> Unit<'a> {
+    fn string(&self, value: FormValue<'a>) -> Option<String> {
+        match value {
+            FormValue::Str(s) => Some(s.to_string()),
+            FormValue::Strx(index) => {
+                let size = if self.format.dwarf64 { 8 } else { 4 };
+                let position = index
+                    .checked_mul(size)
+                    .and_then(|o| self.str_offsets_base.checked_add(o))?;
+                let mut reader = Reader::at(self.sections.debug_str_offsets, position).ok()?;
+                let offset = reader.offset(self.format.dwarf64).ok()?;
+                let s = super::reader::str_at(self.sections.debug_str, offset).ok()?;
+                Some(s.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn address(&self, value: FormValue) -> Option<u64> {
+        match value {
+            FormValue::Addr(addr) => Some(addr),
+            FormValue::Addrx(index) => {
+                let size = u64::from(self.format.address_size);
+                let position = index
+                    .checked_mul(size)
+                    .and_then(|o| self.addr_base.checked_add(o))?;
+                let mut reader = Reader::at(self.sections.debug_addr, position).ok()?;
+                reader.uint(size as usize).ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn reference(&self, value: FormValue) -> Option<u64> {
+        match value {
+            FormValue::UnitRef(offset) => self.offset.checked_add(offset),
+            FormValue::InfoRef(offset) => Some(offset),
+            _ => None,
+        }
+    }
+
+    fn location(&self, value: FormValue) -> Location {
+        let FormValue::Block(bytes) = value else {
+            return Location::Unsupported;
+        };
+        let mut reader = Reader::new(bytes);
+        let mut parse = || -> Result<Location, DwarfParseError> {
+            let location = match reader.u8()? {
+                DW_OP_ADDR => {
+                    Location::Address(Address(reader.uint(self.format.address_size.into())?))
+                }
+                DW_OP_ADDRX => match self.address(FormValue::Addrx(reader.uleb()?)) {
+                    Some(addr) => Location::Address(Address(addr)),
+                    None => Location::Unsupported,
+                },
+                op @ DW_OP_REG0..=DW_OP_REG31 => Location::Register((op - DW_OP_REG0).into()),
+                DW_OP_REGX => Location::Register(reader.uleb()? as u16),
+                op @ DW_OP_BREG0..=DW_OP_BREG31 => {
+                    Location::RegisterOffset((op - DW_OP_BREG0).into(), reader.sleb()?)
+                }
+                DW_OP_BREGX => Location::RegisterOffset(reader.uleb()? as u16, reader.sleb()?),
+                DW_OP_FBREG => Location::FrameOffset(reader.sleb()?),
+                DW_OP_CALL_FRAME_CFA => Location::Cfa,
+                _ => Location::Unsupported,
+            };
+            // Anything after the first operation, like pieces, is not understood
+            Ok(if reader.is_empty() {
+                location
+            } else {
+                Location::Unsupported
+            })
+        };
+        parse().unwrap_or(Location::Unsupported)
+    }
+}
+
+/// A debug information entry.
+struct Die<'a> {
+    offset: u64,
+    tag: u64,
+    attributes: Vec<(u64, FormValue<'a>)>,
+}
+
+impl<'a> Die<'a> {
+    fn get(&self, name: u64) -> Option<FormValue<'a>> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| *value)
+    }
+
+    fn udata(&self, name: u64) -> Option<u64> {
+        match self.get(name)? {
+            FormValue::Udata(value) => Some(value),
+            FormValue::Sdata(value) => Some(value as u64),
+            _ => None,
+        }
+    }
+}
+
+/// The entry an entry with children is in, so its children can be added to it.
+enum Scope {
+    Other,
+    Type(TypeId),
+    /// A function, `None` for declarations and inlined functions without code of their own
+    Function(Option<usize>),
+    Block(Option<(Address, Address)>),
+}
+
+#[derive(Default)]
+struct Builder {
+    info: DebugInfo,
+    type_ids: HashMap<u64, TypeId>,
+    /// The names of the entries, for those that take it from another entry
+    names: HashMap<u64, String>,
+    /// Globals, functions and locals whose name is that of another entry, like the definition
+    /// of a variable whose declaration is elsewhere
+    global_origins: Vec<(usize, u64)>,
+    function_origins: Vec<(usize, u64)>,
+    local_origins: Vec<(usize, usize, u64)>,
+    /// The dimensions of arrays, until their entry ends
+    array_counts: HashMap<TypeId, Vec<Option<u64>>>,
+}
+
+impl Builder {
+    fn type_id(&mut self, offset: u64) -> TypeId {
+        *self.type_ids.entry(offset).or_insert_with(|| {
+            self.info.types.push(Type::Unknown);
+            self.info.types.len() - 1
+        })
+    }
+
+    fn type_of(&mut self, unit: &Unit, die: &Die) -> Option<TypeId> {
+        let offset = unit.reference(die.get(DW_AT_TYPE)?)?;
+        Some(self.type_id(offset))
+    }
+
+    fn unit<'a>(
+        &mut self,
+        reader: &mut Reader<'a>,
+        sections: &DwarfSections<'a>,
+        tables: &mut HashMap<u64, HashMap<u64, Abbreviation>>,
+    ) -> Result<(), DwarfParseError> {
+        let offset = reader.position() as u64;
+        let (length, dwarf64) = reader.initial_length()?;
+        let start = reader.position() as u64;
+        let mut entries = reader.split(length)?;
+
+        let version = entries.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(DwarfParseError::UnsupportedVersion(version));
+        }
+        let (abbrev_offset, address_size) = if version >= 5 {
+            let unit_type = entries.u8()?;
+            let address_size = entries.u8()?;
+            let abbrev_offset = entries.offset(dwarf64)?;
+            match unit_type {
+                // Type units are only referred to by signature, which is not supported
+                DW_UT_TYPE | DW_UT_SPLIT_TYPE => return Ok(()),
+                DW_UT_SKELETON | DW_UT_SPLIT_COMPILE => entries.skip(8)?,
+                _ => {}
+            }
+            (abbrev_offset, address_size)
+        } else {
+            (entries.offset(dwarf64)?, entries.u8()?)
+        };
+        if !(1..=8).contains(&address_size) {
+            return Err(DwarfParseError::InvalidAddressSize(address_size));
+        }
+
+        let table = match tables.entry(abbrev_offset) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(abbreviations(sections.debug_abbrev, abbrev_offset)?)
+            }
+        };
+
+        let mut unit = Unit {
+            offset,
+            format: Format {
+                version,
+                dwarf64,
+                address_size,
+            },
+            sections: *sections,
+            str_offsets_base: 0,
+            addr_base: 0,
+        };
+
+        let mut scopes = vec![];
+        while !entries.is_empty() {
+            let die_offset = start + entries.position() as u64;
+            let code = entries.uleb()?;
+            if code == 0 {
+                if let Some(scope) = scopes.pop() {
+                    self.end(scope);
+                }
+                continue;
+            }
+
+            let abbreviation = table
+                .get(&code)
+                .ok_or(DwarfParseError::InvalidAbbreviation(code))?;
+            let mut attributes = Vec::with_capacity(abbreviation.attributes.len());
+            for (name, form, value) in &abbreviation.attributes {
+                let value = if *form == DW_FORM_IMPLICIT_CONST {
+                    FormValue::Sdata(*value)
+                } else {
+                    read_form(&mut entries, sections, *form, unit.format)?
+                };
+                attributes.push((*name, value));
+            }
+            let die = Die {
+                offset: die_offset,
+                tag: abbreviation.tag,
+                attributes,
+            };
+
+            let scope = self.entry(&mut unit, &die, &scopes);
+            if abbreviation.children {
+                scopes.push(scope);
+            } else {
+                self.end(scope);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add an entry, and return the scope its children are in
+    fn entry(&mut self, unit: &mut Unit, die: &Die, scopes: &[Scope]) -> Scope {
+        if die.tag == DW_TAG_COMPILE_UNIT {
+            if let Some(base) = die.udata(DW_AT_STR_OFFSETS_BASE).or_else(|| {
+                match die.get(DW_AT_STR_OFFSETS_BASE)? {
+                    FormValue::SecOffset(offset) => Some(offset),
+                    _ => None,
+                }
+            }) {
+                unit.str_offsets_base = base;
+            }
+            if let Some(FormValue::SecOffset(base)) = die.get(DW_AT_ADDR_BASE) {
+                unit.addr_base = base;
+            }
+        }
+
+        let name = die.get(DW_AT_NAME).and_then(|n| unit.string(n));
+        if let Some(name) = &name {
+            if matches!(
+                die.tag,
+                DW_TAG_VARIABLE | DW_TAG_SUBPROGRAM | DW_TAG_FORMAL_PARAMETER
+            ) {
+                self.names.insert(die.offset, name.clone());
+            }
+        }
+        let origin = die
+            .get(DW_AT_SPECIFICATION)
+            .or_else(|| die.get(DW_AT_ABSTRACT_ORIGIN))
+            .and_then(|r| unit.reference(r));
+        let size = die.udata(DW_AT_BYTE_SIZE).unwrap_or(0);
+
+        let ty = match die.tag {
+            DW_TAG_BASE_TYPE => Type::Base {
+                name: name.unwrap_or_default(),
+                size,
+                encoding: die.udata(DW_AT_ENCODING).unwrap_or(0) as u8,
+            },
+            DW_TAG_POINTER_TYPE | DW_TAG_REFERENCE_TYPE => Type::Pointer {
+                target: self.type_of(unit, die),
+                size: die
+                    .udata(DW_AT_BYTE_SIZE)
+                    .unwrap_or(unit.format.address_size.into()),
+            },
+            DW_TAG_STRUCTURE_TYPE | DW_TAG_CLASS_TYPE | DW_TAG_UNION_TYPE => Type::Struct {
+                name,
+                size,
+                members: vec![],
+                union: die.tag == DW_TAG_UNION_TYPE,
+            },
+            DW_TAG_ARRAY_TYPE => Type::Array {
+                element: self.type_of(unit, die),
+                count: None,
+            },
+            DW_TAG_ENUMERATION_TYPE => Type::Enum {
+                name,
+                size,
+                values: vec![],
+            },
+            DW_TAG_TYPEDEF => Type::Typedef {
+                name: name.unwrap_or_default(),
+                target: self.type_of(unit, die),
+            },
+            DW_TAG_CONST_TYPE | DW_TAG_VOLATILE_TYPE | DW_TAG_RESTRICT_TYPE
+            | DW_TAG_ATOMIC_TYPE => Type::Qualified {
+                qualifier: match die.tag {
+                    DW_TAG_CONST_TYPE => "const",
+                    DW_TAG_VOLATILE_TYPE => "volatile",
+                    DW_TAG_RESTRICT_TYPE => "restrict",
+                    _ => "_Atomic",
+                },
+                target: self.type_of(unit, die),
+            },
+            DW_TAG_SUBROUTINE_TYPE => Type::Function,
+            _ => return self.other_entry(unit, die, name, origin, scopes),
+        };
+
+        let id = self.type_id(die.offset);
+        self.info.types[id] = ty;
+        Scope::Type(id)
+    }
+
+    /// Add an entry that is not a type
+    fn other_entry(
+        &mut self,
+        unit: &Unit,
+        die: &Die,
+        name: Option<String>,
+        origin: Option<u64>,
+        scopes: &[Scope],
+    ) -> Scope {
+        let parent = scopes.last();
+        match die.tag {
+            DW_TAG_MEMBER => {
+                if let Some(Scope::Type(id)) = parent {
+                    let offset = match die.get(DW_AT_DATA_MEMBER_LOCATION) {
+                        Some(FormValue::Udata(offset)) => offset,
+                        Some(FormValue::Block(bytes)) => {
+                            let mut reader = Reader::new(bytes);
+                            match reader.u8() {
+                                Ok(DW_OP_PLUS_UCONST) => reader.uleb().unwrap_or(0),
+                                _ => 0,
+                            }
+                        }
+                        _ => 0,
+                    };
+                    let member = Member {
+                        name,
+                        ty: self.type_of(unit, die),
+                        offset,
+                    };
+                    if let Type::Struct { members, .. } = &mut self.info.types[*id] {
+                        members.push(member);
+                    }
+                }
+            }
+            DW_TAG_SUBRANGE_TYPE => {
+                if let Some(Scope::Type(id)) = parent {
+                    let count = match (die.udata(DW_AT_COUNT), die.get(DW_AT_UPPER_BOUND)) {
+                        (Some(count), _) => Some(count),
+                        (None, Some(FormValue::Udata(bound))) => bound.checked_add(1),
+                        (None, Some(FormValue::Sdata(bound))) if bound >= 0 => {
+                            Some(bound as u64 + 1)
+                        }
+                        _ => None,
+                    };
+                    self.array_counts.entry(*id).or_default().push(count);
+                }
+            }
+            DW_TAG_ENUMERATOR => {
+                if let Some(Scope::Type(id)) = parent {
+                    let value = match die.get(DW_AT_CONST_VALUE) {
+                        Some(FormValue::Udata(value)) => value as i64,
+                        Some(FormValue::Sdata(value)) => value,
+                        _ => 0,
+                    };
+                    if let Type::Enum { values, .. } = &mut self.info.types[*id] {
+                        values.push((name.unwrap_or_default(), value));
+                    }
+                }
+            }
+            DW_TAG_SUBPROGRAM => {
+                let Some(low_pc) = die.get(DW_AT_LOW_PC).and_then(|v| unit.address(v)) else {
+                    return Scope::Function(None);
+                };
+                let high_pc = match die.get(DW_AT_HIGH_PC) {
+                    Some(FormValue::Udata(size)) => low_pc.checked_add(size).unwrap_or(low_pc),
+                    Some(value) => unit.address(value).unwrap_or(low_pc),
+                    None => low_pc,
+                };
+                self.info.functions.push(Function {
+                    name,
+                    low_pc: Address(low_pc),
+                    high_pc: Address(high_pc),
+                    frame_base: die
+                        .get(DW_AT_FRAME_BASE)
+                        .map_or(Location::None, |l| unit.location(l)),
+                    locals: vec![],
+                });
+                let index = self.info.functions.len() - 1;
+                if let Some(origin) = origin {
+                    self.function_origins.push((index, origin));
+                }
+                return Scope::Function(Some(index));
+            }
+            DW_TAG_LEXICAL_BLOCK => {
+                let low_pc = die.get(DW_AT_LOW_PC).and_then(|v| unit.address(v));
+                let high_pc = match die.get(DW_AT_HIGH_PC) {
+                    Some(FormValue::Udata(size)) => low_pc.and_then(|low| low.checked_add(size)),
+                    Some(value) => unit.address(value),
+                    None => None,
+                };
+                return Scope::Block(low_pc.zip(high_pc).map(|(l, h)| (Address(l), Address(h))));
+            }
+            DW_TAG_VARIABLE | DW_TAG_FORMAL_PARAMETER => {
+                let variable = Variable {
+                    name: name.unwrap_or_default(),
+                    ty: self.type_of(unit, die),
+                    location: die
+                        .get(DW_AT_LOCATION)
+                        .map_or(Location::None, |l| unit.location(l)),
+                };
+                self.variable(variable, die.tag, origin, scopes);
+            }
+            _ => {}
+        }
+        Scope::Other
+    }
+
+    fn variable(&mut self, variable: Variable, tag: u64, origin: Option<u64>, scopes: &[Scope]) {
+        let function = scopes.iter().rev().find_map(|s| match s {
+            Scope::Function(index) => Some(*index),
+            _ => None,
+        });
+        match function {
+            // Parameters of function types and locals of functions without code
+            Some(None) => {}
+            Some(Some(index)) => {
+                let scope = scopes.iter().rev().find_map(|s| match s {
+                    Scope::Block(range) => Some(*range),
+                    _ => None,
+                });
+                let locals = &mut self.info.functions[index].locals;
+                locals.push(Local {
+                    variable,
+                    parameter: tag == DW_TAG_FORMAL_PARAMETER,
+                    scope: scope.flatten(),
+                });
+                if let Some(origin) = origin {
+                    self.local_origins.push((index, locals.len() - 1, origin));
+                }
+            }
+            // Declarations of globals have no location, their definition does
+            None if variable.location == Location::None => {}
+            None => {
+                self.info.globals.push(variable);
+                if let Some(origin) = origin {
+                    self.global_origins
+                        .push((self.info.globals.len() - 1, origin));
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, scope: Scope) {
+        let Scope::Type(id) = scope else {
+            return;
+        };
+        let Some(counts) = self.array_counts.remove(&id) else {
+            return;
+        };
+        let Type::Array { element, .. } = self.info.types[id] else {
+            return;
+        };
+
+        // Arrays of arrays, innermost first
+        let mut element = element;
+        for count in counts[1..].iter().rev() {
+            self.info.types.push(Type::Array {
+                element,
+                count: *count,
+            });
+            element = Some(self.info.types.len() - 1);
+        }
+        self.info.types[id] = Type::Array {
+            element,
+            count: counts[0],
+        };
+    }
+
+    fn finish(mut self) -> DebugInfo {
+        for (index, origin) in self.global_origins {
+            let global = &mut self.info.globals[index];
+            if global.name.is_empty() {
+                global.name = self.names.get(&origin).cloned().unwrap_or_default();
+            }
+        }
+        for (index, origin) in self.function_origins {
+            let function = &mut self.info.functions[index];
+            if function.name.is_none() {
+                function.name = self.names.get(&origin).cloned();
+            }
+        }
+        for (function, index, origin) in self.local_origins {
+            let local = &mut self.info.functions[function].locals[index];
+            if local.variable.name.is_empty() {
+                local.variable.name = self.names.get(&origin).cloned().unwrap_or_default();
+            }
+        }
+        self.info
+    }
+}
@@ -0,0 +1,402 @@
+//! The `.debug_line` section, which maps addresses to source lines.
+
+use std::collections::HashMap;
+
+use crate::{error::DwarfParseError, Address};
+
+use super::{read_form, reader::Reader, DwarfSections, Format, FormValue};
+
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+const DW_LNS_NEGATE_STMT: u8 = 0x06;
+const DW_LNS_SET_BASIC_BLOCK: u8 = 0x07;
+const DW_LNS_CONST_ADD_PC: u8 = 0x08;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 0x09;
+
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+const DW_LNE_DEFINE_FILE: u8 = 0x03;
+
+const DW_LNCT_PATH: u64 = 0x1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 0x2;
+
+/// The file of rows whose file the line table does not name
+const UNKNOWN_FILE: usize = usize::MAX;
+
+/// A row of the line table, the instructions from its address up to that of the next row
+/// belong to its line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRow {
+    pub address: Address,
+    /// The index of the file in [`LineTable::files()`], `usize::MAX` if the line table does not
+    /// name it
+    pub file: usize,
+    pub line: u64,
+    pub column: u64,
+    /// Whether this is a good place for a breakpoint, like the start of a statement
+    pub is_stmt: bool,
+    /// The first address after a sequence of rows, the row itself has no line
+    pub end_sequence: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+    pub file: &'a str,
+    pub line: u64,
+    pub column: u64,
+}
+
+/// The line tables of all compilation units, merged and sorted by address.
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    /// The paths of the files, joined with their directory
+    files: Vec<String>,
+    rows: Vec<LineRow>,
+}
+
+impl LineTable {
+    pub fn parse(sections: &DwarfSections) -> Result<Self, DwarfParseError> {
+        let mut builder = Builder::default();
+        let mut reader = Reader::new(sections.debug_line);
+        while !reader.is_empty() {
+            builder.unit(&mut reader, sections)?;
+        }
+
+        let mut sequences = builder.sequences;
+        sequences.sort_by_key(|s| s[0].address.0);
+
+        // Code removed by the linker leaves sequences at address 0 that overlap, keep the first
+        let mut rows: Vec<LineRow> = Vec::new();
+        for sequence in sequences {
+            if rows
+                .last()
+                .is_some_and(|r| r.address.0 > sequence[0].address.0)
+            {
+                continue;
+            }
+            rows.extend(sequence);
+        }
+
+        Ok(Self {
+            files: builder.files,
+            rows,
+        })
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn rows(&self) -> &[LineRow] {
+        &self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn file(&self, index: usize) -> &str {
+        self.files.get(index).map_or("<unknown>", String::as_str)
+    }
+
+    /// The row `addr` belongs to
+    pub fn row(&self, addr: Address) -> Option<&LineRow> {
+        let end = self.rows.partition_point(|r| r.address.0 <= addr.0);
+        self.rows[..end].last().filter(|r| !r.end_sequence)
+    }
+
+    /// The source line `addr` belongs to
+    pub fn location(&self, addr: Address) -> Option<SourceLocation<'_>> {
+        self.row(addr).map(|r| SourceLocation {
+            file: self.file(r.file),
+            line: r.line,
+            column: r.column,
+        })
+    }
+
+    /// Whether a statement starts at `addr`, a line can span several statements and a statement
+    /// several blocks of instructions.
+    pub fn is_statement(&self, addr: Address) -> bool {
+        let start = self.rows.partition_point(|r| r.address.0 < addr.0);
+        self.rows[start..]
+            .iter()
+            .take_while(|r| r.address == addr)
+            .any(|r| r.is_stmt && !r.end_sequence)
+    }
+
+    /// The addresses where the code of a line starts, sorted. If `line` has no code the next
+    /// line in the file that does is used, like a compiler would when it places a breakpoint.
+    /// `file` matches paths that end with it.
+    pub fn addresses(&self, file: &str, line: u64) -> Vec<Address> {
+        let matches = |r: &LineRow| {
+            let path = self.file(r.file);
+            r.is_stmt
+                && !r.end_sequence
+                && (path == file
+                    || path
+                        .strip_suffix(file)
+                        .is_some_and(|dir| dir.ends_with('/')))
+        };
+
+        let Some(line) = self
+            .rows
+            .iter()
+            .filter(|r| matches(r) && r.line >= line)
+            .map(|r| r.line)
+            .min()
+        else {
+            return Vec::new();
+        };
+
+        let mut addresses: Vec<Address> = self
+            .rows
+            .iter()
+            .enumerate()
+            .filter(|(i, r)| {
+                matches(r)
+                    && r.line == line
+                    && (*i == 0 || {
+                        let prev = &self.rows[i - 1];
+                        prev.end_sequence || prev.file != r.file || prev.line != line
+                    })
+            })
+            .map(|(_, r)| r.address)
+            .collect();
+        addresses.sort_by_key(|a| a.0);
+        addresses.dedup();
+        addresses
+    }
+}
+
+/// Paths are joined with their directory, unless they are absolute
+fn join(dir: &str, path: &str) -> String {
+    if dir.is_empty() || path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), path)
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    files: Vec<String>,
+    file_indices: HashMap<String, usize>,
+    sequences: Vec<Vec<LineRow>>,
+}
+
+impl Builder {
+    fn file(&mut self, path: String) -> usize {
+        if let Some(index) = self.file_indices.get(&path) {
+            return *index;
+        }
+        self.files.push(path.clone());
+        self.file_indices.insert(path, self.files.len() - 1);
+        self.files.len() - 1
+    }
+
+    fn unit(
+        &mut self,
+        reader: &mut Reader,
+        sections: &DwarfSections,
+    ) -> Result<(), DwarfParseError> {
+        let (length, dwarf64) = reader.initial_length()?;
+        let mut unit = reader.split(length)?;
+
+        let version = unit.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(DwarfParseError::UnsupportedVersion(version));
+        }
+        let mut address_size = 8;
+        if version >= 5 {
+            address_size = unit.u8()?;
+            if !(1..=8).contains(&address_size) {
+                return Err(DwarfParseError::InvalidAddressSize(address_size));
+            }
+            // The segment selector size
+            unit.u8()?;
+        }
+        let format = Format {
+            version,
+            dwarf64,
+            address_size,
+        };
+
+        let header_length = unit.offset(dwarf64)?;
+        let mut header = unit.split(header_length)?;
+        let min_inst_length = u64::from(header.u8()?);
+        if version >= 4 {
+            // The maximum operations per instruction, only used for VLIW
+            header.u8()?;
+        }
+        let default_is_stmt = header.u8()? != 0;
+        let line_base = header.u8()? as i8;
+        let line_range = header.u8()?;
+        if line_range == 0 {
+            return Err(DwarfParseError::InvalidLineRange);
+        }
+        let opcode_base = header.u8()?;
+        let opcode_lengths = header.take(usize::from(opcode_base.saturating_sub(1)))?;
+
+        // Before version 5 file 0 and directory 0 are the compilation unit and its directory,
+        // which are only named in .debug_info
+        let mut files = vec![];
+        if version >= 5 {
+            let dirs = entries(&mut header, sections, format)?;
+            let comp_dir = dirs.first().map_or("", |(path, _)| *path);
+            let dirs: Vec<String> = dirs
+                .iter()
+                .enumerate()
+                .map(|(i, (path, _))| {
+                    if i == 0 {
+                        path.to_string()
+                    } else {
+                        join(comp_dir, path)
+                    }
+                })
+                .collect();
+            for (path, dir) in entries(&mut header, sections, format)? {
+                let dir = dirs.get(dir as usize).map_or("", String::as_str);
+                files.push(self.file(join(dir, path)));
+            }
+        } else {
+            let mut dirs = vec![""];
+            loop {
+                let dir = header.str()?;
+                if dir.is_empty() {
+                    break;
+                }
+                dirs.push(dir);
+            }
+            files.push(UNKNOWN_FILE);
+            loop {
+                let path = header.str()?;
+                if path.is_empty() {
+                    break;
+                }
+                let dir = header.uleb()?;
+                // The modification time and size
+                header.uleb()?;
+                header.uleb()?;
+                let dir = dirs.get(dir as usize).copied().unwrap_or("");
+                files.push(self.file(join(dir, path)));
+            }
+        }
+        let new_state = || LineRow {
+            address: Address(0),
+            file: 1,
+            line: 1,
+            column: 0,
+            is_stmt: default_is_stmt,
+            end_sequence: false,
+        };
+        let mut state = new_state();
+        let mut sequence = vec![];
+        let mut program = unit;
+
+        let special_advance = |adjusted: u8| u64::from(adjusted / line_range) * min_inst_length;
+        let row = |state: &LineRow, files: &[usize]| LineRow {
+            file: files.get(state.file).copied().unwrap_or(UNKNOWN_FILE),
+            ..*state
+        };
+
+        while !program.is_empty() {
+            let opcode = program.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = opcode - opcode_base;
+                state.address.0 = state.address.0.wrapping_add(special_advance(adjusted));
+                let advance = i64::from(line_base) + i64::from(adjusted % line_range);
+                state.line = state.line.wrapping_add_signed(advance);
+                sequence.push(row(&state, &files));
+                continue;
+            }
+
+            match opcode {
+                0 => {
+                    let len = program.uleb()?;
+                    let mut extended = program.split(len)?;
+                    match extended.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            state.end_sequence = true;
+                            sequence.push(row(&state, &files));
+                            self.sequences.push(std::mem::take(&mut sequence));
+                            state = new_state();
+                        }
+                        DW_LNE_SET_ADDRESS => {
+                            let size = extended.remaining();
+                            if size > 8 {
+                                return Err(DwarfParseError::UnexpectedEnd);
+                            }
+                            state.address = Address(extended.uint(size)?);
+                        }
+                        DW_LNE_DEFINE_FILE => {
+                            let path = extended.str()?;
+                            files.push(self.file(path.to_string()));
+                        }
+                        _ => {}
+                    }
+                }
+                DW_LNS_COPY => sequence.push(row(&state, &files)),
+                DW_LNS_ADVANCE_PC => {
+                    let advance = program.uleb()?.wrapping_mul(min_inst_length);
+                    state.address.0 = state.address.0.wrapping_add(advance);
+                }
+                DW_LNS_ADVANCE_LINE => {
+                    state.line = state.line.wrapping_add_signed(program.sleb()?);
+                }
+                DW_LNS_SET_FILE => state.file = program.uleb()? as usize,
+                DW_LNS_SET_COLUMN => state.column = program.uleb()?,
+                DW_LNS_NEGATE_STMT => state.is_stmt = !state.is_stmt,
+                DW_LNS_SET_BASIC_BLOCK => {}
+                DW_LNS_CONST_ADD_PC => {
+                    let advance = special_advance(255 - opcode_base);
+                    state.address.0 = state.address.0.wrapping_add(advance);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => {
+                    state.address.0 = state.address.0.wrapping_add(program.u16()?.into());
+                }
+                // Opcodes without effect on the rows, like setting the prologue end
+                opcode => {
+                    for _ in 0..opcode_lengths[usize::from(opcode) - 1] {
+                        program.uleb()?;
+                    }
+                }
+            }
+        }
+
+        // A sequence without an end is not valid, but keep what it has
+        if !sequence.is_empty() {
+            self.sequences.push(sequence);
+        }
+        Ok(())
+    }
+}
+
+/// The directory or file entries of a version 5 header, with their path and directory index
+fn entries<'a>(
+    header: &mut Reader<'a>,
+    sections: &DwarfSections<'a>,
+    format: Format,
+) -> Result<Vec<(&'a str, u64)>, DwarfParseError> {
+    let mut formats = vec![];
+    for _ in 0..header.u8()? {
+        formats.push((header.uleb()?, header.uleb()?));
+    }
+
+    let mut entries = vec![];
+    for _ in 0..header.uleb()? {
+        let mut path = "";
+        let mut dir = 0;
+        for (content, form) in &formats {
+            match (*content, read_form(header, sections, *form, format)?) {
+                (DW_LNCT_PATH, FormValue::Str(s)) => path = s,
+                (DW_LNCT_DIRECTORY_INDEX, FormValue::Udata(d)) => dir = d,
+                _ => {}
+            }
+        }
+        entries.push((path, dir));
+    }
+    Ok(entries)
+}
@@ -0,0 +1,184 @@
+//! Parsing of the DWARF debug information compilers emit with `-g`, see
+//! [`Elf::line_table()`](crate::Elf::line_table()),
+//! [`Elf::debug_info()`](crate::Elf::debug_info()) and
+//! [`Elf::call_frame_info()`](crate::Elf::call_frame_info()).
+
+pub mod frame;
+pub mod info;
+pub mod line;
+pub(crate) mod reader;
+
+use crate::error::DwarfParseError;
+
+use self::reader::{str_at, Reader};
+
+/// The contents of the debug sections, empty for those the elf does not have.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DwarfSections<'a> {
+    pub debug_info: &'a [u8],
+    pub debug_abbrev: &'a [u8],
+    pub debug_line: &'a [u8],
+    pub debug_str: &'a [u8],
+    pub debug_line_str: &'a [u8],
+    pub debug_str_offsets: &'a [u8],
+    pub debug_addr: &'a [u8],
+    pub debug_frame: &'a [u8],
+    pub eh_frame: &'a [u8],
+    /// The address `.eh_frame` is loaded at, which its pc relative pointers are relative to
+    pub eh_frame_address: u64,
+}
+
+/// How the values of a unit are encoded.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Format {
+    pub(crate) version: u16,
+    /// Offsets into other sections are 8 bytes instead of 4
+    pub(crate) dwarf64: bool,
+    pub(crate) address_size: u8,
+}
+
+/// The value of an attribute, references and indices are left to the unit to resolve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FormValue<'a> {
+    Udata(u64),
+    Sdata(i64),
+    Addr(u64),
+    /// An index into the unit's addresses in `.debug_addr`
+    Addrx(u64),
+    Str(&'a str),
+    /// An index into the unit's string offsets in `.debug_str_offsets`
+    Strx(u64),
+    /// An offset from the start of the unit
+    UnitRef(u64),
+    /// An offset from the start of `.debug_info`
+    InfoRef(u64),
+    /// An offset into another section, like a location list
+    SecOffset(u64),
+    Block(&'a [u8]),
+    Flag(bool),
+    /// Values that are only skipped, like type signatures
+    Other,
+}
+
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_BLOCK2: u64 = 0x03;
+const DW_FORM_BLOCK4: u64 = 0x04;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_BLOCK1: u64 = 0x0a;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_FLAG: u64 = 0x0c;
+const DW_FORM_SDATA: u64 = 0x0d;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_REF_ADDR: u64 = 0x10;
+const DW_FORM_REF1: u64 = 0x11;
+const DW_FORM_REF2: u64 = 0x12;
+const DW_FORM_REF4: u64 = 0x13;
+const DW_FORM_REF8: u64 = 0x14;
+const DW_FORM_REF_UDATA: u64 = 0x15;
+const DW_FORM_INDIRECT: u64 = 0x16;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+const DW_FORM_EXPRLOC: u64 = 0x18;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+const DW_FORM_STRX: u64 = 0x1a;
+const DW_FORM_ADDRX: u64 = 0x1b;
+const DW_FORM_REF_SUP4: u64 = 0x1c;
+const DW_FORM_STRP_SUP: u64 = 0x1d;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_REF_SIG8: u64 = 0x20;
+const DW_FORM_LOCLISTX: u64 = 0x22;
+const DW_FORM_RNGLISTX: u64 = 0x23;
+const DW_FORM_REF_SUP8: u64 = 0x24;
+const DW_FORM_STRX1: u64 = 0x25;
+const DW_FORM_STRX4: u64 = 0x28;
+const DW_FORM_ADDRX1: u64 = 0x29;
+const DW_FORM_ADDRX4: u64 = 0x2c;
+
+/// Read a value of the given form. `DW_FORM_implicit_const` has its value in the abbreviation
+/// and is not read here.
+pub(crate) fn read_form<'a>(
+    reader: &mut Reader<'a>,
+    sections: &DwarfSections<'a>,
+    form: u64,
+    format: Format,
+) -> Result<FormValue<'a>, DwarfParseError> {
+    let address_size = usize::from(format.address_size);
+    Ok(match form {
+        DW_FORM_ADDR => FormValue::Addr(reader.uint(address_size)?),
+        DW_FORM_DATA1 => FormValue::Udata(reader.u8()?.into()),
+        DW_FORM_DATA2 => FormValue::Udata(reader.u16()?.into()),
+        DW_FORM_DATA4 => FormValue::Udata(reader.u32()?.into()),
+        DW_FORM_DATA8 => FormValue::Udata(reader.u64()?),
+        DW_FORM_UDATA => FormValue::Udata(reader.uleb()?),
+        DW_FORM_SDATA => FormValue::Sdata(reader.sleb()?),
+        DW_FORM_FLAG => FormValue::Flag(reader.u8()? != 0),
+        DW_FORM_FLAG_PRESENT => FormValue::Flag(true),
+        DW_FORM_STRING => FormValue::Str(reader.str()?),
+        DW_FORM_STRP => FormValue::Str(str_at(sections.debug_str, reader.offset(format.dwarf64)?)?),
+        DW_FORM_LINE_STRP => {
+            FormValue::Str(str_at(sections.debug_line_str, reader.offset(format.dwarf64)?)?)
+        }
+        DW_FORM_STRX => FormValue::Strx(reader.uleb()?),
+        DW_FORM_STRX1..=DW_FORM_STRX4 => {
+            FormValue::Strx(reader.uint((form - DW_FORM_STRX1 + 1) as usize)?)
+        }
+        DW_FORM_ADDRX => FormValue::Addrx(reader.uleb()?),
+        DW_FORM_ADDRX1..=DW_FORM_ADDRX4 => {
+            FormValue::Addrx(reader.uint((form - DW_FORM_ADDRX1 + 1) as usize)?)
+        }
+        DW_FORM_REF1 => FormValue::UnitRef(reader.u8()?.into()),
+        DW_FORM_REF2 => FormValue::UnitRef(reader.u16()?.into()),
+        DW_FORM_REF4 => FormValue::UnitRef(reader.u32()?.into()),
+        DW_FORM_REF8 => FormValue::UnitRef(reader.u64()?),
+        DW_FORM_REF_UDATA => FormValue::UnitRef(reader.uleb()?),
+        DW_FORM_REF_ADDR if format.version == 2 => FormValue::InfoRef(reader.uint(address_size)?),
+        DW_FORM_REF_ADDR => FormValue::InfoRef(reader.offset(format.dwarf64)?),
+        DW_FORM_SEC_OFFSET => FormValue::SecOffset(reader.offset(format.dwarf64)?),
+        DW_FORM_LOCLISTX | DW_FORM_RNGLISTX => {
+            reader.uleb()?;
+            FormValue::Other
+        }
+        DW_FORM_EXPRLOC | DW_FORM_BLOCK => {
+            let len = reader.uleb()?;
+            FormValue::Block(reader.split_bytes(len)?)
+        }
+        DW_FORM_BLOCK1 => {
+            let len = reader.u8()?.into();
+            FormValue::Block(reader.split_bytes(len)?)
+        }
+        DW_FORM_BLOCK2 => {
+            let len = reader.u16()?.into();
+            FormValue::Block(reader.split_bytes(len)?)
+        }
+        DW_FORM_BLOCK4 => {
+            let len = reader.u32()?.into();
+            FormValue::Block(reader.split_bytes(len)?)
+        }
+        DW_FORM_DATA16 => {
+            reader.skip(16)?;
+            FormValue::Other
+        }
+        DW_FORM_REF_SIG8 | DW_FORM_REF_SUP8 => {
+            reader.skip(8)?;
+            FormValue::Other
+        }
+        DW_FORM_REF_SUP4 => {
+            reader.skip(4)?;
+            FormValue::Other
+        }
+        DW_FORM_STRP_SUP => {
+            reader.offset(format.dwarf64)?;
+            FormValue::Other
+        }
+        DW_FORM_INDIRECT => {
+            let form = reader.uleb()?;
+            read_form(reader, sections, form, format)?
+        }
+        form => return Err(DwarfParseError::UnsupportedForm(form)),
+    })
+}
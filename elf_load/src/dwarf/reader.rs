@@ -0,0 +1,152 @@
+use crate::error::DwarfParseError;
+
+/// A cursor over the little endian values of a dwarf section.
+#[derive(Debug, Clone)]
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    /// A reader starting at `offset` into `bytes`
+    pub(crate) fn at(bytes: &'a [u8], offset: u64) -> Result<Self, DwarfParseError> {
+        if offset > bytes.len() as u64 {
+            return Err(DwarfParseError::UnexpectedEnd);
+        }
+        Ok(Self {
+            bytes,
+            pos: offset as usize,
+        })
+    }
+
+    /// The offset of the next value from the start of the bytes
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], DwarfParseError> {
+        if len > self.remaining() {
+            return Err(DwarfParseError::UnexpectedEnd);
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn skip(&mut self, len: u64) -> Result<(), DwarfParseError> {
+        self.split_bytes(len)?;
+        Ok(())
+    }
+
+    /// The next `len` bytes, which are skipped
+    pub(crate) fn split_bytes(&mut self, len: u64) -> Result<&'a [u8], DwarfParseError> {
+        self.take(usize::try_from(len).map_err(|_| DwarfParseError::UnexpectedEnd)?)
+    }
+
+    /// A reader over the next `len` bytes, which are skipped by this one
+    pub(crate) fn split(&mut self, len: u64) -> Result<Reader<'a>, DwarfParseError> {
+        Ok(Reader::new(self.split_bytes(len)?))
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DwarfParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, DwarfParseError> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, DwarfParseError> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DwarfParseError> {
+        self.uint(8)
+    }
+
+    /// An unsigned value of `size` bytes, at most 8
+    pub(crate) fn uint(&mut self, size: usize) -> Result<u64, DwarfParseError> {
+        let mut buf = [0; 8];
+        buf[..size].copy_from_slice(self.take(size)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub(crate) fn uleb(&mut self) -> Result<u64, DwarfParseError> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= u64::from(byte & 0x7F) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    pub(crate) fn sleb(&mut self) -> Result<i64, DwarfParseError> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= i64::from(byte & 0x7F) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    /// A nul terminated string
+    pub(crate) fn str(&mut self) -> Result<&'a str, DwarfParseError> {
+        let len = self.bytes[self.pos..]
+            .iter()
+            .position(|b| *b == b'\0')
+            .ok_or(DwarfParseError::InvalidString)?;
+        let bytes = self.take(len + 1)?;
+        std::str::from_utf8(&bytes[..len]).map_err(|_| DwarfParseError::InvalidString)
+    }
+
+    /// The length at the start of a unit and whether the unit uses the 64 bit format
+    pub(crate) fn initial_length(&mut self) -> Result<(u64, bool), DwarfParseError> {
+        match self.u32()? {
+            0xFFFF_FFFF => Ok((self.u64()?, true)),
+            length => Ok((length.into(), false)),
+        }
+    }
+
+    /// An offset into another section, 8 bytes in the 64 bit format and 4 otherwise
+    pub(crate) fn offset(&mut self, dwarf64: bool) -> Result<u64, DwarfParseError> {
+        if dwarf64 {
+            self.u64()
+        } else {
+            Ok(self.u32()?.into())
+        }
+    }
+}
+
+/// The string at `offset` in a string section like `.debug_str`
+pub(crate) fn str_at(section: &[u8], offset: u64) -> Result<&str, DwarfParseError> {
+    Reader::at(section, offset)
+        .map_err(|_| DwarfParseError::InvalidString)?
+        .str()
+}
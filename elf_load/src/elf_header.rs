@@ -7,6 +7,9 @@ use super::{
     ByteRanges,
 };
 
+/// The size of a 64-bit header
+pub const HEADER_SIZE: u16 = 64;
+
 #[derive(Debug)]
 struct RawElfHeader {
     /// 0x00 : 4
@@ -177,4 +180,35 @@ impl ElfHeader {
             s_header_name_entry,
         })
     }
+
+    /// The header as it is stored at the start of the file, only 64-bit little endian headers
+    /// are supported.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![
+            0x7F,
+            0x45,
+            0x4C,
+            0x46,
+            self.bitness as u8,
+            self.endianess as u8,
+            1,
+            self.abi_type as u8,
+            self.abi_ver,
+        ];
+        bytes.resize(0x10, 0);
+        bytes.extend(u16::from(self.obj_type).to_le_bytes());
+        bytes.extend((self.arch as u16).to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(self.entry.0.to_le_bytes());
+        bytes.extend(self.p_header.to_le_bytes());
+        bytes.extend(self.s_header.to_le_bytes());
+        bytes.extend(self.flags.to_le_bytes());
+        bytes.extend(HEADER_SIZE.to_le_bytes());
+        bytes.extend(self.p_header_size.to_le_bytes());
+        bytes.extend(self.p_header_ecount.to_le_bytes());
+        bytes.extend(self.s_header_size.to_le_bytes());
+        bytes.extend(self.s_header_ecount.to_le_bytes());
+        bytes.extend(self.s_header_name_entry.to_le_bytes());
+        bytes
+    }
 }
@@ -41,12 +41,45 @@ impl<T: BitFlag> From<FromBitsError<T>> for SectionHeaderParseError {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum SymbolParseError {
+    InvalidSymbolType(u8),
+    InvalidBinding(u8),
+    /// The name is not in the string table or not valid utf-8
+    InvalidName,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DwarfParseError {
+    /// A unit or value runs past the end of its section
+    UnexpectedEnd,
+    UnsupportedVersion(u16),
+    UnsupportedForm(u64),
+    InvalidAddressSize(u8),
+    /// A debug information entry uses an abbreviation code its table does not have
+    InvalidAbbreviation(u64),
+    /// A string is not in its section or not valid utf-8
+    InvalidString,
+    InvalidLineRange,
+    /// A frame description entry points to something that is not a common information entry
+    InvalidCiePointer(u64),
+    UnsupportedPointerEncoding(u8),
+    /// A call frame instruction that is not understood
+    UnsupportedInstruction(u8),
+}
+
 #[derive(Debug)]
 pub enum ElfParseError {
     ElfHeaderParseError(ElfHeaderParseError),
     ProgramHeaderParseHeader(ProgramHeaderParseError),
     SectionHeaderParseError(SectionHeaderParseError),
+    SymbolParseError(SymbolParseError),
+    DwarfParseError(DwarfParseError),
     InvalidNameSecionType,
+    /// The section linked to the symbol table is not a string table
+    InvalidStringSectionType,
+    /// The symbol table entries are too small or the table does not fit in the file
+    InvalidSymbolTable,
     SectionNotFound,
 }
 
@@ -68,6 +101,18 @@ impl From<SectionHeaderParseError> for ElfParseError {
     }
 }
 
+impl From<SymbolParseError> for ElfParseError {
+    fn from(value: SymbolParseError) -> Self {
+        Self::SymbolParseError(value)
+    }
+}
+
+impl From<DwarfParseError> for ElfParseError {
+    fn from(value: DwarfParseError) -> Self {
+        Self::DwarfParseError(value)
+    }
+}
+
 impl From<FromUtf8Error> for ElfParseError {
     fn from(value: FromUtf8Error) -> Self {
         Self::SectionHeaderParseError(SectionHeaderParseError::InvalidSectionName(value))
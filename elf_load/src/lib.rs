@@ -1,17 +1,25 @@
 use crate::{data::SectionType, section_header::SectionName};
 
-use self::{program_header::ProgramHeader, section_header::SectionHeader};
+use self::{
+    dwarf::{frame::CallFrameInfo, info::DebugInfo, line::LineTable, DwarfSections},
+    program_header::ProgramHeader,
+    section_header::SectionHeader,
+    symbol::{read_string, Symbol, SymbolTable, SYMBOL_SIZE},
+};
 use elf_header::ElfHeader;
 use error::ElfParseError;
 use std::{fmt::Debug, usize};
 
 pub mod data;
+pub mod dwarf;
 pub mod elf_header;
 pub mod error;
 pub mod program_header;
 pub mod section_header;
+pub mod symbol;
 #[cfg(test)]
 mod tests;
+pub mod writer;
 
 pub struct Elf {
     pub header: elf_header::ElfHeader,
@@ -84,6 +92,114 @@ impl Elf {
 
         Ok(())
     }
+
+    /// Parse the symbol table, it is empty if the elf has none, for example because it was
+    /// stripped.
+    pub fn symbol_table(&self) -> Result<SymbolTable, ElfParseError> {
+        let Some(symtab) = self
+            .section_headers
+            .iter()
+            .find(|s| s.sec_type == SectionType::Symtab)
+        else {
+            return Ok(SymbolTable::default());
+        };
+
+        let strtab = self
+            .section_headers
+            .get(symtab.sec_link as usize)
+            .ok_or(ElfParseError::SectionNotFound)?;
+        if strtab.sec_type != SectionType::Strtab {
+            return Err(ElfParseError::InvalidStringSectionType);
+        }
+
+        let entry_size = symtab.sec_entry_size.unwrap_or(SYMBOL_SIZE);
+        let in_bounds = |offset: u64, size: u64| {
+            offset
+                .checked_add(size)
+                .is_some_and(|end| end <= self.bytes.len() as u64)
+        };
+        if entry_size < SYMBOL_SIZE
+            || !in_bounds(symtab.sec_offset, symtab.sec_size)
+            || !in_bounds(strtab.sec_offset, strtab.sec_size)
+        {
+            return Err(ElfParseError::InvalidSymbolTable);
+        }
+
+        let strings = self.bytes.get_bytes(strtab.sec_offset, strtab.sec_size);
+        let mut symbols = vec![];
+        for i in 0..(symtab.sec_size / entry_size) {
+            symbols.push(Symbol::from_bytes(
+                &self.bytes,
+                symtab.sec_offset + i * entry_size,
+                entry_size,
+                strings,
+            )?);
+        }
+
+        Ok(SymbolTable::new(symbols))
+    }
+
+    /// The header of the section called `name`
+    pub fn section(&self, name: &str) -> Option<&SectionHeader> {
+        let names = self
+            .section_headers
+            .get(self.header.s_header_name_entry as usize)?;
+        let names = self.section_bytes(names)?;
+
+        self.section_headers.iter().find(|s| match &s.name {
+            SectionName::String(_, n) => n == name,
+            SectionName::Offset(offset) => read_string(names, *offset).as_deref() == Some(name),
+        })
+    }
+
+    /// The contents of the section called `name`, `None` if there is no such section or it does
+    /// not fit in the file.
+    pub fn section_data(&self, name: &str) -> Option<&[u8]> {
+        self.section_bytes(self.section(name)?)
+    }
+
+    fn section_bytes(&self, header: &SectionHeader) -> Option<&[u8]> {
+        if header.sec_type == SectionType::Nobits {
+            return Some(&[]);
+        }
+        let end = header.sec_offset.checked_add(header.sec_size)?;
+        self.bytes.get(header.sec_offset as usize..end as usize)
+    }
+
+    /// The debug sections, empty if the elf has no debug information.
+    pub fn dwarf_sections(&self) -> DwarfSections<'_> {
+        let section = |name| self.section_data(name).unwrap_or_default();
+        DwarfSections {
+            debug_info: section(".debug_info"),
+            debug_abbrev: section(".debug_abbrev"),
+            debug_line: section(".debug_line"),
+            debug_str: section(".debug_str"),
+            debug_line_str: section(".debug_line_str"),
+            debug_str_offsets: section(".debug_str_offsets"),
+            debug_addr: section(".debug_addr"),
+            debug_frame: section(".debug_frame"),
+            eh_frame: section(".eh_frame"),
+            eh_frame_address: self.section(".eh_frame").map_or(0, |s| s.sec_addr.0),
+        }
+    }
+
+    /// Parse the line tables, which map addresses to source lines. The table is empty if the elf
+    /// has no debug information.
+    pub fn line_table(&self) -> Result<LineTable, ElfParseError> {
+        Ok(LineTable::parse(&self.dwarf_sections())?)
+    }
+
+    /// Parse the functions, variables and types described in `.debug_info`. Nothing is found if
+    /// the elf has no debug information.
+    pub fn debug_info(&self) -> Result<DebugInfo, ElfParseError> {
+        Ok(DebugInfo::parse(&self.dwarf_sections())?)
+    }
+
+    /// Parse the call frame information in `.debug_frame` and `.eh_frame`, which describes how to
+    /// find the caller of a function. Nothing is found if the elf has neither section.
+    pub fn call_frame_info(&self) -> Result<CallFrameInfo, ElfParseError> {
+        Ok(CallFrameInfo::parse(&self.dwarf_sections())?)
+    }
 }
 
 pub trait ByteRanges {
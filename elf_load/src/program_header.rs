@@ -7,6 +7,9 @@ use super::{
     ByteRanges,
 };
 
+/// The size of a 64-bit program header
+pub const PROGRAM_HEADER_SIZE: u16 = 56;
+
 struct RawProgramHeader {
     /// 0x00 : 4
     program_type: [u8; 4],
@@ -86,4 +89,18 @@ impl ProgramHeader {
             align,
         })
     }
+
+    /// The header as it is stored in a 64-bit little endian elf
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PROGRAM_HEADER_SIZE as usize);
+        bytes.extend(u32::from(self.program_type).to_le_bytes());
+        bytes.extend(self.flags.bits().to_le_bytes());
+        bytes.extend(self.seg_offset.to_le_bytes());
+        bytes.extend(self.seg_v_addr.0.to_le_bytes());
+        bytes.extend(self.seg_p_addr.0.to_le_bytes());
+        bytes.extend(self.seg_f_size.0.to_le_bytes());
+        bytes.extend(self.seg_m_size.0.to_le_bytes());
+        bytes.extend(self.align.to_le_bytes());
+        bytes
+    }
 }
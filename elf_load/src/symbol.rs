@@ -0,0 +1,162 @@
+use crate::{
+    data::{SymbolBinding, SymbolType},
+    error::SymbolParseError,
+    Address,
+};
+
+use super::ByteRanges;
+
+/// The size of a symbol table entry in a 64 bit elf
+pub const SYMBOL_SIZE: u64 = 24;
+
+/// The section index of undefined symbols and the start of the reserved indices, which include
+/// absolute and common symbols
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xFF00;
+
+struct RawSymbol {
+    /// 0x00 : 4
+    name: [u8; 4],
+    /// 0x04 : 1
+    info: [u8; 1],
+    /// 0x05 : 1
+    other: [u8; 1],
+    /// 0x06 : 2
+    section: [u8; 2],
+    /// 0x08 : 8
+    value: [u8; 8],
+    /// 0x10 : 8
+    size: [u8; 8],
+}
+
+impl RawSymbol {
+    fn from_bytes(bytes: &[u8]) -> RawSymbol {
+        Self {
+            name: bytes.get_bytes_copy(0x00),
+            info: bytes.get_bytes_copy(0x04),
+            other: bytes.get_bytes_copy(0x05),
+            section: bytes.get_bytes_copy(0x06),
+            value: bytes.get_bytes_copy(0x08),
+            size: bytes.get_bytes_copy(0x10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub sym_type: SymbolType,
+    pub binding: SymbolBinding,
+    pub other: u8,
+    /// The index of the section the symbol is defined in
+    pub section: u16,
+    pub value: Address,
+    pub size: u64,
+}
+
+impl Symbol {
+    /// Parse the symbol at `offset`, its name is read from the string table `strtab`.
+    pub fn from_bytes(
+        bytes: &[u8],
+        offset: u64,
+        size: u64,
+        strtab: &[u8],
+    ) -> Result<Symbol, SymbolParseError> {
+        let raw = RawSymbol::from_bytes(bytes.get_bytes(offset, size));
+
+        let name = read_string(strtab, u32::from_le_bytes(raw.name))
+            .ok_or(SymbolParseError::InvalidName)?;
+
+        let info = u8::from_le_bytes(raw.info);
+        let sym_type = SymbolType::try_from(info & 0xF)?;
+        let binding = SymbolBinding::try_from(info >> 4)?;
+
+        Ok(Self {
+            name,
+            sym_type,
+            binding,
+            other: u8::from_le_bytes(raw.other),
+            section: u16::from_le_bytes(raw.section),
+            value: u64::from_le_bytes(raw.value).into(),
+            size: u64::from_le_bytes(raw.size),
+        })
+    }
+
+    /// Whether this is a function, object or label defined in a section, as opposed to
+    /// undefined or absolute symbols, section and file symbols or mapping symbols like `$x`.
+    pub fn is_location(&self) -> bool {
+        matches!(
+            self.sym_type,
+            SymbolType::Func | SymbolType::Object | SymbolType::NoType
+        ) && self.section != SHN_UNDEF
+            && self.section < SHN_LORESERVE
+            && !self.name.is_empty()
+            && !self.name.starts_with('$')
+            && !self.name.starts_with(".L")
+    }
+}
+
+/// Read the nul terminated string at `offset` in a string table.
+pub fn read_string(strtab: &[u8], offset: u32) -> Option<String> {
+    let bytes = strtab.get(offset as usize..)?;
+    let len = bytes.iter().position(|b| *b == b'\0')?;
+    String::from_utf8(bytes[..len].to_vec()).ok()
+}
+
+/// The symbols of an elf, see [`Elf::symbol_table()`](crate::Elf::symbol_table()).
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    /// Indices of the symbols that name a location, sorted by address
+    by_addr: Vec<usize>,
+}
+
+impl SymbolTable {
+    pub fn new(symbols: Vec<Symbol>) -> Self {
+        let mut by_addr: Vec<usize> = symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_location())
+            .map(|(i, _)| i)
+            .collect();
+        by_addr.sort_by_key(|i| symbols[*i].value.0);
+        Self { symbols, by_addr }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// The symbol called `name`, global symbols are preferred over local ones.
+    pub fn by_name(&self, name: &str) -> Option<&Symbol> {
+        let mut found = self.symbols.iter().filter(|s| s.name == name);
+        let first = found.next()?;
+        if first.binding == SymbolBinding::Local {
+            if let Some(global) = found.find(|s| s.binding != SymbolBinding::Local) {
+                return Some(global);
+            }
+        }
+        Some(first)
+    }
+
+    /// The function or object `addr` is in and the offset of `addr` into it, that is the nearest
+    /// symbol at or before `addr` if `addr` is within its size. Symbols without a size, like
+    /// labels in assembly, extend up to the next symbol.
+    pub fn by_address(&self, addr: Address) -> Option<(&Symbol, u64)> {
+        let end = self
+            .by_addr
+            .partition_point(|i| self.symbols[*i].value.0 <= addr.0);
+        let nearest = self.symbols[*self.by_addr[..end].last()?].value;
+        self.by_addr[..end]
+            .iter()
+            .rev()
+            .map(|i| &self.symbols[*i])
+            .take_while(|s| s.value == nearest)
+            .find(|s| s.size == 0 || addr.0 - s.value.0 < s.size)
+            .map(|s| (s, addr.0 - s.value.0))
+    }
+}
@@ -334,3 +334,546 @@ mod section_header {
         assert_eq!(result, Err(SectionHeaderParseError::InvalidFlags));
     }
 }
+
+mod symbol {
+    use crate::{
+        data::{SymbolBinding, SymbolType},
+        error::SymbolParseError,
+        symbol::{Symbol, SymbolTable},
+        Address,
+    };
+
+    const STRTAB: &[u8] = b"\0main\0loop\0";
+
+    fn symbol(name: &str, sym_type: SymbolType, value: u64, size: u64) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            sym_type,
+            binding: SymbolBinding::Global,
+            other: 0,
+            section: 1,
+            value: Address(value),
+            size,
+        }
+    }
+
+    #[test]
+    fn load() -> Result<(), SymbolParseError> {
+        let bytes = vec![
+            0x01, 0x00, 0x00, 0x00, 0x12, 0x00, 0x01, 0x00, 0x10, 0x00, 0x00, 0x80, 0x00, 0x00,
+            0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let symbol = Symbol::from_bytes(&bytes, 0, 24, STRTAB)?;
+
+        assert_eq!(
+            symbol,
+            Symbol {
+                name: "main".to_string(),
+                sym_type: SymbolType::Func,
+                binding: SymbolBinding::Global,
+                other: 0,
+                section: 1,
+                value: Address(0x80000010),
+                size: 0x20
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_symbol_type() {
+        let bytes = vec![
+            0x01, 0x00, 0x00, 0x00, 0x18, 0x00, 0x01, 0x00, 0x10, 0x00, 0x00, 0x80, 0x00, 0x00,
+            0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let result = Symbol::from_bytes(&bytes, 0, 24, STRTAB);
+
+        assert_eq!(result, Err(SymbolParseError::InvalidSymbolType(0x8)));
+    }
+
+    #[test]
+    fn invalid_name() {
+        let bytes = vec![
+            0x20, 0x00, 0x00, 0x00, 0x12, 0x00, 0x01, 0x00, 0x10, 0x00, 0x00, 0x80, 0x00, 0x00,
+            0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let result = Symbol::from_bytes(&bytes, 0, 24, STRTAB);
+
+        assert_eq!(result, Err(SymbolParseError::InvalidName));
+    }
+
+    #[test]
+    fn by_address() {
+        let table = SymbolTable::new(vec![
+            symbol("main", SymbolType::Func, 0x1000, 0x20),
+            symbol("loop", SymbolType::NoType, 0x1010, 0),
+            symbol("main.c", SymbolType::File, 0, 0),
+            symbol("data", SymbolType::Object, 0x2000, 8),
+        ]);
+
+        let (sym, offset) = table.by_address(Address(0x1008)).unwrap();
+        assert_eq!((sym.name.as_str(), offset), ("main", 8));
+        let (sym, offset) = table.by_address(Address(0x101c)).unwrap();
+        assert_eq!((sym.name.as_str(), offset), ("loop", 0xc));
+        let (sym, offset) = table.by_address(Address(0x2004)).unwrap();
+        assert_eq!((sym.name.as_str(), offset), ("data", 4));
+
+        assert!(table.by_address(Address(0x800)).is_none());
+        assert!(table.by_address(Address(0x2008)).is_none());
+    }
+
+    #[test]
+    fn by_name() {
+        let mut local = symbol("helper", SymbolType::Func, 0x1000, 4);
+        local.binding = SymbolBinding::Local;
+        let table = SymbolTable::new(vec![local, symbol("helper", SymbolType::Func, 0x2000, 4)]);
+
+        assert_eq!(table.by_name("helper").unwrap().value, Address(0x2000));
+        assert!(table.by_name("missing").is_none());
+    }
+}
+
+mod line {
+    use crate::{
+        dwarf::{
+            line::{LineTable, SourceLocation},
+            DwarfSections,
+        },
+        error::DwarfParseError,
+        Address,
+    };
+
+    /// A version 4 line program for src/main.c with lines 12, 13 and 11 at 0x80000000,
+    /// 0x80000004 and 0x8000000c, ending at 0x80000010
+    const DEBUG_LINE: &[u8] = &[
+        0x41, 0x00, 0x00, 0x00, 0x04, 0x00, 0x22, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0xfb, 0x0e,
+        0x0d, 0x00, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, b's', b'r',
+        b'c', 0x00, 0x00, b'm', b'a', b'i', b'n', b'.', b'c', 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x09, 0x02, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x03, 0x0b, 0x01, 0x4b, 0x02,
+        0x08, 0x03, 0x7e, 0x01, 0x02, 0x04, 0x00, 0x01, 0x01,
+    ];
+
+    fn table() -> Result<LineTable, DwarfParseError> {
+        LineTable::parse(&DwarfSections {
+            debug_line: DEBUG_LINE,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn load() -> Result<(), DwarfParseError> {
+        let table = table()?;
+
+        assert_eq!(table.files(), ["src/main.c"]);
+        let rows: Vec<_> = table
+            .rows()
+            .iter()
+            .map(|r| (r.address, r.line, r.end_sequence))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (Address(0x80000000), 12, false),
+                (Address(0x80000004), 13, false),
+                (Address(0x8000000c), 11, false),
+                (Address(0x80000010), 11, true)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn location() -> Result<(), DwarfParseError> {
+        let table = table()?;
+
+        assert_eq!(
+            table.location(Address(0x80000006)),
+            Some(SourceLocation {
+                file: "src/main.c",
+                line: 13,
+                column: 0
+            })
+        );
+        assert_eq!(table.location(Address(0x7ffffffc)), None);
+        assert_eq!(table.location(Address(0x80000010)), None);
+        assert!(table.is_statement(Address(0x80000004)));
+        assert!(!table.is_statement(Address(0x80000006)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn addresses() -> Result<(), DwarfParseError> {
+        let table = table()?;
+
+        assert_eq!(table.addresses("main.c", 13), [Address(0x80000004)]);
+        assert_eq!(table.addresses("src/main.c", 10), [Address(0x8000000c)]);
+        assert_eq!(table.addresses("ain.c", 13), []);
+        assert_eq!(table.addresses("main.c", 14), []);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = DEBUG_LINE.to_vec();
+        bytes[4] = 0x06;
+
+        let result = LineTable::parse(&DwarfSections {
+            debug_line: &bytes,
+            ..Default::default()
+        });
+
+        assert_eq!(result.err(), Some(DwarfParseError::UnsupportedVersion(6)));
+    }
+}
+
+mod info {
+    use crate::{
+        dwarf::{
+            info::{DebugInfo, Location, Type},
+            DwarfSections,
+        },
+        error::DwarfParseError,
+        Address,
+    };
+
+    /// A version 4 unit with `struct task { int id; int state; } task_list[2]`, `struct task
+    /// *current` and a function `main` at 0x80000000 with the locals `int i` on the stack and
+    /// `int n` in a0
+    const DEBUG_ABBREV: &[u8] = &[
+        0x01, 0x11, 0x01, 0x03, 0x08, 0x00, 0x00, 0x02, 0x24, 0x00, 0x03, 0x08, 0x0b, 0x0b, 0x3e,
+        0x0b, 0x00, 0x00, 0x03, 0x13, 0x01, 0x03, 0x08, 0x0b, 0x0b, 0x00, 0x00, 0x04, 0x0d, 0x00,
+        0x03, 0x08, 0x49, 0x13, 0x38, 0x0b, 0x00, 0x00, 0x05, 0x01, 0x01, 0x49, 0x13, 0x00, 0x00,
+        0x06, 0x21, 0x00, 0x37, 0x0b, 0x00, 0x00, 0x07, 0x34, 0x00, 0x03, 0x08, 0x49, 0x13, 0x02,
+        0x18, 0x00, 0x00, 0x08, 0x2e, 0x01, 0x03, 0x08, 0x11, 0x01, 0x12, 0x06, 0x40, 0x18, 0x00,
+        0x00, 0x09, 0x0f, 0x00, 0x49, 0x13, 0x0b, 0x0b, 0x00, 0x00, 0x00,
+    ];
+    const DEBUG_INFO: &[u8] = &[
+        0x97, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x01, 0x74, 0x2e, 0x63,
+        0x00, 0x02, 0x69, 0x6e, 0x74, 0x00, 0x04, 0x05, 0x03, 0x74, 0x61, 0x73, 0x6b, 0x00, 0x08,
+        0x04, 0x69, 0x64, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x04, 0x73, 0x74, 0x61, 0x74, 0x65,
+        0x00, 0x10, 0x00, 0x00, 0x00, 0x04, 0x00, 0x05, 0x17, 0x00, 0x00, 0x00, 0x06, 0x02, 0x00,
+        0x09, 0x17, 0x00, 0x00, 0x00, 0x08, 0x07, 0x74, 0x61, 0x73, 0x6b, 0x5f, 0x6c, 0x69, 0x73,
+        0x74, 0x00, 0x34, 0x00, 0x00, 0x00, 0x09, 0x03, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0x00,
+        0x00, 0x07, 0x63, 0x75, 0x72, 0x72, 0x65, 0x6e, 0x74, 0x00, 0x3c, 0x00, 0x00, 0x00, 0x09,
+        0x03, 0x10, 0x10, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x08, 0x6d, 0x61, 0x69, 0x6e, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x01, 0x9c, 0x07,
+        0x69, 0x00, 0x10, 0x00, 0x00, 0x00, 0x02, 0x91, 0x6c, 0x07, 0x6e, 0x00, 0x10, 0x00, 0x00,
+        0x00, 0x01, 0x5a, 0x00, 0x00,
+    ];
+
+    fn info() -> Result<DebugInfo, DwarfParseError> {
+        DebugInfo::parse(&DwarfSections {
+            debug_info: DEBUG_INFO,
+            debug_abbrev: DEBUG_ABBREV,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn globals() -> Result<(), DwarfParseError> {
+        let info = info()?;
+
+        let task_list = info.global("task_list").unwrap();
+        assert_eq!(task_list.location, Location::Address(Address(0x80001000)));
+        assert_eq!(info.type_name(task_list.ty), "struct task [2]");
+        assert_eq!(info.size_of(task_list.ty), Some(16));
+
+        let current = info.global("current").unwrap();
+        assert_eq!(info.type_name(current.ty), "struct task *");
+        assert_eq!(info.size_of(current.ty), Some(8));
+
+        assert!(info.global("i").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn types() -> Result<(), DwarfParseError> {
+        let info = info()?;
+
+        let Some(Type::Array { element, count }) =
+            info.global("task_list").map(|v| info.ty(v.ty.unwrap()))
+        else {
+            panic!("task_list is not an array");
+        };
+        assert_eq!(*count, Some(2));
+        let Type::Struct {
+            name,
+            size,
+            members,
+            union,
+        } = info.ty(element.unwrap())
+        else {
+            panic!("task is not a struct");
+        };
+        assert_eq!(name.as_deref(), Some("task"));
+        assert_eq!(*size, 8);
+        assert!(!union);
+        let members: Vec<_> = members
+            .iter()
+            .map(|m| (m.name.as_deref().unwrap(), info.type_name(m.ty), m.offset))
+            .collect();
+        assert_eq!(
+            members,
+            [
+                ("id", "int".to_string(), 0),
+                ("state", "int".to_string(), 4)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn locals() -> Result<(), DwarfParseError> {
+        let info = info()?;
+
+        let main = info.function(Address(0x80000010)).unwrap();
+        assert_eq!(main.name.as_deref(), Some("main"));
+        assert_eq!(main.high_pc, Address(0x80000020));
+        assert_eq!(main.frame_base, Location::Cfa);
+        assert!(info.function(Address(0x80000020)).is_none());
+
+        let (i, function) = info.variable("i", Address(0x80000004)).unwrap();
+        assert_eq!(i.location, Location::FrameOffset(-20));
+        assert_eq!(function, Some(main));
+        let (n, _) = info.variable("n", Address(0x80000004)).unwrap();
+        assert_eq!(n.location, Location::Register(10));
+        assert!(info.variable("i", Address(0x80000020)).is_none());
+
+        let (task_list, function) = info.variable("task_list", Address(0x80000004)).unwrap();
+        assert_eq!(task_list.name, "task_list");
+        assert_eq!(function, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_abbreviation() {
+        let mut bytes = DEBUG_INFO.to_vec();
+        // The abbreviation code of the base type
+        bytes[0x10] = 0x0a;
+
+        let result = DebugInfo::parse(&DwarfSections {
+            debug_info: &bytes,
+            debug_abbrev: DEBUG_ABBREV,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            result.err(),
+            Some(DwarfParseError::InvalidAbbreviation(0x0a))
+        );
+    }
+
+    #[test]
+    fn high_pc_overflow() -> Result<(), DwarfParseError> {
+        let mut bytes = DEBUG_INFO.to_vec();
+        // The low pc of main, less than its size before the end of the address space
+        bytes[0x78..0x80].copy_from_slice(&0xfffffffffffffff0u64.to_le_bytes());
+
+        let info = DebugInfo::parse(&DwarfSections {
+            debug_info: &bytes,
+            debug_abbrev: DEBUG_ABBREV,
+            ..Default::default()
+        })?;
+
+        let main = &info.functions[0];
+        assert_eq!(main.low_pc, Address(0xfffffffffffffff0));
+        assert_eq!(main.high_pc, main.low_pc);
+
+        Ok(())
+    }
+}
+
+mod frame {
+    use crate::{
+        dwarf::{
+            frame::{CallFrameInfo, CfaRule, RegisterRule},
+            DwarfSections,
+        },
+        error::DwarfParseError,
+        Address,
+    };
+
+    /// `.eh_frame` at 0x80000060 for `outer` at 0x80000020..0x80000038, which sets up a frame
+    /// pointer, `middle` at 0x80000038..0x80000050, which does not, and the frameless `leaf` at
+    /// 0x80000050..0x80000056
+    const EH_FRAME: &[u8] = &[
+        0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x7a, 0x52, 0x00, 0x01, 0x78, 0x01,
+        0x01, 0x1b, 0x0c, 0x02, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0xa4, 0xff,
+        0xff, 0xff, 0x18, 0x00, 0x00, 0x00, 0x00, 0x42, 0x0e, 0x10, 0x44, 0x81, 0x01, 0x88, 0x02,
+        0x42, 0x0c, 0x08, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00,
+        0x9c, 0xff, 0xff, 0xff, 0x18, 0x00, 0x00, 0x00, 0x00, 0x42, 0x0e, 0x20, 0x44, 0x81, 0x01,
+        0x88, 0x02, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x54, 0x00, 0x00, 0x00, 0x98, 0xff,
+        0xff, 0xff, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+
+    /// The same functions in a 64-bit `.debug_frame`
+    const DEBUG_FRAME: &[u8] = &[
+        0x14, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x04, 0x00, 0x08, 0x00, 0x01, 0x78, 0x01,
+        0x0c, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x20, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x42, 0x0e, 0x10, 0x44, 0x81, 0x01, 0x88, 0x02, 0x42, 0x0c, 0x08, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00,
+        0x80, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x0e,
+        0x20, 0x44, 0x81, 0x01, 0x88, 0x02, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50,
+        0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn check(info: &CallFrameInfo) {
+        let rules = info.rules(Address(0x80000020)).unwrap();
+        assert_eq!(rules.cfa, CfaRule::RegisterOffset(2, 0));
+        assert_eq!(rules.return_address, 1);
+        assert_eq!(rules.register(1), RegisterRule::SameValue);
+
+        let rules = info.rules(Address(0x80000028)).unwrap();
+        assert_eq!(rules.cfa, CfaRule::RegisterOffset(8, 0));
+        assert_eq!(rules.register(1), RegisterRule::Offset(-8));
+        assert_eq!(rules.register(8), RegisterRule::Offset(-16));
+
+        let rules = info.rules(Address(0x80000048)).unwrap();
+        assert_eq!(rules.cfa, CfaRule::RegisterOffset(2, 32));
+        assert_eq!(rules.register(1), RegisterRule::Offset(-8));
+
+        let rules = info.rules(Address(0x80000054)).unwrap();
+        assert_eq!(rules.cfa, CfaRule::RegisterOffset(2, 0));
+
+        assert!(info.rules(Address(0x80000056)).is_none());
+        assert!(info.rules(Address(0x80000000)).is_none());
+    }
+
+    #[test]
+    fn eh_frame() -> Result<(), DwarfParseError> {
+        check(&CallFrameInfo::parse(&DwarfSections {
+            eh_frame: EH_FRAME,
+            eh_frame_address: 0x80000060,
+            ..Default::default()
+        })?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn debug_frame() -> Result<(), DwarfParseError> {
+        check(&CallFrameInfo::parse(&DwarfSections {
+            debug_frame: DEBUG_FRAME,
+            ..Default::default()
+        })?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_cie_pointer() {
+        let mut bytes = EH_FRAME.to_vec();
+        bytes[0x18] = 0x40;
+
+        let result = CallFrameInfo::parse(&DwarfSections {
+            eh_frame: &bytes,
+            eh_frame_address: 0x80000060,
+            ..Default::default()
+        });
+
+        assert!(matches!(result, Err(DwarfParseError::InvalidCiePointer(_))));
+    }
+
+    #[test]
+    fn address_wraps() -> Result<(), DwarfParseError> {
+        // Pc relative pointers wrap around instead of overflowing
+        let info = CallFrameInfo::parse(&DwarfSections {
+            eh_frame: EH_FRAME,
+            eh_frame_address: u64::MAX,
+            ..Default::default()
+        })?;
+
+        assert!(info.rules(Address(0x80000020)).is_none());
+        assert!(info.rules(Address(0xffffffffffffffbf)).is_some());
+
+        Ok(())
+    }
+}
+
+mod writer {
+    use enumflags2::BitFlags;
+
+    use crate::{
+        data::{ObjectType, ProgramFlags, ProgramType, ASI},
+        writer::{ElfWriter, Note, Segment, NT_PRSTATUS},
+        Address, Elf,
+    };
+
+    #[test]
+    fn note() {
+        let note = Note {
+            name: "CORE".to_string(),
+            note_type: NT_PRSTATUS,
+            desc: vec![1, 2, 3, 4, 5],
+        };
+
+        assert_eq!(
+            note.to_bytes(),
+            [
+                0x05, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, b'C', b'O',
+                b'R', b'E', 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x00, 0x00, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn core() {
+        let note = Note {
+            name: "CORE".to_string(),
+            note_type: NT_PRSTATUS,
+            desc: vec![0xAA; 6],
+        };
+        let bytes = ElfWriter::new(ObjectType::Core, ASI::RISCV)
+            .set_flags(0x5)
+            .add_note(note.clone())
+            .add_segment(Segment {
+                addr: Address(0x80000000),
+                flags: ProgramFlags::Read | ProgramFlags::Write | ProgramFlags::Exec,
+                data: vec![0x13, 0x05, 0x15, 0x00],
+            })
+            .add_segment(Segment {
+                addr: Address(0x10000000),
+                flags: ProgramFlags::Read | ProgramFlags::Write,
+                data: vec![0x55; 3],
+            })
+            .to_bytes();
+
+        let elf = Elf::from_bytes(bytes).unwrap();
+        assert_eq!(elf.header.obj_type, ObjectType::Core);
+        assert_eq!(elf.header.arch, ASI::RISCV);
+        assert_eq!(elf.header.flags, 0x5);
+        assert!(elf.section_headers.is_empty());
+
+        let segment = |i: usize| {
+            let header = &elf.program_headers[i];
+            &elf.bytes[header.seg_offset as usize..][..header.seg_f_size.0 as usize]
+        };
+        let types: Vec<_> = elf.program_headers.iter().map(|p| p.program_type).collect();
+        assert_eq!(
+            types,
+            [ProgramType::Note, ProgramType::Load, ProgramType::Load]
+        );
+        assert_eq!(segment(0), note.to_bytes());
+
+        let ram = &elf.program_headers[1];
+        assert_eq!(ram.seg_v_addr, Address(0x80000000));
+        assert_eq!(ram.flags, BitFlags::all());
+        assert_eq!(segment(1), [0x13, 0x05, 0x15, 0x00]);
+        assert_eq!(ram.seg_offset % 8, 0);
+
+        assert_eq!(elf.program_headers[2].seg_m_size, Address(3));
+        assert_eq!(segment(2), [0x55; 3]);
+    }
+}
@@ -0,0 +1,182 @@
+//! Writing elf files made of segments and notes, like core files.
+
+use std::io::{self, Write};
+
+use enumflags2::BitFlags;
+
+use crate::{
+    data::{AbiType, Bitness, Endianess, ObjectType, ProgramFlags, ProgramType, ASI},
+    elf_header::{ElfHeader, HEADER_SIZE},
+    program_header::{ProgramHeader, PROGRAM_HEADER_SIZE},
+    Address,
+};
+
+/// The registers of a thread, `struct elf_prstatus` on linux
+pub const NT_PRSTATUS: u32 = 1;
+/// The floating point registers of a thread, `struct user_fpregs_struct` on linux
+pub const NT_PRFPREG: u32 = 2;
+
+/// Alignment of the notes and of the data of loadable segments in the file
+const NOTE_ALIGN: u64 = 4;
+const SEGMENT_ALIGN: u64 = 8;
+
+/// An entry of a `PT_NOTE` segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    /// The owner of the note, `CORE` for the notes of core files
+    pub name: String,
+    pub note_type: u32,
+    pub desc: Vec<u8>,
+}
+
+impl Note {
+    /// The note as it is stored in a segment, the name and desc are padded to 4 bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend((self.name.len() as u32 + 1).to_le_bytes());
+        bytes.extend((self.desc.len() as u32).to_le_bytes());
+        bytes.extend(self.note_type.to_le_bytes());
+        bytes.extend(self.name.as_bytes());
+        bytes.push(0);
+        pad(&mut bytes, NOTE_ALIGN);
+        bytes.extend(&self.desc);
+        pad(&mut bytes, NOTE_ALIGN);
+        bytes
+    }
+}
+
+/// A `PT_LOAD` segment, its contents are loaded at `addr`.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub addr: Address,
+    pub flags: BitFlags<ProgramFlags>,
+    pub data: Vec<u8>,
+}
+
+/// Builds a 64-bit little endian elf without sections, all notes are put in one `PT_NOTE`
+/// segment before the loadable segments.
+#[derive(Debug, Clone)]
+pub struct ElfWriter {
+    obj_type: ObjectType,
+    arch: ASI,
+    flags: u32,
+    entry: Address,
+    notes: Vec<Note>,
+    segments: Vec<Segment>,
+}
+
+impl ElfWriter {
+    pub fn new(obj_type: ObjectType, arch: ASI) -> Self {
+        Self {
+            obj_type,
+            arch,
+            flags: 0,
+            entry: Address(0),
+            notes: vec![],
+            segments: vec![],
+        }
+    }
+
+    /// Set the architecture specific flags of the header
+    pub fn set_flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn set_entry(mut self, entry: Address) -> Self {
+        self.entry = entry;
+        self
+    }
+
+    pub fn add_note(mut self, note: Note) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn add_segment(mut self, segment: Segment) -> Self {
+        self.segments.push(segment);
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let notes: Vec<u8> = self.notes.iter().flat_map(Note::to_bytes).collect();
+        let has_notes = !self.notes.is_empty();
+        let p_header_ecount = self.segments.len() + has_notes as usize;
+
+        let header = ElfHeader {
+            bitness: Bitness::B64,
+            endianess: Endianess::Little,
+            abi_type: AbiType::SystemV,
+            abi_ver: 0,
+            obj_type: self.obj_type,
+            arch: self.arch,
+            entry: self.entry,
+            p_header: HEADER_SIZE as u64,
+            s_header: 0,
+            flags: self.flags,
+            p_header_size: PROGRAM_HEADER_SIZE,
+            p_header_ecount: p_header_ecount as u16,
+            s_header_size: 0,
+            s_header_ecount: 0,
+            s_header_name_entry: 0,
+        };
+
+        // The data of the segments follows the program headers
+        let mut offset = HEADER_SIZE as u64 + p_header_ecount as u64 * PROGRAM_HEADER_SIZE as u64;
+        let mut p_headers = vec![];
+        if has_notes {
+            offset = align(offset, NOTE_ALIGN);
+            p_headers.push(ProgramHeader {
+                program_type: ProgramType::Note,
+                flags: BitFlags::empty(),
+                seg_offset: offset,
+                seg_v_addr: Address(0),
+                seg_p_addr: Address(0),
+                seg_f_size: Address(notes.len() as u64),
+                seg_m_size: Address(0),
+                align: NOTE_ALIGN,
+            });
+            offset += notes.len() as u64;
+        }
+        for segment in &self.segments {
+            offset = align(offset, SEGMENT_ALIGN);
+            p_headers.push(ProgramHeader {
+                program_type: ProgramType::Load,
+                flags: segment.flags,
+                seg_offset: offset,
+                seg_v_addr: segment.addr,
+                seg_p_addr: segment.addr,
+                seg_f_size: Address(segment.data.len() as u64),
+                seg_m_size: Address(segment.data.len() as u64),
+                align: 1,
+            });
+            offset += segment.data.len() as u64;
+        }
+
+        let mut bytes = header.to_bytes();
+        for p_header in &p_headers {
+            bytes.extend(p_header.to_bytes());
+        }
+        if has_notes {
+            pad(&mut bytes, NOTE_ALIGN);
+            bytes.extend(&notes);
+        }
+        for segment in &self.segments {
+            pad(&mut bytes, SEGMENT_ALIGN);
+            bytes.extend(&segment.data);
+        }
+        bytes
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+}
+
+fn align(offset: u64, alignment: u64) -> u64 {
+    offset.next_multiple_of(alignment)
+}
+
+fn pad(bytes: &mut Vec<u8>, alignment: u64) {
+    bytes.resize(align(bytes.len() as u64, alignment) as usize, 0);
+}
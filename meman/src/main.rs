@@ -5,6 +5,8 @@ use std::{
     io::{stdin, stdout, BufRead, BufReader, Write},
 };
 
+use riscv_vm::decode::disassemble;
+
 use crate::memory::Memory;
 
 fn main() {
@@ -119,6 +121,50 @@ fn main() {
 
                     print_fragement(fragment);
                 }
+                "disas" => {
+                    let Some(bottom) = args.get(1) else {
+                        println!("Missing address");
+                        continue;
+                    };
+
+                    let Some(bottom) = bottom.strip_prefix("0x") else {
+                        println!("Invalid format for address, use 0xXXXX (hex)");
+                        continue;
+                    };
+
+                    let Ok(bottom) = usize::from_str_radix(bottom, 16) else {
+                        println!("Invalid format for address, use 0xXXXX (hex)");
+                        continue;
+                    };
+
+                    let count = match args.get(2) {
+                        Some(count) => {
+                            let Ok(count) = count.parse::<usize>() else {
+                                println!("Invalid instruction count");
+                                continue;
+                            };
+                            count
+                        }
+                        None => 1,
+                    };
+
+                    if bottom < (mem.range.start as usize) || bottom >= (mem.range.end as usize) {
+                        println!("Address out of memory bounds");
+                        continue;
+                    }
+
+                    let start = bottom - mem.range.start as usize;
+                    // A huge count disassembles up to the end of memory
+                    let end = start
+                        .saturating_add(count.saturating_mul(4))
+                        .min(mem.mem.len());
+                    for inst in disassemble(&mem.mem[start..end], (bottom as u64).into())
+                        .iter()
+                        .take(count)
+                    {
+                        println!("{:x}:\t{}", u64::from(inst.pc.unwrap()), inst);
+                    }
+                }
                 "exit" => break,
                 "q" => break,
                 _ => println!("Invalid Command"),
@@ -3,7 +3,6 @@ use crate::hart::registers::IntRegister;
 use super::Instruction;
 
 pub fn decode_compact(inst: u16) -> Instruction {
-    println!("{:#018b}", inst);
     let opcode = inst & 0b11;
     let funct3 = (inst >> 13) & 0b111;
 
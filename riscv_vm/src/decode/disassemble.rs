@@ -0,0 +1,813 @@
+use std::fmt::{self, Display};
+
+use crate::{
+    hart::{registers::IntRegister, CsrAddress},
+    memory::address::Address,
+};
+
+#[cfg(feature = "float")]
+use super::instruction::RoundingMode;
+use super::{decode, Instruction};
+
+/// An instruction rendered in GNU objdump syntax, with ABI register names and
+/// pseudo-instructions where objdump would use them. Compressed instructions are rendered as
+/// their `c.` form, branch and jump targets are resolved against `pc` if it is known.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Disassembly {
+    pub inst: Instruction,
+    pub compressed: bool,
+    /// The encoding the instruction was decoded from, if it is known. Some compressed
+    /// instructions expand to the same instruction and can only be told apart by it.
+    pub raw: Option<u32>,
+    pub pc: Option<Address>,
+}
+
+impl Instruction {
+    /// Disassemble this instruction as fetched from `pc`.
+    pub fn disassemble(&self, pc: Address, compressed: bool) -> Disassembly {
+        Disassembly {
+            inst: *self,
+            compressed,
+            raw: None,
+            pc: Some(pc),
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Disassembly {
+            inst: *self,
+            compressed: false,
+            raw: None,
+            pc: None,
+        }
+        .fmt(f)
+    }
+}
+
+/// Decode and disassemble the instructions in `bytes`, which start at `pc`. A trailing partial
+/// instruction is ignored.
+pub fn disassemble(bytes: &[u8], pc: Address) -> Vec<Disassembly> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while offset + 2 <= bytes.len() {
+        let mut raw = [0u8; 4];
+        let len = (bytes.len() - offset).min(4);
+        raw[..len].copy_from_slice(&bytes[offset..(offset + len)]);
+        let raw = u32::from_le_bytes(raw);
+
+        if raw & 0b11 == 0b11 && len < 4 {
+            break;
+        }
+
+        let dis = Disassembly::decode(raw, pc + offset as u64);
+        offset += dis.size() as usize;
+        out.push(dis);
+    }
+    out
+}
+
+impl Disassembly {
+    /// Decode and disassemble the instruction encoded in `raw` as fetched from `pc`, only the
+    /// low half is used if it is a compressed instruction.
+    pub fn decode(raw: u32, pc: Address) -> Self {
+        let (inst, compressed) = decode(raw);
+        Self {
+            inst,
+            compressed,
+            raw: Some(if compressed { raw & 0xffff } else { raw }),
+            pc: Some(pc),
+        }
+    }
+
+    /// The size of the encoded instruction in bytes
+    pub fn size(&self) -> u64 {
+        if self.compressed {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn target(&self, imm: i32) -> Target {
+        match self.pc {
+            Some(pc) => Target::Absolute(u64::from(pc).wrapping_add(imm as i64 as u64)),
+            None => Target::Relative(imm),
+        }
+    }
+
+    /// Whether an `addi sp,sp,imm` was encoded as c.addi16sp rather than c.addi. Without the
+    /// encoding this guesses like the assembler, which only uses c.addi16sp if the immediate does
+    /// not fit c.addi.
+    fn is_addi16sp(&self, imm: i32) -> bool {
+        match self.raw {
+            // c.addi16sp is quadrant 1 with funct3 0b011, c.addi has funct3 0b000
+            Some(raw) => raw & 0xe003 == 0x6001,
+            None => !(-32..32).contains(&imm),
+        }
+    }
+
+    /// The `c.` form of compressed instructions, `None` if the expanded instruction has no
+    /// compressed form we know of.
+    fn fmt_compressed(&self, f: &mut fmt::Formatter<'_>) -> Option<fmt::Result> {
+        use IntRegister::{X0, X1, X2};
+
+        Some(match self.inst {
+            Instruction::ADDI {
+                rd: X0,
+                rs1: X0,
+                imm: 0,
+            } => write!(f, "c.nop"),
+            Instruction::ADDI {
+                rd: X2,
+                rs1: X2,
+                imm,
+            } if self.is_addi16sp(imm) => write!(f, "c.addi16sp\tsp,{}", imm),
+            Instruction::ADDI { rd, rs1, imm } if rd == rs1 => write!(f, "c.addi\t{},{}", rd, imm),
+            Instruction::ADDI { rd, rs1: X2, imm } => write!(f, "c.addi4spn\t{},sp,{}", rd, imm),
+            Instruction::ADDI { rd, rs1: X0, imm } => write!(f, "c.li\t{},{}", rd, imm),
+            // The decoder expands c.li to addiw
+            Instruction::ADDIW { rd, rs1: X0, imm } => write!(f, "c.li\t{},{}", rd, imm),
+            Instruction::ADDIW { rd, rs1, imm } if rd == rs1 => {
+                write!(f, "c.addiw\t{},{}", rd, imm)
+            }
+            Instruction::LUI { rd, imm } => write!(f, "c.lui\t{},{:#x}", rd, upper(imm)),
+            Instruction::SLLI { rd, shamt, .. } => write!(f, "c.slli\t{},{:#x}", rd, shamt),
+            Instruction::SRLI { rd, shamt, .. } => write!(f, "c.srli\t{},{:#x}", rd, shamt),
+            Instruction::SRAI { rd, shamt, .. } => write!(f, "c.srai\t{},{:#x}", rd, shamt),
+            Instruction::ANDI { rd, imm, .. } => write!(f, "c.andi\t{},{}", rd, imm),
+            Instruction::ADD { rd, rs1: X0, rs2 } => write!(f, "c.mv\t{},{}", rd, rs2),
+            Instruction::ADD { rd, rs2, .. } => write!(f, "c.add\t{},{}", rd, rs2),
+            Instruction::SUB { rd, rs2, .. } => write!(f, "c.sub\t{},{}", rd, rs2),
+            Instruction::XOR { rd, rs2, .. } => write!(f, "c.xor\t{},{}", rd, rs2),
+            Instruction::OR { rd, rs2, .. } => write!(f, "c.or\t{},{}", rd, rs2),
+            Instruction::AND { rd, rs2, .. } => write!(f, "c.and\t{},{}", rd, rs2),
+            Instruction::ADDW { rd, rs2, .. } => write!(f, "c.addw\t{},{}", rd, rs2),
+            Instruction::SUBW { rd, rs2, .. } => write!(f, "c.subw\t{},{}", rd, rs2),
+            Instruction::JAL { rd: X0, imm } => write!(f, "c.j\t{}", self.target(imm)),
+            Instruction::JALR { rd: X0, rs1, .. } => write!(f, "c.jr\t{}", rs1),
+            Instruction::JALR { rd: X1, rs1, .. } => write!(f, "c.jalr\t{}", rs1),
+            Instruction::BEQ { rs1, imm, .. } => {
+                write!(f, "c.beqz\t{},{}", rs1, self.target(imm))
+            }
+            Instruction::BNE { rs1, imm, .. } => {
+                write!(f, "c.bnez\t{},{}", rs1, self.target(imm))
+            }
+            Instruction::LW { rd, rs1: X2, imm } => write!(f, "c.lwsp\t{},{}(sp)", rd, imm),
+            Instruction::LD { rd, rs1: X2, imm } => write!(f, "c.ldsp\t{},{}(sp)", rd, imm),
+            Instruction::SW { rs1: X2, rs2, imm } => write!(f, "c.swsp\t{},{}(sp)", rs2, imm),
+            Instruction::SD { rs1: X2, rs2, imm } => write!(f, "c.sdsp\t{},{}(sp)", rs2, imm),
+            Instruction::LW { rd, rs1, imm } => write!(f, "c.lw\t{},{}({})", rd, imm, rs1),
+            Instruction::LD { rd, rs1, imm } => write!(f, "c.ld\t{},{}({})", rd, imm, rs1),
+            Instruction::SW { rs1, rs2, imm } => write!(f, "c.sw\t{},{}({})", rs2, imm, rs1),
+            Instruction::SD { rs1, rs2, imm } => write!(f, "c.sd\t{},{}({})", rs2, imm, rs1),
+            #[cfg(feature = "float")]
+            Instruction::FLD { rd, rs1: X2, imm } => write!(f, "c.fldsp\t{},{}(sp)", rd, imm),
+            #[cfg(feature = "float")]
+            Instruction::FSD { rs1: X2, rs2, imm } => write!(f, "c.fsdsp\t{},{}(sp)", rs2, imm),
+            #[cfg(feature = "float")]
+            Instruction::FLD { rd, rs1, imm } => write!(f, "c.fld\t{},{}({})", rd, imm, rs1),
+            #[cfg(feature = "float")]
+            Instruction::FSD { rs1, rs2, imm } => write!(f, "c.fsd\t{},{}({})", rs2, imm, rs1),
+            Instruction::EBREAK => write!(f, "c.ebreak"),
+            Instruction::Undifined(raw) => write!(f, ".2byte\t{:#x}", raw as u16),
+            _ => return None,
+        })
+    }
+
+    #[cfg(feature = "float")]
+    fn fmt_float(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.inst {
+            Instruction::FLW { rd, rs1, imm } => write!(f, "flw\t{},{}({})", rd, imm, rs1),
+            Instruction::FSW { rs1, rs2, imm } => write!(f, "fsw\t{},{}({})", rs2, imm, rs1),
+            Instruction::FLD { rd, rs1, imm } => write!(f, "fld\t{},{}({})", rd, imm, rs1),
+            Instruction::FSD { rs1, rs2, imm } => write!(f, "fsd\t{},{}({})", rs2, imm, rs1),
+            Instruction::FMADD_S {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fmadd.s\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FMSUB_S {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fmsub.s\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FNMADD_S {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fnmadd.s\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FNMSUB_S {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fnmsub.s\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FMADD_D {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fmadd.d\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FMSUB_D {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fmsub.d\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FNMADD_D {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fnmadd.d\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FNMSUB_D {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => write!(f, "fnmsub.d\t{},{},{},{}{}", rd, rs1, rs2, rs3, Rm(rm)),
+            Instruction::FADD_S { rd, rs1, rs2, rm } => {
+                write!(f, "fadd.s\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FSUB_S { rd, rs1, rs2, rm } => {
+                write!(f, "fsub.s\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FMUL_S { rd, rs1, rs2, rm } => {
+                write!(f, "fmul.s\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FDIV_S { rd, rs1, rs2, rm } => {
+                write!(f, "fdiv.s\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FADD_D { rd, rs1, rs2, rm } => {
+                write!(f, "fadd.d\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FSUB_D { rd, rs1, rs2, rm } => {
+                write!(f, "fsub.d\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FMUL_D { rd, rs1, rs2, rm } => {
+                write!(f, "fmul.d\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FDIV_D { rd, rs1, rs2, rm } => {
+                write!(f, "fdiv.d\t{},{},{}{}", rd, rs1, rs2, Rm(rm))
+            }
+            Instruction::FSQRT_S { rd, rs1, rm } => write!(f, "fsqrt.s\t{},{}{}", rd, rs1, Rm(rm)),
+            Instruction::FSQRT_D { rd, rs1, rm } => write!(f, "fsqrt.d\t{},{}{}", rd, rs1, Rm(rm)),
+            Instruction::FSGNJ_S { rd, rs1, rs2 } if rs1 == rs2 => {
+                write!(f, "fmv.s\t{},{}", rd, rs1)
+            }
+            Instruction::FSGNJN_S { rd, rs1, rs2 } if rs1 == rs2 => {
+                write!(f, "fneg.s\t{},{}", rd, rs1)
+            }
+            Instruction::FSGNJX_S { rd, rs1, rs2 } if rs1 == rs2 => {
+                write!(f, "fabs.s\t{},{}", rd, rs1)
+            }
+            Instruction::FSGNJ_D { rd, rs1, rs2 } if rs1 == rs2 => {
+                write!(f, "fmv.d\t{},{}", rd, rs1)
+            }
+            Instruction::FSGNJN_D { rd, rs1, rs2 } if rs1 == rs2 => {
+                write!(f, "fneg.d\t{},{}", rd, rs1)
+            }
+            Instruction::FSGNJX_D { rd, rs1, rs2 } if rs1 == rs2 => {
+                write!(f, "fabs.d\t{},{}", rd, rs1)
+            }
+            Instruction::FSGNJ_S { rd, rs1, rs2 } => write!(f, "fsgnj.s\t{},{},{}", rd, rs1, rs2),
+            Instruction::FSGNJN_S { rd, rs1, rs2 } => {
+                write!(f, "fsgnjn.s\t{},{},{}", rd, rs1, rs2)
+            }
+            Instruction::FSGNJX_S { rd, rs1, rs2 } => {
+                write!(f, "fsgnjx.s\t{},{},{}", rd, rs1, rs2)
+            }
+            Instruction::FSGNJ_D { rd, rs1, rs2 } => write!(f, "fsgnj.d\t{},{},{}", rd, rs1, rs2),
+            Instruction::FSGNJN_D { rd, rs1, rs2 } => {
+                write!(f, "fsgnjn.d\t{},{},{}", rd, rs1, rs2)
+            }
+            Instruction::FSGNJX_D { rd, rs1, rs2 } => {
+                write!(f, "fsgnjx.d\t{},{},{}", rd, rs1, rs2)
+            }
+            Instruction::FMIN_S { rd, rs1, rs2 } => write!(f, "fmin.s\t{},{},{}", rd, rs1, rs2),
+            Instruction::FMAX_S { rd, rs1, rs2 } => write!(f, "fmax.s\t{},{},{}", rd, rs1, rs2),
+            Instruction::FMIN_D { rd, rs1, rs2 } => write!(f, "fmin.d\t{},{},{}", rd, rs1, rs2),
+            Instruction::FMAX_D { rd, rs1, rs2 } => write!(f, "fmax.d\t{},{},{}", rd, rs1, rs2),
+            Instruction::FEQ_S { rd, rs1, rs2 } => write!(f, "feq.s\t{},{},{}", rd, rs1, rs2),
+            Instruction::FLT_S { rd, rs1, rs2 } => write!(f, "flt.s\t{},{},{}", rd, rs1, rs2),
+            Instruction::FLE_S { rd, rs1, rs2 } => write!(f, "fle.s\t{},{},{}", rd, rs1, rs2),
+            Instruction::FEQ_D { rd, rs1, rs2 } => write!(f, "feq.d\t{},{},{}", rd, rs1, rs2),
+            Instruction::FLT_D { rd, rs1, rs2 } => write!(f, "flt.d\t{},{},{}", rd, rs1, rs2),
+            Instruction::FLE_D { rd, rs1, rs2 } => write!(f, "fle.d\t{},{},{}", rd, rs1, rs2),
+            Instruction::FCLASS_S { rd, rs1 } => write!(f, "fclass.s\t{},{}", rd, rs1),
+            Instruction::FCLASS_D { rd, rs1 } => write!(f, "fclass.d\t{},{}", rd, rs1),
+            Instruction::FMV_X_W { rd, rs1 } => write!(f, "fmv.x.w\t{},{}", rd, rs1),
+            Instruction::FMV_W_X { rd, rs1 } => write!(f, "fmv.w.x\t{},{}", rd, rs1),
+            Instruction::FMV_X_D { rd, rs1 } => write!(f, "fmv.x.d\t{},{}", rd, rs1),
+            Instruction::FMV_D_X { rd, rs1 } => write!(f, "fmv.d.x\t{},{}", rd, rs1),
+            Instruction::FCVT_W_S { rd, rs1, rm } => {
+                write!(f, "fcvt.w.s\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_WU_S { rd, rs1, rm } => {
+                write!(f, "fcvt.wu.s\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_L_S { rd, rs1, rm } => {
+                write!(f, "fcvt.l.s\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_LU_S { rd, rs1, rm } => {
+                write!(f, "fcvt.lu.s\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_S_W { rd, rs1, rm } => {
+                write!(f, "fcvt.s.w\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_S_WU { rd, rs1, rm } => {
+                write!(f, "fcvt.s.wu\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_S_L { rd, rs1, rm } => {
+                write!(f, "fcvt.s.l\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_S_LU { rd, rs1, rm } => {
+                write!(f, "fcvt.s.lu\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_W_D { rd, rs1, rm } => {
+                write!(f, "fcvt.w.d\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_WU_D { rd, rs1, rm } => {
+                write!(f, "fcvt.wu.d\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_L_D { rd, rs1, rm } => {
+                write!(f, "fcvt.l.d\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_LU_D { rd, rs1, rm } => {
+                write!(f, "fcvt.lu.d\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_D_W { rd, rs1, rm } => {
+                write!(f, "fcvt.d.w\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_D_WU { rd, rs1, rm } => {
+                write!(f, "fcvt.d.wu\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_D_L { rd, rs1, rm } => {
+                write!(f, "fcvt.d.l\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_D_LU { rd, rs1, rm } => {
+                write!(f, "fcvt.d.lu\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_S_D { rd, rs1, rm } => {
+                write!(f, "fcvt.s.d\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            Instruction::FCVT_D_S { rd, rs1, rm } => {
+                write!(f, "fcvt.d.s\t{},{}{}", rd, rs1, Rm(rm))
+            }
+            _ => unreachable!("not a float instruction"),
+        }
+    }
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use IntRegister::{X0, X1};
+
+        if self.compressed {
+            if let Some(result) = self.fmt_compressed(f) {
+                return result;
+            }
+        }
+
+        match self.inst {
+            Instruction::LUI { rd, imm } => write!(f, "lui\t{},{:#x}", rd, upper(imm)),
+            Instruction::AUIPC { rd, imm } => write!(f, "auipc\t{},{:#x}", rd, upper(imm)),
+            Instruction::JAL { rd: X0, imm } => write!(f, "j\t{}", self.target(imm)),
+            Instruction::JAL { rd: X1, imm } => write!(f, "jal\t{}", self.target(imm)),
+            Instruction::JAL { rd, imm } => write!(f, "jal\t{},{}", rd, self.target(imm)),
+            Instruction::JALR {
+                rd: X0,
+                rs1: X1,
+                imm: 0,
+            } => write!(f, "ret"),
+            Instruction::JALR {
+                rd: X0,
+                rs1,
+                imm: 0,
+            } => write!(f, "jr\t{}", rs1),
+            Instruction::JALR {
+                rd: X1,
+                rs1,
+                imm: 0,
+            } => write!(f, "jalr\t{}", rs1),
+            Instruction::JALR { rd, rs1, imm } => write!(f, "jalr\t{},{}({})", rd, imm, rs1),
+            Instruction::BEQ { rs1, rs2: X0, imm } => {
+                write!(f, "beqz\t{},{}", rs1, self.target(imm))
+            }
+            Instruction::BNE { rs1, rs2: X0, imm } => {
+                write!(f, "bnez\t{},{}", rs1, self.target(imm))
+            }
+            Instruction::BLT { rs1, rs2: X0, imm } => {
+                write!(f, "bltz\t{},{}", rs1, self.target(imm))
+            }
+            Instruction::BLT { rs1: X0, rs2, imm } => {
+                write!(f, "bgtz\t{},{}", rs2, self.target(imm))
+            }
+            Instruction::BGE { rs1, rs2: X0, imm } => {
+                write!(f, "bgez\t{},{}", rs1, self.target(imm))
+            }
+            Instruction::BGE { rs1: X0, rs2, imm } => {
+                write!(f, "blez\t{},{}", rs2, self.target(imm))
+            }
+            Instruction::BEQ { rs1, rs2, imm } => {
+                write!(f, "beq\t{},{},{}", rs1, rs2, self.target(imm))
+            }
+            Instruction::BNE { rs1, rs2, imm } => {
+                write!(f, "bne\t{},{},{}", rs1, rs2, self.target(imm))
+            }
+            Instruction::BLT { rs1, rs2, imm } => {
+                write!(f, "blt\t{},{},{}", rs1, rs2, self.target(imm))
+            }
+            Instruction::BGE { rs1, rs2, imm } => {
+                write!(f, "bge\t{},{},{}", rs1, rs2, self.target(imm))
+            }
+            Instruction::BLTU { rs1, rs2, imm } => {
+                write!(f, "bltu\t{},{},{}", rs1, rs2, self.target(imm as i32))
+            }
+            Instruction::BGEU { rs1, rs2, imm } => {
+                write!(f, "bgeu\t{},{},{}", rs1, rs2, self.target(imm as i32))
+            }
+            Instruction::LB { rd, rs1, imm } => write!(f, "lb\t{},{}({})", rd, imm, rs1),
+            Instruction::LH { rd, rs1, imm } => write!(f, "lh\t{},{}({})", rd, imm, rs1),
+            Instruction::LW { rd, rs1, imm } => write!(f, "lw\t{},{}({})", rd, imm, rs1),
+            Instruction::LD { rd, rs1, imm } => write!(f, "ld\t{},{}({})", rd, imm, rs1),
+            Instruction::LBU { rd, rs1, imm } => write!(f, "lbu\t{},{}({})", rd, imm, rs1),
+            Instruction::LHU { rd, rs1, imm } => write!(f, "lhu\t{},{}({})", rd, imm, rs1),
+            Instruction::LWU { rd, rs1, imm } => write!(f, "lwu\t{},{}({})", rd, imm, rs1),
+            Instruction::SB { rs1, rs2, imm } => write!(f, "sb\t{},{}({})", rs2, imm, rs1),
+            Instruction::SH { rs1, rs2, imm } => write!(f, "sh\t{},{}({})", rs2, imm, rs1),
+            Instruction::SW { rs1, rs2, imm } => write!(f, "sw\t{},{}({})", rs2, imm, rs1),
+            Instruction::SD { rs1, rs2, imm } => write!(f, "sd\t{},{}({})", rs2, imm, rs1),
+            Instruction::ADDI {
+                rd: X0,
+                rs1: X0,
+                imm: 0,
+            } => write!(f, "nop"),
+            Instruction::ADDI { rd, rs1: X0, imm } => write!(f, "li\t{},{}", rd, imm),
+            Instruction::ADDI { rd, rs1, imm: 0 } => write!(f, "mv\t{},{}", rd, rs1),
+            Instruction::ADDI { rd, rs1, imm } => write!(f, "addi\t{},{},{}", rd, rs1, imm),
+            Instruction::SLTI { rd, rs1, imm } => write!(f, "slti\t{},{},{}", rd, rs1, imm),
+            Instruction::SLTIU { rd, rs1, imm: 1 } => write!(f, "seqz\t{},{}", rd, rs1),
+            Instruction::SLTIU { rd, rs1, imm } => {
+                write!(f, "sltiu\t{},{},{}", rd, rs1, imm as i32)
+            }
+            Instruction::XORI { rd, rs1, imm: -1 } => write!(f, "not\t{},{}", rd, rs1),
+            Instruction::XORI { rd, rs1, imm } => write!(f, "xori\t{},{},{}", rd, rs1, imm),
+            Instruction::ORI { rd, rs1, imm } => write!(f, "ori\t{},{},{}", rd, rs1, imm),
+            Instruction::ANDI { rd, rs1, imm } => write!(f, "andi\t{},{},{}", rd, rs1, imm),
+            Instruction::SLLI { rd, rs1, shamt } => {
+                write!(f, "slli\t{},{},{:#x}", rd, rs1, shamt)
+            }
+            Instruction::SRLI { rd, rs1, shamt } => {
+                write!(f, "srli\t{},{},{:#x}", rd, rs1, shamt)
+            }
+            Instruction::SRAI { rd, rs1, shamt } => {
+                write!(f, "srai\t{},{},{:#x}", rd, rs1, shamt)
+            }
+            Instruction::ADD { rd, rs1, rs2 } => write!(f, "add\t{},{},{}", rd, rs1, rs2),
+            Instruction::SUB { rd, rs1: X0, rs2 } => write!(f, "neg\t{},{}", rd, rs2),
+            Instruction::SUB { rd, rs1, rs2 } => write!(f, "sub\t{},{},{}", rd, rs1, rs2),
+            Instruction::SLL { rd, rs1, rs2 } => write!(f, "sll\t{},{},{}", rd, rs1, rs2),
+            Instruction::SLT { rd, rs1, rs2: X0 } => write!(f, "sltz\t{},{}", rd, rs1),
+            Instruction::SLT { rd, rs1: X0, rs2 } => write!(f, "sgtz\t{},{}", rd, rs2),
+            Instruction::SLT { rd, rs1, rs2 } => write!(f, "slt\t{},{},{}", rd, rs1, rs2),
+            Instruction::SLTU { rd, rs1: X0, rs2 } => write!(f, "snez\t{},{}", rd, rs2),
+            Instruction::SLTU { rd, rs1, rs2 } => write!(f, "sltu\t{},{},{}", rd, rs1, rs2),
+            Instruction::XOR { rd, rs1, rs2 } => write!(f, "xor\t{},{},{}", rd, rs1, rs2),
+            Instruction::SRL { rd, rs1, rs2 } => write!(f, "srl\t{},{},{}", rd, rs1, rs2),
+            Instruction::SRA { rd, rs1, rs2 } => write!(f, "sra\t{},{},{}", rd, rs1, rs2),
+            Instruction::OR { rd, rs1, rs2 } => write!(f, "or\t{},{},{}", rd, rs1, rs2),
+            Instruction::AND { rd, rs1, rs2 } => write!(f, "and\t{},{},{}", rd, rs1, rs2),
+            Instruction::ADDIW { rd, rs1, imm: 0 } => write!(f, "sext.w\t{},{}", rd, rs1),
+            Instruction::ADDIW { rd, rs1, imm } => write!(f, "addiw\t{},{},{}", rd, rs1, imm),
+            Instruction::SLTIW { rd, rs1, imm } => write!(f, "sltiw\t{},{},{}", rd, rs1, imm),
+            Instruction::SLLIW { rd, rs1, shamt } => {
+                write!(f, "slliw\t{},{},{:#x}", rd, rs1, shamt)
+            }
+            Instruction::SRLIW { rd, rs1, shamt } => {
+                write!(f, "srliw\t{},{},{:#x}", rd, rs1, shamt)
+            }
+            Instruction::SRAIW { rd, rs1, shamt } => {
+                write!(f, "sraiw\t{},{},{:#x}", rd, rs1, shamt)
+            }
+            Instruction::ADDW { rd, rs1, rs2 } => write!(f, "addw\t{},{},{}", rd, rs1, rs2),
+            Instruction::SUBW { rd, rs1: X0, rs2 } => write!(f, "negw\t{},{}", rd, rs2),
+            Instruction::SUBW { rd, rs1, rs2 } => write!(f, "subw\t{},{},{}", rd, rs1, rs2),
+            Instruction::SLLW { rd, rs1, rs2 } => write!(f, "sllw\t{},{},{}", rd, rs1, rs2),
+            Instruction::SLTW { rd, rs1, rs2 } => write!(f, "sltw\t{},{},{}", rd, rs1, rs2),
+            Instruction::SRLW { rd, rs1, rs2 } => write!(f, "srlw\t{},{},{}", rd, rs1, rs2),
+            Instruction::SRAW { rd, rs1, rs2 } => write!(f, "sraw\t{},{},{}", rd, rs1, rs2),
+            Instruction::FENCE { imm, .. } => {
+                let (fm, pred, succ) = ((imm >> 8) & 0xF, (imm >> 4) & 0xF, imm & 0xF);
+                match (fm, pred, succ) {
+                    (0b0000, 0b1111, 0b1111) => write!(f, "fence"),
+                    (0b1000, 0b0011, 0b0011) => write!(f, "fence.tso"),
+                    _ => write!(f, "fence\t{},{}", FenceSet(pred), FenceSet(succ)),
+                }
+            }
+            Instruction::ECALL => write!(f, "ecall"),
+            Instruction::EBREAK => write!(f, "ebreak"),
+            Instruction::MUL { rd, rs1, rs2 } => write!(f, "mul\t{},{},{}", rd, rs1, rs2),
+            Instruction::MULH { rd, rs1, rs2 } => write!(f, "mulh\t{},{},{}", rd, rs1, rs2),
+            Instruction::MULHSU { rd, rs1, rs2 } => {
+                write!(f, "mulhsu\t{},{},{}", rd, rs1, rs2)
+            }
+            Instruction::MULHU { rd, rs1, rs2 } => write!(f, "mulhu\t{},{},{}", rd, rs1, rs2),
+            Instruction::DIV { rd, rs1, rs2 } => write!(f, "div\t{},{},{}", rd, rs1, rs2),
+            Instruction::DIVU { rd, rs1, rs2 } => write!(f, "divu\t{},{},{}", rd, rs1, rs2),
+            Instruction::REM { rd, rs1, rs2 } => write!(f, "rem\t{},{},{}", rd, rs1, rs2),
+            Instruction::REMU { rd, rs1, rs2 } => write!(f, "remu\t{},{},{}", rd, rs1, rs2),
+            Instruction::MULW { rd, rs1, rs2 } => write!(f, "mulw\t{},{},{}", rd, rs1, rs2),
+            Instruction::DIVW { rd, rs1, rs2 } => write!(f, "divw\t{},{},{}", rd, rs1, rs2),
+            Instruction::DIVUW { rd, rs1, rs2 } => write!(f, "divuw\t{},{},{}", rd, rs1, rs2),
+            Instruction::REMW { rd, rs1, rs2 } => write!(f, "remw\t{},{},{}", rd, rs1, rs2),
+            Instruction::REMUW { rd, rs1, rs2 } => write!(f, "remuw\t{},{},{}", rd, rs1, rs2),
+            Instruction::CSRRS { rd, rs1: X0, csr } => match csr_alias(csr, "rd", "fr") {
+                Some(alias) => write!(f, "{}\t{}", alias, rd),
+                None => write!(f, "csrr\t{},{}", rd, csr),
+            },
+            Instruction::CSRRW { rd: X0, rs1, csr } => match csr_alias(csr, "", "fs") {
+                Some(alias) => write!(f, "{}\t{}", alias, rs1),
+                None => write!(f, "csrw\t{},{}", csr, rs1),
+            },
+            Instruction::CSRRW { rd, rs1, csr } => match csr_alias(csr, "", "fs") {
+                Some(alias) => write!(f, "{}\t{},{}", alias, rd, rs1),
+                None => write!(f, "csrrw\t{},{},{}", rd, csr, rs1),
+            },
+            Instruction::CSRRS { rd: X0, rs1, csr } => write!(f, "csrs\t{},{}", csr, rs1),
+            Instruction::CSRRS { rd, rs1, csr } => write!(f, "csrrs\t{},{},{}", rd, csr, rs1),
+            Instruction::CSRRC { rd: X0, rs1, csr } => write!(f, "csrc\t{},{}", csr, rs1),
+            Instruction::CSRRC { rd, rs1, csr } => write!(f, "csrrc\t{},{},{}", rd, csr, rs1),
+            Instruction::CSRRWI { rd: X0, uimm, csr } => write!(f, "csrwi\t{},{}", csr, uimm),
+            Instruction::CSRRWI { rd, uimm, csr } => {
+                write!(f, "csrrwi\t{},{},{}", rd, csr, uimm)
+            }
+            Instruction::CSRRSI { rd: X0, uimm, csr } => write!(f, "csrsi\t{},{}", csr, uimm),
+            Instruction::CSRRSI { rd, uimm, csr } => {
+                write!(f, "csrrsi\t{},{},{}", rd, csr, uimm)
+            }
+            Instruction::CSRRCI { rd: X0, uimm, csr } => write!(f, "csrci\t{},{}", csr, uimm),
+            Instruction::CSRRCI { rd, uimm, csr } => {
+                write!(f, "csrrci\t{},{},{}", rd, csr, uimm)
+            }
+            Instruction::LR_W { rd, rs1, rl, aq } => {
+                write!(f, "lr.w{}\t{},({})", Order(aq, rl), rd, rs1)
+            }
+            Instruction::LR_D { rd, rs1, rl, aq } => {
+                write!(f, "lr.d{}\t{},({})", Order(aq, rl), rd, rs1)
+            }
+            Instruction::SC_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "sc.w", rd, rs1, rs2, aq, rl),
+            Instruction::SC_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "sc.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOSWAP_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoswap.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOADD_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoadd.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOXOR_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoxor.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOAND_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoand.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOOR_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoor.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMIN_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amomin.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMAX_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amomax.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMINU_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amominu.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMAXU_W {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amomaxu.w", rd, rs1, rs2, aq, rl),
+            Instruction::AMOSWAP_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoswap.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOADD_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoadd.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOXOR_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoxor.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOAND_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoand.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOOR_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amoor.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMIN_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amomin.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMAX_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amomax.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMINU_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amominu.d", rd, rs1, rs2, aq, rl),
+            Instruction::AMOMAXU_D {
+                rd,
+                rs1,
+                rs2,
+                rl,
+                aq,
+            } => amo(f, "amomaxu.d", rd, rs1, rs2, aq, rl),
+            Instruction::MRET => write!(f, "mret"),
+            Instruction::SRET => write!(f, "sret"),
+            Instruction::WFI => write!(f, "wfi"),
+            Instruction::Undifined(raw) => write!(f, ".4byte\t{:#x}", raw),
+            #[cfg(feature = "float")]
+            _ => self.fmt_float(f),
+        }
+    }
+}
+
+/// A branch or jump target, printed as an address if the pc is known and `.+offset` otherwise
+enum Target {
+    Absolute(u64),
+    Relative(i32),
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Absolute(addr) => write!(f, "{:x}", addr),
+            Target::Relative(offset) => write!(f, ".{:+}", offset),
+        }
+    }
+}
+
+/// The predecessor or successor set of a fence
+struct FenceSet(i32);
+
+impl Display for FenceSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (bit, c) in [(0b1000, 'i'), (0b0100, 'o'), (0b0010, 'r'), (0b0001, 'w')] {
+            if self.0 & bit != 0 {
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `.aq`, `.rl` or `.aqrl` suffix of atomics
+struct Order(bool, bool);
+
+impl Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0, self.1) {
+            (true, true) => write!(f, ".aqrl"),
+            (true, false) => write!(f, ".aq"),
+            (false, true) => write!(f, ".rl"),
+            (false, false) => Ok(()),
+        }
+    }
+}
+
+/// A rounding mode operand, objdump leaves out the dynamic rounding mode
+#[cfg(feature = "float")]
+struct Rm(RoundingMode);
+
+#[cfg(feature = "float")]
+impl Display for Rm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RoundingMode::ToNearestTieEven => write!(f, ",rne"),
+            RoundingMode::ToZero => write!(f, ",rtz"),
+            RoundingMode::Down => write!(f, ",rdn"),
+            RoundingMode::Up => write!(f, ",rup"),
+            RoundingMode::ToNearestTieMagnitude => write!(f, ",rmm"),
+            RoundingMode::Dynamic => Ok(()),
+        }
+    }
+}
+
+fn amo(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    rd: IntRegister,
+    rs1: IntRegister,
+    rs2: IntRegister,
+    aq: bool,
+    rl: bool,
+) -> fmt::Result {
+    write!(f, "{}{}\t{},{},({})", name, Order(aq, rl), rd, rs2, rs1)
+}
+
+/// The upper immediate of lui and auipc as written in assembly
+fn upper(imm: i32) -> u32 {
+    (imm as u32) >> 12
+}
+
+/// Aliases for reading and writing the counter and float csrs, `counter` and `float` are the
+/// prefixes used for the counters (`rdcycle`) and float csrs (`frflags`, `fsflags`).
+fn csr_alias(csr: CsrAddress, counter: &str, float: &str) -> Option<String> {
+    let name = match u16::from(csr) {
+        0x001 => "flags",
+        0x002 => "rm",
+        0x003 => "csr",
+        0xC00 if !counter.is_empty() => return Some(format!("{}cycle", counter)),
+        0xC01 if !counter.is_empty() => return Some(format!("{}time", counter)),
+        0xC02 if !counter.is_empty() => return Some(format!("{}instret", counter)),
+        _ => return None,
+    };
+    Some(format!("{}{}", float, name))
+}
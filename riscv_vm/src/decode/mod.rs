@@ -1,12 +1,13 @@
 use compact::decode_compact;
-#[cfg(feature = "float")]
-use instruction::RoundingMode;
-
 use crate::hart::registers::IntRegister;
 
+pub use self::disassemble::{disassemble, Disassembly};
 pub use self::instruction::Instruction;
+#[cfg(feature = "float")]
+pub use self::instruction::RoundingMode;
 
 mod compact;
+mod disassemble;
 pub(crate) mod instruction;
 #[cfg(test)]
 mod tests;
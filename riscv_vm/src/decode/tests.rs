@@ -504,3 +504,61 @@ mod instructions {
         );
     }
 }
+
+mod disassembly {
+    use crate::decode::{decode, disassemble, Disassembly};
+
+    fn dis(inst: u32, pc: u64) -> String {
+        Disassembly::decode(inst, pc.into()).to_string()
+    }
+
+    #[test]
+    fn base() {
+        assert_eq!(dis(0x00150513, 0), "addi\ta0,a0,1");
+        assert_eq!(dis(0x0dead037, 0), "lui\tzero,0xdead");
+        assert_eq!(dis(0x10a2a023, 0), "sw\ta0,256(t0)");
+        assert_eq!(dis(0x06b6252f, 0), "amoadd.w.aqrl\ta0,a1,(a2)");
+        assert_eq!(dis(0x0330000f, 0), "fence\trw,rw");
+    }
+
+    #[test]
+    fn pseudo() {
+        assert_eq!(dis(0x00000013, 0), "nop");
+        assert_eq!(dis(0x00100513, 0), "li\ta0,1");
+        assert_eq!(dis(0x00058513, 0), "mv\ta0,a1");
+        assert_eq!(dis(0x00008067, 0), "ret");
+        assert_eq!(dis(0x30002573, 0), "csrr\ta0,mstatus");
+        assert_eq!(dis(0x34151073, 0), "csrw\tmepc,a0");
+        assert_eq!(dis(0xc0002573, 0), "rdcycle\ta0");
+        assert_eq!(dis(0x0ff0000f, 0), "fence");
+    }
+
+    #[test]
+    fn targets() {
+        assert_eq!(dis(0xff9ff06f, 0x80000008), "j\t80000000");
+        assert_eq!(dis(0x000c4463, 0x80000000), "bltz\ts8,80000008");
+        let (inst, _) = decode(0xff9ff06f);
+        assert_eq!(inst.to_string(), "j\t.-8");
+    }
+
+    #[test]
+    fn compressed() {
+        assert_eq!(dis(0x4505, 0), "c.li\ta0,1");
+        assert_eq!(dis(0x8082, 0), "c.jr\tra");
+        assert_eq!(dis(0x0505, 0), "c.addi\ta0,1");
+        assert_eq!(dis(0x1141, 0), "c.addi\tsp,-16");
+        assert_eq!(dis(0x6141, 0), "c.addi16sp\tsp,16");
+        assert_eq!(dis(0x713d, 0), "c.addi16sp\tsp,-32");
+        assert_eq!(dis(0x0141, 0), "c.addi\tsp,16");
+    }
+
+    #[test]
+    fn buffer() {
+        // c.li a0, 1 ; addi a0, a0, 1 ; half of an instruction
+        let bytes = [0x05, 0x45, 0x13, 0x05, 0x15, 0x00, 0x13, 0x05];
+        let insts = disassemble(&bytes, 0x80000000u64.into());
+        assert_eq!(insts.len(), 2);
+        assert_eq!(insts[1].pc, Some(0x80000002u64.into()));
+        assert_eq!(insts[1].to_string(), "addi\ta0,a0,1");
+    }
+}
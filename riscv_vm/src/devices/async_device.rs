@@ -56,7 +56,7 @@ impl AsyncDeviceHolder {
     }
 
     pub fn init_device(&mut self, mem: &mut Memory) -> Result<(), DeviceInitError> {
-        DeviceObject::init(self.device.as_mut(), DeviceMemHandle::new(mem))?;
+        DeviceObject::init(self.device.as_mut(), DeviceMemHandle::new(mem, 0u64.into()))?;
         Ok(())
     }
 
@@ -0,0 +1,720 @@
+//! Where serial devices send the bytes written by the guest and get the bytes it reads, see
+//! [`CharBackend`]. A backend belongs to a single device, so each uart can be connected to its
+//! own console.
+
+use std::{
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{self, stdin, stdout, ErrorKind, Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+};
+
+use crate::vmstate::IdleWaker;
+
+/// How long the input threads wait before they try again after an error, or check whether
+/// their backend was dropped
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The writes a socket backend queues for a client that does not keep up, more are dropped
+const OUTPUT_QUEUE: usize = 256;
+
+/// Output kept instead of printed while captured, see [`capture_output()`].
+static CAPTURED: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Keep the output of all [`StdioBackend`]s to be read with [`take_output()`] instead of
+/// printing it to stdout, used by frontends that own the terminal. Stopping discards
+/// uncollected output.
+pub fn capture_output(capture: bool) {
+    *CAPTURED.lock().unwrap() = capture.then(Vec::new);
+}
+
+/// The output written since the last call while capturing.
+pub fn take_output() -> Vec<u8> {
+    CAPTURED
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(std::mem::take)
+        .unwrap_or_default()
+}
+
+/// The host side of a serial device. Neither function may block, the device calls them from
+/// the vm's step.
+pub trait CharBackend: Debug + Send {
+    /// Send bytes written by the guest. Backends without anything connected drop them.
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// The next byte for the guest, if one has arrived. A device only asks when it has room for
+    /// the byte.
+    fn read(&mut self) -> Option<u8>;
+
+    /// Given by the device once it is added to a vm. Backends that receive input on a thread of
+    /// their own wake the vm through it when a byte arrives, so a guest waiting for an
+    /// interrupt gets it without delay.
+    fn set_waker(&mut self, waker: IdleWaker) {}
+}
+
+impl CharBackend for Box<dyn CharBackend> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.as_mut().write(bytes)
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.as_mut().read()
+    }
+
+    fn set_waker(&mut self, waker: IdleWaker) {
+        self.as_mut().set_waker(waker)
+    }
+}
+
+/// The waker of the vm a backend belongs to, shared with the thread receiving its input. It is
+/// not known yet when the thread is started.
+#[derive(Debug, Clone, Default)]
+struct SharedWaker(Arc<Mutex<Option<IdleWaker>>>);
+
+impl SharedWaker {
+    fn set(&self, waker: IdleWaker) {
+        *self.0.lock().unwrap() = Some(waker);
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = &*self.0.lock().unwrap() {
+            waker.wake();
+        }
+    }
+}
+
+/// Prints output to stdout, unless it is [captured](capture_output()). Only a backend made
+/// with [`StdioBackend::raw()`] passes input from stdin to the guest.
+#[derive(Debug, Default)]
+pub struct StdioBackend {
+    input: Option<Receiver<u8>>,
+    waker: SharedWaker,
+    #[cfg(unix)]
+    terminal: Option<RawTerminal>,
+}
+
+impl StdioBackend {
+    /// Output only, stdin is left to the host.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pass stdin to the guest. If stdin is a terminal it is put in raw mode until the backend
+    /// is dropped, so keys reach the guest as they are pressed, ctrl-c still interrupts the host.
+    pub fn raw() -> io::Result<Self> {
+        #[cfg(unix)]
+        let terminal = RawTerminal::enable()?;
+
+        let (sender, input) = mpsc::channel();
+        let waker = SharedWaker::default();
+        let thread_waker = waker.clone();
+        thread::spawn(move || {
+            for byte in stdin().lock().bytes() {
+                match byte {
+                    Ok(byte) if sender.send(byte).is_ok() => thread_waker.wake(),
+                    _ => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            input: Some(input),
+            waker,
+            #[cfg(unix)]
+            terminal,
+        })
+    }
+}
+
+impl CharBackend for StdioBackend {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(captured) = CAPTURED.lock().unwrap().as_mut() {
+            captured.extend_from_slice(bytes);
+            return Ok(());
+        }
+        let mut stdout = stdout().lock();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.as_ref()?.try_recv().ok()
+    }
+
+    fn set_waker(&mut self, waker: IdleWaker) {
+        self.waker.set(waker);
+    }
+}
+
+/// The settings of the terminal on stdin from before it was put in raw mode, restored on drop.
+#[cfg(unix)]
+struct RawTerminal(libc::termios);
+
+#[cfg(unix)]
+impl RawTerminal {
+    fn enable() -> io::Result<Option<Self>> {
+        let fd = stdin().as_raw_fd();
+        // SAFETY: termios is plain data, tcgetattr fills it in completely on success
+        let mut original = unsafe { std::mem::zeroed() };
+        unsafe {
+            if libc::isatty(fd) == 0 {
+                return Ok(None);
+            }
+            if libc::tcgetattr(fd, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            // Keep ctrl-c for the host and let newlines printed by the host start a new line
+            raw.c_lflag |= libc::ISIG;
+            raw.c_oflag |= libc::OPOST | libc::ONLCR;
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Some(Self(original)))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(stdin().as_raw_fd(), libc::TCSANOW, &self.0);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Debug for RawTerminal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RawTerminal")
+    }
+}
+
+/// Writes output to a file, for logs of a run, the guest never receives input.
+#[derive(Debug)]
+pub struct FileBackend(File);
+
+impl FileBackend {
+    /// Write to a new or truncated file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self(File::create(path)?))
+    }
+
+    /// Add to the end of the file at `path`, creating it if it does not exist.
+    pub fn append<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+}
+
+impl CharBackend for FileBackend {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)?;
+        self.0.flush()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Gives the guest's output and input to the host program through channels. The host program
+/// wakes an idle vm itself, with [`VMState::idle_waker()`](crate::vmstate::VMState::idle_waker()).
+#[derive(Debug)]
+pub struct ChannelBackend {
+    input: Receiver<u8>,
+    output: Sender<u8>,
+}
+
+impl ChannelBackend {
+    /// The backend, a sender of bytes for the guest and a receiver of the guest's output.
+    pub fn new() -> (Self, Sender<u8>, Receiver<u8>) {
+        let (input_sender, input) = mpsc::channel();
+        let (output, output_receiver) = mpsc::channel();
+        (Self { input, output }, input_sender, output_receiver)
+    }
+}
+
+impl CharBackend for ChannelBackend {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        for byte in bytes {
+            // Nobody listening is the same as an unplugged cable
+            let _ = self.output.send(*byte);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+}
+
+/// A server for a single client on a local tcp port or a unix socket, a new client can connect
+/// once the last one disconnected. Output is dropped while no client is connected. Tcp clients
+/// are spoken to as telnet clients in character mode, so `telnet localhost <port>` gives a
+/// console. Clients are accepted and read from on a thread, which wakes the vm when they send
+/// something.
+#[derive(Debug)]
+pub struct SocketBackend {
+    address: SocketAddress,
+    input: Receiver<u8>,
+    output: SyncSender<Vec<u8>>,
+    shared: Arc<SocketShared>,
+}
+
+#[derive(Debug)]
+enum SocketAddress {
+    Tcp(u16),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+#[derive(Debug)]
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+#[derive(Debug)]
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// What a socket backend shares with its threads
+#[derive(Debug, Default)]
+struct SocketShared {
+    /// The connected client, output is written to it
+    client: Mutex<Option<Connection>>,
+    /// The same client, shut down to end the read of the accepting thread
+    reading: Mutex<Option<Connection>>,
+    closed: AtomicBool,
+    waker: SharedWaker,
+}
+
+/// Telnet IAC, the escape starting commands in both directions
+const IAC: u8 = 255;
+const WILL: u8 = 251;
+const DONT: u8 = 254;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// Where the filter of telnet commands from the client is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TelnetState {
+    Data,
+    /// After a carriage return, telnet follows it by a NUL or line feed that are dropped
+    Return,
+    Command,
+    /// After WILL, WONT, DO or DONT, the option is dropped
+    Option,
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+impl SocketBackend {
+    /// Listen for a telnet client on the given port on localhost, port 0 picks a free one.
+    pub fn bind_tcp(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let port = listener.local_addr()?.port();
+        Ok(Self::new(Listener::Tcp(listener), SocketAddress::Tcp(port)))
+    }
+
+    /// Listen for a client on a unix socket at the given path, the socket file is removed when
+    /// the backend is dropped.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        Ok(Self::new(
+            Listener::Unix(listener),
+            SocketAddress::Unix(path),
+        ))
+    }
+
+    /// The port a tcp backend listens on
+    pub fn port(&self) -> Option<u16> {
+        match &self.address {
+            SocketAddress::Tcp(port) => Some(*port),
+            #[cfg(unix)]
+            SocketAddress::Unix(_) => None,
+        }
+    }
+
+    fn new(listener: Listener, address: SocketAddress) -> Self {
+        let shared = Arc::new(SocketShared::default());
+        let (input_sender, input) = mpsc::channel();
+        let (output, output_receiver) = mpsc::sync_channel::<Vec<u8>>(OUTPUT_QUEUE);
+
+        let accepting = shared.clone();
+        thread::spawn(move || serve_clients(listener, input_sender, &accepting));
+        let writing = shared.clone();
+        thread::spawn(move || {
+            for bytes in output_receiver {
+                let mut client = writing.client.lock().unwrap();
+                if client
+                    .as_mut()
+                    .is_some_and(|c| c.write_all(&bytes).is_err())
+                {
+                    *client = None;
+                }
+            }
+        });
+
+        Self {
+            address,
+            input,
+            output,
+            shared,
+        }
+    }
+}
+
+/// Accept clients one after the other and pass what they send on to `input`, until the backend
+/// is dropped
+fn serve_clients(listener: Listener, input: Sender<u8>, shared: &SocketShared) {
+    let telnet = matches!(listener, Listener::Tcp(_));
+    while !shared.closed.load(Ordering::Relaxed) {
+        let Ok(mut client) = listener.accept() else {
+            thread::sleep(RETRY_INTERVAL);
+            continue;
+        };
+        if telnet {
+            // Ask for character mode, the guest does the echoing
+            let _ = client.write_all(&[IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD]);
+        }
+        let (Ok(writer), Ok(reading)) = (client.try_clone(), client.try_clone()) else {
+            continue;
+        };
+        *shared.client.lock().unwrap() = Some(writer);
+        *shared.reading.lock().unwrap() = Some(reading);
+        if shared.closed.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut state = TelnetState::Data;
+        let mut buf = [0u8; 64];
+        let mut received = Vec::new();
+        loop {
+            let n = match client.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if telnet {
+                for byte in &buf[..n] {
+                    state = state.filter(*byte, &mut received);
+                }
+            } else {
+                received.extend_from_slice(&buf[..n]);
+            }
+            for byte in received.drain(..) {
+                if input.send(byte).is_err() {
+                    return;
+                }
+            }
+            shared.waker.wake();
+        }
+
+        *shared.client.lock().unwrap() = None;
+        *shared.reading.lock().unwrap() = None;
+    }
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(l) => {
+                let stream = l.accept()?.0;
+                stream.set_nodelay(true)?;
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(l) => Ok(Connection::Unix(l.accept()?.0)),
+        }
+    }
+}
+
+impl Connection {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Connection::Tcp(s) => Connection::Tcp(s.try_clone()?),
+            #[cfg(unix)]
+            Connection::Unix(s) => Connection::Unix(s.try_clone()?),
+        })
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+impl TelnetState {
+    /// Add a byte from the client to `input` unless it is part of a telnet command
+    fn filter(self, byte: u8, input: &mut Vec<u8>) -> TelnetState {
+        match (self, byte) {
+            (TelnetState::Data | TelnetState::Return, IAC) => TelnetState::Command,
+            (TelnetState::Return, 0 | b'\n') => TelnetState::Data,
+            (TelnetState::Data | TelnetState::Return, b'\r') => {
+                input.push(byte);
+                TelnetState::Return
+            }
+            (TelnetState::Data | TelnetState::Return, _) => {
+                input.push(byte);
+                TelnetState::Data
+            }
+            (TelnetState::Command, IAC) => {
+                input.push(IAC);
+                TelnetState::Data
+            }
+            (TelnetState::Command, WILL..=DONT) => TelnetState::Option,
+            (TelnetState::Command, SB) => TelnetState::Subnegotiation,
+            (TelnetState::Command | TelnetState::Option, _) => TelnetState::Data,
+            (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationCommand,
+            (TelnetState::SubnegotiationCommand, SE) => TelnetState::Data,
+            (TelnetState::Subnegotiation | TelnetState::SubnegotiationCommand, _) => {
+                TelnetState::Subnegotiation
+            }
+        }
+    }
+}
+
+impl CharBackend for SocketBackend {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let bytes = match self.address {
+            // Data bytes that look like IAC are doubled
+            SocketAddress::Tcp(_) => bytes
+                .iter()
+                .flat_map(|b| {
+                    if *b == IAC {
+                        &[IAC, IAC][..]
+                    } else {
+                        std::slice::from_ref(b)
+                    }
+                })
+                .copied()
+                .collect(),
+            #[cfg(unix)]
+            SocketAddress::Unix(_) => bytes.to_vec(),
+        };
+        // A client that does not keep up loses output instead of stalling the vm
+        let _ = self.output.try_send(bytes);
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn set_waker(&mut self, waker: IdleWaker) {
+        self.shared.waker.set(waker);
+    }
+}
+
+impl Drop for SocketBackend {
+    /// Stop the accepting thread, a connected client is hung up on and the thread blocked in
+    /// accept is given a connection of its own to notice the backend is gone
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        if let Some(client) = &*self.shared.reading.lock().unwrap() {
+            let _ = client.shutdown();
+        }
+        match &self.address {
+            SocketAddress::Tcp(port) => {
+                let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, *port));
+            }
+            #[cfg(unix)]
+            SocketAddress::Unix(path) => {
+                let _ = UnixStream::connect(path);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// A pseudo terminal for the guest, connect to it with a terminal program like
+/// `screen <path>`. The guest gets the bytes unchanged, the terminal is in raw mode.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct PtyBackend {
+    master: File,
+    path: PathBuf,
+    input: Receiver<u8>,
+    closed: Arc<AtomicBool>,
+    waker: SharedWaker,
+}
+
+#[cfg(unix)]
+impl PtyBackend {
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: the fd is checked before it is given to the File, which then owns it
+        let (master, path) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = std::ffi::CStr::from_ptr(name)
+                .to_string_lossy()
+                .into_owned();
+            (master, PathBuf::from(path))
+        };
+
+        let (sender, input) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let waker = SharedWaker::default();
+        let reader = master.try_clone()?;
+        let (thread_closed, thread_waker) = (closed.clone(), waker.clone());
+        thread::spawn(move || read_pty(reader, sender, &thread_closed, &thread_waker));
+
+        Ok(Self {
+            master,
+            path,
+            input,
+            closed,
+            waker,
+        })
+    }
+
+    /// The path of the terminal to connect to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Pass what the terminal sends on to `input` until the backend is dropped. The master is non
+/// blocking, so the thread waits for input in poll.
+#[cfg(unix)]
+fn read_pty(mut master: File, input: Sender<u8>, closed: &AtomicBool, waker: &SharedWaker) {
+    let mut buf = [0u8; 64];
+    while !closed.load(Ordering::Relaxed) {
+        let mut fd = libc::pollfd {
+            fd: master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: a single pollfd is passed, which lives for the duration of the call
+        let ready = unsafe { libc::poll(&mut fd, 1, RETRY_INTERVAL.as_millis() as i32) };
+        if ready <= 0 {
+            continue;
+        }
+        // Poll returns right away, and reading fails, while no terminal is connected
+        let n = match master.read(&mut buf) {
+            Ok(n) if n > 0 => n,
+            _ => {
+                thread::sleep(RETRY_INTERVAL);
+                continue;
+            }
+        };
+        for byte in &buf[..n] {
+            if input.send(*byte).is_err() {
+                return;
+            }
+        }
+        waker.wake();
+    }
+}
+
+#[cfg(unix)]
+impl CharBackend for PtyBackend {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        // Without a terminal connected the pty fills up or hangs up, output is then dropped
+        let _ = self.master.write_all(bytes);
+        Ok(())
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn set_waker(&mut self, waker: IdleWaker) {
+        self.waker.set(waker);
+    }
+}
+
+#[cfg(unix)]
+impl Drop for PtyBackend {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
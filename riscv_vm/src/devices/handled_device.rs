@@ -3,7 +3,10 @@ use std::{
     sync::mpsc::{self, Receiver, Sender},
 };
 
-use crate::memory::Memory;
+use crate::{
+    memory::{address::Address, Memory},
+    vmstate::{IdleWaker, SnapshotError, SnapshotReader, SnapshotWriter},
+};
 
 use super::{DeviceError, DeviceInitError, DeviceMemHandle, DeviceObject};
 
@@ -12,25 +15,61 @@ use super::{DeviceError, DeviceInitError, DeviceMemHandle, DeviceObject};
 pub trait HandledDevice: Debug + DeviceObject {
     /// The main way for the device to do logic, this function is called once per clock cycle.
     fn update(&mut self) -> Result<(), DeviceError>;
+
+    /// The bytes the device took from outside the vm since the last call, like the input of a
+    /// serial backend. The vm records them with its history, so the update can be replayed.
+    fn take_input(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Redo an update while the vm replays its history for reverse execution. The device takes
+    /// `input` instead of new input from outside the vm, and sends nothing out, as that was
+    /// done when the update first ran. Devices that neither take input nor send output can keep
+    /// the default.
+    fn replay(&mut self, input: &[u8]) -> Result<(), DeviceError> {
+        self.update()
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct HandledDeviceHolder {
     device: Box<dyn HandledDevice>,
+    base: Address,
 }
 
 impl HandledDeviceHolder {
-    pub(crate) fn new(device: Box<dyn HandledDevice>) -> (Sender<()>, Self) {
+    pub(crate) fn new(device: Box<dyn HandledDevice>, base: Address) -> (Sender<()>, Self) {
         let (s, r) = mpsc::channel();
-        (s, Self { device })
+        (s, Self { device, base })
     }
 
-    pub(crate) fn init_device(&mut self, mem: &mut Memory) -> Result<(), DeviceInitError> {
-        DeviceObject::init(self.device.as_mut(), DeviceMemHandle::new(mem))?;
+    pub(crate) fn init_device(
+        &mut self,
+        mem: &mut Memory,
+        waker: IdleWaker,
+    ) -> Result<(), DeviceInitError> {
+        let handle = DeviceMemHandle::new(mem, self.base).with_waker(waker);
+        DeviceObject::init(self.device.as_mut(), handle)?;
         Ok(())
     }
 
     pub(crate) fn update(&mut self) -> Result<(), DeviceError> {
         self.device.update()
     }
+
+    pub(crate) fn take_input(&mut self) -> Vec<u8> {
+        self.device.take_input()
+    }
+
+    pub(crate) fn replay(&mut self, input: &[u8]) -> Result<(), DeviceError> {
+        self.device.replay(input)
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        self.device.save(snapshot)
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.device.restore(snapshot)
+    }
 }
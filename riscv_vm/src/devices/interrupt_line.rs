@@ -0,0 +1,31 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A level triggered interrupt output of a device, clones share the same line. The device
+/// raises and lowers the line, an interrupt controller samples it.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptLine(Arc<AtomicBool>);
+
+impl InterruptLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_level(&self, raised: bool) {
+        self.0.store(raised, Ordering::Release);
+    }
+
+    pub fn raise(&self) {
+        self.set_level(true);
+    }
+
+    pub fn lower(&self) {
+        self.set_level(false);
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
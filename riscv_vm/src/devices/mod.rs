@@ -28,12 +28,18 @@ use std::{
 pub use crate::memory::memory_buffer;
 use crate::{
     memory::{memory_buffer::MemoryBuffer, Memory},
+    vmstate::{IdleWaker, SnapshotError, SnapshotReader, SnapshotWriter},
     Address,
 };
 
 pub mod async_device;
+pub mod char_backend;
 pub mod handled_device;
+pub mod interrupt_line;
+pub mod ns16550a;
 pub mod simple_uart;
+#[cfg(test)]
+mod tests;
 #[cfg(feature = "vga_text_buf")]
 pub mod vga_text_mode;
 
@@ -55,11 +61,34 @@ pub enum DeviceError {
 /// device memory regions.
 pub struct DeviceMemHandle<'a> {
     mem: &'a mut Memory,
+    base: Address,
+    waker: Option<IdleWaker>,
 }
 
 impl<'a> DeviceMemHandle<'a> {
-    pub(crate) fn new(mem: &'a mut Memory) -> Self {
-        Self { mem }
+    pub(crate) fn new(mem: &'a mut Memory, base: Address) -> Self {
+        Self {
+            mem,
+            base,
+            waker: None,
+        }
+    }
+
+    pub(crate) fn with_waker(mut self, waker: IdleWaker) -> Self {
+        self.waker = Some(waker);
+        self
+    }
+
+    /// The waker of the vm, devices that get input from other threads use it to wake the vm
+    /// while all harts wait for an interrupt. Async devices are not given one.
+    pub fn idle_waker(&self) -> Option<&IdleWaker> {
+        self.waker.as_ref()
+    }
+
+    /// The address the device was added at, devices should place their registers relative to
+    /// this address. Always 0 for async devices, which are not given an address.
+    pub fn base(&self) -> Address {
+        self.base
     }
 
     /// Register a memory region to live at `base`, the buffer is consumed, but
@@ -90,8 +119,18 @@ pub trait Device {
 /// the vm and devices are initializing but before any code is ran, this is the time when the
 /// device can register any memory regions, this initialization is allowed to error, these
 /// errors should be passed up as [`DeviceInitError::Other`].
+///
+/// [`DeviceObject::save()`] and [`DeviceObject::restore()`] are called when the vm is
+/// snapshotted or restored, a device with state (including its memory regions) should
+/// implement both, the default saves nothing.
 pub trait DeviceObject {
     fn init(&mut self, mem: DeviceMemHandle) -> Result<(), DeviceInitError>;
+
+    fn save(&self, snapshot: &mut SnapshotWriter) {}
+
+    fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        Ok(())
+    }
 }
 
 impl<T: Error + Send + 'static> From<T> for DeviceError {
@@ -0,0 +1,562 @@
+//! A 16550A uart with 16 byte fifos, as found in most riscv platforms. The other side of the
+//! serial line is a [`CharBackend`], stdout by default. The baud rate and line settings are
+//! kept but have no effect, transmission and reception are instant.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::{
+    memory::memory_buffer::{MemoryBuffer, MemoryBufferError},
+    vmstate::{SnapshotError, SnapshotReader, SnapshotWriter},
+    Address,
+};
+
+use super::{
+    char_backend::{CharBackend, StdioBackend},
+    handled_device::HandledDevice,
+    interrupt_line::InterruptLine,
+    Device, DeviceError, DeviceInitError, DeviceMemHandle, DeviceObject,
+};
+
+const FIFO_SIZE: usize = 16;
+/// Steps without reception or reads before a receive fifo below its trigger level raises a
+/// character timeout interrupt
+const CHARACTER_TIMEOUT: u64 = 256;
+
+/// Register indices, multiplied by the register stride to get offsets
+const RBR_THR_DLL: u64 = 0;
+const IER_DLM: u64 = 1;
+const IIR_FCR: u64 = 2;
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+const IER_RDI: u8 = 0x01;
+const IER_THRI: u8 = 0x02;
+const IER_RLSI: u8 = 0x04;
+const IER_MSI: u8 = 0x08;
+
+const IIR_NO_INT: u8 = 0x01;
+const IIR_MSI: u8 = 0x00;
+const IIR_THRI: u8 = 0x02;
+const IIR_RDI: u8 = 0x04;
+const IIR_RLSI: u8 = 0x06;
+const IIR_TIMEOUT: u8 = 0x0C;
+const IIR_FIFO_ENABLED: u8 = 0xC0;
+
+const FCR_ENABLE_FIFO: u8 = 0x01;
+const FCR_CLEAR_RCVR: u8 = 0x02;
+const FCR_CLEAR_XMIT: u8 = 0x04;
+
+const LCR_DLAB: u8 = 0x80;
+
+const MCR_DTR: u8 = 0x01;
+const MCR_RTS: u8 = 0x02;
+const MCR_OUT1: u8 = 0x04;
+const MCR_OUT2: u8 = 0x08;
+const MCR_LOOP: u8 = 0x10;
+
+const LSR_DR: u8 = 0x01;
+const LSR_OE: u8 = 0x02;
+const LSR_THRE: u8 = 0x20;
+const LSR_TEMT: u8 = 0x40;
+
+const MSR_DELTA: u8 = 0x0F;
+const MSR_TERI: u8 = 0x04;
+const MSR_CTS: u8 = 0x10;
+const MSR_DSR: u8 = 0x20;
+const MSR_RI: u8 = 0x40;
+const MSR_DCD: u8 = 0x80;
+
+/// The receive fifo trigger levels selected by bits 6 and 7 of the FCR
+const TRIGGER_LEVELS: [usize; 4] = [1, 4, 8, 14];
+
+/// A 16550A uart, its eight registers are `1 << reg_shift` bytes apart, only the lowest byte
+/// of each register is used. The interrupt output is raised while the IIR reports a pending
+/// interrupt, OUT2 does not gate it.
+#[derive(Debug)]
+pub struct Ns16550a {
+    reg_shift: u32,
+    irq: Option<InterruptLine>,
+    backend: Box<dyn CharBackend>,
+    regs: Option<Arc<RwLock<Registers>>>,
+    /// Bytes taken from the backend since the last [`HandledDevice::take_input()`]
+    received: Vec<u8>,
+}
+
+impl Ns16550a {
+    /// Set the register stride to `1 << shift` bytes, the `reg-shift` of the device tree.
+    pub fn with_reg_shift(mut self, shift: u32) -> Self {
+        self.reg_shift = shift;
+        self
+    }
+
+    /// Drive `line` with the interrupt output of the uart.
+    pub fn with_interrupt(mut self, line: InterruptLine) -> Self {
+        self.irq = Some(line);
+        self
+    }
+
+    /// Connect the serial line to `backend`. Bytes from the backend are only taken as space in
+    /// the receive fifo allows, so none are lost while the guest does not read them.
+    pub fn with_backend<B: CharBackend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Box::new(backend);
+        self
+    }
+}
+
+impl Device for Ns16550a {
+    /// Hint for vm's using this device, the size with the default stride of 1.
+    const MEM_SIZE: u64 = 8;
+
+    fn new() -> Self {
+        Self {
+            reg_shift: 0,
+            irq: None,
+            backend: Box::new(StdioBackend::new()),
+            regs: None,
+            received: Vec::new(),
+        }
+    }
+}
+
+impl DeviceObject for Ns16550a {
+    fn init(&mut self, mut mem: DeviceMemHandle) -> Result<(), DeviceInitError> {
+        if let Some(waker) = mem.idle_waker() {
+            self.backend.set_waker(waker.clone());
+        }
+        let regs = Registers {
+            shift: self.reg_shift,
+            uart: Mutex::new(Uart::new(self.irq.clone())),
+        };
+        self.regs = Some(mem.add_memory_buffer(mem.base(), regs)?);
+        Ok(())
+    }
+
+    fn save(&self, snapshot: &mut SnapshotWriter) {
+        if let Some(regs) = &self.regs {
+            regs.read().unwrap().uart.lock().unwrap().save(snapshot);
+        }
+    }
+
+    fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        if let Some(regs) = &self.regs {
+            regs.read()
+                .unwrap()
+                .uart
+                .lock()
+                .unwrap()
+                .restore(snapshot)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ns16550a {
+    /// Move bytes between the fifos and the backend, or only take bytes from `replay` and drop
+    /// the output while the vm replays its history
+    fn exchange(&mut self, mut replay: Option<&[u8]>) -> Result<(), DeviceError> {
+        let regs = self.regs.as_ref().unwrap().read().unwrap();
+        let mut uart = regs.uart.lock().unwrap();
+
+        let sent = uart.transmit();
+        if !sent.is_empty() && replay.is_none() {
+            self.backend.write(&sent)?;
+        }
+
+        // In loopback the receiver is disconnected from the outside
+        let mut received = false;
+        while uart.mcr & MCR_LOOP == 0 && uart.rx.len() < uart.fifo_size() {
+            let byte = match &mut replay {
+                Some(input) => input.split_first().map(|(byte, rest)| {
+                    *input = rest;
+                    *byte
+                }),
+                None => self
+                    .backend
+                    .read()
+                    .inspect(|byte| self.received.push(*byte)),
+            };
+            match byte {
+                Some(byte) => {
+                    uart.receive(byte);
+                    received = true;
+                }
+                None => break,
+            }
+        }
+        if !received {
+            uart.rx_idle += 1;
+        }
+        uart.update_irq();
+
+        Ok(())
+    }
+}
+
+impl HandledDevice for Ns16550a {
+    fn update(&mut self) -> Result<(), DeviceError> {
+        self.exchange(None)
+    }
+
+    fn take_input(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.received)
+    }
+
+    fn replay(&mut self, input: &[u8]) -> Result<(), DeviceError> {
+        self.exchange(Some(input))
+    }
+}
+
+/// The memory of the uart, reads have side effects so the state lives behind a mutex.
+#[derive(Debug)]
+struct Registers {
+    shift: u32,
+    uart: Mutex<Uart>,
+}
+
+impl Registers {
+    /// The register at `offset`, if it is not in the unused upper bytes of one
+    fn register(&self, offset: u64) -> Option<u64> {
+        (offset & ((1 << self.shift) - 1) == 0).then_some(offset >> self.shift)
+    }
+}
+
+impl MemoryBuffer for Registers {
+    fn size(&self) -> u64 {
+        8 << self.shift
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr + bytes.len() as u64 > self.size() {
+            return Err(MemoryBufferError::OutOfBoundsWrite(addr.into()));
+        }
+        let mut uart = self.uart.lock().unwrap();
+        for (offset, byte) in (addr..).zip(bytes) {
+            if let Some(reg) = self.register(offset) {
+                uart.write(reg, *byte);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr + size as u64 > self.size() {
+            return Err(MemoryBufferError::OutOfBoundsRead(addr.into()));
+        }
+        let mut uart = self.uart.lock().unwrap();
+        Ok((addr..addr + size as u64)
+            .map(|offset| self.register(offset).map_or(0, |reg| uart.read(reg)))
+            .collect())
+    }
+
+    fn peek_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr + size as u64 > self.size() {
+            return Err(MemoryBufferError::OutOfBoundsRead(addr.into()));
+        }
+        let uart = self.uart.lock().unwrap();
+        Ok((addr..addr + size as u64)
+            .map(|offset| self.register(offset).map_or(0, |reg| uart.peek(reg)))
+            .collect())
+    }
+}
+
+#[derive(Debug)]
+struct Uart {
+    rx: VecDeque<u8>,
+    tx: VecDeque<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    msr: u8,
+    scr: u8,
+    dll: u8,
+    dlm: u8,
+    fifo_enabled: bool,
+    trigger_level: usize,
+    overrun: bool,
+    /// The transmitter holding register empty interrupt, cleared by writing the THR or reading
+    /// the IIR while it is the reported interrupt
+    thre_pending: bool,
+    /// Steps since the last reception or read of the receive fifo
+    rx_idle: u64,
+    irq: Option<InterruptLine>,
+}
+
+impl Uart {
+    fn new(irq: Option<InterruptLine>) -> Self {
+        let mut uart = Self {
+            rx: VecDeque::with_capacity(FIFO_SIZE),
+            tx: VecDeque::with_capacity(FIFO_SIZE),
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            msr: 0,
+            scr: 0,
+            dll: 0,
+            dlm: 0,
+            fifo_enabled: false,
+            trigger_level: 1,
+            overrun: false,
+            thre_pending: false,
+            rx_idle: 0,
+            irq,
+        };
+        uart.msr = uart.modem_lines();
+        uart
+    }
+
+    /// Without fifos the uart only holds a single byte each way
+    fn fifo_size(&self) -> usize {
+        if self.fifo_enabled {
+            FIFO_SIZE
+        } else {
+            1
+        }
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    fn read(&mut self, reg: u64) -> u8 {
+        let value = self.peek(reg);
+        match reg {
+            RBR_THR_DLL if self.dlab() => {}
+            RBR_THR_DLL => {
+                self.rx_idle = 0;
+                self.rx.pop_front();
+            }
+            IIR_FCR if value & 0x0F == IIR_THRI => self.thre_pending = false,
+            LSR => self.overrun = false,
+            MSR => self.msr &= !MSR_DELTA,
+            _ => {}
+        }
+        self.update_irq();
+        value
+    }
+
+    /// The value a read of `reg` returns, without what the read changes
+    fn peek(&self, reg: u64) -> u8 {
+        match reg {
+            RBR_THR_DLL if self.dlab() => self.dll,
+            RBR_THR_DLL => self.rx.front().copied().unwrap_or(0),
+            IER_DLM if self.dlab() => self.dlm,
+            IER_DLM => self.ier,
+            IIR_FCR => self.iir(),
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => self.lsr(),
+            MSR => self.msr,
+            SCR => self.scr,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u64, value: u8) {
+        match reg {
+            RBR_THR_DLL if self.dlab() => self.dll = value,
+            RBR_THR_DLL => {
+                if self.tx.len() < self.fifo_size() {
+                    self.tx.push_back(value);
+                }
+                self.thre_pending = false;
+            }
+            IER_DLM if self.dlab() => self.dlm = value,
+            IER_DLM => {
+                let value = value & 0x0F;
+                // Enabling the interrupt while the holding register is empty raises it
+                if value & !self.ier & IER_THRI != 0 && self.tx.is_empty() {
+                    self.thre_pending = true;
+                }
+                self.ier = value;
+            }
+            IIR_FCR => {
+                let enable = value & FCR_ENABLE_FIFO != 0;
+                if enable != self.fifo_enabled {
+                    self.rx.clear();
+                    self.tx.clear();
+                }
+                self.fifo_enabled = enable;
+                if value & FCR_CLEAR_RCVR != 0 {
+                    self.rx.clear();
+                    self.rx_idle = 0;
+                }
+                if value & FCR_CLEAR_XMIT != 0 {
+                    self.tx.clear();
+                }
+                self.trigger_level = TRIGGER_LEVELS[(value >> 6) as usize];
+            }
+            LCR => self.lcr = value,
+            MCR => {
+                self.mcr = value & 0x1F;
+                self.set_modem_lines(self.modem_lines());
+            }
+            // The line and modem status registers are read only
+            LSR | MSR => {}
+            SCR => self.scr = value,
+            _ => unreachable!(),
+        }
+        self.update_irq();
+    }
+
+    fn lsr(&self) -> u8 {
+        let mut lsr = 0;
+        if !self.rx.is_empty() {
+            lsr |= LSR_DR;
+        }
+        if self.overrun {
+            lsr |= LSR_OE;
+        }
+        if self.tx.is_empty() {
+            lsr |= LSR_THRE | LSR_TEMT;
+        }
+        lsr
+    }
+
+    /// The highest priority pending interrupt that is enabled
+    fn iir(&self) -> u8 {
+        let id = if self.ier & IER_RLSI != 0 && self.overrun {
+            IIR_RLSI
+        } else if self.ier & IER_RDI != 0 && self.rx.len() >= self.rx_trigger() {
+            IIR_RDI
+        } else if self.ier & IER_RDI != 0
+            && !self.rx.is_empty()
+            && self.rx_idle >= CHARACTER_TIMEOUT
+        {
+            IIR_TIMEOUT
+        } else if self.ier & IER_THRI != 0 && self.thre_pending {
+            IIR_THRI
+        } else if self.ier & IER_MSI != 0 && self.msr & MSR_DELTA != 0 {
+            IIR_MSI
+        } else {
+            IIR_NO_INT
+        };
+        if self.fifo_enabled {
+            id | IIR_FIFO_ENABLED
+        } else {
+            id
+        }
+    }
+
+    fn rx_trigger(&self) -> usize {
+        if self.fifo_enabled {
+            self.trigger_level
+        } else {
+            1
+        }
+    }
+
+    /// The state of the modem inputs, in loopback they follow the modem outputs, otherwise
+    /// the other side is always ready
+    fn modem_lines(&self) -> u8 {
+        if self.mcr & MCR_LOOP == 0 {
+            return MSR_CTS | MSR_DSR | MSR_DCD;
+        }
+        [
+            (MCR_DTR, MSR_DSR),
+            (MCR_RTS, MSR_CTS),
+            (MCR_OUT1, MSR_RI),
+            (MCR_OUT2, MSR_DCD),
+        ]
+        .into_iter()
+        .filter(|(out, _)| self.mcr & out != 0)
+        .fold(0, |lines, (_, line)| lines | line)
+    }
+
+    /// Update the upper half of the MSR and set the delta bits of the lines that changed
+    fn set_modem_lines(&mut self, lines: u8) {
+        let changed = self.msr ^ lines;
+        let mut delta = (changed >> 4) & !MSR_TERI;
+        // The ring indicator only reports its trailing edge
+        if changed & self.msr & MSR_RI != 0 {
+            delta |= MSR_TERI;
+        }
+        self.msr = lines | (self.msr & MSR_DELTA) | delta;
+    }
+
+    fn receive(&mut self, byte: u8) {
+        if self.rx.len() < self.fifo_size() {
+            self.rx.push_back(byte);
+        } else {
+            self.overrun = true;
+        }
+        self.rx_idle = 0;
+    }
+
+    /// Send everything in the transmit fifo, bytes sent in loopback are received instead and
+    /// not returned
+    fn transmit(&mut self) -> Vec<u8> {
+        if self.tx.is_empty() {
+            return Vec::new();
+        }
+        self.thre_pending = true;
+        let sent: Vec<u8> = self.tx.drain(..).collect();
+        if self.mcr & MCR_LOOP != 0 {
+            for byte in sent {
+                self.receive(byte);
+            }
+            return Vec::new();
+        }
+        sent
+    }
+
+    fn update_irq(&self) {
+        if let Some(irq) = &self.irq {
+            irq.set_level(self.iir() & IIR_NO_INT == 0);
+        }
+    }
+
+    fn save(&self, snapshot: &mut SnapshotWriter) {
+        snapshot.write_bytes(&self.rx.iter().copied().collect::<Vec<_>>());
+        snapshot.write_bytes(&self.tx.iter().copied().collect::<Vec<_>>());
+        for reg in [
+            self.ier, self.lcr, self.mcr, self.msr, self.scr, self.dll, self.dlm,
+        ] {
+            snapshot.write_u8(reg);
+        }
+        snapshot.write_bool(self.fifo_enabled);
+        snapshot.write_u64(self.trigger_level as u64);
+        snapshot.write_bool(self.overrun);
+        snapshot.write_bool(self.thre_pending);
+        snapshot.write_u64(self.rx_idle);
+    }
+
+    fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        let rx = snapshot.read_bytes()?;
+        let tx = snapshot.read_bytes()?;
+        if rx.len() > FIFO_SIZE || tx.len() > FIFO_SIZE {
+            return Err(SnapshotError::Corrupt);
+        }
+        self.rx = rx.iter().copied().collect();
+        self.tx = tx.iter().copied().collect();
+        for reg in [
+            &mut self.ier,
+            &mut self.lcr,
+            &mut self.mcr,
+            &mut self.msr,
+            &mut self.scr,
+            &mut self.dll,
+            &mut self.dlm,
+        ] {
+            *reg = snapshot.read_u8()?;
+        }
+        self.fifo_enabled = snapshot.read_bool()?;
+        self.trigger_level = snapshot.read_u64()? as usize;
+        if !TRIGGER_LEVELS.contains(&self.trigger_level) {
+            return Err(SnapshotError::Corrupt);
+        }
+        self.overrun = snapshot.read_bool()?;
+        self.thre_pending = snapshot.read_bool()?;
+        self.rx_idle = snapshot.read_u64()?;
+        self.update_irq();
+        Ok(())
+    }
+}
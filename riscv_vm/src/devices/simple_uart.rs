@@ -1,22 +1,31 @@
 use core::panic;
-use std::{
-    io::{stdout, Write},
-    sync::{Arc, RwLock},
-};
+use std::sync::{Arc, RwLock};
 
 use crate::{
     hart::registers,
     memory::memory_buffer::{MemoryBuffer, NaiveBuffer},
+    vmstate::{SnapshotError, SnapshotReader, SnapshotWriter},
 };
 
 use super::{
-    handled_device::HandledDevice, Device, DeviceError, DeviceInitError, DeviceMemHandle,
-    DeviceObject,
+    char_backend::{CharBackend, StdioBackend},
+    handled_device::HandledDevice,
+    Device, DeviceError, DeviceInitError, DeviceMemHandle, DeviceObject,
 };
 
+pub use super::char_backend::{capture_output, take_output};
+
 /// It's not uart and probably breaks if you look at it wrong.
 #[derive(Debug)]
-pub struct SimpleUart(Option<Arc<RwLock<NaiveBuffer<8>>>>);
+pub struct SimpleUart(Option<Arc<RwLock<NaiveBuffer<8>>>>, Box<dyn CharBackend>);
+
+impl SimpleUart {
+    /// Send the output to `backend` instead of stdout.
+    pub fn with_backend<B: CharBackend + 'static>(mut self, backend: B) -> Self {
+        self.1 = Box::new(backend);
+        self
+    }
+}
 
 // struct UartMem([u8; 8]);
 
@@ -25,7 +34,7 @@ impl Device for SimpleUart {
     const MEM_SIZE: u64 = 8;
 
     fn new() -> Self {
-        Self(None)
+        Self(None, Box::new(StdioBackend::new()))
     }
 }
 
@@ -37,18 +46,41 @@ impl DeviceObject for SimpleUart {
             &[dev_mem.read_bytes(5u64.into(), 1).unwrap()[0] | 0x40],
             5u64.into(),
         );
-        self.0 = Some(mem.add_memory_buffer(0x10000000u64.into(), dev_mem)?);
+        self.0 = Some(mem.add_memory_buffer(mem.base(), dev_mem)?);
+        Ok(())
+    }
+
+    fn save(&self, snapshot: &mut SnapshotWriter) {
+        if let Some(mem) = &self.0 {
+            snapshot.write_bytes(&mem.read().unwrap().read_bytes(0u64.into(), 8).unwrap());
+        }
+    }
+
+    fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        if let Some(mem) = &self.0 {
+            let bytes = snapshot.read_bytes()?;
+            if bytes.len() != 8 {
+                return Err(SnapshotError::Corrupt);
+            }
+            mem.write()
+                .unwrap()
+                .write_bytes(bytes, 0u64.into())
+                .unwrap();
+        }
         Ok(())
     }
 }
 
-impl HandledDevice for SimpleUart {
-    fn update(&mut self) -> Result<(), DeviceError> {
+impl SimpleUart {
+    /// Send the byte written by the guest, unless the vm replays its history and it was
+    /// already sent
+    fn transmit(&mut self, replay: bool) -> Result<(), DeviceError> {
         let mut mem = self.0.as_ref().unwrap().write().unwrap();
         let reg = mem.read_bytes(0u64.into(), 1).unwrap()[0];
         if reg != 0 {
-            print!("{}", std::str::from_utf8(&[reg])?);
-            stdout().flush();
+            if !replay {
+                self.1.write(&[reg])?;
+            }
             mem.write_bytes(&[0], 0u64.into());
             let byte = mem.read_bytes(5u64.into(), 1).unwrap()[0] | 0x40;
             mem.write_bytes(&[byte], 5u64.into()).unwrap();
@@ -57,3 +89,13 @@ impl HandledDevice for SimpleUart {
         Ok(())
     }
 }
+
+impl HandledDevice for SimpleUart {
+    fn update(&mut self) -> Result<(), DeviceError> {
+        self.transmit(false)
+    }
+
+    fn replay(&mut self, _input: &[u8]) -> Result<(), DeviceError> {
+        self.transmit(true)
+    }
+}
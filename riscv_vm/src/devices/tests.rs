@@ -0,0 +1,231 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpStream},
+    sync::mpsc::{Receiver, Sender},
+    time::Duration,
+};
+
+use crate::memory::{address::Address, Memory};
+
+use super::{
+    char_backend::{ChannelBackend, CharBackend, FileBackend, SocketBackend},
+    handled_device::HandledDevice,
+    interrupt_line::InterruptLine,
+    ns16550a::Ns16550a,
+    Device, DeviceMemHandle, DeviceObject,
+};
+
+const BASE: u64 = 0x10000000;
+
+struct Serial {
+    mem: Memory,
+    uart: Ns16550a,
+    irq: InterruptLine,
+    input: Sender<u8>,
+    output: Receiver<u8>,
+}
+
+fn uart(shift: u32) -> Serial {
+    let mut mem = Memory::new::<256>();
+    let irq = InterruptLine::new();
+    let (backend, input, output) = ChannelBackend::new();
+    let mut uart = Ns16550a::new()
+        .with_reg_shift(shift)
+        .with_interrupt(irq.clone())
+        .with_backend(backend);
+    uart.init(DeviceMemHandle::new(&mut mem, BASE.into()))
+        .unwrap();
+    Serial {
+        mem,
+        uart,
+        irq,
+        input,
+        output,
+    }
+}
+
+fn reg(mem: &Memory, offset: u64) -> u8 {
+    mem.read_bytes(Address::from(BASE + offset), 1).unwrap()[0]
+}
+
+fn set_reg(mem: &mut Memory, offset: u64, value: u8) {
+    mem.write_bytes(&[value], Address::from(BASE + offset))
+        .unwrap();
+}
+
+#[test]
+fn ns16550a_receive_fifo() {
+    let Serial {
+        mut mem,
+        mut uart,
+        irq,
+        input,
+        ..
+    } = uart(0);
+
+    // Enable the fifos with a trigger level of 4 and the receive interrupt
+    set_reg(&mut mem, 2, 0x41);
+    set_reg(&mut mem, 1, 0x01);
+    assert_eq!(reg(&mem, 2), 0xC1);
+    assert!(!irq.is_raised());
+
+    for byte in b"abc" {
+        input.send(*byte).unwrap();
+    }
+    uart.update().unwrap();
+    assert_eq!(reg(&mem, 5) & 0x01, 0x01);
+    assert!(!irq.is_raised());
+
+    input.send(b'd').unwrap();
+    uart.update().unwrap();
+    assert!(irq.is_raised());
+    assert_eq!(reg(&mem, 2), 0xC4);
+    assert_eq!(reg(&mem, 0), b'a');
+    assert!(!irq.is_raised());
+
+    // Below the trigger level the remaining bytes raise a character timeout
+    while !irq.is_raised() {
+        uart.update().unwrap();
+    }
+    assert_eq!(reg(&mem, 2), 0xCC);
+    assert_eq!(reg(&mem, 0), b'b');
+    assert_eq!(reg(&mem, 0), b'c');
+    assert_eq!(reg(&mem, 0), b'd');
+    assert_eq!(reg(&mem, 5) & 0x01, 0);
+    assert!(!irq.is_raised());
+
+    // Bytes that do not fit wait for space instead of overrunning
+    for byte in 0..20 {
+        input.send(byte).unwrap();
+    }
+    uart.update().unwrap();
+    let received: Vec<u8> = (0..16).map(|_| reg(&mem, 0)).collect();
+    assert_eq!(received, (0..16).collect::<Vec<u8>>());
+    uart.update().unwrap();
+    assert_eq!(reg(&mem, 0), 16);
+    assert_eq!(reg(&mem, 5) & 0x02, 0);
+}
+
+#[test]
+fn ns16550a_transmit_interrupt() {
+    let Serial {
+        mut mem,
+        mut uart,
+        irq,
+        output,
+        ..
+    } = uart(2);
+
+    // Registers are 4 bytes apart and only use their lowest byte
+    set_reg(&mut mem, 7 << 2, 0x5A);
+    assert_eq!(reg(&mem, 7 << 2), 0x5A);
+    assert_eq!(reg(&mem, (7 << 2) + 1), 0);
+    assert_eq!(reg(&mem, 5 << 2), 0x60);
+
+    // Enabling the interrupt with an empty holding register raises it, reading the IIR clears it
+    set_reg(&mut mem, 1 << 2, 0x02);
+    assert!(irq.is_raised());
+    assert_eq!(reg(&mem, 2 << 2), 0x02);
+    assert!(!irq.is_raised());
+    assert_eq!(reg(&mem, 2 << 2), 0x01);
+
+    // Loopback keeps the output from being printed and raises the interrupt once it is sent
+    set_reg(&mut mem, 4 << 2, 0x1A);
+    assert_eq!(reg(&mem, 6 << 2) & 0xF0, 0x90);
+    set_reg(&mut mem, 0, b'x');
+    assert_eq!(reg(&mem, 5 << 2) & 0x60, 0);
+    uart.update().unwrap();
+    assert!(irq.is_raised());
+    assert_eq!(reg(&mem, 5 << 2), 0x61);
+    assert_eq!(reg(&mem, 0), b'x');
+    assert!(output.try_recv().is_err());
+
+    set_reg(&mut mem, 4 << 2, 0x0B);
+    set_reg(&mut mem, 0, b'y');
+    uart.update().unwrap();
+    assert_eq!(output.try_recv(), Ok(b'y'));
+
+    // The divisor latch replaces the data and interrupt enable registers
+    set_reg(&mut mem, 3 << 2, 0x83);
+    set_reg(&mut mem, 0, 0x01);
+    set_reg(&mut mem, 1 << 2, 0x00);
+    set_reg(&mut mem, 3 << 2, 0x03);
+    assert_eq!(reg(&mem, 1 << 2), 0x02);
+}
+
+#[test]
+fn ns16550a_peek() {
+    let Serial {
+        mut mem,
+        mut uart,
+        irq,
+        input,
+        ..
+    } = uart(0);
+    let peek =
+        |mem: &Memory, offset: u64| mem.peek_bytes(Address::from(BASE + offset), 1).unwrap()[0];
+
+    // A debugger sees the received byte and pending interrupt without taking them
+    set_reg(&mut mem, 1, 0x03);
+    input.send(b'a').unwrap();
+    uart.update().unwrap();
+    assert_eq!(peek(&mem, 0), b'a');
+    assert_eq!(peek(&mem, 0), b'a');
+    assert_eq!(peek(&mem, 2), 0x04);
+    assert_eq!(peek(&mem, 5) & 0x01, 0x01);
+    assert!(irq.is_raised());
+
+    assert_eq!(reg(&mem, 0), b'a');
+    assert_eq!(peek(&mem, 2), 0x02);
+    assert_eq!(peek(&mem, 2), 0x02);
+    assert_eq!(reg(&mem, 2), 0x02);
+    assert_eq!(peek(&mem, 2), 0x01);
+    assert!(!irq.is_raised());
+}
+
+#[test]
+fn telnet_backend() {
+    let mut backend = SocketBackend::bind_tcp(0).unwrap();
+    let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, backend.port().unwrap())).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // Connecting asks the client for character mode
+    while backend.read().is_some() {}
+    let mut negotiation = [0; 6];
+    client.read_exact(&mut negotiation).unwrap();
+    assert_eq!(negotiation, [255, 251, 1, 255, 251, 3]);
+
+    // Commands, escaped IACs and the byte after a carriage return are filtered out
+    client
+        .write_all(&[
+            b'a', b'\r', 0, 255, 253, 1, b'b', 255, 255, b'\r', b'\n', 255, 241,
+        ])
+        .unwrap();
+    let mut input = Vec::new();
+    while input.len() < 5 {
+        input.extend(backend.read());
+    }
+    assert_eq!(input, [b'a', b'\r', b'b', 255, b'\r']);
+    assert_eq!(backend.read(), None);
+
+    backend.write(&[b'c', 255]).unwrap();
+    let mut output = [0; 3];
+    client.read_exact(&mut output).unwrap();
+    assert_eq!(output, [b'c', 255, 255]);
+}
+
+#[test]
+fn file_backend() {
+    let path = std::env::temp_dir().join(format!("riscv_vm_serial_{}", std::process::id()));
+    let mut backend = FileBackend::create(&path).unwrap();
+    backend.write(b"hello").unwrap();
+    drop(backend);
+    let mut backend = FileBackend::append(&path).unwrap();
+    backend.write(b" world").unwrap();
+    assert_eq!(backend.read(), None);
+    assert_eq!(fs::read(&path).unwrap(), b"hello world");
+    fs::remove_file(path).unwrap();
+}
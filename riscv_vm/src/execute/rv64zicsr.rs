@@ -88,7 +88,7 @@ pub(super) fn csrwi(
     let privilege = hart.privilege();
     hart.get_csr_mut()
         .write_csr(csr, imm as u64, privilege, false)?;
-    Ok(ExecuteResult::Continue)
+    Ok(ExecuteResult::CsrUpdate(csr))
 }
 
 pub(super) fn csrri(
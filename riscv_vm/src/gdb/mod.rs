@@ -0,0 +1,630 @@
+//! A stub for the gdb remote serial protocol, it allows gdb to attach to a [`VMState`] over a
+//! local tcp port or a unix socket. Each hart is exposed as a thread (hart `n` is thread `n + 1`),
+//! breakpoints are kept by the stub and never written into guest memory.
+//!
+//! ```text
+//! (gdb) set architecture riscv:rv64
+//! (gdb) target remote localhost:1234
+//! ```
+
+mod packet;
+mod target;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::BTreeSet,
+    io::{self, ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hart::privilege::PrivilegeMode,
+    memory::address::Address,
+    vmstate::{ReverseError, ShutdownRequest, StopReason, VMError, VMState},
+};
+
+use self::packet::{decode_hex, encode_hex, parse_hex, Incoming, INTERRUPT};
+
+/// How many instructions to run between checks for a Ctrl-C from gdb while continuing.
+const INTERRUPT_POLL_INTERVAL: u64 = 1024;
+
+/// The largest amount of memory returned for a single `m` packet.
+const MAX_MEMORY_READ: u64 = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGKILL: u8 = 9;
+
+/// A listening gdb stub, gdb can connect to it once [`GdbServer::serve()`] is called.
+pub struct GdbServer {
+    listener: Listener,
+}
+
+#[derive(Debug)]
+pub enum GdbError {
+    Io(io::Error),
+    /// The vm errored while gdb was running it, gdb was told the target aborted and could still
+    /// inspect it until the session ended
+    Vm(VMError),
+    /// Reverse execution could not replay the recorded history, gdb was told the target aborted
+    /// and recording was turned off
+    Reverse(ReverseError),
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl GdbServer {
+    /// Listen for gdb on the given port on localhost.
+    pub fn bind_tcp(port: u16) -> Result<Self, GdbError> {
+        Ok(Self {
+            listener: Listener::Tcp(TcpListener::bind((Ipv4Addr::LOCALHOST, port))?),
+        })
+    }
+
+    /// Listen for gdb on a unix socket at the given path, the socket file is removed when the
+    /// server is dropped.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P) -> Result<Self, GdbError> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self {
+            listener: Listener::Unix(UnixListener::bind(&path)?, path),
+        })
+    }
+
+    /// Wait for gdb to connect and serve it until it detaches, kills the target or disconnects.
+    /// The vm is left in whatever state gdb left it in. If the vm errored while gdb ran it, the
+    /// last such error is returned once the session ends. An `ebreak` in the guest stops the vm
+    /// and is reported as a breakpoint while gdb is connected.
+    pub fn serve(&self, vm: &mut VMState) -> Result<(), GdbError> {
+        let halt = vm.set_halt_on_ebreak(true);
+        let result = self.serve_session(vm);
+        vm.set_halt_on_ebreak(halt);
+        result
+    }
+
+    fn serve_session(&self, vm: &mut VMState) -> Result<(), GdbError> {
+        let mut conn = match &self.listener {
+            Listener::Tcp(l) => Connection::Tcp(l.accept()?.0),
+            #[cfg(unix)]
+            Listener::Unix(l, _) => Connection::Unix(l.accept()?.0),
+        };
+
+        let mut session = GdbSession::new();
+
+        loop {
+            let data = match packet::read_packet(&mut conn) {
+                Ok(Incoming::Packet(data)) => data,
+                Ok(Incoming::Interrupt) => {
+                    let reply = session.stop_reply(Stop::Interrupt(session.thread));
+                    packet::write_packet(&mut conn, reply.as_bytes())?;
+                    continue;
+                }
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return session.finish(),
+                Err(e) => return Err(e.into()),
+            };
+
+            match session.handle_packet(vm, &String::from_utf8_lossy(&data)) {
+                Action::Reply(reply) => packet::write_packet(&mut conn, reply.as_bytes())?,
+                Action::Resume(resume) => {
+                    let stop = session.resume(vm, resume, || conn.poll_interrupt());
+                    let reply = session.stop_reply(stop);
+                    packet::write_packet(&mut conn, reply.as_bytes())?;
+                }
+                Action::Detach => {
+                    packet::write_packet(&mut conn, b"OK")?;
+                    return session.finish();
+                }
+                Action::Kill => return session.finish(),
+            }
+        }
+    }
+}
+
+impl Drop for GdbServer {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Connection {
+    /// Check, without blocking, whether gdb sent an interrupt. A closed connection also counts as
+    /// an interrupt so a running vm does not outlive its debugger.
+    fn poll_interrupt(&mut self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let mut buf = [0u8; 1];
+        let interrupted = match self.read(&mut buf) {
+            Ok(0) => true,
+            Ok(_) => buf[0] == INTERRUPT,
+            Err(e) => e.kind() != ErrorKind::WouldBlock,
+        };
+        self.set_nonblocking(false).is_err() || interrupted
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+impl From<io::Error> for GdbError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// The state gdb has set up during a connection, independent of the transport.
+struct GdbSession {
+    /// The hart (not the gdb thread id) register and memory accesses go to
+    thread: usize,
+    breakpoints: BTreeSet<Address>,
+    /// The last error the vm stopped with, returned from [`GdbServer::serve()`]
+    error: Option<GdbError>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Reply(String),
+    Resume(Resume),
+    Detach,
+    Kill,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Resume {
+    Step(usize),
+    Continue,
+    ReverseStep(usize),
+    ReverseContinue,
+}
+
+#[derive(Debug)]
+enum Stop {
+    Step(usize),
+    Breakpoint(usize),
+    Interrupt(usize),
+    /// All harts wait for an interrupt and nothing is armed to wake them
+    Idle(usize),
+    /// The guest requested a shutdown, gdb is told the process exited
+    Exit(ShutdownRequest),
+    /// Reverse execution reached the oldest recorded state
+    HistoryStart(usize),
+    Error(usize, VMError),
+    /// Reverse execution failed to replay the history
+    ReverseError(usize, ReverseError),
+}
+
+impl GdbSession {
+    fn new() -> Self {
+        Self {
+            thread: 0,
+            breakpoints: BTreeSet::new(),
+            error: None,
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), GdbError> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    fn handle_packet(&mut self, vm: &mut VMState, packet: &str) -> Action {
+        let Some(cmd) = packet.chars().next() else {
+            return Action::Reply(String::new());
+        };
+        let args = &packet[cmd.len_utf8()..];
+
+        let reply = match cmd {
+            '?' => Some(self.stop_reply(Stop::Step(self.thread))),
+            'q' => self.query(vm, args),
+            'H' => self.set_thread(vm, args),
+            'T' => Some(match parse_thread(args) {
+                Some(Some(h)) if h < vm.hart_count() => "OK".to_string(),
+                _ => "E01".to_string(),
+            }),
+            'g' => Some(self.read_registers(vm)),
+            'G' => self.write_registers(vm, args),
+            'p' => self.read_register(vm, args),
+            'P' => self.write_register(vm, args),
+            'm' => self.read_memory(vm, args),
+            'M' => self.write_memory(vm, args),
+            'Z' | 'z' => self.breakpoint(args, cmd == 'Z'),
+            'c' | 's' => {
+                if !args.is_empty() {
+                    let Some(addr) = parse_hex(args) else {
+                        return Action::Reply("E01".to_string());
+                    };
+                    vm.get_hart_mut(self.thread).unwrap().set_pc(addr.into());
+                }
+                return Action::Resume(if cmd == 's' {
+                    Resume::Step(self.thread)
+                } else {
+                    Resume::Continue
+                });
+            }
+            'b' => {
+                if !vm.history_enabled() {
+                    return Action::Reply("E01".to_string());
+                }
+                return match args {
+                    "s" => Action::Resume(Resume::ReverseStep(self.thread)),
+                    "c" => Action::Resume(Resume::ReverseContinue),
+                    _ => Action::Reply(String::new()),
+                };
+            }
+            'v' => return self.v_packet(vm, args),
+            'D' => return Action::Detach,
+            'k' => return Action::Kill,
+            _ => None,
+        };
+
+        Action::Reply(reply.unwrap_or_default())
+    }
+
+    fn query(&mut self, vm: &mut VMState, args: &str) -> Option<String> {
+        if args.starts_with("Supported") {
+            return Some(
+                concat!(
+                    "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;vContSupported+;",
+                    "ReverseStep+;ReverseContinue+"
+                )
+                .to_string(),
+            );
+        }
+
+        if let Some(annex) = args.strip_prefix("Xfer:features:read:") {
+            let (name, range) = annex.split_once(':')?;
+            if name != "target.xml" {
+                return Some("E00".to_string());
+            }
+            let (offset, length) = range.split_once(',')?;
+            let (offset, length) = (parse_hex(offset)? as usize, parse_hex(length)? as usize);
+            let Some(end) = offset.checked_add(length) else {
+                return Some("E01".to_string());
+            };
+
+            let xml = target::target_xml();
+            let chunk = xml.get(offset.min(xml.len())..end.min(xml.len()))?;
+            let more = end < xml.len();
+            return Some(format!("{}{}", if more { 'm' } else { 'l' }, chunk));
+        }
+
+        if let Some(thread) = args.strip_prefix("ThreadExtraInfo,") {
+            let hart = parse_thread(thread)??;
+            let hart = vm.get_hart(hart)?;
+            let privilege = match hart.privilege() {
+                PrivilegeMode::User => "U",
+                PrivilegeMode::Supervisor => "S",
+                PrivilegeMode::Machine => "M",
+            };
+            let info = format!("hart {} ({}-mode)", hart.get_hart_id(), privilege);
+            return Some(encode_hex(info.as_bytes()));
+        }
+
+        match args {
+            "C" => Some(format!("QC{:x}", self.thread + 1)),
+            "Attached" => Some("1".to_string()),
+            "fThreadInfo" => Some(format!(
+                "m{}",
+                (0..vm.hart_count())
+                    .map(|h| format!("{:x}", h + 1))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )),
+            "sThreadInfo" => Some("l".to_string()),
+            "Symbol::" => Some("OK".to_string()),
+            _ => None,
+        }
+    }
+
+    fn set_thread(&mut self, vm: &VMState, args: &str) -> Option<String> {
+        // Only one thread is tracked for both `Hg` and `Hc`, continuing always resumes all harts
+        let thread = args.get(1..)?;
+        match parse_thread(thread) {
+            Some(Some(h)) if h < vm.hart_count() => {
+                self.thread = h;
+                Some("OK".to_string())
+            }
+            Some(None) => Some("OK".to_string()),
+            _ => Some("E01".to_string()),
+        }
+    }
+
+    fn read_registers(&self, vm: &VMState) -> String {
+        let hart = vm.get_hart(self.thread).unwrap();
+        (0..=target::PC_REGNUM)
+            .map(|r| encode_hex(&target::read_register(hart, r).unwrap().to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&self, vm: &mut VMState, args: &str) -> Option<String> {
+        // The same registers `g` returns, x0 to pc
+        let Some(bytes) =
+            decode_hex(args).filter(|b| b.len() as u64 == (target::PC_REGNUM + 1) * 8)
+        else {
+            return Some("E01".to_string());
+        };
+        let hart = vm.get_hart_mut(self.thread).unwrap();
+        for (r, value) in bytes.chunks_exact(8).enumerate() {
+            let value = u64::from_le_bytes(value.try_into().unwrap());
+            target::write_register(hart, r as u64, value);
+        }
+        Some("OK".to_string())
+    }
+
+    fn read_register(&self, vm: &VMState, args: &str) -> Option<String> {
+        let hart = vm.get_hart(self.thread).unwrap();
+        Some(
+            match parse_hex(args).and_then(|r| target::read_register(hart, r)) {
+                Some(value) => encode_hex(&value.to_le_bytes()),
+                None => "E01".to_string(),
+            },
+        )
+    }
+
+    fn write_register(&self, vm: &mut VMState, args: &str) -> Option<String> {
+        let (regnum, value) = args.split_once('=')?;
+        let regnum = parse_hex(regnum)?;
+        let mut bytes = decode_hex(value)?;
+        bytes.resize(8, 0);
+        let value = u64::from_le_bytes(bytes.try_into().unwrap());
+
+        let hart = vm.get_hart_mut(self.thread).unwrap();
+        if target::write_register(hart, regnum, value) {
+            Some("OK".to_string())
+        } else {
+            Some("E01".to_string())
+        }
+    }
+
+    fn read_memory(&self, vm: &mut VMState, args: &str) -> Option<String> {
+        let (addr, length) = args.split_once(',')?;
+        let addr = parse_hex(addr)?;
+        let length = parse_hex(length)?.min(MAX_MEMORY_READ);
+        let Some(end) = addr.checked_add(length) else {
+            return Some("E01".to_string());
+        };
+
+        if let Ok(bytes) = vm.read_memory(self.thread, addr.into(), length as usize) {
+            return Some(encode_hex(&bytes));
+        }
+
+        // The range may cross into unmapped memory, gdb accepts a partial read
+        let mut bytes = Vec::new();
+        for a in addr..end {
+            match vm.read_memory(self.thread, a.into(), 1) {
+                Ok(b) => bytes.extend(b),
+                Err(_) => break,
+            }
+        }
+
+        if bytes.is_empty() {
+            Some("E14".to_string())
+        } else {
+            Some(encode_hex(&bytes))
+        }
+    }
+
+    fn write_memory(&self, vm: &mut VMState, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, length) = range.split_once(',')?;
+        let addr = parse_hex(addr)?;
+        let bytes = decode_hex(data)?;
+        if parse_hex(length)? != bytes.len() as u64 {
+            return Some("E01".to_string());
+        }
+
+        match vm.write_memory(self.thread, &bytes, addr.into()) {
+            Ok(_) => Some("OK".to_string()),
+            Err(_) => Some("E14".to_string()),
+        }
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?;
+        let addr: Address = parse_hex(parts.next()?)?.into();
+
+        // Both software and hardware breakpoints are handled by the stub, watchpoints are not
+        // supported.
+        if kind != "0" && kind != "1" {
+            return None;
+        }
+
+        if insert {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+        Some("OK".to_string())
+    }
+
+    fn v_packet(&mut self, vm: &mut VMState, args: &str) -> Action {
+        if args == "Cont?" {
+            return Action::Reply("vCont;c;C;s;S".to_string());
+        }
+
+        if args == "Kill" || args.starts_with("Kill;") {
+            return Action::Kill;
+        }
+
+        if let Some(actions) = args.strip_prefix("Cont;") {
+            // Harts can not be resumed individually, so a step on any thread takes precedence and
+            // everything else is treated as a continue.
+            let mut resume = None;
+            for action in actions.split(';') {
+                let (action, thread) = match action.split_once(':') {
+                    Some((a, t)) => (a, parse_thread(t)),
+                    None => (action, Some(None)),
+                };
+                let hart = match thread {
+                    Some(Some(h)) if h < vm.hart_count() => h,
+                    Some(None) => self.thread,
+                    _ => return Action::Reply("E01".to_string()),
+                };
+                match action.chars().next() {
+                    Some('s' | 'S') => {
+                        if !matches!(resume, Some(Resume::Step(_))) {
+                            resume = Some(Resume::Step(hart));
+                        }
+                    }
+                    Some('c' | 'C') => {
+                        resume.get_or_insert(Resume::Continue);
+                    }
+                    _ => return Action::Reply(String::new()),
+                }
+            }
+            return match resume {
+                Some(r) => Action::Resume(r),
+                None => Action::Reply("E01".to_string()),
+            };
+        }
+
+        Action::Reply(String::new())
+    }
+
+    /// Run the vm as requested by gdb, `interrupted` is polled every so often while continuing
+    /// and stops the vm when it returns true.
+    fn resume<F: FnMut() -> bool>(
+        &mut self,
+        vm: &mut VMState,
+        resume: Resume,
+        mut interrupted: F,
+    ) -> Stop {
+        match resume {
+            Resume::Step(hart) => {
+                self.thread = hart;
+                match vm.step_hart(hart, false) {
+                    Ok(_) => Stop::Step(hart),
+                    Err(VMError::MBreak) => Stop::Breakpoint(hart),
+                    Err(e) => Stop::Error(hart, e),
+                }
+            }
+            Resume::Continue => loop {
+                // While the harts wait for an interrupt every step may sleep, so poll more often
+                let idle = (0..vm.hart_count()).all(|h| {
+                    vm.is_hart_frozen(h) || vm.get_hart(h).unwrap().is_waiting_for_interrupt()
+                });
+                let budget = if idle { 1 } else { INTERRUPT_POLL_INTERVAL };
+                let breakpoints = &self.breakpoints;
+                match vm.run_until(Some(budget), |h| breakpoints.contains(&h.get_pc())) {
+                    StopReason::BudgetExhausted { .. } | StopReason::Woken { .. } => {
+                        if interrupted() {
+                            return Stop::Interrupt(self.thread);
+                        }
+                    }
+                    StopReason::Breakpoint { hart, .. } | StopReason::EBreak { hart, .. } => {
+                        return Stop::Breakpoint(hart)
+                    }
+                    // Watchpoints are not set through gdb, so it is only told the hart trapped
+                    StopReason::Watchpoint { hart, .. } => return Stop::Step(hart),
+                    StopReason::AllHartsIdle { hart, .. } => return Stop::Idle(hart),
+                    StopReason::Shutdown { request, .. } => return Stop::Exit(request),
+                    StopReason::Error { hart, error, .. } => return Stop::Error(hart, error),
+                }
+            },
+            Resume::ReverseStep(hart) => match vm.reverse_step(hart, 1) {
+                Ok(_) => Stop::Step(hart),
+                Err(ReverseError::HistoryDisabled | ReverseError::HistoryStart) => {
+                    Stop::HistoryStart(hart)
+                }
+                Err(e) => Stop::ReverseError(hart, e),
+            },
+            Resume::ReverseContinue => {
+                let breakpoints = &self.breakpoints;
+                match vm.reverse_continue(|h| breakpoints.contains(&h.get_pc())) {
+                    Ok(StopReason::Breakpoint { hart, .. }) => Stop::Breakpoint(hart),
+                    Ok(stop) => Stop::Step(stop.hart()),
+                    Err(ReverseError::HistoryDisabled | ReverseError::HistoryStart) => {
+                        Stop::HistoryStart(self.thread)
+                    }
+                    Err(e) => Stop::ReverseError(self.thread, e),
+                }
+            }
+        }
+    }
+
+    fn stop_reply(&mut self, stop: Stop) -> String {
+        let (signal, hart, extra) = match stop {
+            Stop::Step(h) => (SIGTRAP, h, ""),
+            Stop::Breakpoint(h) => (SIGTRAP, h, "swbreak:;"),
+            Stop::Interrupt(h) => (SIGINT, h, ""),
+            Stop::Idle(h) => (SIGTRAP, h, ""),
+            Stop::Exit(ShutdownRequest::Pass) => return "W00".to_string(),
+            Stop::Exit(ShutdownRequest::Fail(code)) => return format!("W{:02x}", code.min(0xff)),
+            Stop::Exit(ShutdownRequest::Reset) => return format!("X{:02x}", SIGKILL),
+            Stop::HistoryStart(h) => (SIGTRAP, h, "replaylog:begin;"),
+            Stop::Error(h, e) => {
+                self.error = Some(GdbError::Vm(e));
+                (SIGABRT, h, "")
+            }
+            Stop::ReverseError(h, e) => {
+                self.error = Some(GdbError::Reverse(e));
+                (SIGABRT, h, "")
+            }
+        };
+        self.thread = hart;
+        format!("T{:02x}thread:{:x};{}", signal, hart + 1, extra)
+    }
+}
+
+/// Parse a gdb thread id into a hart index, `Some(None)` means any or all threads.
+fn parse_thread(thread: &str) -> Option<Option<usize>> {
+    match thread {
+        "-1" | "0" => Some(None),
+        t => parse_hex(t)
+            .and_then(|t| (t as usize).checked_sub(1))
+            .map(Some),
+    }
+}
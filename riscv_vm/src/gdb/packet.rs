@@ -0,0 +1,102 @@
+use std::io::{self, ErrorKind, Read, Write};
+
+/// The byte gdb sends out of band to interrupt a running target.
+pub(super) const INTERRUPT: u8 = 0x03;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Incoming {
+    Packet(Vec<u8>),
+    Interrupt,
+}
+
+pub(super) fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+/// Read bytes until a full packet or an interrupt is received, acks are consumed and a packet
+/// with a bad checksum is nacked and skipped.
+pub(super) fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Incoming> {
+    loop {
+        match read_byte(stream)? {
+            b'$' => {}
+            INTERRUPT => return Ok(Incoming::Interrupt),
+            // Acks and line noise
+            _ => continue,
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                b'#' => break,
+                b'}' => data.push(read_byte(stream)? ^ 0x20),
+                b => data.push(b),
+            }
+        }
+
+        let sum = [read_byte(stream)?, read_byte(stream)?];
+        let sum = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok());
+
+        if sum == Some(checksum(&data)) {
+            stream.write_all(b"+")?;
+            stream.flush()?;
+            return Ok(Incoming::Packet(data));
+        } else {
+            stream.write_all(b"-")?;
+            stream.flush()?;
+        }
+    }
+}
+
+/// Frame and send a packet, the data is escaped where needed.
+pub(super) fn write_packet<S: Write>(stream: &mut S, data: &[u8]) -> io::Result<()> {
+    let data = escape(data);
+    let mut buf = Vec::with_capacity(data.len() + 4);
+    buf.push(b'$');
+    buf.extend_from_slice(&data);
+    buf.push(b'#');
+    buf.extend_from_slice(format!("{:02x}", checksum(&data)).as_bytes());
+    stream.write_all(&buf)?;
+    stream.flush()
+}
+
+pub(super) fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for b in data {
+        match b {
+            b'$' | b'#' | b'}' | b'*' => {
+                out.push(b'}');
+                out.push(b ^ 0x20);
+            }
+            b => out.push(*b),
+        }
+    }
+    out
+}
+
+pub(super) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(super) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..(i + 2))?, 16).ok())
+        .collect()
+}
+
+pub(super) fn parse_hex(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+fn read_byte<S: Read>(stream: &mut S) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    match stream.read(&mut buf)? {
+        0 => Err(ErrorKind::UnexpectedEof.into()),
+        _ => Ok(buf[0]),
+    }
+}
@@ -0,0 +1,149 @@
+//! Register numbering and the target description gdb uses to learn about them, the numbers
+//! follow gdb's own riscv numbering so the stock register names and groups work.
+
+use std::fmt::Write;
+
+#[cfg(feature = "float")]
+use softfloat_wrapper::{Float, F64};
+
+#[cfg(feature = "float")]
+use crate::hart::registers::FloatRegister;
+use crate::hart::{privilege::PrivilegeMode, registers::IntRegister, CsrAddress, Hart};
+
+pub(super) const PC_REGNUM: u64 = 32;
+pub(super) const FIRST_FPR_REGNUM: u64 = 33;
+pub(super) const FIRST_CSR_REGNUM: u64 = 65;
+pub(super) const PRIV_REGNUM: u64 = FIRST_CSR_REGNUM + 4096;
+
+#[cfg(feature = "float")]
+const FLOAT_CSRS: [u16; 3] = [0x001, 0x002, 0x003];
+
+pub(super) fn target_xml() -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\"?>\n");
+    xml.push_str("<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n");
+    xml.push_str("<target version=\"1.0\">\n");
+    xml.push_str("<architecture>riscv:rv64</architecture>\n");
+
+    xml.push_str("<feature name=\"org.gnu.gdb.riscv.cpu\">\n");
+    for i in 0..32 {
+        let ty = match i {
+            1 => "code_ptr",
+            2 | 3 | 4 | 8 => "data_ptr",
+            _ => "int",
+        };
+        let name = IntRegister::from(i as u32).abi_name();
+        reg(&mut xml, name, i, ty, "general");
+    }
+    reg(&mut xml, "pc", PC_REGNUM, "code_ptr", "general");
+    xml.push_str("</feature>\n");
+
+    #[cfg(feature = "float")]
+    {
+        xml.push_str("<feature name=\"org.gnu.gdb.riscv.fpu\">\n");
+        for i in 0..32 {
+            let name = FloatRegister::from(i as u32).abi_name();
+            reg(&mut xml, name, FIRST_FPR_REGNUM + i, "ieee_double", "float");
+        }
+        for addr in FLOAT_CSRS {
+            let name = CsrAddress::from(addr).name().unwrap();
+            reg(&mut xml, &name, csr_regnum(addr), "int", "float");
+        }
+        xml.push_str("</feature>\n");
+    }
+
+    xml.push_str("<feature name=\"org.gnu.gdb.riscv.csr\">\n");
+    // The floating point csrs are part of the fpu feature
+    for addr in CsrAddress::named().filter(|a| !(0x001..=0x003).contains(&u16::from(*a))) {
+        let name = addr.name().unwrap();
+        reg(&mut xml, &name, csr_regnum(addr.into()), "int", "csr");
+    }
+    xml.push_str("</feature>\n");
+
+    xml.push_str("<feature name=\"org.gnu.gdb.riscv.virtual\">\n");
+    reg(&mut xml, "priv", PRIV_REGNUM, "int", "general");
+    xml.push_str("</feature>\n");
+
+    xml.push_str("</target>\n");
+    xml
+}
+
+fn reg(xml: &mut String, name: &str, regnum: u64, ty: &str, group: &str) {
+    writeln!(
+        xml,
+        "<reg name=\"{}\" bitsize=\"64\" regnum=\"{}\" type=\"{}\" group=\"{}\"/>",
+        name, regnum, ty, group
+    )
+    .unwrap();
+}
+
+fn csr_regnum(addr: u16) -> u64 {
+    FIRST_CSR_REGNUM + addr as u64
+}
+
+/// Read a register by its gdb register number, None if the register does not exist.
+pub(super) fn read_register(hart: &Hart, regnum: u64) -> Option<u64> {
+    match regnum {
+        0..=31 => Some(hart.get_int_reg(IntRegister::from(regnum as u32)) as u64),
+        PC_REGNUM => Some(hart.get_pc().into()),
+        #[cfg(feature = "float")]
+        33..=64 => Some(
+            hart.get_f64_reg(FloatRegister::from((regnum - FIRST_FPR_REGNUM) as u32))
+                .to_bits(),
+        ),
+        PRIV_REGNUM => Some(hart.privilege() as u64),
+        r if (FIRST_CSR_REGNUM..PRIV_REGNUM).contains(&r) => Some(
+            hart.get_csr()
+                .get_csr(CsrAddress::from((r - FIRST_CSR_REGNUM) as u16)),
+        ),
+        _ => None,
+    }
+}
+
+/// Write a register by its gdb register number, returns false if the register does not exist
+/// or can not be written.
+pub(super) fn write_register(hart: &mut Hart, regnum: u64, value: u64) -> bool {
+    match regnum {
+        0..=31 => {
+            hart.set_int_reg(IntRegister::from(regnum as u32), value as i64);
+            true
+        }
+        PC_REGNUM => {
+            hart.set_pc(value.into());
+            true
+        }
+        #[cfg(feature = "float")]
+        33..=64 => {
+            hart.set_f64_reg(
+                FloatRegister::from((regnum - FIRST_FPR_REGNUM) as u32),
+                F64::from_bits(value),
+            );
+            true
+        }
+        PRIV_REGNUM => match value {
+            0b00 => {
+                hart.set_privilege(PrivilegeMode::User);
+                true
+            }
+            0b01 => {
+                hart.set_privilege(PrivilegeMode::Supervisor);
+                true
+            }
+            0b11 => {
+                hart.set_privilege(PrivilegeMode::Machine);
+                true
+            }
+            _ => false,
+        },
+        r if (FIRST_CSR_REGNUM..PRIV_REGNUM).contains(&r) => hart
+            .get_csr_mut()
+            .write_csr(
+                CsrAddress::from((r - FIRST_CSR_REGNUM) as u16),
+                value,
+                PrivilegeMode::Machine,
+                false,
+            )
+            .is_ok(),
+        _ => false,
+    }
+}
@@ -0,0 +1,305 @@
+use std::io::{Cursor, Read, Write};
+
+use crate::{
+    vmstate::{ReverseError, SnapshotError, VMSettings, VMState, VMStateBuilder},
+    KB,
+};
+
+use super::{
+    packet::{self, Incoming},
+    Action, GdbError, GdbSession, Resume, Stop,
+};
+
+/// Reads from a fixed input and records everything written
+struct MockStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn vm() -> VMState {
+    VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(2)
+        .build()
+        .unwrap()
+}
+
+fn reply(session: &mut GdbSession, vm: &mut VMState, packet: &str) -> String {
+    match session.handle_packet(vm, packet) {
+        Action::Reply(r) => r,
+        a => panic!("expected reply, got {:?}", a),
+    }
+}
+
+#[test]
+fn read_packet() {
+    let mut stream = MockStream {
+        input: Cursor::new(b"+$g#67".to_vec()),
+        output: Vec::new(),
+    };
+    assert_eq!(
+        packet::read_packet(&mut stream).unwrap(),
+        Incoming::Packet(b"g".to_vec())
+    );
+    assert_eq!(stream.output, b"+");
+}
+
+#[test]
+fn read_packet_bad_checksum() {
+    let mut stream = MockStream {
+        input: Cursor::new(b"$g#00$?#3f".to_vec()),
+        output: Vec::new(),
+    };
+    assert_eq!(
+        packet::read_packet(&mut stream).unwrap(),
+        Incoming::Packet(b"?".to_vec())
+    );
+    assert_eq!(stream.output, b"-+");
+}
+
+#[test]
+fn read_interrupt() {
+    let mut stream = MockStream {
+        input: Cursor::new(vec![0x03]),
+        output: Vec::new(),
+    };
+    assert_eq!(
+        packet::read_packet(&mut stream).unwrap(),
+        Incoming::Interrupt
+    );
+}
+
+#[test]
+fn write_packet_escapes() {
+    let mut out = Vec::new();
+    packet::write_packet(&mut out, b"a#b").unwrap();
+    assert_eq!(out, b"$a}\x03b#43");
+}
+
+#[test]
+fn hex_roundtrip() {
+    assert_eq!(packet::encode_hex(&[0x00, 0xAB, 0x10]), "00ab10");
+    assert_eq!(packet::decode_hex("00ab10"), Some(vec![0x00, 0xAB, 0x10]));
+    assert_eq!(packet::decode_hex("0"), None);
+    assert_eq!(packet::decode_hex("zz"), None);
+}
+
+#[test]
+fn threads() {
+    let mut vm = vm();
+    let mut session = GdbSession::new();
+    assert_eq!(reply(&mut session, &mut vm, "qfThreadInfo"), "m1,2");
+    assert_eq!(reply(&mut session, &mut vm, "qsThreadInfo"), "l");
+    assert_eq!(reply(&mut session, &mut vm, "Hg2"), "OK");
+    assert_eq!(reply(&mut session, &mut vm, "qC"), "QC2");
+    assert_eq!(reply(&mut session, &mut vm, "Hg3"), "E01");
+    assert_eq!(reply(&mut session, &mut vm, "T1"), "OK");
+    assert_eq!(reply(&mut session, &mut vm, "T3"), "E01");
+}
+
+#[test]
+fn registers() {
+    let mut vm = vm();
+    let mut session = GdbSession::new();
+    assert_eq!(reply(&mut session, &mut vm, "p20"), "0000008000000000");
+    assert_eq!(reply(&mut session, &mut vm, "P5=efbeadde00000000"), "OK");
+    assert_eq!(reply(&mut session, &mut vm, "p5"), "efbeadde00000000");
+    // x0 is hardwired to zero
+    assert_eq!(reply(&mut session, &mut vm, "P0=0100000000000000"), "OK");
+    assert_eq!(reply(&mut session, &mut vm, "p0"), "0000000000000000");
+    // mscratch, 65 + 0x340
+    assert_eq!(reply(&mut session, &mut vm, "P381=2a00000000000000"), "OK");
+    assert_eq!(reply(&mut session, &mut vm, "p381"), "2a00000000000000");
+    // mhartid is read only
+    assert_eq!(reply(&mut session, &mut vm, "Pf55=0100000000000000"), "E01");
+
+    let regs = reply(&mut session, &mut vm, "g");
+    assert_eq!(regs.len(), 33 * 16);
+    assert_eq!(&regs[(5 * 16)..(6 * 16)], "efbeadde00000000");
+
+    // A register blob that is not exactly x0 to pc is rejected as a whole
+    let mut short = regs.clone();
+    short.replace_range((5 * 16)..(6 * 16), "0100000000000000");
+    short.truncate(32 * 16);
+    assert_eq!(reply(&mut session, &mut vm, &format!("G{}", short)), "E01");
+    assert_eq!(reply(&mut session, &mut vm, &format!("G{}00", regs)), "E01");
+    assert_eq!(reply(&mut session, &mut vm, "p5"), "efbeadde00000000");
+    let mut regs = regs;
+    regs.replace_range((5 * 16)..(6 * 16), "0100000000000000");
+    assert_eq!(reply(&mut session, &mut vm, &format!("G{}", regs)), "OK");
+    assert_eq!(reply(&mut session, &mut vm, "p5"), "0100000000000000");
+}
+
+#[test]
+fn memory() {
+    let mut vm = vm();
+    let mut session = GdbSession::new();
+    assert_eq!(reply(&mut session, &mut vm, "M80000010,4:13050000"), "OK");
+    assert_eq!(reply(&mut session, &mut vm, "m80000010,4"), "13050000");
+    assert_eq!(reply(&mut session, &mut vm, "m0,4"), "E14");
+    assert_eq!(reply(&mut session, &mut vm, "mfffffffffffffffe,4"), "E01");
+}
+
+#[test]
+fn target_description() {
+    let mut vm = vm();
+    let mut session = GdbSession::new();
+    let first = reply(&mut session, &mut vm, "qXfer:features:read:target.xml:0,f");
+    assert_eq!(first, "m<?xml version=\"");
+    let all = reply(
+        &mut session,
+        &mut vm,
+        "qXfer:features:read:target.xml:0,ffff",
+    );
+    assert!(all.starts_with('l'));
+    assert!(all.contains("<reg name=\"mstatus\" bitsize=\"64\" regnum=\"833\""));
+    let overflow = reply(
+        &mut session,
+        &mut vm,
+        "qXfer:features:read:target.xml:1,ffffffffffffffff",
+    );
+    assert_eq!(overflow, "E01");
+}
+
+#[test]
+fn step_and_breakpoint() {
+    let mut vm = vm();
+    let mut session = GdbSession::new();
+    // addi a0, a0, 1 ; addi a0, a0, 1 ; jal x0, -8
+    for (i, inst) in [0x00150513u32, 0x00150513, 0xff9ff06f].iter().enumerate() {
+        vm.write_memory(
+            0,
+            &inst.to_le_bytes(),
+            (0x80000000u64 + i as u64 * 4).into(),
+        )
+        .unwrap();
+    }
+
+    assert_eq!(
+        session.handle_packet(&mut vm, "vCont;s:1;c"),
+        Action::Resume(Resume::Step(0))
+    );
+    let stop = session.resume(&mut vm, Resume::Step(0), || false);
+    assert_eq!(session.stop_reply(stop), "T05thread:1;");
+    assert_eq!(reply(&mut session, &mut vm, "p20"), "0400008000000000");
+
+    assert_eq!(reply(&mut session, &mut vm, "Z0,80000008,4"), "OK");
+    let stop = session.resume(&mut vm, Resume::Continue, || false);
+    assert!(matches!(stop, Stop::Breakpoint(_)));
+    assert_eq!(vm.get_hart(0).unwrap().get_pc(), 0x80000008u64.into());
+
+    assert_eq!(reply(&mut session, &mut vm, "z0,80000008,4"), "OK");
+    let stop = session.resume(&mut vm, Resume::Continue, || true);
+    assert!(matches!(stop, Stop::Interrupt(_)));
+}
+
+#[test]
+fn ebreak_stops() {
+    let mut vm = vm();
+    let mut session = GdbSession::new();
+    // addi a0, a0, 1 ; c.ebreak
+    vm.write_memory(0, &0x00150513u32.to_le_bytes(), 0x80000000u64.into())
+        .unwrap();
+    vm.write_memory(0, &0x9002u16.to_le_bytes(), 0x80000004u64.into())
+        .unwrap();
+
+    assert!(!vm.set_halt_on_ebreak(true));
+    let stop = session.resume(&mut vm, Resume::Continue, || false);
+    assert!(matches!(stop, Stop::Breakpoint(0)));
+    assert_eq!(vm.get_hart(0).unwrap().get_pc(), 0x80000004u64.into());
+    assert!(session.finish().is_ok());
+}
+
+#[test]
+fn reverse_step() {
+    let mut vm = vm();
+    let mut session = GdbSession::new();
+    assert_eq!(reply(&mut session, &mut vm, "bs"), "E01");
+
+    // addi a0, a0, 1 ; addi a0, a0, 1 ; jal x0, -8
+    for (i, inst) in [0x00150513u32, 0x00150513, 0xff9ff06f].iter().enumerate() {
+        vm.write_phys(&inst.to_le_bytes(), (0x80000000u64 + i as u64 * 4).into())
+            .unwrap();
+    }
+    vm.enable_history(2, 8);
+    vm.step_hart(0, false).unwrap();
+
+    assert_eq!(
+        session.handle_packet(&mut vm, "bs"),
+        Action::Resume(Resume::ReverseStep(0))
+    );
+    let stop = session.resume(&mut vm, Resume::ReverseStep(0), || false);
+    assert_eq!(session.stop_reply(stop), "T05thread:1;");
+    assert_eq!(vm.get_hart(0).unwrap().get_pc(), 0x80000000u64.into());
+
+    let stop = session.resume(&mut vm, Resume::ReverseStep(0), || false);
+    assert_eq!(session.stop_reply(stop), "T05thread:1;replaylog:begin;");
+}
+
+#[test]
+fn reverse_error_aborts() {
+    let mut session = GdbSession::new();
+    let stop = Stop::ReverseError(1, ReverseError::Snapshot(SnapshotError::Corrupt));
+    assert_eq!(session.stop_reply(stop), "T06thread:2;");
+    assert!(matches!(
+        session.finish(),
+        Err(GdbError::Reverse(ReverseError::Snapshot(_)))
+    ));
+}
+
+#[test]
+fn continue_until_exit_or_idle() {
+    let settings = VMSettings {
+        shutdown_enable: true,
+        ..Default::default()
+    };
+    let mut exiting = VMStateBuilder::<{ 4 * KB }>::new(settings)
+        .set_hart_count(1)
+        .build()
+        .unwrap();
+    let mut session = GdbSession::new();
+    // lui t0, 0x100 ; lui t1, 0x33 ; addi t1, t1, 0x333 ; sw t1, 0(t0) ; jal x0, 0
+    let program = [
+        0x001002b7u32,
+        0x00033337,
+        0x33330313,
+        0x0062a023,
+        0x0000006f,
+    ];
+    for (i, inst) in program.iter().enumerate() {
+        exiting
+            .write_memory(
+                0,
+                &inst.to_le_bytes(),
+                (0x80000000u64 + i as u64 * 4).into(),
+            )
+            .unwrap();
+    }
+    let stop = session.resume(&mut exiting, Resume::Continue, || false);
+    assert_eq!(session.stop_reply(stop), "W03");
+
+    let mut vm = vm();
+    // wfi
+    for hart in 0..2 {
+        vm.write_memory(hart, &0x10500073u32.to_le_bytes(), 0x80000000u64.into())
+            .unwrap();
+    }
+    let stop = session.resume(&mut vm, Resume::Continue, || false);
+    assert!(matches!(stop, Stop::Idle(_)));
+    assert_eq!(session.stop_reply(stop), "T05thread:2;");
+}
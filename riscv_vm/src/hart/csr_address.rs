@@ -1,5 +1,5 @@
 use std::{
-    fmt::Debug,
+    fmt::{Debug, Display},
     ops::{Add, AddAssign, Sub},
 };
 
@@ -8,6 +8,57 @@ use super::privilege::PrivilegeMode;
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct CsrAddress(u16);
 
+/// Names of the csrs the csr holder implements, the pmp csrs are named separately
+const CSR_NAMES: &[(u16, &str)] = &[
+    (0x001, "fflags"),
+    (0x002, "frm"),
+    (0x003, "fcsr"),
+    (0xC00, "cycle"),
+    (0xC01, "time"),
+    (0xC02, "instret"),
+    (0x100, "sstatus"),
+    (0x104, "sie"),
+    (0x105, "stvec"),
+    (0x106, "scounteren"),
+    (0x10A, "senvcfg"),
+    (0x140, "sscratch"),
+    (0x141, "sepc"),
+    (0x142, "scause"),
+    (0x143, "stval"),
+    (0x144, "sip"),
+    (0x150, "siselect"),
+    (0x151, "sireg"),
+    (0x15C, "stopei"),
+    (0x180, "satp"),
+    (0xDB0, "stopi"),
+    (0xF11, "mvendorid"),
+    (0xF12, "marchid"),
+    (0xF13, "mimpid"),
+    (0xF14, "mhartid"),
+    (0xF15, "mconfigptr"),
+    (0x300, "mstatus"),
+    (0x301, "misa"),
+    (0x302, "medeleg"),
+    (0x303, "mideleg"),
+    (0x304, "mie"),
+    (0x305, "mtvec"),
+    (0x306, "mcounteren"),
+    (0x30A, "menvcfg"),
+    (0x320, "mcountinhibit"),
+    (0x340, "mscratch"),
+    (0x341, "mepc"),
+    (0x342, "mcause"),
+    (0x343, "mtval"),
+    (0x344, "mip"),
+    (0x350, "miselect"),
+    (0x351, "mireg"),
+    (0x35C, "mtopei"),
+    (0xFB0, "mtopi"),
+    (0x747, "mseccfg"),
+    (0xB00, "mcycle"),
+    (0xB02, "minstret"),
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CsrType {
     StandardRW,
@@ -22,6 +73,16 @@ impl Debug for CsrAddress {
     }
 }
 
+/// Prints the name of the csr if it has one and its address in hex otherwise
+impl Display for CsrAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
 impl CsrAddress {
     pub const fn new(addr: u16) -> Self {
         Self(addr & 0xFFF)
@@ -74,6 +135,49 @@ impl CsrAddress {
         }
     }
 
+    /// The standard name of this csr, if it is one the vm implements
+    pub fn name(&self) -> Option<String> {
+        match self.0 {
+            i @ 0x3A0..=0x3AF if i % 2 == 0 => Some(format!("pmpcfg{}", i - 0x3A0)),
+            i @ 0x3B0..=0x3EF => Some(format!("pmpaddr{}", i - 0x3B0)),
+            i => CSR_NAMES
+                .iter()
+                .find(|(a, _)| *a == i)
+                .map(|(_, n)| n.to_string()),
+        }
+    }
+
+    /// The inverse of [`CsrAddress::name()`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(i) = name.strip_prefix("pmpcfg") {
+            return i
+                .parse::<u16>()
+                .ok()
+                .filter(|i| *i < 16 && i % 2 == 0)
+                .map(|i| Self(0x3A0 + i));
+        }
+        if let Some(i) = name.strip_prefix("pmpaddr") {
+            return i
+                .parse::<u16>()
+                .ok()
+                .filter(|i| *i < 64)
+                .map(|i| Self(0x3B0 + i));
+        }
+        CSR_NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(a, _)| Self(*a))
+    }
+
+    /// All csrs that have a name
+    pub fn named() -> impl Iterator<Item = CsrAddress> {
+        CSR_NAMES
+            .iter()
+            .map(|(a, _)| Self(*a))
+            .chain((0x3A0..=0x3AE).step_by(2).map(Self))
+            .chain((0x3B0..=0x3EF).map(Self))
+    }
+
     pub fn get_privilege(&self) -> PrivilegeMode {
         match (self.0 & 0b001100000000) >> 8 {
             0b00 => PrivilegeMode::User,
@@ -5,7 +5,11 @@ use softfloat_wrapper::ExceptionFlags;
 use crate::{
     execute::ExecuteError,
     memory::{address::Address, paging::Satp, pmp::PMP},
-    vmstate::timer::{MTimer, TimerRef},
+    vmstate::{
+        imsic::FileRef,
+        timer::{MTimer, TimerRef},
+        SnapshotError, SnapshotReader, SnapshotWriter,
+    },
 };
 
 #[cfg(feature = "float")]
@@ -16,7 +20,7 @@ use super::{
     csr_address::CsrType,
     isa::Isa,
     privilege::{self, PrivilegeMode},
-    trap::{Exception, Interrupt, InterruptInternal},
+    trap::{Exception, Interrupt, InterruptInternal, INTERRUPT_PRIORITY},
     CsrAddress,
 };
 use std::{
@@ -56,6 +60,8 @@ pub enum FFlags {
 
 const S_INTERRUPT_MASK: u64 = 0b0000_0010_0010_0010;
 const TOGGLEABLE_INTERRUPTS: u64 = 0b0000_1010_0010_0000;
+/// `miselect` and `siselect` select registers 0 through 0xFFF
+const ISELECT_MASK: u64 = 0xFFF;
 
 pub struct CsrHolder {
     // UserMode
@@ -102,6 +108,11 @@ pub struct CsrHolder {
     pub(in crate::hart) mip: Rc<Mutex<BitFlags<InterruptInternal>>>,
     menvcfg: u64,
     mseccfg: u64,
+    miselect: u64,
+    siselect: u64,
+    /// The machine and supervisor level imsic interrupt files of this hart, `*ireg` accesses
+    /// their registers and `*topei` claims their interrupts
+    interrupt_files: Option<(FileRef, FileRef)>,
 
     mcycle: u64,
     minstret: u64,
@@ -218,6 +229,8 @@ impl Debug for CsrHolder {
             .field("mtval", &self.mtval)
             .field("menvcfg", &self.menvcfg)
             .field("mseccfg", &self.mseccfg)
+            .field("miselect", &self.miselect)
+            .field("siselect", &self.siselect)
             .field("mcycle", &self.mcycle)
             .field("minstret", &self.minstret)
             .field("mcounterinhibit", &self.mcounterinhibit)
@@ -274,6 +287,9 @@ impl CsrHolder {
             mip: Rc::new(Mutex::new(InterruptInternal::empty())),
             menvcfg: 0,
             mseccfg: 0,
+            miselect: 0,
+            siselect: 0,
+            interrupt_files: None,
             mcycle: 0,
             minstret: 0,
             mcounterinhibit: Counters::empty(),
@@ -303,6 +319,10 @@ impl CsrHolder {
         self.misa
     }
 
+    pub(crate) fn get_instret(&self) -> u64 {
+        self.minstret
+    }
+
     pub(in crate::hart) fn inc_instret(&mut self, value: u64) {
         if !self.mcounterinhibit.contains(Counters::InstRet) {
             self.minstret += value;
@@ -357,6 +377,182 @@ impl CsrHolder {
         flags.set();
     }
 
+    pub(crate) fn set_interrupt_files(&mut self, m_file: FileRef, s_file: FileRef) {
+        self.interrupt_files = Some((m_file, s_file));
+    }
+
+    fn interrupt_file(&self, privilege: PrivilegeMode) -> Option<&FileRef> {
+        let (m_file, s_file) = self.interrupt_files.as_ref()?;
+        Some(match privilege {
+            PrivilegeMode::Machine => m_file,
+            _ => s_file,
+        })
+    }
+
+    /// The register selected by `*iselect`, the priorities of the major interrupts at 0x30 to
+    /// 0x3F are not configurable and read as 0 like every register without an interrupt file
+    fn read_ireg(&self, privilege: PrivilegeMode) -> u64 {
+        let select = match privilege {
+            PrivilegeMode::Machine => self.miselect,
+            _ => self.siselect,
+        };
+        self.interrupt_file(privilege)
+            .map_or(0, |file| file.lock().unwrap().read_indirect(select))
+    }
+
+    fn write_ireg(&self, privilege: PrivilegeMode, value: u64) {
+        let select = match privilege {
+            PrivilegeMode::Machine => self.miselect,
+            _ => self.siselect,
+        };
+        if let Some(file) = self.interrupt_file(privilege) {
+            file.lock().unwrap().write_indirect(select, value);
+        }
+    }
+
+    fn topei(&self, privilege: PrivilegeMode) -> u64 {
+        self.interrupt_file(privilege)
+            .map_or(0, |file| file.lock().unwrap().topei())
+    }
+
+    /// Any write to `*topei` claims the top interrupt of the interrupt file
+    fn claim_external(&self, privilege: PrivilegeMode) {
+        if let Some(file) = self.interrupt_file(privilege) {
+            file.lock().unwrap().claim();
+        }
+    }
+
+    /// The highest priority interrupt that is pending and enabled and traps to `privilege`,
+    /// regardless of whether interrupts are enabled in mstatus
+    pub(in crate::hart) fn top_interrupt(
+        &self,
+        privilege: PrivilegeMode,
+    ) -> Option<InterruptInternal> {
+        let pending = *self.mip.lock().unwrap();
+        let interrupts = match privilege {
+            PrivilegeMode::Machine => pending & self.mie & !self.mideleg,
+            _ => pending & self.sie & self.mideleg,
+        };
+        INTERRUPT_PRIORITY
+            .into_iter()
+            .find(|i| interrupts.contains(*i))
+    }
+
+    /// The value of `*topi`, major interrupt priorities are not configurable so the priority
+    /// field is 1 for any interrupt
+    fn topi(&self, privilege: PrivilegeMode) -> u64 {
+        self.top_interrupt(privilege)
+            .map_or(0, |i| i.get_code() << 16 | 1)
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        #[cfg(feature = "float")]
+        {
+            snapshot.write_u64(self.fflags.bits());
+            snapshot.write_u64(self.frm as u64);
+        }
+
+        snapshot.write_u64(self.sie.bits());
+        snapshot.write_u64(self.stvec.to_bits());
+        snapshot.write_u64(self.scounteren.bits() as u64);
+        snapshot.write_u64(self.senvcfg);
+        snapshot.write_u64(self.sscratch);
+        snapshot.write_address(self.sepc);
+        snapshot.write_u64(self.scause);
+        snapshot.write_u64(self.stval);
+        snapshot.write_u64(self.satp.to_bits());
+
+        snapshot.write_u64(self.mhartid);
+        snapshot.write_u64(self.mconfigptr);
+        snapshot.write_u64(self.misa.bits());
+        snapshot.write_u64(self.medeleg.bits());
+        snapshot.write_u64(self.mideleg.bits());
+        snapshot.write_u64(self.mie.bits());
+        snapshot.write_u64(self.mtvec.to_bits());
+        snapshot.write_u64(self.mcounteren.bits() as u64);
+        snapshot.write_u64(self.mscratch);
+        snapshot.write_address(self.mepc);
+        snapshot.write_u64(self.mcause);
+        snapshot.write_u64(self.mtval);
+        snapshot.write_u64(self.mip.lock().unwrap().bits());
+        snapshot.write_u64(self.menvcfg);
+        snapshot.write_u64(self.mseccfg);
+        snapshot.write_u64(self.mcycle);
+        snapshot.write_u64(self.minstret);
+        snapshot.write_u64(self.mcounterinhibit.bits() as u64);
+        snapshot.write_u64(self.miselect);
+        snapshot.write_u64(self.siselect);
+
+        self.pmp.save(snapshot);
+
+        let mut csrs: Vec<_> = self.csr.iter().collect();
+        csrs.sort();
+        snapshot.write_u64(csrs.len() as u64);
+        for (addr, value) in csrs {
+            snapshot.write_u64(u16::from(*addr) as u64);
+            snapshot.write_u64(*value);
+        }
+
+        snapshot.write_u64(self.status.to_m_bits());
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        #[cfg(feature = "float")]
+        {
+            self.fflags = FFlags::from_bits_truncate(snapshot.read_u64()?);
+            self.frm = RoundingMode::try_from(snapshot.read_u64()? as u32)
+                .map_err(|_| SnapshotError::Corrupt)?;
+        }
+
+        self.sie = InterruptInternal::from_bits_truncate(snapshot.read_u64()?);
+        self.stvec.update_from_bits(snapshot.read_u64()?);
+        self.scounteren = Counters::from_bits_truncate(snapshot.read_u64()? as u32);
+        self.senvcfg = snapshot.read_u64()?;
+        self.sscratch = snapshot.read_u64()?;
+        self.sepc = snapshot.read_address()?;
+        self.scause = snapshot.read_u64()?;
+        self.stval = snapshot.read_u64()?;
+        self.satp = Satp::from_bits(snapshot.read_u64()?).ok_or(SnapshotError::Corrupt)?;
+
+        self.mhartid = snapshot.read_u64()?;
+        self.mconfigptr = snapshot.read_u64()?;
+        self.misa = Isa::from_bits_truncate(snapshot.read_u64()?);
+        self.medeleg = Exception::from_bits_truncate(snapshot.read_u64()?);
+        self.mideleg = InterruptInternal::from_bits_truncate(snapshot.read_u64()?);
+        self.mie = InterruptInternal::from_bits_truncate(snapshot.read_u64()?);
+        self.mtvec.update_from_bits(snapshot.read_u64()?);
+        self.mcounteren = Counters::from_bits_truncate(snapshot.read_u64()? as u32);
+        self.mscratch = snapshot.read_u64()?;
+        self.mepc = snapshot.read_address()?;
+        self.mcause = snapshot.read_u64()?;
+        self.mtval = snapshot.read_u64()?;
+        *self.mip.lock().unwrap() = InterruptInternal::from_bits_truncate(snapshot.read_u64()?);
+        self.menvcfg = snapshot.read_u64()?;
+        self.mseccfg = snapshot.read_u64()?;
+        self.mcycle = snapshot.read_u64()?;
+        self.minstret = snapshot.read_u64()?;
+        self.mcounterinhibit = Counters::from_bits_truncate(snapshot.read_u64()? as u32);
+        self.miselect = snapshot.read_u64()? & ISELECT_MASK;
+        self.siselect = snapshot.read_u64()? & ISELECT_MASK;
+
+        self.pmp.restore(snapshot)?;
+
+        self.csr.clear();
+        for _ in 0..snapshot.read_u64()? {
+            let addr = CsrAddress::from(snapshot.read_u64()? as u16);
+            self.csr.insert(addr, snapshot.read_u64()?);
+        }
+
+        let status = snapshot.read_u64()?;
+        // mpp of 0b10 is reserved and would not be produced by to_m_bits
+        if (status >> 11) & 0b11 == 0b10 {
+            return Err(SnapshotError::Corrupt);
+        }
+        self.status.update_from_m_bits(status);
+
+        Ok(())
+    }
+
     pub fn get_csr(&self, addr: CsrAddress) -> u64 {
         match addr.into() {
             #[cfg(feature = "float")]
@@ -380,7 +576,11 @@ impl CsrHolder {
             0x142 => self.scause,
             0x143 => self.stval,
             0x144 => (*self.mip.lock().unwrap() & self.mideleg).bits(),
+            0x150 => self.siselect,
+            0x151 => self.read_ireg(PrivilegeMode::Supervisor),
+            0x15C => self.topei(PrivilegeMode::Supervisor),
             0x180 => self.satp.to_bits(),
+            0xDB0 => self.topi(PrivilegeMode::Supervisor),
             0xF11 => self.mvendorid,
             0xF12 => self.marchid,
             0xF13 => self.mimpid,
@@ -399,6 +599,10 @@ impl CsrHolder {
             0x342 => self.mcause,
             0x343 => self.mtval,
             0x344 => self.mip.lock().unwrap().bits(),
+            0x350 => self.miselect,
+            0x351 => self.read_ireg(PrivilegeMode::Machine),
+            0x35C => self.topei(PrivilegeMode::Machine),
+            0xFB0 => self.topi(PrivilegeMode::Machine),
             0x30A => self.menvcfg,
             i @ 0x3A0..=0x3AF if i % 2 == 0 => self.pmp.read_cfg_rv64((i - 0x3A0) as usize),
             i @ 0x3B0..=0x3EF => self.pmp.read_addr_rv64((i - 0x3B0) as usize),
@@ -485,6 +689,15 @@ impl CsrHolder {
                             | (value & (TOGGLEABLE_INTERRUPTS & S_INTERRUPT_MASK)),
                     );
                 }
+                0x150 => {
+                    self.siselect = value & ISELECT_MASK;
+                }
+                0x151 => {
+                    self.write_ireg(PrivilegeMode::Supervisor, value);
+                }
+                0x15C => {
+                    self.claim_external(PrivilegeMode::Supervisor);
+                }
                 0x180 if !self.status.tvm => {
                     if let Some(val) = Satp::from_bits(value) {
                         self.satp = val;
@@ -536,6 +749,15 @@ impl CsrHolder {
                         (mip.bits() & !TOGGLEABLE_INTERRUPTS) | (value & TOGGLEABLE_INTERRUPTS),
                     );
                 }
+                0x350 => {
+                    self.miselect = value & ISELECT_MASK;
+                }
+                0x351 => {
+                    self.write_ireg(PrivilegeMode::Machine, value);
+                }
+                0x35C => {
+                    self.claim_external(PrivilegeMode::Machine);
+                }
                 0x30A => {
                     self.menvcfg = (value & (0b1 | 0b1 << 62));
                 }
@@ -643,6 +865,15 @@ impl CsrHolder {
                         mip.bits() | (mask & (TOGGLEABLE_INTERRUPTS & S_INTERRUPT_MASK)),
                     );
                 }
+                0x150 => {
+                    self.siselect = (self.siselect | mask) & ISELECT_MASK;
+                }
+                0x151 => {
+                    self.write_ireg(PrivilegeMode::Supervisor, old | mask);
+                }
+                0x15C => {
+                    self.claim_external(PrivilegeMode::Supervisor);
+                }
                 0x180 if !self.status.tvm => {
                     if let Some(val) = Satp::from_bits(self.satp.to_bits() | mask) {
                         self.satp = val;
@@ -700,6 +931,15 @@ impl CsrHolder {
                         self.mie.bits() | (mask & TOGGLEABLE_INTERRUPTS),
                     );
                 }
+                0x350 => {
+                    self.miselect = (self.miselect | mask) & ISELECT_MASK;
+                }
+                0x351 => {
+                    self.write_ireg(PrivilegeMode::Machine, old | mask);
+                }
+                0x35C => {
+                    self.claim_external(PrivilegeMode::Machine);
+                }
                 0x30A => {
                     self.menvcfg = ((self.menvcfg | mask) & (0b1 | 0b1 << 62));
                 }
@@ -808,6 +1048,15 @@ impl CsrHolder {
                         mip.bits() & !(mask & (S_INTERRUPT_MASK & TOGGLEABLE_INTERRUPTS)),
                     );
                 }
+                0x150 => {
+                    self.siselect = (self.siselect & !mask) & ISELECT_MASK;
+                }
+                0x151 => {
+                    self.write_ireg(PrivilegeMode::Supervisor, old & !mask);
+                }
+                0x15C => {
+                    self.claim_external(PrivilegeMode::Supervisor);
+                }
                 0x180 if !self.status.tvm => {
                     if let Some(val) = Satp::from_bits(self.satp.to_bits() & !mask) {
                         self.satp = val;
@@ -865,6 +1114,15 @@ impl CsrHolder {
                         self.mie.bits() | (mask & TOGGLEABLE_INTERRUPTS),
                     );
                 }
+                0x350 => {
+                    self.miselect = (self.miselect & !mask) & ISELECT_MASK;
+                }
+                0x351 => {
+                    self.write_ireg(PrivilegeMode::Machine, old & !mask);
+                }
+                0x35C => {
+                    self.claim_external(PrivilegeMode::Machine);
+                }
                 0x30A => {
                     self.menvcfg = ((self.menvcfg & !mask) & (0b1 | 0b1 << 62));
                 }
@@ -10,9 +10,9 @@ pub mod trap;
 
 use core::panic;
 #[cfg(feature = "float")]
-use softfloat_wrapper::{F32, F64};
+use softfloat_wrapper::{Float, F32, F64};
 use std::{
-    collections::{BinaryHeap, HashMap},
+    collections::{HashMap, VecDeque},
     rc::Rc,
     sync::Mutex,
     time::Instant,
@@ -20,11 +20,15 @@ use std::{
 };
 
 use crate::{
-    decode::{decode, Instruction},
+    decode::{decode, Disassembly, Instruction},
     execute::{execute_rv64, ExecuteError, ExecuteResult},
     hart::csr_holder::TrapMode,
-    memory::{address::Address, Memory, MemoryError},
-    vmstate::{timer::TimerRef, VMError, VMSettings},
+    memory::{address::Address, fault::FaultDiagnostic, Memory, MemoryError},
+    trace::{Commit, RegWrite, TraceEvent, TraceSink, Trap},
+    vmstate::{
+        imsic::FileRef, timer::TimerRef, SnapshotError, SnapshotReader, SnapshotWriter, VMError,
+        VMSettings,
+    },
 };
 
 pub use csr_address::CsrAddress;
@@ -39,6 +43,13 @@ use self::{
     trap::{Exception, Interrupt, InterruptInternal, TrapCause},
 };
 
+/// The number of retired instructions each hart remembers for crash reports
+const RECENT_INSTRUCTIONS: usize = 32;
+
+/// Exceptions with at most this many instructions retired between them count towards an
+/// exception storm, see [`VMSettings::exception_storm_limit`].
+const STORM_GAP: u64 = 16;
+
 #[derive(Debug)]
 pub struct Hart {
     hart_id: u64,
@@ -48,6 +59,22 @@ pub struct Hart {
     privilege: PrivilegeMode,
     vm_settings: VMSettings,
     waiting_for_interrupt: bool,
+    tracer: Option<TraceSink>,
+    /// The instruction being executed while tracing
+    commit: Option<Commit>,
+    /// The pc and raw bits of the last retired instructions, oldest first
+    recent: VecDeque<(Address, u32)>,
+    storm: ExceptionStorm,
+    /// Why the last access or page fault this hart took was raised, for crash reports
+    last_fault: Option<FaultDiagnostic>,
+}
+
+/// Counts exceptions taken in quick succession
+#[derive(Debug, Default)]
+struct ExceptionStorm {
+    count: u64,
+    retired_since: u64,
+    last: Option<Exception>,
 }
 
 impl Hart {
@@ -60,6 +87,11 @@ impl Hart {
             privilege: PrivilegeMode::Machine,
             vm_settings,
             waiting_for_interrupt: false,
+            tracer: None,
+            commit: None,
+            recent: VecDeque::with_capacity(RECENT_INSTRUCTIONS),
+            storm: ExceptionStorm::default(),
+            last_fault: None,
         }
     }
 
@@ -88,7 +120,12 @@ impl Hart {
     }
 
     pub fn set_int_reg(&mut self, register: IntRegister, value: i64) {
-        self.registers.set_int(register, value)
+        self.registers.set_int(register, value);
+        if let Some(commit) = &mut self.commit {
+            if register != IntRegister::X0 {
+                commit.write(RegWrite::Int(register, value as u64));
+            }
+        }
     }
 
     #[cfg(feature = "float")]
@@ -98,7 +135,8 @@ impl Hart {
 
     #[cfg(feature = "float")]
     pub fn set_f32_reg(&mut self, register: FloatRegister, value: F32) {
-        self.registers.set_f32(register, value)
+        self.registers.set_f32(register, value);
+        self.trace_float_write(register);
     }
 
     #[cfg(feature = "float")]
@@ -108,7 +146,16 @@ impl Hart {
 
     #[cfg(feature = "float")]
     pub fn set_f64_reg(&mut self, register: FloatRegister, value: F64) {
-        self.registers.set_f64(register, value)
+        self.registers.set_f64(register, value);
+        self.trace_float_write(register);
+    }
+
+    #[cfg(feature = "float")]
+    fn trace_float_write(&mut self, register: FloatRegister) {
+        if let Some(commit) = &mut self.commit {
+            let bits = self.registers.get_f64(register).to_bits();
+            commit.write(RegWrite::Float(register as u8, bits));
+        }
     }
 
     pub fn get_csr_mut(&mut self) -> &mut CsrHolder {
@@ -123,6 +170,11 @@ impl Hart {
         self.csr.mip.clone()
     }
 
+    /// Give the hart the imsic interrupt files its `*ireg` and `*topei` csrs access
+    pub(crate) fn set_interrupt_files(&mut self, m_file: FileRef, s_file: FileRef) {
+        self.csr.set_interrupt_files(m_file, s_file);
+    }
+
     pub fn privilege(&self) -> PrivilegeMode {
         self.privilege
     }
@@ -135,92 +187,154 @@ impl Hart {
         self.waiting_for_interrupt = true;
     }
 
+    pub fn is_waiting_for_interrupt(&self) -> bool {
+        self.waiting_for_interrupt
+    }
+
+    pub(crate) fn set_halt_on_ebreak(&mut self, halt: bool) {
+        self.vm_settings.halt_on_ebreak = halt;
+    }
+
+    /// Write a trace line to `tracer` for every instruction this hart retires and every trap
+    /// it takes, or stop tracing if `None`.
+    pub(crate) fn set_tracer(&mut self, tracer: Option<TraceSink>) {
+        self.tracer = tracer;
+    }
+
+    fn trace(&self, event: TraceEvent) {
+        if let Some(tracer) = &self.tracer {
+            tracer.borrow_mut().emit(event);
+        }
+    }
+
+    /// The pc and raw bits of the last instructions this hart retired, oldest first
+    pub fn recent_instructions(&self) -> impl Iterator<Item = (Address, u32)> + '_ {
+        self.recent.iter().copied()
+    }
+
+    /// Why the last access or page fault this hart took was raised, if it came from a pmp check
+    /// or page table walk
+    pub fn last_fault(&self) -> Option<&FaultDiagnostic> {
+        self.last_fault.as_ref()
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        snapshot.write_address(self.pc);
+        self.registers.save(snapshot);
+        self.csr.save(snapshot);
+        snapshot.write_u8(self.privilege as u8);
+        snapshot.write_bool(self.waiting_for_interrupt);
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.pc = snapshot.read_address()?;
+        self.registers.restore(snapshot)?;
+        self.csr.restore(snapshot)?;
+        self.privilege = match snapshot.read_u8()? {
+            0 => PrivilegeMode::User,
+            1 => PrivilegeMode::Supervisor,
+            3 => PrivilegeMode::Machine,
+            _ => return Err(SnapshotError::Corrupt),
+        };
+        self.waiting_for_interrupt = snapshot.read_bool()?;
+        self.recent.clear();
+        self.storm = ExceptionStorm::default();
+        self.last_fault = None;
+        Ok(())
+    }
+
     pub fn step(&mut self, mem: &mut Memory, verbose: bool) -> Result<(), VMError> {
-        let mip_ref = self.get_mip_ref();
-        let mip = mip_ref.lock().unwrap();
-        if mip.bits() != 0 {
-            'interrupt_loop: for i in mip
-                .iter()
-                .collect::<BinaryHeap<InterruptInternal>>()
-                .into_sorted_vec()
-            {
-                match i {
-                    InterruptInternal::SupervisorSoftware
-                    | InterruptInternal::SupervisorTimer
-                    | InterruptInternal::SupervisorExternal => {
-                        if self.csr.mideleg.contains(i) {
-                            if (self.privilege < PrivilegeMode::Supervisor
-                                || (self.privilege == PrivilegeMode::Supervisor
-                                    && self.csr.status.sie))
-                                && self.csr.sie.contains(i)
-                            {
-                                self.trap(TrapCause::Interrupt(i), PrivilegeMode::Supervisor);
-                                break 'interrupt_loop;
-                            }
-                        } else if (self.privilege < PrivilegeMode::Machine || self.csr.status.mie)
-                            && self.csr.mie.contains(i)
-                        {
-                            self.trap(TrapCause::Interrupt(i), PrivilegeMode::Machine);
-                            break 'interrupt_loop;
-                        }
-                    }
-                    InterruptInternal::MachineSoftware
-                    | InterruptInternal::MachineTimer
-                    | InterruptInternal::MachineExternal => {
-                        if (self.privilege < PrivilegeMode::Machine || self.csr.status.mie)
-                            && self.csr.mie.contains(i)
-                        {
-                            self.trap(TrapCause::Interrupt(i), PrivilegeMode::Machine);
-                            break 'interrupt_loop;
-                        }
-                    }
-                }
+        self.step_inner(mem, verbose)?;
+
+        if let Some(limit) = self.vm_settings.exception_storm_limit {
+            if self.storm.count >= limit {
+                self.storm.count = 0;
+                return Err(VMError::ExceptionStorm(self.storm.last.unwrap()));
             }
         }
-        drop(mip);
-        drop(mip_ref);
+        Ok(())
+    }
+
+    fn step_inner(&mut self, mem: &mut Memory, verbose: bool) -> Result<(), VMError> {
+        // The interrupt mtopi reports is taken before the one stopi reports, each only while
+        // interrupts of its privilege mode are enabled, which they always are in lower modes
+        let m_enabled = self.privilege < PrivilegeMode::Machine || self.csr.status.mie;
+        let s_enabled = self.privilege < PrivilegeMode::Supervisor
+            || (self.privilege == PrivilegeMode::Supervisor && self.csr.status.sie);
+        let machine = self.csr.top_interrupt(PrivilegeMode::Machine);
+        let supervisor = self.csr.top_interrupt(PrivilegeMode::Supervisor);
+        if let Some(i) = machine.filter(|_| m_enabled) {
+            self.trap(TrapCause::Interrupt(i), PrivilegeMode::Machine, None);
+        } else if let Some(i) = supervisor.filter(|_| s_enabled) {
+            self.trap(TrapCause::Interrupt(i), PrivilegeMode::Supervisor, None);
+        }
 
         if (self.waiting_for_interrupt) {
             return Ok(());
         }
 
-        let (inst, is_compact) = match self.fetch(mem) {
-            Ok(inst) => inst,
+        // Accesses whose errors were not turned into exceptions may have left a fault behind
+        mem.take_fault();
+        let raw = match self.fetch_raw(mem) {
+            Ok(raw) => raw,
             Err(err) => match err {
                 MemoryError::PmpDeniedFetch => {
-                    self.exception(Exception::InstructionAccessFault);
+                    self.exception(Exception::InstructionAccessFault, mem.take_fault());
                     return Ok(());
                 }
                 MemoryError::PageFaultFetch => {
-                    self.exception(Exception::InstructionPageFault);
+                    self.exception(Exception::InstructionPageFault, mem.take_fault());
                     return Ok(());
                 }
                 MemoryError::OutOfBoundsRead(_) => {
-                    self.exception(Exception::InstructionAccessFault);
+                    self.exception(Exception::InstructionAccessFault, None);
                     return Ok(());
                 }
                 _ => unreachable!("fetch may not return non fetch errors"),
             },
         };
+        let (inst, is_compact) = decode(raw);
 
         if verbose {
-            println!("{:#?}", &inst);
+            println!(
+                "{:#x}: {}",
+                u64::from(self.pc),
+                Disassembly::decode(raw, self.pc)
+            );
+        }
+
+        if self.vm_settings.halt_on_ebreak && inst == Instruction::EBREAK {
+            return Err(VMError::MBreak);
         }
 
+        if self.tracer.is_some() {
+            self.commit = Some(Commit::new(self.hart_id, self.privilege, self.pc, raw));
+            mem.start_access_log();
+        }
+
+        let pc = self.pc;
         let result = execute_rv64(self, mem, inst, is_compact, self.csr.isa());
+        let mut commit = self.commit.take();
+        if let Some(commit) = &mut commit {
+            commit.set_mem(mem.take_access_log());
+        }
+
         match result {
             Ok(ExecuteResult::Continue) => self.inc_pc(is_compact),
             Ok(ExecuteResult::WFI) => self.wait_for_interrupt(),
             Ok(ExecuteResult::Jump(pc)) => self.set_pc(pc),
             Ok(ExecuteResult::CsrUpdate(addr)) => {
                 if addr == 0x180u16.into() && self.csr.status.tvm {
-                    self.exception(Exception::IllegalInstruction);
+                    self.exception(Exception::IllegalInstruction, None);
                     return Ok(());
                 }
+                if let Some(commit) = &mut commit {
+                    commit.write(RegWrite::Csr(addr, self.csr.get_csr(addr)));
+                }
                 self.inc_pc(is_compact);
             }
             Err(ExecuteError::Exception(e)) => {
-                self.exception(e);
+                self.exception(e, mem.take_fault());
                 return Ok(());
             }
             Err(ExecuteError::Fatal) => return Err(VMError::ExecureError(ExecuteError::Fatal)),
@@ -229,6 +343,16 @@ impl Hart {
         self.csr.inc_cycle(1);
         self.csr.inc_instret(1);
 
+        if self.recent.len() == RECENT_INSTRUCTIONS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((pc, raw));
+        self.storm.retired_since += 1;
+
+        if let Some(commit) = commit {
+            self.trace(TraceEvent::Commit(commit));
+        }
+
         Ok(())
     }
 
@@ -251,27 +375,61 @@ impl Hart {
     }
 
     pub fn fetch(&self, mem: &mut Memory) -> Result<(Instruction, bool), MemoryError> {
-        let mut window = mem.window(self);
-        let inst = window.fetch(self.get_pc())?;
-        Ok(decode(inst))
+        Ok(decode(self.fetch_raw(mem)?))
+    }
+
+    fn fetch_raw(&self, mem: &mut Memory) -> Result<u32, MemoryError> {
+        mem.window(self).fetch(self.get_pc())
     }
 
     pub fn pmp_enable(&self) -> bool {
         self.vm_settings.pmp_enable
     }
 
-    fn exception(&mut self, exception: Exception) {
+    /// Take an exception, `fault` is why the access that raised it failed
+    fn exception(&mut self, exception: Exception, fault: Option<FaultDiagnostic>) {
         eprintln!("Exeption hit: {:?} ({:?})", exception, exception.get_code());
+        if let Some(fault) = &fault {
+            self.last_fault = Some(*fault);
+        }
+        // Ecalls and breakpoints are asked for, a loop of them is not a storm
+        if !matches!(
+            exception,
+            Exception::EcallUMode
+                | Exception::EcallSMode
+                | Exception::EcallMMode
+                | Exception::BreakPoint
+        ) {
+            if self.storm.retired_since > STORM_GAP {
+                self.storm.count = 0;
+            }
+            self.storm.count += 1;
+            self.storm.retired_since = 0;
+            self.storm.last = Some(exception);
+        }
         if self.csr.medeleg.contains(exception) && self.privilege < PrivilegeMode::Machine {
             eprintln!("Delegating exception to S mode");
-            self.trap(TrapCause::Exception(exception), PrivilegeMode::Supervisor);
+            self.trap(
+                TrapCause::Exception(exception),
+                PrivilegeMode::Supervisor,
+                fault,
+            );
         } else {
             eprintln!("Delegating exception to M mode");
-            self.trap(TrapCause::Exception(exception), PrivilegeMode::Machine);
+            self.trap(
+                TrapCause::Exception(exception),
+                PrivilegeMode::Machine,
+                fault,
+            );
         }
     }
 
-    fn trap(&mut self, cause: TrapCause, target: PrivilegeMode) {
+    fn trap(&mut self, cause: TrapCause, target: PrivilegeMode, fault: Option<FaultDiagnostic>) {
+        let epc = self.get_pc();
+        let code = match &cause {
+            TrapCause::Exception(e) => e.get_code(),
+            TrapCause::Interrupt(i) => i.get_code() | (0x1 << 63),
+        };
         self.waiting_for_interrupt = false;
         match target {
             PrivilegeMode::User => unreachable!("User mode cannot handle traps"),
@@ -296,7 +454,12 @@ impl Hart {
                         self.csr.inc_cycle(1);
                         self.csr.inc_instret(1);
                         self.csr.scause = i.get_code();
-                        self.set_pc(self.csr.stvec.base + 4 * i.get_code());
+                        self.csr.scause |= 0x1 << 63;
+                        if self.csr.stvec.mode == TrapMode::Direct {
+                            self.set_pc(self.csr.stvec.base);
+                        } else {
+                            self.set_pc(self.csr.stvec.base + 4 * i.get_code());
+                        }
                     }
                 }
             }
@@ -331,5 +494,18 @@ impl Hart {
                 }
             }
         }
+
+        let tval = match target {
+            PrivilegeMode::Supervisor => self.csr.stval,
+            _ => self.csr.mtval,
+        };
+        self.trace(TraceEvent::Trap(Trap {
+            hart: self.hart_id,
+            cause: code,
+            epc,
+            tval: Some(tval),
+            target: Some(target),
+            fault,
+        }));
     }
 }
@@ -1,8 +1,13 @@
-use std::fmt::Debug;
+use std::{
+    fmt::{Debug, Display},
+    str::FromStr,
+};
 
 #[cfg(feature = "float")]
 use softfloat_wrapper::{Float, F32, F64};
 
+use crate::vmstate::{SnapshotError, SnapshotReader, SnapshotWriter};
+
 #[repr(transparent)]
 pub struct InvalidNaNBox(pub F32);
 
@@ -84,6 +89,27 @@ impl Registers {
     pub fn set_f64(&mut self, register: FloatRegister, value: F64) {
         self.float_registers[register as usize] = value.to_bits();
     }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        for r in self.int_registers {
+            snapshot.write_u64(r as u64);
+        }
+        #[cfg(feature = "float")]
+        for r in self.float_registers {
+            snapshot.write_u64(r);
+        }
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        for r in self.int_registers.iter_mut() {
+            *r = snapshot.read_u64()? as i64;
+        }
+        #[cfg(feature = "float")]
+        for r in self.float_registers.iter_mut() {
+            *r = snapshot.read_u64()?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -126,6 +152,47 @@ pub enum IntRegister {
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct PCRegister;
 
+/// Returned when parsing a register name that does not exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRegisterName(pub String);
+
+impl IntRegister {
+    const ABI_NAMES: [&'static str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+        "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+        "t5", "t6",
+    ];
+
+    /// The name of this register in the standard calling convention, e.g. `sp` for `x2`
+    pub fn abi_name(&self) -> &'static str {
+        Self::ABI_NAMES[*self as usize]
+    }
+}
+
+/// Accepts both the `xN` and the abi names, including `fp` for `x8`
+impl Display for IntRegister {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.abi_name())
+    }
+}
+
+impl FromStr for IntRegister {
+    type Err = InvalidRegisterName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "fp" {
+            return Ok(IntRegister::X8);
+        }
+        if let Some(i) = Self::ABI_NAMES.iter().position(|n| *n == s) {
+            return Ok(IntRegister::from(i as u32));
+        }
+        match s.strip_prefix('x').and_then(|i| i.parse::<u32>().ok()) {
+            Some(i) if i < 32 => Ok(IntRegister::from(i)),
+            _ => Err(InvalidRegisterName(s.to_string())),
+        }
+    }
+}
+
 impl From<u32> for IntRegister {
     fn from(value: u32) -> Self {
         match value {
@@ -277,3 +344,40 @@ impl From<u16> for FloatRegister {
         }
     }
 }
+
+#[cfg(feature = "float")]
+impl FloatRegister {
+    const ABI_NAMES: [&'static str; 32] = [
+        "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+        "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+        "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+    ];
+
+    /// The name of this register in the standard calling convention, e.g. `fa0` for `f10`
+    pub fn abi_name(&self) -> &'static str {
+        Self::ABI_NAMES[*self as usize]
+    }
+}
+
+/// Accepts both the `fN` and the abi names
+#[cfg(feature = "float")]
+impl Display for FloatRegister {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.abi_name())
+    }
+}
+
+#[cfg(feature = "float")]
+impl FromStr for FloatRegister {
+    type Err = InvalidRegisterName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(i) = Self::ABI_NAMES.iter().position(|n| *n == s) {
+            return Ok(FloatRegister::from(i as u32));
+        }
+        match s.strip_prefix('f').and_then(|i| i.parse::<u32>().ok()) {
+            Some(i) if i < 32 => Ok(FloatRegister::from(i)),
+            _ => Err(InvalidRegisterName(s.to_string())),
+        }
+    }
+}
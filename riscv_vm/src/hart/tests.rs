@@ -1,4 +1,16 @@
-use crate::hart::csr_address::{CsrAddress, CsrType};
+use enumflags2::{make_bitflags, BitFlags};
+
+use crate::{
+    hart::{
+        csr_address::{CsrAddress, CsrType},
+        csr_holder::TrapMode,
+        privilege::PrivilegeMode,
+        trap::InterruptInternal,
+        Hart,
+    },
+    memory::Memory,
+    vmstate::{timer::TimerRef, VMSettings},
+};
 
 #[test]
 fn cycle_csr_type() {
@@ -23,3 +35,90 @@ fn instret_csr_type() {
         CsrType::StandardRO
     )
 }
+
+#[test]
+fn csr_names() {
+    assert_eq!(CsrAddress::from_name("mstatus"), Some(0x300u16.into()));
+    assert_eq!(CsrAddress::from_name("pmpaddr3"), Some(0x3B3u16.into()));
+    assert_eq!(<u16 as Into<CsrAddress>>::into(0x341).to_string(), "mepc");
+    assert_eq!(<u16 as Into<CsrAddress>>::into(0x7FF).to_string(), "0x7ff");
+}
+
+#[test]
+fn int_register_names() {
+    use crate::hart::registers::IntRegister;
+
+    assert_eq!("a0".parse::<IntRegister>().ok(), Some(IntRegister::X10));
+    assert_eq!("x10".parse::<IntRegister>().ok(), Some(IntRegister::X10));
+    assert_eq!("fp".parse::<IntRegister>().ok(), Some(IntRegister::X8));
+    assert!("x32".parse::<IntRegister>().is_err());
+    assert_eq!(IntRegister::X2.abi_name(), "sp");
+}
+
+fn interrupt_hart(pending: BitFlags<InterruptInternal>) -> Hart {
+    let mut hart = Hart::new(0, VMSettings::default(), TimerRef::dummy());
+    *hart.csr.mip.lock().unwrap() = pending;
+    hart.csr.mie = pending;
+    hart.csr.sie = pending;
+    hart
+}
+
+/// Memory with a nop at each of the given addresses
+fn nops(addrs: &[u64]) -> Memory {
+    let mut mem = Memory::new::<512>();
+    for addr in addrs {
+        mem.write_bytes(&0x13u32.to_le_bytes(), (*addr).into())
+            .unwrap();
+    }
+    mem
+}
+
+#[test]
+fn supervisor_interrupt_trap() {
+    for (mode, handler) in [
+        (TrapMode::Direct, 0x80000100u64),
+        (TrapMode::Vectored, 0x80000104),
+    ] {
+        let mut mem = nops(&[handler]);
+        let pending = make_bitflags!(InterruptInternal::{SupervisorSoftware});
+        let mut hart = interrupt_hart(pending);
+        hart.csr.mideleg = pending;
+        hart.csr.stvec.mode = mode;
+        hart.csr.stvec.base = 0x80000100u64.into();
+        hart.set_privilege(PrivilegeMode::User);
+
+        hart.step(&mut mem, false).unwrap();
+        assert_eq!(hart.get_csr().get_csr(CsrAddress::new(0x142)), 1 << 63 | 1);
+        assert_eq!(hart.get_pc(), (handler + 4).into());
+    }
+}
+
+#[test]
+fn machine_interrupt_priority() {
+    let mut mem = nops(&[0x80000100]);
+    let mut hart =
+        interrupt_hart(make_bitflags!(InterruptInternal::{MachineExternal | MachineTimer}));
+    hart.csr.mtvec.base = 0x80000100u64.into();
+    hart.csr.status.mie = true;
+
+    assert_eq!(hart.get_csr().get_csr(CsrAddress::new(0xFB0)), 11 << 16 | 1);
+    hart.step(&mut mem, false).unwrap();
+    assert_eq!(hart.get_csr().get_csr(CsrAddress::new(0x342)), 1 << 63 | 11);
+    assert_eq!(hart.get_pc(), 0x80000104u64.into());
+}
+
+#[test]
+fn supervisor_interrupt_priority() {
+    let mut mem = nops(&[0x80000100]);
+    let pending = make_bitflags!(InterruptInternal::{SupervisorExternal | SupervisorTimer});
+    let mut hart = interrupt_hart(pending);
+    hart.csr.mideleg = pending;
+    hart.csr.stvec.base = 0x80000100u64.into();
+    hart.set_privilege(PrivilegeMode::User);
+
+    assert_eq!(hart.get_csr().get_csr(CsrAddress::new(0xDB0)), 9 << 16 | 1);
+    assert_eq!(hart.get_csr().get_csr(CsrAddress::new(0xFB0)), 0);
+    hart.step(&mut mem, false).unwrap();
+    assert_eq!(hart.get_csr().get_csr(CsrAddress::new(0x142)), 1 << 63 | 9);
+    assert_eq!(hart.get_pc(), 0x80000104u64.into());
+}
@@ -53,57 +53,15 @@ pub enum InterruptInternal {
     MachineExternal = 0b1 << 11,
 }
 
-impl Ord for InterruptInternal {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match self {
-            InterruptInternal::MachineExternal => match other {
-                Self::MachineExternal => std::cmp::Ordering::Equal,
-                _ => std::cmp::Ordering::Greater,
-            },
-            InterruptInternal::MachineSoftware => match other {
-                Self::MachineExternal => std::cmp::Ordering::Greater,
-                Self::MachineSoftware => std::cmp::Ordering::Equal,
-                _ => std::cmp::Ordering::Less,
-            },
-            InterruptInternal::MachineTimer => match other {
-                Self::MachineExternal | Self::MachineSoftware => std::cmp::Ordering::Greater,
-                Self::MachineTimer => std::cmp::Ordering::Equal,
-                _ => std::cmp::Ordering::Less,
-            },
-            InterruptInternal::SupervisorExternal => match other {
-                Self::MachineExternal | Self::MachineSoftware | Self::MachineTimer => {
-                    std::cmp::Ordering::Greater
-                }
-                Self::SupervisorExternal => std::cmp::Ordering::Equal,
-                _ => std::cmp::Ordering::Less,
-            },
-            InterruptInternal::SupervisorSoftware => match other {
-                Self::MachineExternal
-                | Self::MachineSoftware
-                | Self::MachineTimer
-                | InterruptInternal::SupervisorExternal => std::cmp::Ordering::Greater,
-                Self::SupervisorSoftware => std::cmp::Ordering::Equal,
-                _ => std::cmp::Ordering::Less,
-            },
-
-            InterruptInternal::SupervisorTimer => match other {
-                Self::MachineExternal
-                | Self::MachineSoftware
-                | Self::MachineTimer
-                | InterruptInternal::SupervisorExternal
-                | InterruptInternal::SupervisorSoftware => std::cmp::Ordering::Greater,
-                Self::SupervisorTimer => std::cmp::Ordering::Equal,
-                _ => std::cmp::Ordering::Less,
-            },
-        }
-    }
-}
-
-impl PartialOrd for InterruptInternal {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
+/// Major interrupts from highest to lowest priority, as fixed by the privileged spec
+pub const INTERRUPT_PRIORITY: [InterruptInternal; 6] = [
+    InterruptInternal::MachineExternal,
+    InterruptInternal::MachineSoftware,
+    InterruptInternal::MachineTimer,
+    InterruptInternal::SupervisorExternal,
+    InterruptInternal::SupervisorSoftware,
+    InterruptInternal::SupervisorTimer,
+];
 
 impl InterruptInternal {
     pub fn get_code(&self) -> u64 {
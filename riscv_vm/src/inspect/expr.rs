@@ -0,0 +1,180 @@
+//! Parsing of the C like expressions accepted by [`Inspector::evaluate()`](super::Inspector).
+
+use std::{iter::Peekable, str::CharIndices};
+
+use super::InspectError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Expr {
+    Variable(String),
+    Number(u64),
+    /// `a.b`
+    Member(Box<Expr>, String),
+    /// `a->b`
+    Arrow(Box<Expr>, String),
+    /// `a[b]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `*a`
+    Deref(Box<Expr>),
+    /// `&a`
+    AddressOf(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(u64),
+    Dot,
+    Arrow,
+    Star,
+    Amp,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, InspectError> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            '*' => Token::Star,
+            '&' => Token::Amp,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '-' if chars.next_if(|(_, c)| *c == '>').is_some() => Token::Arrow,
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let end = word_end(&mut chars, input.len());
+                let word = &input[start..end];
+                if c.is_ascii_digit() {
+                    Token::Number(parse_number(word)?)
+                } else {
+                    Token::Ident(word.to_string())
+                }
+            }
+            c => return Err(InspectError::Syntax(format!("unexpected '{}'", c))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Skip the rest of an identifier or number and return where it ends
+fn word_end(chars: &mut Peekable<CharIndices>, len: usize) -> usize {
+    while chars
+        .next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+        .is_some()
+    {}
+    chars.peek().map_or(len, |(i, _)| *i)
+}
+
+fn parse_number(word: &str) -> Result<u64, InspectError> {
+    let result = match word.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    result.map_err(|_| InspectError::Syntax(format!("invalid number {}", word)))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), InspectError> {
+        if self.next() == Some(token) {
+            Ok(())
+        } else {
+            Err(InspectError::Syntax(format!("expected {}", what)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, InspectError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            _ => Err(InspectError::Syntax("expected a member name".to_string())),
+        }
+    }
+
+    /// `*a`, `&a` or a postfix expression
+    fn unary(&mut self) -> Result<Expr, InspectError> {
+        match self.peek() {
+            Some(Token::Star) => {
+                self.pos += 1;
+                Ok(Expr::Deref(Box::new(self.unary()?)))
+            }
+            Some(Token::Amp) => {
+                self.pos += 1;
+                Ok(Expr::AddressOf(Box::new(self.unary()?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    /// Member accesses and indexing, which bind tighter than `*` and `&`
+    fn postfix(&mut self) -> Result<Expr, InspectError> {
+        let mut expr = self.primary()?;
+        loop {
+            expr = match self.peek() {
+                Some(Token::Dot) => {
+                    self.pos += 1;
+                    Expr::Member(Box::new(expr), self.ident()?)
+                }
+                Some(Token::Arrow) => {
+                    self.pos += 1;
+                    Expr::Arrow(Box::new(expr), self.ident()?)
+                }
+                Some(Token::OpenBracket) => {
+                    self.pos += 1;
+                    let index = self.unary()?;
+                    self.expect(Token::CloseBracket, "']'")?;
+                    Expr::Index(Box::new(expr), Box::new(index))
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, InspectError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Open) => {
+                let expr = self.unary()?;
+                self.expect(Token::Close, "')'")?;
+                Ok(expr)
+            }
+            _ => Err(InspectError::Syntax("expected a variable".to_string())),
+        }
+    }
+}
+
+pub(super) fn parse(input: &str) -> Result<Expr, InspectError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let expr = parser.unary()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(InspectError::Syntax(
+            "unexpected tokens after the expression".to_string(),
+        ));
+    }
+    Ok(expr)
+}
@@ -0,0 +1,500 @@
+//! Printing guest variables by name, using the debug information of the elf the guest runs.
+//!
+//! Expressions are a small subset of C: variables, member access with `.` and `->`, indexing
+//! with `[]`, `*` and `&`, for example `task_list[0].state` or `*current->next`. Locals are looked
+//! up in the function the hart is in, globals everywhere.
+
+mod expr;
+#[cfg(test)]
+mod tests;
+
+use elf_load::dwarf::info::{
+    DebugInfo, Location, Member, Type, TypeId, DW_ATE_BOOLEAN, DW_ATE_FLOAT, DW_ATE_SIGNED,
+    DW_ATE_SIGNED_CHAR, DW_ATE_UNSIGNED_CHAR,
+};
+
+use crate::{
+    hart::registers::IntRegister,
+    memory::{address::Address, MemoryError},
+    vmstate::VMState,
+};
+
+use self::expr::Expr;
+
+#[cfg(feature = "float")]
+use crate::hart::registers::FloatRegister;
+#[cfg(feature = "float")]
+use softfloat_wrapper::Float;
+
+/// The number of array elements and string characters that are printed
+const MAX_ELEMENTS: u64 = 64;
+/// How deep nested structs and arrays are printed before they are shown as `{...}`
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub enum InspectError {
+    /// The expression could not be parsed, with the reason
+    Syntax(String),
+    UnknownVariable(String),
+    /// The variable has no location, usually because it was optimized out
+    OptimizedOut(String),
+    /// The location of the variable is a location list or an expression that is not understood
+    UnsupportedLocation(String),
+    NoMember(String),
+    NotAStruct,
+    NotAPointer,
+    NotAnInteger,
+    /// Only values in memory have an address and members
+    NotInMemory,
+    /// The value has a type that is not described or has no size
+    UnknownType,
+    MemoryError(MemoryError),
+}
+
+impl From<MemoryError> for InspectError {
+    fn from(value: MemoryError) -> Self {
+        Self::MemoryError(value)
+    }
+}
+
+/// The type of a value, which is not always described by the debug information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// A type from the debug information, `None` for `void`
+    Typed(Option<TypeId>),
+    /// A number in the expression
+    Integer,
+    /// The address of a value of the type, the result of `&`
+    AddressOf(Option<TypeId>),
+}
+
+/// Where a value is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Memory(Address),
+    /// A dwarf register number
+    Register(u16),
+    /// A value that is not stored anywhere, like a number or an address
+    Value(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    pub ty: ValueType,
+    pub place: Place,
+}
+
+/// Evaluates expressions as seen by a hart, memory is read through its address translation.
+pub struct Inspector<'a> {
+    vmstate: &'a mut VMState,
+    hart: usize,
+    info: &'a DebugInfo,
+}
+
+impl<'a> Inspector<'a> {
+    pub fn new(vmstate: &'a mut VMState, hart: usize, info: &'a DebugInfo) -> Self {
+        Self {
+            vmstate,
+            hart,
+            info,
+        }
+    }
+
+    /// Evaluate and format an expression
+    pub fn print(&mut self, expr: &str) -> Result<String, InspectError> {
+        let value = self.evaluate(expr)?;
+        self.format(&value)
+    }
+
+    pub fn evaluate(&mut self, expr: &str) -> Result<Value, InspectError> {
+        let expr = expr::parse(expr)?;
+        self.eval(&expr)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, InspectError> {
+        match expr {
+            Expr::Variable(name) => self.variable(name),
+            Expr::Number(value) => Ok(Value {
+                ty: ValueType::Integer,
+                place: Place::Value(*value),
+            }),
+            Expr::Member(expr, name) => {
+                let value = self.eval(expr)?;
+                self.member(value, name)
+            }
+            Expr::Arrow(expr, name) => {
+                let value = self.eval(expr)?;
+                let value = self.deref(value)?;
+                self.member(value, name)
+            }
+            Expr::Index(expr, index) => {
+                let value = self.eval(expr)?;
+                let index = self.eval(index)?;
+                let index = self.integer(index)?;
+                self.index(value, index)
+            }
+            Expr::Deref(expr) => {
+                let value = self.eval(expr)?;
+                self.deref(value)
+            }
+            Expr::AddressOf(expr) => match self.eval(expr)? {
+                Value {
+                    ty: ValueType::Typed(ty),
+                    place: Place::Memory(addr),
+                } => Ok(Value {
+                    ty: ValueType::AddressOf(ty),
+                    place: Place::Value(addr.into()),
+                }),
+                _ => Err(InspectError::NotInMemory),
+            },
+        }
+    }
+
+    fn variable(&mut self, name: &str) -> Result<Value, InspectError> {
+        let pc = self.vmstate.get_hart(self.hart).unwrap().get_pc();
+        let (variable, function) = self
+            .info
+            .variable(name, elf_load::Address(pc.into()))
+            .ok_or_else(|| InspectError::UnknownVariable(name.to_string()))?;
+
+        let place = match variable.location {
+            Location::Address(addr) => Place::Memory(addr.into()),
+            Location::Register(reg) => Place::Register(reg),
+            Location::RegisterOffset(reg, offset) => {
+                Place::Memory(self.register(reg)?.wrapping_add_signed(offset).into())
+            }
+            Location::FrameOffset(offset) => {
+                let base = match function.map(|f| f.frame_base) {
+                    Some(Location::Cfa) => self
+                        .vmstate
+                        .cfa(self.hart)
+                        .ok_or_else(|| InspectError::UnsupportedLocation(name.to_string()))?,
+                    Some(Location::Register(reg)) => self.register(reg)?,
+                    Some(Location::RegisterOffset(reg, offset)) => {
+                        self.register(reg)?.wrapping_add_signed(offset)
+                    }
+                    _ => return Err(InspectError::UnsupportedLocation(name.to_string())),
+                };
+                Place::Memory(base.wrapping_add_signed(offset).into())
+            }
+            Location::None => return Err(InspectError::OptimizedOut(name.to_string())),
+            Location::Cfa | Location::Unsupported => {
+                return Err(InspectError::UnsupportedLocation(name.to_string()))
+            }
+        };
+        Ok(Value {
+            ty: ValueType::Typed(variable.ty),
+            place,
+        })
+    }
+
+    /// The value of a dwarf register, 0 to 31 are the integer and 32 to 63 the float registers
+    fn register(&self, reg: u16) -> Result<u64, InspectError> {
+        let hart = self.vmstate.get_hart(self.hart).unwrap();
+        match reg {
+            0..=31 => Ok(hart.get_int_reg(IntRegister::from(u32::from(reg))) as u64),
+            #[cfg(feature = "float")]
+            32..=63 => Ok(hart
+                .get_f64_reg(FloatRegister::from(u32::from(reg - 32)))
+                .to_bits()),
+            _ => Err(InspectError::UnsupportedLocation(format!(
+                "register {}",
+                reg
+            ))),
+        }
+    }
+
+    fn read(&mut self, place: Place, size: u64) -> Result<Vec<u8>, InspectError> {
+        match place {
+            Place::Memory(addr) => Ok(self.vmstate.read_memory(self.hart, addr, size as usize)?),
+            Place::Register(reg) => {
+                let bytes = self.register(reg)?.to_le_bytes();
+                Ok(bytes[..(size as usize).min(8)].to_vec())
+            }
+            Place::Value(value) => Ok(value.to_le_bytes()[..(size as usize).min(8)].to_vec()),
+        }
+    }
+
+    fn read_uint(&mut self, place: Place, size: u64) -> Result<u64, InspectError> {
+        let mut buf = [0; 8];
+        let bytes = self.read(place, size.min(8))?;
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// The value of an integer, enum or pointer, for indices
+    fn integer(&mut self, value: Value) -> Result<u64, InspectError> {
+        match value.ty {
+            ValueType::Integer | ValueType::AddressOf(_) => self.read_uint(value.place, 8),
+            ValueType::Typed(ty) => match self.info.resolve(ty).map(|t| self.info.ty(t)) {
+                Some(Type::Base { size, encoding, .. }) if *encoding != DW_ATE_FLOAT => {
+                    let size = *size;
+                    let value = self.read_uint(value.place, size)?;
+                    let signed = matches!(*encoding, DW_ATE_SIGNED | DW_ATE_SIGNED_CHAR);
+                    Ok(if signed {
+                        sign_extend(value, size) as u64
+                    } else {
+                        value
+                    })
+                }
+                Some(Type::Enum { size, .. }) | Some(Type::Pointer { size, .. }) => {
+                    let size = *size;
+                    self.read_uint(value.place, size)
+                }
+                _ => Err(InspectError::NotAnInteger),
+            },
+        }
+    }
+
+    fn member(&mut self, value: Value, name: &str) -> Result<Value, InspectError> {
+        let ValueType::Typed(ty) = value.ty else {
+            return Err(InspectError::NotAStruct);
+        };
+        let Some(Type::Struct { members, .. }) = self.info.resolve(ty).map(|t| self.info.ty(t))
+        else {
+            return Err(InspectError::NotAStruct);
+        };
+        let Place::Memory(addr) = value.place else {
+            return Err(InspectError::NotInMemory);
+        };
+        let (ty, offset) = find_member(self.info, members, name)
+            .ok_or_else(|| InspectError::NoMember(name.to_string()))?;
+        Ok(Value {
+            ty: ValueType::Typed(ty),
+            place: Place::Memory(u64::from(addr).wrapping_add(offset).into()),
+        })
+    }
+
+    fn index(&mut self, value: Value, index: u64) -> Result<Value, InspectError> {
+        let (element, start) = match value.ty {
+            ValueType::Typed(ty) => match self.info.resolve(ty).map(|t| self.info.ty(t)) {
+                Some(Type::Array { element, .. }) => match value.place {
+                    Place::Memory(addr) => (*element, addr),
+                    _ => return Err(InspectError::NotInMemory),
+                },
+                Some(Type::Pointer { target, size }) => {
+                    let (target, size) = (*target, *size);
+                    (target, self.read_uint(value.place, size)?.into())
+                }
+                _ => return Err(InspectError::NotAPointer),
+            },
+            ValueType::AddressOf(ty) => (ty, self.read_uint(value.place, 8)?.into()),
+            ValueType::Integer => return Err(InspectError::NotAPointer),
+        };
+        let size = self
+            .info
+            .size_of(element)
+            .ok_or(InspectError::UnknownType)?;
+        Ok(Value {
+            ty: ValueType::Typed(element),
+            place: Place::Memory(
+                u64::from(start)
+                    .wrapping_add(index.wrapping_mul(size))
+                    .into(),
+            ),
+        })
+    }
+
+    fn deref(&mut self, value: Value) -> Result<Value, InspectError> {
+        if let ValueType::Typed(ty) = value.ty {
+            // Arrays decay to a pointer to their first element
+            if let Some(Type::Array { .. }) = self.info.resolve(ty).map(|t| self.info.ty(t)) {
+                return self.index(value, 0);
+            }
+        }
+        let (target, addr) = match value.ty {
+            ValueType::Typed(ty) => match self.info.resolve(ty).map(|t| self.info.ty(t)) {
+                Some(Type::Pointer { target, size }) => {
+                    let (target, size) = (*target, *size);
+                    (target, self.read_uint(value.place, size)?)
+                }
+                _ => return Err(InspectError::NotAPointer),
+            },
+            ValueType::AddressOf(ty) => (ty, self.read_uint(value.place, 8)?),
+            ValueType::Integer => return Err(InspectError::NotAPointer),
+        };
+        Ok(Value {
+            ty: ValueType::Typed(target),
+            place: Place::Memory(addr.into()),
+        })
+    }
+
+    /// Format a value like C initializers, `{id = 1, name = "init"}` for structs
+    pub fn format(&mut self, value: &Value) -> Result<String, InspectError> {
+        match value.ty {
+            ValueType::Typed(ty) => self.format_typed(ty, value.place, 0),
+            ValueType::Integer => Ok(self.read_uint(value.place, 8)?.to_string()),
+            ValueType::AddressOf(ty) => Ok(format!(
+                "({} *) {:#x}",
+                self.info.type_name(ty),
+                self.read_uint(value.place, 8)?
+            )),
+        }
+    }
+
+    fn format_typed(
+        &mut self,
+        ty: Option<TypeId>,
+        place: Place,
+        depth: usize,
+    ) -> Result<String, InspectError> {
+        let Some(id) = self.info.resolve(ty) else {
+            return Err(InspectError::UnknownType);
+        };
+        let info = self.info;
+        match info.ty(id) {
+            Type::Base { size, encoding, .. } => {
+                let bytes = self.read(place, *size)?;
+                Ok(format_base(&bytes, *encoding))
+            }
+            Type::Pointer { target, size } => {
+                let addr = self.read_uint(place, *size)?;
+                if !is_char(info, *target) || addr == 0 {
+                    return Ok(format!("{:#x}", addr));
+                }
+                // Strings are read one at a time, as they may end just before unmapped memory
+                let mut bytes = vec![];
+                for i in 0..MAX_ELEMENTS {
+                    match self
+                        .vmstate
+                        .read_memory(self.hart, addr.wrapping_add(i).into(), 1)
+                    {
+                        Ok(byte) => bytes.push(byte[0]),
+                        Err(_) if i == 0 => return Ok(format!("{:#x}", addr)),
+                        Err(_) => break,
+                    }
+                    if bytes.last() == Some(&0) {
+                        break;
+                    }
+                }
+                Ok(format!("{:#x} {}", addr, format_string(&bytes)))
+            }
+            Type::Struct { members, .. } if depth < MAX_DEPTH => {
+                let Place::Memory(addr) = place else {
+                    return Err(InspectError::NotInMemory);
+                };
+                let mut fields = vec![];
+                for member in members {
+                    let value = self.format_typed(
+                        member.ty,
+                        Place::Memory(u64::from(addr).wrapping_add(member.offset).into()),
+                        depth + 1,
+                    )?;
+                    match &member.name {
+                        Some(name) => fields.push(format!("{} = {}", name, value)),
+                        None => fields.push(value),
+                    }
+                }
+                Ok(format!("{{{}}}", fields.join(", ")))
+            }
+            Type::Array { element, count } if depth < MAX_DEPTH => {
+                let Place::Memory(addr) = place else {
+                    return Err(InspectError::NotInMemory);
+                };
+                let count = count.unwrap_or(0);
+                if is_char(info, *element) {
+                    let bytes = self.read(place, count.min(MAX_ELEMENTS))?;
+                    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                    return Ok(format_string(&bytes[..len]));
+                }
+
+                let size = info.size_of(*element).ok_or(InspectError::UnknownType)?;
+                let mut elements = vec![];
+                for i in 0..count.min(MAX_ELEMENTS) {
+                    let place =
+                        Place::Memory(u64::from(addr).wrapping_add(i.wrapping_mul(size)).into());
+                    elements.push(self.format_typed(*element, place, depth + 1)?);
+                }
+                if count > MAX_ELEMENTS {
+                    elements.push("...".to_string());
+                }
+                Ok(format!("{{{}}}", elements.join(", ")))
+            }
+            Type::Struct { .. } | Type::Array { .. } => Ok("{...}".to_string()),
+            Type::Enum { size, values, .. } => {
+                let value = sign_extend(self.read_uint(place, *size)?, *size);
+                match values.iter().find(|(_, v)| *v == value) {
+                    Some((name, _)) => Ok(name.clone()),
+                    None => Ok(value.to_string()),
+                }
+            }
+            Type::Function => match place {
+                Place::Memory(addr) => Ok(format!("<function> {:#x}", u64::from(addr))),
+                _ => Ok("<function>".to_string()),
+            },
+            Type::Typedef { .. } | Type::Qualified { .. } | Type::Unknown => {
+                Err(InspectError::UnknownType)
+            }
+        }
+    }
+}
+
+/// Find a member by name, members of anonymous structs and unions are found as well. Returns the
+/// type of the member and its offset.
+fn find_member(info: &DebugInfo, members: &[Member], name: &str) -> Option<(Option<TypeId>, u64)> {
+    for member in members {
+        match &member.name {
+            Some(n) if n == name => return Some((member.ty, member.offset)),
+            Some(_) => {}
+            None => {
+                if let Some(Type::Struct { members, .. }) =
+                    info.resolve(member.ty).map(|t| info.ty(t))
+                {
+                    if let Some((ty, offset)) = find_member(info, members, name) {
+                        return Some((ty, member.offset + offset));
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+fn is_char(info: &DebugInfo, ty: Option<TypeId>) -> bool {
+    matches!(
+        info.resolve(ty).map(|t| info.ty(t)),
+        Some(Type::Base {
+            size: 1,
+            encoding: DW_ATE_SIGNED_CHAR | DW_ATE_UNSIGNED_CHAR,
+            ..
+        })
+    )
+}
+
+fn sign_extend(value: u64, size: u64) -> i64 {
+    let shift = 64 - 8 * size.clamp(1, 8);
+    ((value << shift) as i64) >> shift
+}
+
+fn format_base(bytes: &[u8], encoding: u8) -> String {
+    let mut buf = [0; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    let value = u64::from_le_bytes(buf);
+    let size = len as u64;
+
+    match encoding {
+        DW_ATE_BOOLEAN => (value != 0).to_string(),
+        DW_ATE_FLOAT if size == 4 => f32::from_bits(value as u32).to_string(),
+        DW_ATE_FLOAT if size == 8 => f64::from_bits(value).to_string(),
+        DW_ATE_SIGNED => sign_extend(value, size).to_string(),
+        DW_ATE_SIGNED_CHAR => format!("{} {}", sign_extend(value, size), format_char(value as u8)),
+        DW_ATE_UNSIGNED_CHAR => format!("{} {}", value, format_char(value as u8)),
+        // Unsigned and anything that is not understood
+        _ => value.to_string(),
+    }
+}
+
+fn format_char(byte: u8) -> String {
+    format!("'{}'", std::ascii::escape_default(byte))
+}
+
+fn format_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    let escaped: String = bytes[..len]
+        .iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect();
+    format!("\"{}\"", escaped)
+}
@@ -0,0 +1,273 @@
+use elf_load::dwarf::{
+    frame::CallFrameInfo,
+    info::{
+        DebugInfo, Function, Local, Location, Member, Type, Variable, DW_ATE_SIGNED,
+        DW_ATE_SIGNED_CHAR,
+    },
+    DwarfSections,
+};
+
+use crate::{
+    registers::IntRegister,
+    vmstate::{UnwindInfo, VMSettings, VMState, VMStateBuilder},
+    KB,
+};
+
+use super::{
+    expr::{parse, Expr},
+    InspectError, Inspector,
+};
+
+/// `struct task { int id; char name[8]; enum state state; struct task *next; } task_list[2]`,
+/// `struct task *current`, `char *greeting` and a function `main` with the locals `int i` on the
+/// stack and `int n` in a0
+fn info() -> DebugInfo {
+    let member = |name: &str, ty, offset| Member {
+        name: Some(name.to_string()),
+        ty: Some(ty),
+        offset,
+    };
+    let global = |name: &str, ty, addr| Variable {
+        name: name.to_string(),
+        ty: Some(ty),
+        location: Location::Address(elf_load::Address(addr)),
+    };
+    let local = |name: &str, location| Local {
+        variable: Variable {
+            name: name.to_string(),
+            ty: Some(0),
+            location,
+        },
+        parameter: false,
+        scope: None,
+    };
+
+    DebugInfo {
+        types: vec![
+            Type::Base {
+                name: "int".to_string(),
+                size: 4,
+                encoding: DW_ATE_SIGNED,
+            },
+            Type::Base {
+                name: "char".to_string(),
+                size: 1,
+                encoding: DW_ATE_SIGNED_CHAR,
+            },
+            Type::Array {
+                element: Some(1),
+                count: Some(8),
+            },
+            Type::Struct {
+                name: Some("task".to_string()),
+                size: 24,
+                members: vec![
+                    member("id", 0, 0),
+                    member("name", 2, 4),
+                    member("state", 5, 12),
+                    member("next", 4, 16),
+                ],
+                union: false,
+            },
+            Type::Pointer {
+                target: Some(3),
+                size: 8,
+            },
+            Type::Enum {
+                name: Some("state".to_string()),
+                size: 4,
+                values: vec![("RUNNING".to_string(), 0), ("SLEEPING".to_string(), 1)],
+            },
+            Type::Array {
+                element: Some(3),
+                count: Some(2),
+            },
+            Type::Pointer {
+                target: Some(1),
+                size: 8,
+            },
+        ],
+        globals: vec![
+            global("task_list", 6, 0x80000100),
+            global("current", 4, 0x80000200),
+            global("greeting", 7, 0x80000208),
+        ],
+        functions: vec![Function {
+            name: Some("main".to_string()),
+            low_pc: elf_load::Address(0x80000000),
+            high_pc: elf_load::Address(0x80000040),
+            frame_base: Location::Cfa,
+            locals: vec![
+                local("i", Location::FrameOffset(-20)),
+                local("n", Location::Register(10)),
+            ],
+        }],
+    }
+}
+
+/// A `.debug_frame` with a cie that sets the cfa to sp, and an fde for `main` at
+/// 0x80000000..0x80000040 that sets it to sp + 16
+const DEBUG_FRAME: &[u8] = &[
+    0x14, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x04, 0x00, 0x08, 0x00, 0x01, 0x78, 0x01, 0x0c,
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x0e, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+fn task(id: u32, name: &str, state: u32, next: u64) -> Vec<u8> {
+    let mut bytes = id.to_le_bytes().to_vec();
+    let mut padded = name.as_bytes().to_vec();
+    padded.resize(8, 0);
+    bytes.extend(padded);
+    bytes.extend(state.to_le_bytes());
+    bytes.extend(next.to_le_bytes());
+    bytes
+}
+
+fn vm() -> VMState {
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::new(VMSettings::default())
+        .set_hart_count(1)
+        .build()
+        .unwrap();
+    let mut write = |bytes: &[u8], addr: u64| vm.write_memory(0, bytes, addr.into()).unwrap();
+    write(&task(1, "init", 0, 0x80000118), 0x80000100);
+    write(&task(2, "idle", 1, 0), 0x80000118);
+    write(&0x80000118u64.to_le_bytes(), 0x80000200);
+    write(&0x80000300u64.to_le_bytes(), 0x80000208);
+    write(b"hi\n\0", 0x80000300);
+    write(&(-5i32).to_le_bytes(), 0x800003ec);
+
+    let hart = vm.get_hart_mut(0).unwrap();
+    hart.set_int_reg(IntRegister::X2, 0x800003f0);
+    hart.set_int_reg(IntRegister::X10, 1);
+    vm.set_unwind_info(UnwindInfo {
+        frames: CallFrameInfo::parse(&DwarfSections {
+            debug_frame: DEBUG_FRAME,
+            ..Default::default()
+        })
+        .unwrap(),
+        ..Default::default()
+    });
+    vm
+}
+
+fn print(vm: &mut VMState, expr: &str) -> Result<String, InspectError> {
+    let info = info();
+    Inspector::new(vm, 0, &info).print(expr)
+}
+
+#[test]
+fn parse_expr() {
+    let var = |name: &str| Box::new(Expr::Variable(name.to_string()));
+    assert_eq!(
+        parse("*a->b[0x10]").unwrap(),
+        Expr::Deref(Box::new(Expr::Index(
+            Box::new(Expr::Arrow(var("a"), "b".to_string())),
+            Box::new(Expr::Number(0x10))
+        )))
+    );
+    assert_eq!(
+        parse("(&a).b").unwrap(),
+        Expr::Member(Box::new(Expr::AddressOf(var("a"))), "b".to_string())
+    );
+    assert!(matches!(parse("a["), Err(InspectError::Syntax(_))));
+    assert!(matches!(parse("a b"), Err(InspectError::Syntax(_))));
+    assert!(matches!(parse("a - b"), Err(InspectError::Syntax(_))));
+}
+
+#[test]
+fn globals() {
+    let mut vm = vm();
+
+    assert_eq!(print(&mut vm, "task_list[0].state").unwrap(), "RUNNING");
+    assert_eq!(print(&mut vm, "task_list[1].id").unwrap(), "2");
+    assert_eq!(
+        print(&mut vm, "task_list[0]").unwrap(),
+        "{id = 1, name = \"init\", state = RUNNING, next = 0x80000118}"
+    );
+    assert_eq!(print(&mut vm, "current->name").unwrap(), "\"idle\"");
+    assert_eq!(
+        print(&mut vm, "task_list[0].next->state").unwrap(),
+        "SLEEPING"
+    );
+    assert_eq!(print(&mut vm, "(*current).id").unwrap(), "2");
+    assert_eq!(print(&mut vm, "greeting").unwrap(), "0x80000300 \"hi\\n\"");
+    assert_eq!(print(&mut vm, "*greeting").unwrap(), "104 'h'");
+    assert_eq!(
+        print(&mut vm, "&task_list[1]").unwrap(),
+        "(struct task *) 0x80000118"
+    );
+}
+
+#[test]
+fn locals() {
+    let mut vm = vm();
+
+    assert_eq!(print(&mut vm, "i").unwrap(), "-5");
+    assert_eq!(print(&mut vm, "n").unwrap(), "1");
+    assert_eq!(print(&mut vm, "task_list[n].name").unwrap(), "\"idle\"");
+
+    // The frame base is the cfa, which is not known without call frame information
+    vm.set_unwind_info(UnwindInfo::default());
+    assert!(matches!(
+        print(&mut vm, "i"),
+        Err(InspectError::UnsupportedLocation(_))
+    ));
+
+    // Locals are only visible inside their function
+    vm.get_hart_mut(0).unwrap().set_pc(0x80000040u64.into());
+    assert!(matches!(
+        print(&mut vm, "i"),
+        Err(InspectError::UnknownVariable(_))
+    ));
+}
+
+#[test]
+fn errors() {
+    let mut vm = vm();
+
+    assert!(matches!(
+        print(&mut vm, "missing"),
+        Err(InspectError::UnknownVariable(_))
+    ));
+    assert!(matches!(
+        print(&mut vm, "task_list.id"),
+        Err(InspectError::NotAStruct)
+    ));
+    assert!(matches!(
+        print(&mut vm, "current->missing"),
+        Err(InspectError::NoMember(_))
+    ));
+    assert!(matches!(
+        print(&mut vm, "*task_list[0].id"),
+        Err(InspectError::NotAPointer)
+    ));
+    assert!(matches!(
+        print(&mut vm, "&n"),
+        Err(InspectError::NotInMemory)
+    ));
+    assert!(matches!(
+        print(&mut vm, "task_list[1].next->id"),
+        Err(InspectError::MemoryError(_))
+    ));
+
+    // Addresses wrap around instead of overflowing, whatever a garbage pointer holds
+    assert_eq!(
+        print(&mut vm, "current[0xffffffffffffffff]").unwrap(),
+        print(&mut vm, "task_list[0]").unwrap()
+    );
+    vm.write_memory(0, &u64::MAX.to_le_bytes(), 0x80000128u64.into())
+        .unwrap();
+    assert!(matches!(
+        print(&mut vm, "current->next->next"),
+        Err(InspectError::MemoryError(_))
+    ));
+    assert!(matches!(
+        print(&mut vm, "*current->next"),
+        Err(InspectError::MemoryError(_))
+    ));
+    assert!(matches!(
+        print(&mut vm, "current->next[1]"),
+        Err(InspectError::MemoryError(_))
+    ));
+}
@@ -1,17 +1,22 @@
 #![allow(dead_code)]
 #![allow(unused)]
 
-mod decode;
+pub mod decode;
 pub mod devices;
 mod execute;
+pub mod gdb;
 mod hart;
+pub mod inspect;
 mod memory;
 
-pub use crate::hart::trap;
-pub use memory::{address::Address, KB, MB};
+pub use crate::hart::{privilege, registers, trap, CsrAddress};
+pub use memory::{address::Address, paging, pmp, KB, MB};
 
 #[cfg(test)]
 mod tests;
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
 #[cfg(test)]
 mod vm_tests;
 pub mod vmstate;
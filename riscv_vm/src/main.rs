@@ -1,45 +1,218 @@
 use std::{
-    fs,
-    io::{stdin, stdout, Write},
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{self, stderr, stdin, stdout, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     usize,
 };
 
-use elf_load::Elf;
+use elf_load::{
+    dwarf::{frame::CallFrameInfo, info::DebugInfo, line::LineTable},
+    symbol::SymbolTable,
+    Elf,
+};
+use enumflags2::BitFlags;
+#[cfg(unix)]
+use riscv_vm::devices::char_backend::PtyBackend;
 #[cfg(feature = "vga_text_buf")]
 use riscv_vm::devices::vga_text_mode::VgaTextMode;
+#[cfg(feature = "tui")]
+use riscv_vm::tui::Tui;
 use riscv_vm::{
-    devices::simple_uart::SimpleUart, vmstate::VMSettings, vmstate::VMStateBuilder, MB,
+    decode::{disassemble, Instruction},
+    devices::{
+        char_backend::{CharBackend, FileBackend, SocketBackend, StdioBackend},
+        ns16550a::Ns16550a,
+        Device,
+    },
+    gdb::{GdbError, GdbServer},
+    inspect::Inspector,
+    paging::{AddressTranslationMode, PteFlags, PteType, Satp},
+    pmp::AccessMode,
+    privilege::PrivilegeMode,
+    registers::IntRegister,
+    trace::LockstepError,
+    vmstate::{
+        ReverseError, StopReason, UnwindInfo, VMError, VMSettings, VMState, VMStateBuilder,
+        WatchKind,
+    },
+    Address, CsrAddress, MB,
 };
 
+/// Read when no startup file is given with `-x`
+const DEFAULT_INIT_FILE: &str = ".riscv_vm_init";
+
+/// Steps between checks for ctrl-c in `run` and `continue`
+const RUN_CHUNK: u64 = 100_000;
+
+/// Lines shown before and after the current one by `list`
+const LIST_CONTEXT: u64 = 5;
+
+/// Where the report is written when stepping fails
+const CRASH_REPORT_FILE: &str = "riscv_vm.crash";
+
+/// Where `gcore` writes the core file if no path is given
+const CORE_FILE: &str = "riscv_vm.core";
+
+/// Steps between checkpoints and the number of checkpoints kept for reverse execution
+const HISTORY_INTERVAL: u64 = 100_000;
+const HISTORY_CHECKPOINTS: usize = 32;
+
+/// Stores kept by `storelog on` if no capacity is given
+const STORE_LOG_CAPACITY: usize = 65_536;
+
+/// Bytes `x` reads at most at once
+const EXAMINE_LIMIT: usize = 65_536;
+
+/// Where the uart of each `-serial` is placed and the plic or aplic source it raises, the nth one
+/// is `n` strides and sources after the first
+const SERIAL_BASE: u64 = 0x10000000;
+const SERIAL_STRIDE: u64 = 0x1000;
+const SERIAL_IRQ: u32 = 10;
+
+struct Cli {
+    /// The hart commands apply to when no hart is given
+    focus: usize,
+    quickstep: bool,
+    breakpoints: BTreeSet<Address>,
+    interrupted: Arc<AtomicBool>,
+    /// The symbols of the kernel, addresses are printed and can be given relative to them
+    symbols: SymbolTable,
+    /// The source lines of the kernel's code, if it has debug information
+    lines: LineTable,
+    /// The variables and types of the kernel, for `print`
+    debug_info: DebugInfo,
+}
+
+/// Where a call returns to, the hart is back in the caller once its pc is there and its stack
+/// pointer is no lower than at the call.
+#[derive(Clone, Copy)]
+struct Return {
+    hart: usize,
+    pc: Address,
+    sp: u64,
+}
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     let bytes = fs::read(&args[1]).unwrap();
     let elf = Elf::from_bytes(bytes).unwrap();
 
-    #[cfg_attr(not(feature = "vga_text_buf"), allow(unused_mut))]
+    // With -aia the devices interrupt through an aplic and imsics instead of the plic
+    let aia = args.iter().any(|a| a == "-aia");
     let mut builder = VMStateBuilder::<{ 3 * MB }>::new(VMSettings {
         m_mode_swi_enable: true,
         s_mode_swi_enable: true,
+        shutdown_enable: true,
+        plic_enable: !aia,
+        aia_enable: aia,
+        exception_storm_limit: Some(1000),
         ..Default::default()
     })
-    .add_sync_device::<SimpleUart>(0x10000000u64.into())
     .set_hart_count(1);
 
+    // Without any -serial the first uart prints to stdout
+    let mut serials: Vec<&str> = args
+        .windows(2)
+        .filter(|a| a[0] == "-serial")
+        .map(|a| a[1].as_str())
+        .collect();
+    if serials.is_empty() {
+        serials.push("stdout");
+    }
+    for (i, spec) in serials.iter().enumerate() {
+        let backend = match serial_backend(spec) {
+            Ok(b) => b,
+            Err(e) => {
+                println!("Failed to open serial {}: {}", spec, e);
+                return;
+            }
+        };
+        let addr = SERIAL_BASE + i as u64 * SERIAL_STRIDE;
+        let uart = Ns16550a::new()
+            .with_backend(backend)
+            .with_interrupt(builder.interrupt_line(SERIAL_IRQ + i as u32));
+        builder = builder.add_sync_device_with(addr.into(), uart);
+    }
+    // The guest owns the terminal, so there is no prompt
+    let console = serials.contains(&"stdio");
+
     #[cfg(feature = "vga_text_buf")]
     let builder = builder.add_sync_device::<VgaTextMode>(0xB8000u64);
 
     let mut vmstate = builder.build().unwrap();
 
     vmstate.load_elf_kernel(&elf).unwrap();
+    // Every checkpoint is a copy of memory and every step is logged in between, so recording is
+    // only on when asked for
+    if args.iter().any(|a| a == "-record") {
+        vmstate.enable_history(HISTORY_INTERVAL, HISTORY_CHECKPOINTS);
+    }
+    vmstate.set_crash_report(Some(CRASH_REPORT_FILE.into()));
 
     // vmstate.step_hart_until(0, 0x2d8u64.into()).unwrap();
     // vmstate.dump_mem();
 
-    println!("Input a command or type help");
+    let mut cli = Cli {
+        focus: 0,
+        quickstep: false,
+        breakpoints: BTreeSet::new(),
+        interrupted: Arc::new(AtomicBool::new(false)),
+        symbols: elf.symbol_table().unwrap_or_else(|e| {
+            println!("Failed to read the symbol table: {:?}", e);
+            SymbolTable::default()
+        }),
+        lines: elf.line_table().unwrap_or_else(|e| {
+            println!("Failed to read the line table: {:?}", e);
+            LineTable::default()
+        }),
+        debug_info: elf.debug_info().unwrap_or_else(|e| {
+            println!("Failed to read the debug information: {:?}", e);
+            DebugInfo::default()
+        }),
+    };
+
+    vmstate.set_unwind_info(UnwindInfo {
+        symbols: cli.symbols.clone(),
+        frames: elf.call_frame_info().unwrap_or_else(|e| {
+            println!("Failed to read the call frame information: {:?}", e);
+            CallFrameInfo::default()
+        }),
+    });
+
+    let interrupted = cli.interrupted.clone();
+    let waker = vmstate.idle_waker();
+    ctrlc::set_handler(move || {
+        interrupted.store(true, Ordering::SeqCst);
+        waker.wake();
+    })
+    .unwrap();
+
+    let init_file = match args.iter().position(|a| a == "-x") {
+        Some(i) => args.get(i + 1).cloned(),
+        None if Path::new(DEFAULT_INIT_FILE).exists() => Some(DEFAULT_INIT_FILE.to_string()),
+        None => None,
+    };
 
-    let mut quickstep = false;
+    if let Some(init_file) = init_file {
+        if !source(&mut vmstate, &mut cli, &init_file) {
+            return;
+        }
+    }
+
+    if console {
+        println!("The terminal is connected to the guest, ctrl-c stops the vm");
+        execute(&mut vmstate, &mut cli, "continue");
+        return;
+    }
 
-    'cmdline: loop {
+    println!("Input a command or type help");
+
+    loop {
         print!("> ");
         let mut buf = String::new();
         stdout().flush().unwrap();
@@ -48,263 +221,1458 @@ fn main() {
             return;
         };
 
-        let args: Vec<&str> = buf.split(' ').collect();
-        if let Some(cmd) = args.first() {
-            match *cmd {
-                "step" =>
-                {
-                    #[allow(clippy::collapsible_else_if)]
-                    if let Some(count) = args.get(1) {
-                        let Ok(count) = count.parse::<usize>() else {
-                            println!("Invalid number of steps: {}", count);
-                            continue;
-                        };
-                        for _ in 0..count {
-                            if let Err(e) = vmstate.step(false) {
-                                println!("Stepping errored at {:?}", e);
-                                continue 'cmdline;
-                            }
-                        }
-                    } else {
-                        if let Err(e) = vmstate.step(false) {
-                            println!("Stepping errored at {:?}", e);
+        if !execute(&mut vmstate, &mut cli, buf) {
+            break;
+        }
+    }
+}
+
+/// Open the backend of a uart given with `-serial`: `stdout`, `stdio` for a console on the
+/// terminal, `file:<path>`, `unix:<path>`, `tcp:<port>` for telnet, or `pty`.
+fn serial_backend(spec: &str) -> io::Result<Box<dyn CharBackend>> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    Ok(match kind {
+        "stdout" => Box::new(StdioBackend::new()),
+        "stdio" => Box::new(StdioBackend::raw()?),
+        "file" => Box::new(FileBackend::create(arg)?),
+        #[cfg(unix)]
+        "unix" => {
+            println!("Serial console on unix socket {}", arg);
+            Box::new(SocketBackend::bind_unix(arg)?)
+        }
+        "tcp" => {
+            let port = arg
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid port"))?;
+            let backend = SocketBackend::bind_tcp(port)?;
+            let port = backend.port().unwrap();
+            println!("Serial console on telnet localhost {}", port);
+            Box::new(backend)
+        }
+        #[cfg(unix)]
+        "pty" => {
+            let backend = PtyBackend::open()?;
+            println!("Serial console on {}", backend.path().display());
+            Box::new(backend)
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected stdout, stdio, file:<path>, unix:<path>, tcp:<port> or pty",
+            ))
+        }
+    })
+}
+
+/// Step the vm once, returns false if stepping errored or hit a watchpoint
+fn step(vmstate: &mut VMState, verbose: bool) -> bool {
+    if let Err(e) = vmstate.step(verbose) {
+        println!("Stepping errored at {:?}", e);
+        return false;
+    }
+    if let Some(hit) = vmstate.step_watch_hit() {
+        println!("Stopped: {:?}", hit);
+        return false;
+    }
+    true
+}
+
+/// Execute every line in `file` as a command, returns false if one of them exits.
+fn source(vmstate: &mut VMState, cli: &mut Cli, file: &str) -> bool {
+    let commands = match fs::read_to_string(file) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Failed to read {}: {}", file, e);
+            return true;
+        }
+    };
+
+    for line in commands.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        println!("> {}", line);
+        if !execute(vmstate, cli, line) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Execute a single command, returns false if the cli should exit.
+fn execute(vmstate: &mut VMState, cli: &mut Cli, buf: &str) -> bool {
+    let args: Vec<&str> = buf.split(' ').collect();
+    if let Some(cmd) = args.first() {
+        match *cmd {
+            "step" =>
+            {
+                #[allow(clippy::collapsible_else_if)]
+                if let Some(count) = args.get(1) {
+                    let Ok(count) = count.parse::<usize>() else {
+                        println!("Invalid number of steps: {}", count);
+                        return true;
+                    };
+                    for _ in 0..count {
+                        if !step(vmstate, false) {
+                            return true;
                         }
                     }
+                } else {
+                    step(vmstate, false);
                 }
-                "stepv" =>
-                {
-                    #[allow(clippy::collapsible_else_if)]
-                    if let Some(count) = args.get(1) {
-                        let Ok(count) = count.parse::<usize>() else {
-                            println!("Invalid number of steps: {}", count);
-                            continue;
-                        };
-                        for _ in 0..count {
-                            if let Err(e) = vmstate.step(true) {
-                                println!("Stepping errored at {:?}", e);
-                                continue 'cmdline;
-                            }
+            }
+            "stepv" =>
+            {
+                #[allow(clippy::collapsible_else_if)]
+                if let Some(count) = args.get(1) {
+                    let Ok(count) = count.parse::<usize>() else {
+                        println!("Invalid number of steps: {}", count);
+                        return true;
+                    };
+                    for _ in 0..count {
+                        if !step(vmstate, true) {
+                            return true;
                         }
-                    } else {
-                        if let Err(e) = vmstate.step(true) {
-                            println!("Stepping errored at {:?}", e);
+                    }
+                } else {
+                    step(vmstate, true);
+                }
+            }
+            "quickstep" => {
+                cli.quickstep = !cli.quickstep;
+                if cli.quickstep {
+                    println!("quickstep enabled");
+                } else {
+                    println!("quickstep disabled");
+                }
+            }
+            "" if cli.quickstep =>
+            {
+                #[allow(clippy::collapsible_else_if)]
+                if let Some(count) = args.get(1) {
+                    let Ok(count) = count.parse::<usize>() else {
+                        println!("Invalid number of steps: {}", count);
+                        return true;
+                    };
+                    for _ in 0..count {
+                        if !step(vmstate, true) {
+                            return true;
                         }
                     }
+                } else {
+                    step(vmstate, true);
                 }
-                "quickstep" => {
-                    quickstep = !quickstep;
-                    if quickstep {
-                        println!("quickstep enabled");
-                    } else {
-                        println!("quickstep disabled");
+            }
+            "run" => {
+                let stop = run(vmstate, cli, false, None);
+                println!("Stopped: {:?}", stop);
+            }
+            "continue" | "c" => {
+                let stop = run(vmstate, cli, true, None);
+                print_stop(vmstate, cli, &stop);
+            }
+            "step-line" | "sl" | "next" | "n" => {
+                let over_calls = *cmd == "next" || *cmd == "n";
+                match step_line(vmstate, cli, over_calls) {
+                    Ok(()) => print_position(vmstate, cli),
+                    Err(stop) => print_stop(vmstate, cli, &stop),
+                }
+            }
+            "finish" | "fin" => match finish(vmstate, cli) {
+                Ok(()) => {
+                    print_position(vmstate, cli);
+                    let hart = vmstate.get_hart(cli.focus).unwrap();
+                    println!("a0 = {:#x}", hart.get_int_reg(IntRegister::X10));
+                }
+                Err(stop) => print_stop(vmstate, cli, &stop),
+            },
+            "list" | "l" => list(vmstate, cli, args.get(1).copied()),
+            "print" | "p" => {
+                let expr = args[1..].join(" ");
+                if expr.is_empty() {
+                    println!("Usage: print <expr>");
+                    return true;
+                }
+                match Inspector::new(vmstate, cli.focus, &cli.debug_info).print(&expr) {
+                    Ok(value) => println!("{} = {}", expr, value),
+                    Err(e) => println!("Printing {} failed with {:?}", expr, e),
+                }
+            }
+            "backtrace" | "bt" => print_backtrace(vmstate, cli, cli.focus),
+            "reverse-step" | "rs" => {
+                let count = match args.get(1).map(|c| c.parse::<u64>()) {
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        println!("Invalid number of steps: {}", args[1]);
+                        return true;
                     }
+                    None => 1,
+                };
+                if let Err(e) = vmstate.reverse_step(cli.focus, count) {
+                    print_reverse_error(e);
                 }
-                "" if quickstep =>
-                {
-                    #[allow(clippy::collapsible_else_if)]
-                    if let Some(count) = args.get(1) {
-                        let Ok(count) = count.parse::<usize>() else {
-                            println!("Invalid number of steps: {}", count);
-                            continue;
-                        };
-                        for _ in 0..count {
-                            if let Err(e) = vmstate.step(true) {
-                                println!("Stepping errored at {:?}", e);
-                                continue 'cmdline;
-                            }
+                if let Some(hart) = vmstate.get_hart(cli.focus) {
+                    println!("hart {} at {}", cli.focus, location(cli, hart.get_pc()));
+                }
+            }
+            "reverse-continue" | "rc" => {
+                let breakpoints = &cli.breakpoints;
+                match vmstate.reverse_continue(|h| breakpoints.contains(&h.get_pc())) {
+                    Ok(stop) => print_stop(vmstate, cli, &stop),
+                    Err(e) => {
+                        print_reverse_error(e);
+                        if let Some(hart) = vmstate.get_hart(cli.focus) {
+                            println!("hart {} at {}", cli.focus, location(cli, hart.get_pc()));
                         }
+                    }
+                }
+            }
+            "record" => match args.get(1).copied() {
+                Some("on") => vmstate.enable_history(HISTORY_INTERVAL, HISTORY_CHECKPOINTS),
+                Some("off") => vmstate.disable_history(),
+                _ => println!(
+                    "recording is {}",
+                    if vmstate.history_enabled() {
+                        "on"
                     } else {
-                        if let Err(e) = vmstate.step(true) {
-                            println!("Stepping errored at {:?}", e);
+                        "off"
+                    }
+                ),
+            },
+            "trace" => {
+                if let Err(e) = vmstate.stop_trace() {
+                    println!("Failed to write trace: {e}");
+                }
+                match args.get(1).copied() {
+                    Some("off") => {}
+                    Some("stderr") | None => vmstate.start_trace(stderr()),
+                    Some(path) => match File::create(path) {
+                        Ok(file) => vmstate.start_trace(BufWriter::new(file)),
+                        Err(e) => println!("Failed to open {path}: {e}"),
+                    },
+                }
+            }
+            "crash_report" => match args.get(1).copied() {
+                Some("off") => vmstate.set_crash_report(None),
+                Some(path) => vmstate.set_crash_report(Some(path.into())),
+                None => println!("Usage: crash_report <file|off>"),
+            },
+            "lockstep" => {
+                let Some(path) = args.get(1) else {
+                    println!("Usage: lockstep <file>");
+                    return true;
+                };
+                let reference = match File::open(path) {
+                    Ok(file) => BufReader::new(file),
+                    Err(e) => {
+                        println!("Failed to open {path}: {e}");
+                        return true;
+                    }
+                };
+                match vmstate.lockstep(reference) {
+                    Ok(matched) => println!("Matched all {matched} events of the reference"),
+                    Err(LockstepError::Diverged(divergence)) => println!("{divergence}"),
+                    Err(LockstepError::Parse(e)) => println!("Failed to read reference: {e:?}"),
+                    Err(LockstepError::Stopped(stop)) => print_stop(vmstate, cli, &stop),
+                }
+            }
+            "break" | "b" => {
+                let Some(target) = args.get(1) else {
+                    for bp in &cli.breakpoints {
+                        println!("{}", location(cli, *bp));
+                    }
+                    return true;
+                };
+
+                let Some(target) = parse_location(cli, target) else {
+                    println!("Invalid target, use 0xXXXX (hex) or a symbol");
+                    return true;
+                };
+                cli.breakpoints.insert(target);
+            }
+            "watch" => {
+                let Some(target) = args.get(1) else {
+                    for wp in vmstate.get_watchpoints() {
+                        println!("{:#x} {:?}", u64::from(wp.addr), wp.kind);
+                    }
+                    for w in vmstate.store_log().map_or(&[][..], |l| l.get_watches()) {
+                        println!(
+                            "{:#x} Write {} of {} (physical)",
+                            u64::from(w.addr),
+                            w.seen,
+                            w.count
+                        );
+                    }
+                    return true;
+                };
+
+                let Some(target) = parse_location(cli, target) else {
+                    println!("Invalid target, use 0xXXXX (hex) or a symbol");
+                    return true;
+                };
+
+                if let Some(count) = args.get(3) {
+                    let Some(count) = parse_value(count).filter(|c| args[2] == "w" && *c > 0)
+                    else {
+                        println!("Invalid count, use watch <addr> w <n> with n > 0");
+                        return true;
+                    };
+                    match vmstate.store_log_mut() {
+                        Some(log) => log.add_watch(target, count),
+                        None => println!("The store log is off, turn it on with storelog on"),
+                    }
+                    return true;
+                }
+
+                let kind = match args.get(2).copied() {
+                    Some("r") => WatchKind::Read,
+                    Some("w") | None => WatchKind::Write,
+                    Some("rw") => WatchKind::ReadWrite,
+                    Some(kind) => {
+                        println!("Invalid watch kind {}, use r, w or rw", kind);
+                        return true;
+                    }
+                };
+                vmstate.add_watchpoint(target, kind);
+            }
+            "delete" | "d" => {
+                let Some(target) = args.get(1) else {
+                    cli.breakpoints.clear();
+                    let watchpoints: Vec<_> =
+                        vmstate.get_watchpoints().iter().map(|w| w.addr).collect();
+                    for addr in watchpoints {
+                        vmstate.remove_watchpoint(addr);
+                    }
+                    if let Some(log) = vmstate.store_log_mut() {
+                        let watches: Vec<_> = log.get_watches().iter().map(|w| w.addr).collect();
+                        for addr in watches {
+                            log.remove_watch(addr);
                         }
                     }
+                    return true;
+                };
+
+                let Some(target) = parse_location(cli, target) else {
+                    println!("Invalid target, use 0xXXXX (hex) or a symbol");
+                    return true;
+                };
+                cli.breakpoints.remove(&target);
+                vmstate.remove_watchpoint(target);
+                if let Some(log) = vmstate.store_log_mut() {
+                    log.remove_watch(target);
                 }
-                "run" => vmstate.run().unwrap(),
-                "step_until" => {
-                    if let Some(target) = args.get(1) {
-                        match *target {
-                            "hart" => {
-                                let Some(index) = args.get(2) else {
-                                    println!("Missing hart index");
-                                    continue;
-                                };
-
-                                let Ok(index) = index.parse::<usize>() else {
-                                    println!("Invalid hard index: {}", index);
-                                    continue;
-                                };
-
-                                let Some(target) = args.get(3) else {
-                                    println!("Missing target");
-                                    continue;
-                                };
-
-                                let Some(target) = target.strip_prefix("0x") else {
-                                    println!("Invalid format for target, use 0xXXXX (hex)");
-                                    continue;
-                                };
-
-                                let Ok(target) = u64::from_str_radix(target, 16) else {
-                                    println!("Invalid format for target, use 0xXXXX (hex)");
-                                    continue;
-                                };
-                                if let Err(e) = vmstate.step_hart_until(index, target.into()) {
-                                    println!("Stepping errored at {:?}", e);
-                                }
+            }
+            cmd if cmd == "x" || cmd.starts_with("x/") => {
+                examine(
+                    vmstate,
+                    cli,
+                    cmd.strip_prefix("x/").unwrap_or(""),
+                    &args[1..],
+                );
+            }
+            "set" => set(vmstate, cli, &args[1..]),
+            "translate" => translate(vmstate, cli, &args[1..]),
+            "hart" => {
+                let Some(index) = args.get(1) else {
+                    for i in 0..vmstate.hart_count() {
+                        let hart = vmstate.get_hart(i).unwrap();
+                        println!(
+                            "{} hart {}: pc {:#x}{}",
+                            if i == cli.focus { "*" } else { " " },
+                            i,
+                            u64::from(hart.get_pc()),
+                            if vmstate.is_hart_frozen(i) {
+                                " (frozen)"
+                            } else {
+                                ""
                             }
-                            "all" => {
-                                let Some(target) = args.get(2) else {
-                                    println!("Missing target");
-                                    continue;
-                                };
-
-                                let Some(target) = target.strip_prefix("0x") else {
-                                    println!("Invalid format for target, use 0xXXXX (hex)");
-                                    continue;
-                                };
-
-                                let Ok(target) = u64::from_str_radix(target, 16) else {
-                                    println!("Invalid format for target, use 0xXXXX (hex)");
-                                    continue;
-                                };
-                                if let Err(e) = vmstate.step_all_until(target.into()) {
-                                    println!("Stepping errored at {:?}", e);
-                                }
+                        );
+                    }
+                    return true;
+                };
+
+                let Some(index) = parse_hart(vmstate, index) else {
+                    return true;
+                };
+                cli.focus = index;
+            }
+            "freeze" | "thaw" => {
+                let Some(index) = args.get(1) else {
+                    println!("Missing hart index");
+                    return true;
+                };
+
+                let Some(index) = parse_hart(vmstate, index) else {
+                    return true;
+                };
+                vmstate.set_hart_frozen(index, *cmd == "freeze");
+            }
+            "source" => {
+                let Some(file) = args.get(1) else {
+                    println!("Missing file");
+                    return true;
+                };
+                return source(vmstate, cli, file);
+            }
+            "gdb" => {
+                let Some(target) = args.get(1) else {
+                    println!("Missing port or socket path");
+                    return true;
+                };
+
+                let server = match target.parse::<u16>() {
+                    Ok(port) => GdbServer::bind_tcp(port),
+                    #[cfg(unix)]
+                    Err(_) => GdbServer::bind_unix(target),
+                    #[cfg(not(unix))]
+                    Err(_) => {
+                        println!("Invalid port: {}", target);
+                        return true;
+                    }
+                };
+
+                let server = match server {
+                    Ok(s) => s,
+                    Err(e) => {
+                        println!("Failed to listen on {}: {:?}", target, e);
+                        return true;
+                    }
+                };
+
+                println!("Waiting for gdb on {}", target);
+                match server.serve(vmstate) {
+                    Ok(_) => println!("gdb detached"),
+                    Err(GdbError::Vm(e)) => println!("gdb detached, the vm errored with {:?}", e),
+                    Err(GdbError::Reverse(e)) => {
+                        println!("gdb detached, reverse execution failed with {:?}", e)
+                    }
+                    Err(e) => println!("gdb connection errored with {:?}", e),
+                }
+            }
+            "step_until" => {
+                if let Some(target) = args.get(1) {
+                    match *target {
+                        "hart" => {
+                            let Some(index) = args.get(2) else {
+                                println!("Missing hart index");
+                                return true;
+                            };
+
+                            let Ok(index) = index.parse::<usize>() else {
+                                println!("Invalid hard index: {}", index);
+                                return true;
+                            };
+
+                            let Some(target) = args.get(3) else {
+                                println!("Missing target");
+                                return true;
+                            };
+
+                            let Some(target) = parse_location(cli, target) else {
+                                println!("Invalid target, use 0xXXXX (hex) or a symbol");
+                                return true;
+                            };
+                            if let Err(e) = vmstate.step_hart_until(index, target) {
+                                println!("Stepping errored at {:?}", e);
                             }
-                            _ => {
-                                println!("Invalid target for stepping");
-                                continue;
+                        }
+                        "all" => {
+                            let Some(target) = args.get(2) else {
+                                println!("Missing target");
+                                return true;
+                            };
+
+                            let Some(target) = parse_location(cli, target) else {
+                                println!("Invalid target, use 0xXXXX (hex) or a symbol");
+                                return true;
+                            };
+                            if let Err(e) = vmstate.step_all_until(target) {
+                                println!("Stepping errored at {:?}", e);
                             }
                         }
+                        _ => {
+                            println!("Invalid target for stepping");
+                            return true;
+                        }
                     }
                 }
-                "state" | "status" => {
-                    if let Some(target) = args.get(1) {
-                        match *target {
-                            "hart" => {
-                                let Some(index) = args.get(2) else {
-                                    println!("Missing hart index");
-                                    continue;
-                                };
-
-                                let Ok(index) = index.parse::<usize>() else {
-                                    println!("Invalid hard index: {}", index);
-                                    continue;
-                                };
-
-                                let Some(hart) = vmstate.get_hart(index) else {
-                                    println!("Hart with id {} doesn't exist", index);
-                                    continue;
-                                };
-
-                                println!("{:#?}", hart);
-                            }
-                            "inst" => {
-                                let Some(index) = args.get(2) else {
-                                    println!("Missing hart index");
-                                    continue;
-                                };
-
-                                let Ok(index) = index.parse::<usize>() else {
-                                    println!("Invalid hard index: {}", index);
-                                    continue;
-                                };
-
-                                let Some(_) = vmstate.get_hart(index) else {
-                                    println!("Hart with id {} doesn't exist", index);
-                                    continue;
-                                };
-
-                                match vmstate.fetch(index) {
-                                    Ok(inst) => println!("{:#?}", inst),
-                                    Err(e) => println!(
-                                        "Fetch of instruction for hart {} failed with error {:?}",
-                                        index, e
-                                    ),
-                                }
-                            }
-                            "pmp" => {
-                                let Some(index) = args.get(2) else {
-                                    println!("Missing hart index");
-                                    continue;
-                                };
-
-                                let Ok(index) = index.parse::<usize>() else {
-                                    println!("Invalid hard index: {}", index);
-                                    continue;
-                                };
-
-                                let Some(hart) = vmstate.get_hart(index) else {
-                                    println!("Hart with id {} doesn't exist", index);
-                                    continue;
-                                };
-
-                                println!("{:#?}", hart.get_csr().pmp);
-                            }
-                            "vmstate" => {
-                                println!("{:#?}", vmstate);
-                            }
-                            // "page_table" => {}
-                            _ => {
-                                println!("Invalid Subcommand for state")
+            }
+            "state" | "status" => {
+                if let Some(target) = args.get(1) {
+                    let index = match args.get(2) {
+                        Some(index) => {
+                            let Some(index) = parse_hart(vmstate, index) else {
+                                return true;
+                            };
+                            index
+                        }
+                        None => cli.focus,
+                    };
+
+                    match *target {
+                        "hart" => {
+                            println!("{:#?}", vmstate.get_hart(index).unwrap());
+                        }
+                        "inst" => match vmstate.fetch(index) {
+                            Ok((inst, compressed)) => {
+                                let pc = vmstate.get_hart(index).unwrap().get_pc();
+                                println!(
+                                    "{}: {}",
+                                    location(cli, pc),
+                                    inst.disassemble(pc, compressed)
+                                );
                             }
+                            Err(e) => println!(
+                                "Fetch of instruction for hart {} failed with error {:?}",
+                                index, e
+                            ),
+                        },
+                        "pmp" => {
+                            println!("{:#?}", vmstate.get_hart(index).unwrap().get_csr().pmp);
+                        }
+                        "vmstate" => {
+                            println!("{:#?}", vmstate);
+                        }
+                        "page_table" => print_page_table(vmstate, index),
+                        _ => {
+                            println!("Invalid Subcommand for state")
                         }
-                    } else {
-                        println!("{:#?}", vmstate);
                     }
+                } else {
+                    println!("{:#?}", vmstate);
+                }
+            }
+            "dump_mem" => {
+                #[allow(deprecated)]
+                vmstate.dump_mem();
+                println!("Dumped memory to mem.dump");
+            }
+            "gcore" => {
+                let devices = args.contains(&"devices");
+                let path = match args.get(1) {
+                    Some(&"devices") | None => CORE_FILE,
+                    Some(path) => path,
+                };
+                match vmstate.write_core(path, devices) {
+                    Ok(()) => println!("Wrote a core file to {}", path),
+                    Err(e) => println!("Writing a core file to {} failed with {:?}", path, e),
+                }
+            }
+            "storelog" => match args.get(1).copied() {
+                Some("on") => {
+                    let capacity = match args.get(2) {
+                        Some(capacity) => match parse_value(capacity) {
+                            Some(capacity) => capacity as usize,
+                            None => {
+                                println!("Invalid capacity {}", capacity);
+                                return true;
+                            }
+                        },
+                        None => STORE_LOG_CAPACITY,
+                    };
+                    vmstate.enable_store_log(capacity);
+                }
+                Some("off") => vmstate.disable_store_log(),
+                Some(arg) => println!("Invalid argument {}, use on or off", arg),
+                None => match vmstate.store_log() {
+                    Some(log) => println!(
+                        "{} stores logged, {} dropped",
+                        log.stores().count(),
+                        log.dropped()
+                    ),
+                    None => println!("The store log is off"),
+                },
+            },
+            "writes" => {
+                let Some(target) = args.get(1) else {
+                    println!("Usage: writes <addr> [size]");
+                    return true;
+                };
+                let Some(target) = parse_location(cli, target) else {
+                    println!("Invalid target, use 0xXXXX (hex) or a symbol");
+                    return true;
+                };
+                let size = match args.get(2) {
+                    Some(size) => match parse_value(size) {
+                        Some(size) => size,
+                        None => {
+                            println!("Invalid size {}", size);
+                            return true;
+                        }
+                    },
+                    None => 1,
+                };
+                let Some(log) = vmstate.store_log() else {
+                    println!("The store log is off, turn it on with storelog on");
+                    return true;
+                };
+
+                if log.dropped() > 0 {
+                    println!("{} older stores were dropped", log.dropped());
+                }
+                for store in log.writes_to(target, size) {
+                    println!(
+                        "instret {} hart {} at {}: {} bytes to {:#x}, {:#x} -> {:#x}",
+                        store.instret,
+                        store.hart,
+                        location(cli, store.pc),
+                        store.size,
+                        u64::from(store.addr),
+                        store.old,
+                        store.new
+                    );
+                }
+            }
+            "stats" => {
+                let stats = vmstate.stats();
+                println!("steps: {}", stats.steps);
+                let (time, freq) = (vmstate.time(), vmstate.timebase_freq());
+                println!(
+                    "time: {:.6}s, {} ticks at {} Hz",
+                    time as f64 / freq as f64,
+                    time,
+                    freq
+                );
+                println!(
+                    "idle: {:.3}s in {} sleeps",
+                    stats.idle_time.as_secs_f64(),
+                    stats.idle_sleeps
+                );
+            }
+            "mem_map" => {
+                #[allow(deprecated)]
+                vmstate.print_mem_map();
+            }
+            #[cfg(feature = "tui")]
+            "tui" => {
+                let mut tui = Tui::new(vmstate, &mut cli.breakpoints, cli.focus);
+                if let Err(e) = tui.run() {
+                    println!("Terminal ui errored with {:?}", e);
                 }
-                "dump_mem" => {
-                    #[allow(deprecated)]
-                    vmstate.dump_mem();
-                    println!("Dumped memory to mem.dump");
+                cli.focus = tui.focus();
+            }
+            "help" | "h" => print_help(),
+            "exit" | "q" => return false,
+            _ => println!("Invalid Command"),
+        }
+    }
+    true
+}
+
+/// Run until something stops the vm or ctrl-c is pressed, breakpoints are only honoured if
+/// `breakpoints` is set. With `until` the vm also stops, as if at a breakpoint, once a call
+/// returns.
+fn run(vmstate: &mut VMState, cli: &Cli, breakpoints: bool, until: Option<Return>) -> StopReason {
+    cli.interrupted.store(false, Ordering::SeqCst);
+    loop {
+        let stop = vmstate.run_until(Some(RUN_CHUNK), |h| {
+            (breakpoints && cli.breakpoints.contains(&h.get_pc()))
+                || until.is_some_and(|r| {
+                    h.get_hart_id() == r.hart as u64
+                        && h.get_pc() == r.pc
+                        && h.get_int_reg(IntRegister::X2) as u64 >= r.sp
+                })
+        });
+
+        if !matches!(
+            stop,
+            StopReason::BudgetExhausted { .. } | StopReason::Woken { .. }
+        ) || cli.interrupted.load(Ordering::SeqCst)
+        {
+            return stop;
+        }
+    }
+}
+
+/// Step the focused hart one instruction. A call is stepped over, stopping at breakpoints in
+/// it, if `over_calls` is set or the called code has no line information. Returns why the vm
+/// stopped if that was for another reason.
+fn step_instruction(vmstate: &mut VMState, cli: &Cli, over_calls: bool) -> Result<(), StopReason> {
+    let hart = vmstate.get_hart(cli.focus).unwrap();
+    let (pc, sp) = (hart.get_pc(), hart.get_int_reg(IntRegister::X2) as u64);
+    let return_pc = match vmstate.fetch(cli.focus) {
+        Ok((Instruction::JAL { rd, .. } | Instruction::JALR { rd, .. }, compressed))
+            if rd == IntRegister::X1 || rd == IntRegister::X5 =>
+        {
+            Some(u64::from(pc) + if compressed { 2 } else { 4 })
+        }
+        _ => None,
+    };
+
+    // Only the focused hart steps, the others stay where they are
+    let hart = cli.focus;
+    match vmstate.step_hart(hart, false) {
+        Ok(()) => {}
+        Err(VMError::MBreak) => return Err(StopReason::EBreak { hart, pc }),
+        Err(error) => return Err(StopReason::Error { hart, pc, error }),
+    }
+    if let Some(&StopReason::Watchpoint {
+        hart,
+        pc,
+        addr,
+        access,
+    }) = vmstate.step_watch_hit()
+    {
+        return Err(StopReason::Watchpoint {
+            hart,
+            pc,
+            addr,
+            access,
+        });
+    }
+
+    let Some(return_pc) = return_pc else {
+        return Ok(());
+    };
+    let target = vmstate.get_hart(cli.focus).unwrap().get_pc();
+    if !over_calls && cli.lines.row(elf_load::Address(target.into())).is_some() {
+        return Ok(());
+    }
+
+    let until = Return {
+        hart: cli.focus,
+        pc: return_pc.into(),
+        sp,
+    };
+    match run(vmstate, cli, true, Some(until)) {
+        StopReason::Breakpoint { hart, pc } if hart == until.hart && pc == until.pc => Ok(()),
+        stop => Err(stop),
+    }
+}
+
+/// The file and line of the focused hart
+fn current_line(vmstate: &VMState, cli: &Cli) -> Option<(usize, u64)> {
+    let pc = vmstate.get_hart(cli.focus).unwrap().get_pc();
+    cli.lines
+        .row(elf_load::Address(pc.into()))
+        .map(|r| (r.file, r.line))
+}
+
+/// Step the focused hart until it reaches the start of a statement on another source line,
+/// see [`step_instruction()`] for `over_calls`.
+fn step_line(vmstate: &mut VMState, cli: &Cli, over_calls: bool) -> Result<(), StopReason> {
+    let start = current_line(vmstate, cli);
+    cli.interrupted.store(false, Ordering::SeqCst);
+    loop {
+        step_instruction(vmstate, cli, over_calls)?;
+
+        let pc = vmstate.get_hart(cli.focus).unwrap().get_pc();
+        if cli.lines.is_statement(elf_load::Address(pc.into()))
+            && current_line(vmstate, cli) != start
+        {
+            return Ok(());
+        }
+        if cli.interrupted.load(Ordering::SeqCst) {
+            return Err(StopReason::BudgetExhausted {
+                hart: cli.focus,
+                pc,
+            });
+        }
+    }
+}
+
+/// Step the focused hart until it returns from the current function, calls are stepped over.
+fn finish(vmstate: &mut VMState, cli: &Cli) -> Result<(), StopReason> {
+    cli.interrupted.store(false, Ordering::SeqCst);
+    loop {
+        let returning = matches!(
+            vmstate.fetch(cli.focus),
+            Ok((
+                Instruction::JALR {
+                    rd: IntRegister::X0,
+                    rs1: IntRegister::X1,
+                    ..
+                },
+                _
+            ))
+        );
+        step_instruction(vmstate, cli, true)?;
+        if returning {
+            return Ok(());
+        }
+
+        if cli.interrupted.load(Ordering::SeqCst) {
+            let pc = vmstate.get_hart(cli.focus).unwrap().get_pc();
+            return Err(StopReason::BudgetExhausted {
+                hart: cli.focus,
+                pc,
+            });
+        }
+    }
+}
+
+/// `file:line` of `addr` followed by the text of that line, if the file can be read
+fn source_line(cli: &Cli, addr: Address) -> Option<String> {
+    let location = cli.lines.location(elf_load::Address(addr.into()))?;
+    let text = fs::read_to_string(location.file).ok().and_then(|source| {
+        let line = location.line.checked_sub(1)?;
+        source.lines().nth(line as usize).map(str::to_string)
+    });
+    Some(match text {
+        Some(text) => format!("{}:{}\t{}", location.file, location.line, text.trim()),
+        None => format!("{}:{}", location.file, location.line),
+    })
+}
+
+/// Print where the focused hart is, with the source line if there is one
+fn print_position(vmstate: &VMState, cli: &Cli) {
+    let pc = vmstate.get_hart(cli.focus).unwrap().get_pc();
+    println!("hart {} at {}", cli.focus, location(cli, pc));
+    if let Some(line) = source_line(cli, pc) {
+        println!("{}", line);
+    }
+}
+
+/// `list [location]`, the source around a location or the pc of the focused hart
+fn list(vmstate: &VMState, cli: &Cli, target: Option<&str>) {
+    let addr = match target {
+        Some(target) => {
+            let Some(addr) = parse_location(cli, target) else {
+                println!("Invalid location, use 0xXXXX (hex), a symbol or file:line");
+                return;
+            };
+            addr
+        }
+        None => vmstate.get_hart(cli.focus).unwrap().get_pc(),
+    };
+
+    let Some(location) = cli.lines.location(elf_load::Address(addr.into())) else {
+        println!("No line information for {}", location(cli, addr));
+        return;
+    };
+    let source = match fs::read_to_string(location.file) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Failed to read {}: {}", location.file, e);
+            return;
+        }
+    };
+
+    let first = location.line.saturating_sub(LIST_CONTEXT).max(1);
+    for (number, text) in (first..).zip(source.lines().skip(first as usize - 1)) {
+        if number > location.line + LIST_CONTEXT {
+            break;
+        }
+        let marker = if number == location.line { '>' } else { ' ' };
+        println!("{}{:>5}\t{}", marker, number, text);
+    }
+}
+
+/// Print the call stack of a hart, with the source line of each frame if there is one
+fn print_backtrace(vmstate: &mut VMState, cli: &Cli, hart: usize) {
+    for (i, frame) in vmstate.backtrace(hart).iter().enumerate() {
+        println!("#{:<2} {}", i, frame);
+        if let Some(line) = source_line(cli, frame.lookup_pc()) {
+            println!("    {}", line);
+        }
+    }
+}
+
+/// The satp of a hart
+fn satp(vmstate: &VMState, hart: usize) -> Satp {
+    let satp = vmstate.get_hart(hart).unwrap().get_csr();
+    Satp::from_bits(satp.get_csr(CsrAddress::new(0x180))).expect("satp holds a valid mode")
+}
+
+/// Flags of a pte like `rw-u-ad`, without V
+fn pte_flags(flags: BitFlags<PteFlags>) -> String {
+    [
+        (PteFlags::R, 'r'),
+        (PteFlags::W, 'w'),
+        (PteFlags::X, 'x'),
+        (PteFlags::U, 'u'),
+        (PteFlags::G, 'g'),
+        (PteFlags::A, 'a'),
+        (PteFlags::D, 'd'),
+    ]
+    .iter()
+    .map(|(flag, c)| if flags.contains(*flag) { *c } else { '-' })
+    .collect()
+}
+
+/// A page or mapping size like `4K` or `2M`
+fn page_size(size: u64) -> String {
+    let units = ["K", "M", "G", "T", "P"];
+    let mut size = size >> 10;
+    let mut unit = 0;
+    while size >= 1024 && size.is_multiple_of(1024) && unit < units.len() - 1 {
+        size >>= 10;
+        unit += 1;
+    }
+    format!("{}{}", size, units[unit])
+}
+
+/// `state page_table`, the mappings of the page table of a hart coalesced into ranges
+fn print_page_table(vmstate: &VMState, hart: usize) {
+    let satp = satp(vmstate, hart);
+    if satp.mode == AddressTranslationMode::Bare {
+        println!("satp is bare, hart {} does not translate addresses", hart);
+        return;
+    }
+    println!("{:?} page table at {:#x}", satp.mode, satp.ppn << 12);
+    for mapping in vmstate.page_mappings(hart) {
+        let end = u64::from(mapping.virt) + mapping.size - 1;
+        println!(
+            "{:#018x}-{:#018x} -> {:#x} {} {:>5} in {} pages",
+            u64::from(mapping.virt),
+            end,
+            u64::from(mapping.phys),
+            pte_flags(mapping.flags),
+            page_size(mapping.size),
+            page_size(mapping.page_size)
+        );
+    }
+}
+
+/// `translate <vaddr> [r|w|x]`, walk the page table of the focused hart and show each pte read
+fn translate(vmstate: &VMState, cli: &Cli, args: &[&str]) {
+    let Some(addr) = args.first().and_then(|a| parse_location(cli, a)) else {
+        println!("Usage: translate <vaddr> [r|w|x]");
+        return;
+    };
+    let mode = match args.get(1).copied() {
+        Some("r") | None => AccessMode::Read,
+        Some("w") => AccessMode::Write,
+        Some("x") => AccessMode::Exec,
+        Some(mode) => {
+            println!("Invalid access {}, use r, w or x", mode);
+            return;
+        }
+    };
+
+    let satp = satp(vmstate, cli.focus);
+    let (steps, result) = vmstate.translate(cli.focus, addr, mode);
+    for step in steps {
+        print!(
+            "level {} pte {:#018x} at {:#x}: ",
+            step.level,
+            step.pte,
+            u64::from(step.pte_addr)
+        );
+        match PteType::from_bytes(step.pte, satp.mode) {
+            PteType::Branch(pte) | PteType::Leaf(pte) if !pte.flags().contains(PteFlags::V) => {
+                println!("invalid")
+            }
+            PteType::Branch(pte) => println!("table at {:#x}", pte.ppn() << 12),
+            PteType::Leaf(pte) => println!(
+                "{} page at {:#x} {}",
+                page_size(4096 << (9 * step.level)),
+                pte.ppn() << 12,
+                pte_flags(*pte.flags())
+            ),
+        }
+    }
+    match result {
+        Ok(phys) => println!("{:#x} -> {:#x}", u64::from(addr), u64::from(phys)),
+        Err(fault) => println!("{:?} of {:#x} faults: {}", mode, u64::from(addr), fault),
+    }
+}
+
+fn print_stop(vmstate: &mut VMState, cli: &Cli, stop: &StopReason) {
+    match stop {
+        StopReason::BudgetExhausted { hart, pc } | StopReason::Woken { hart, pc } => {
+            println!("Interrupted, hart {} at {}", hart, location(cli, *pc))
+        }
+        StopReason::Breakpoint { hart, pc } => {
+            println!("Breakpoint hit, hart {} at {}", hart, location(cli, *pc))
+        }
+        StopReason::Watchpoint {
+            hart,
+            pc,
+            addr,
+            access,
+        } => println!(
+            "Watchpoint hit, hart {} at {}: {:?} of {}",
+            hart,
+            location(cli, *pc),
+            access,
+            location(cli, *addr)
+        ),
+        stop => println!("Stopped: {:?}", stop),
+    }
+
+    if let Some(hart) = vmstate.get_hart(stop.hart()) {
+        println!("next pc {}", location(cli, hart.get_pc()));
+        if let Some(line) = source_line(cli, hart.get_pc()) {
+            println!("{}", line);
+        }
+    }
+
+    if let StopReason::Breakpoint { hart, .. } | StopReason::EBreak { hart, .. } = stop {
+        print_backtrace(vmstate, cli, *hart);
+    }
+}
+
+fn print_reverse_error(error: ReverseError) {
+    match error {
+        ReverseError::HistoryDisabled => println!("Recording is off, enable it with record on"),
+        ReverseError::HistoryStart => println!("Reached the start of the recorded history"),
+        ReverseError::Snapshot(e) => {
+            println!("Restoring a checkpoint failed with {:?}", e);
+            println!("Recording was turned off");
+        }
+        ReverseError::DeviceError(e) => {
+            println!("Replaying a device failed with {:?}", e);
+            println!("Recording was turned off");
+        }
+    }
+}
+
+/// `x/<n><fmt><size> [-v] <addr>`, fmt is one of x, d, u, c or i and size one of b, h, w or g.
+fn examine(vmstate: &mut VMState, cli: &Cli, spec: &str, args: &[&str]) {
+    let digits = spec.chars().take_while(char::is_ascii_digit).count();
+    let count = if digits == 0 {
+        1
+    } else {
+        let Ok(count) = spec[..digits].parse::<usize>() else {
+            println!("Invalid count: {}", &spec[..digits]);
+            return;
+        };
+        count
+    };
+
+    let mut fmt = 'x';
+    let mut size = 4;
+    for c in spec[digits..].chars() {
+        match c {
+            'x' | 'd' | 'u' | 'c' | 'i' => fmt = c,
+            'b' => size = 1,
+            'h' => size = 2,
+            'w' => size = 4,
+            'g' => size = 8,
+            c => {
+                println!("Invalid format or size: {}", c);
+                return;
+            }
+        }
+    }
+
+    let virt = args.first() == Some(&"-v");
+    let Some(addr) = args.get(if virt { 1 } else { 0 }) else {
+        println!("Missing address");
+        return;
+    };
+
+    let Some(addr) = parse_location(cli, addr) else {
+        println!("Invalid address, use 0xXXXX (hex) or a symbol");
+        return;
+    };
+
+    // Instructions are at most 4 bytes
+    if fmt == 'i' {
+        size = 4;
+    }
+
+    let Some(len) = count.checked_mul(size).filter(|len| *len <= EXAMINE_LIMIT) else {
+        println!(
+            "Too many values, at most {} bytes can be examined",
+            EXAMINE_LIMIT
+        );
+        return;
+    };
+
+    let bytes = if virt {
+        vmstate.read_memory(cli.focus, addr, len)
+    } else {
+        vmstate.read_phys(addr, len)
+    };
+
+    let bytes = match bytes {
+        Ok(b) => b,
+        Err(e) => {
+            println!("Reading memory failed with {:?}", e);
+            return;
+        }
+    };
+
+    if fmt == 'i' {
+        for inst in disassemble(&bytes, addr).iter().take(count) {
+            let pc = inst.pc.unwrap();
+            println!("{:#018x}{}: {}", u64::from(pc), symbolize(cli, pc), inst);
+        }
+        return;
+    }
+
+    let per_line = if fmt == 'c' { 16 } else { 16 / size };
+    for (line, values) in bytes.chunks(size * per_line).enumerate() {
+        print!(
+            "{:#018x}:",
+            u64::from(addr) + (line * size * per_line) as u64
+        );
+        for value in values.chunks(size) {
+            let mut buf = [0u8; 8];
+            buf[..size].copy_from_slice(value);
+            let value = u64::from_le_bytes(buf);
+            let shift = 64 - size * 8;
+            match fmt {
+                'x' => print!(" {:#0width$x}", value, width = size * 2 + 2),
+                'd' => print!(" {}", ((value << shift) as i64) >> shift),
+                'u' => print!(" {}", value),
+                'c' => print!(" {:?}", value as u8 as char),
+                _ => unreachable!(),
+            }
+        }
+        println!();
+    }
+}
+
+/// `set reg <name|pc> <value>`, `set csr <name|0xNNN> <value>` or
+/// `set mem[/<size>] [-v] <addr> <value>`
+fn set(vmstate: &mut VMState, cli: &Cli, args: &[&str]) {
+    let Some(target) = args.first() else {
+        println!("Missing target, use reg, csr or mem");
+        return;
+    };
+
+    match *target {
+        "reg" => {
+            let (Some(name), Some(value)) = (args.get(1), args.get(2)) else {
+                println!("Usage: set reg <name> <value>");
+                return;
+            };
+
+            let Some(value) = parse_value(value) else {
+                println!("Invalid value: {}", value);
+                return;
+            };
+
+            let hart = vmstate.get_hart_mut(cli.focus).unwrap();
+            if *name == "pc" {
+                hart.set_pc(value.into());
+            } else {
+                match name.parse::<IntRegister>() {
+                    Ok(reg) => hart.set_int_reg(reg, value as i64),
+                    Err(e) => println!("Invalid register: {}", e.0),
                 }
-                "mem_map" => {
-                    #[allow(deprecated)]
-                    vmstate.print_mem_map();
+            }
+        }
+        "csr" => {
+            let (Some(name), Some(value)) = (args.get(1), args.get(2)) else {
+                println!("Usage: set csr <name> <value>");
+                return;
+            };
+
+            let csr = match parse_addr(name) {
+                Some(addr) => CsrAddress::from(u64::from(addr) as u16),
+                None => {
+                    let Some(csr) = CsrAddress::from_name(name) else {
+                        println!("Invalid csr: {}", name);
+                        return;
+                    };
+                    csr
                 }
-                "help" | "h" => {
-                    println!("step [count]:");
-                    println!("\tIf count is given step all hearts that many cycles");
-                    println!("\tOtherwise step all harts once cycle.");
-                    println!();
-                    println!("stepv [count]:");
-                    println!("\tIf count is given step all hearts that many cycles");
-                    println!("\tOtherwise step all harts once cycle.");
-                    println!("\tPrint the vmstate and instruction on each step.");
-                    println!();
-                    println!("step_until hart <id> <target>:");
-                    println!("step_until all <target>:");
-                    println!("\tStep either one hart or all harts until they hit a ");
-                    println!("\tgiven address, or they have steped 10000 cycles");
-                    println!("\twhichever condition is met first.");
-                    println!();
-                    println!("state hart <hart_id>:");
-                    println!("state pmp <hart_id>:");
-                    println!("state inst <hart_id>:");
-                    println!("state vmstate:");
-                    println!("state:");
-                    println!("\tPrint the state of the given hart/pmp, the ");
-                    println!("\tentire vm or the current instruction in the");
-                    println!("\tgiven hart.");
-                    println!();
-                    println!("dump_mem:");
-                    println!("\tDump the vm's memory to mem.dump for analisys");
-                    println!("\tusing meman");
-                    println!();
-                    println!("mem_map:");
-                    println!("\t Print a (crude) map of the vm's memory");
-                    println!();
-                    println!("run:");
-                    println!("\tRun the vm until an mbreak instruction or fatal");
-                    println!("\terror is hit. (Option for the vm to request ");
-                    println!("\tshutdown will be added).");
-                    println!();
-                    println!("help:");
-                    println!("\tPrint this");
+            };
+
+            let Some(value) = parse_value(value) else {
+                println!("Invalid value: {}", value);
+                return;
+            };
+
+            let hart = vmstate.get_hart_mut(cli.focus).unwrap();
+            if let Err(e) = hart
+                .get_csr_mut()
+                .write_csr(csr, value, PrivilegeMode::Machine, false)
+            {
+                println!("Writing {} failed with {:?}", csr, e);
+            }
+        }
+        cmd if cmd == "mem" || cmd.starts_with("mem/") => {
+            let size = match cmd.strip_prefix("mem/") {
+                None | Some("w") => 4,
+                Some("b") => 1,
+                Some("h") => 2,
+                Some("g") => 8,
+                Some(size) => {
+                    println!("Invalid size: {}", size);
+                    return;
                 }
-                "exit" | "q" => break,
-                _ => println!("Invalid Command"),
+            };
+
+            let virt = args.get(1) == Some(&"-v");
+            let args = &args[if virt { 2 } else { 1 }..];
+            let (Some(addr), Some(value)) = (args.first(), args.get(1)) else {
+                println!("Usage: set mem[/size] [-v] <addr> <value>");
+                return;
+            };
+
+            let Some(addr) = parse_location(cli, addr) else {
+                println!("Invalid address, use 0xXXXX (hex) or a symbol");
+                return;
+            };
+
+            let Some(value) = parse_value(value) else {
+                println!("Invalid value: {}", value);
+                return;
+            };
+
+            let bytes = &value.to_le_bytes()[..size];
+            let result = if virt {
+                vmstate.write_memory(cli.focus, bytes, addr)
+            } else {
+                vmstate.write_phys(bytes, addr)
+            };
+
+            if let Err(e) = result {
+                println!("Writing memory failed with {:?}", e);
             }
         }
+        _ => println!("Invalid target for set, use reg, csr or mem"),
+    }
+}
+
+fn parse_addr(addr: &str) -> Option<Address> {
+    let addr = addr.strip_prefix("0x")?;
+    u64::from_str_radix(addr, 16).ok().map(Address::from)
+}
+
+/// A hex address, a source line like `main.c:12` or a symbol optionally followed by an offset
+/// like `main+0x1c`
+fn parse_location(cli: &Cli, location: &str) -> Option<Address> {
+    if let Some(addr) = parse_addr(location) {
+        return Some(addr);
+    }
+
+    if let Some((file, line)) = location.rsplit_once(':') {
+        if let Ok(line) = line.parse::<u64>() {
+            let addr = cli.lines.addresses(file, line).first()?.0;
+            return Some(Address::from(addr));
+        }
+    }
+
+    let (name, offset) = match location.split_once('+') {
+        Some((name, offset)) => (name, parse_value(offset)?),
+        None => (location, 0),
+    };
+    let symbol = cli.symbols.by_name(name)?;
+    Some(Address::from(symbol.value.0.wrapping_add(offset)))
+}
+
+/// The address followed by the symbol it is in, like `0x80000010 <main+0x1c>`
+fn location(cli: &Cli, addr: Address) -> String {
+    format!("{:#x}{}", u64::from(addr), symbolize(cli, addr))
+}
+
+/// ` <symbol+offset>` if addr is in a symbol, empty otherwise
+fn symbolize(cli: &Cli, addr: Address) -> String {
+    match cli.symbols.by_address(elf_load::Address(addr.into())) {
+        Some((symbol, 0)) => format!(" <{}>", symbol.name),
+        Some((symbol, offset)) => format!(" <{}+{:#x}>", symbol.name, offset),
+        None => String::new(),
+    }
+}
+
+/// A hex value prefixed with 0x or a (possibly negative) decimal value
+fn parse_value(value: &str) -> Option<u64> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        value
+            .parse::<u64>()
+            .ok()
+            .or_else(|| value.parse::<i64>().ok().map(|v| v as u64))
+    }
+}
+
+fn parse_hart(vmstate: &VMState, index: &str) -> Option<usize> {
+    let Ok(index) = index.parse::<usize>() else {
+        println!("Invalid hard index: {}", index);
+        return None;
+    };
+
+    if index >= vmstate.hart_count() {
+        println!("Hart with id {} doesn't exist", index);
+        return None;
+    }
+    Some(index)
+}
+
+fn print_help() {
+    println!("step [count]:");
+    println!("\tIf count is given step all hearts that many cycles");
+    println!("\tOtherwise step all harts once cycle.");
+    println!();
+    println!("stepv [count]:");
+    println!("\tIf count is given step all hearts that many cycles");
+    println!("\tOtherwise step all harts once cycle.");
+    println!("\tPrint the vmstate and instruction on each step.");
+    println!();
+    println!("step_until hart <id> <target>:");
+    println!("step_until all <target>:");
+    println!("\tStep either one hart or all harts until they hit a ");
+    println!("\tgiven address, or they have steped 10000 cycles");
+    println!("\twhichever condition is met first.");
+    println!("\tAddresses can also be given as a symbol of the");
+    println!("\tkernel, optionally with an offset like main+0x1c,");
+    println!("\tor as a source line like main.c:12.");
+    println!();
+    println!("state hart [hart_id]:");
+    println!("state pmp [hart_id]:");
+    println!("state inst [hart_id]:");
+    println!("state page_table [hart_id]:");
+    println!("state vmstate:");
+    println!("state:");
+    println!("\tPrint the state of the given hart/pmp, the ");
+    println!("\tentire vm or the current instruction in the");
+    println!("\tgiven hart, defaults to the focused hart.");
+    println!("\tpage_table lists the mappings of the page table");
+    println!("\tof the hart with their flags and page sizes.");
+    println!();
+    println!("dump_mem:");
+    println!("\tDump the vm's memory to mem.dump for analisys");
+    println!("\tusing meman");
+    println!();
+    println!("gcore [file] [devices]:");
+    println!("\tWrite an elf core file of the vm to file, riscv_vm.core");
+    println!("\tby default, with the contents of device memory if");
+    println!("\tdevices is given. Open it with gdb <kernel> <file>.");
+    println!();
+    println!("stats:");
+    println!("\tPrint how many steps the vm took, the time of mtime and");
+    println!("\thow long the host slept while all harts waited for an");
+    println!("\tinterrupt.");
+    println!();
+    println!("mem_map:");
+    println!("\t Print a (crude) map of the vm's memory");
+    println!();
+    println!("run:");
+    println!("\tRun the vm until an ebreak instruction or fatal");
+    println!("\terror is hit, all harts are idle or the guest");
+    println!("\trequests shutdown (via the test finisher at 0x100000).");
+    println!("\tCtrl-C stops the vm.");
+    println!();
+    println!("continue, c:");
+    println!("\tLike run, but also stop at breakpoints.");
+    println!();
+    println!("step-line, sl:");
+    println!("\tStep the focused hart until it reaches another source");
+    println!("\tline, calls into code without line information are");
+    println!("\tstepped over.");
+    println!();
+    println!("next, n:");
+    println!("\tLike step-line, but step over all calls.");
+    println!();
+    println!("finish, fin:");
+    println!("\tRun the focused hart until the current function");
+    println!("\treturns, and print the returned a0.");
+    println!();
+    println!("list [location], l [location]:");
+    println!("\tShow the source around location, or around the pc of");
+    println!("\tthe focused hart.");
+    println!();
+    println!("print <expr>, p <expr>:");
+    println!("\tPrint a variable as seen by the focused hart, expr is a");
+    println!("\tvariable with member accesses, indexing, * and &, for");
+    println!("\texample task_list[0].state.");
+    println!();
+    println!("backtrace, bt:");
+    println!("\tPrint the call stack of the focused hart, it is also");
+    println!("\tprinted when a breakpoint or ebreak stops a hart. Trap");
+    println!("\thandlers are unwound to where the trap was taken.");
+    println!();
+    println!("reverse-step [count], rs [count]:");
+    println!("\tUndo count (default 1) steps of the focused hart, and");
+    println!("\twhatever the other harts did in the meantime.");
+    println!();
+    println!("reverse-continue, rc:");
+    println!("\tGo back to the previous breakpoint or watchpoint hit.");
+    println!();
+    println!("record [on|off]:");
+    println!("\tStart or stop recording the history reverse-step and");
+    println!("\treverse-continue need, recording starts off unless");
+    println!("\tgiven -record. Starting discards any existing history.");
+    println!("\tRecording keeps up to 32 copies of memory and logs");
+    println!("\tevery step, which uses a lot of memory and slows the vm.");
+    println!();
+    println!("trace [file|stderr|off]:");
+    println!("\tWrite a line in spike's commit log format for every");
+    println!("\tretired instruction and trap to file, or stderr by");
+    println!("\tdefault, or stop tracing.");
+    println!();
+    println!("crash_report <file|off>:");
+    println!("\tWhere to write a report of the harts, recently");
+    println!("\texecuted instructions and memory when stepping fails");
+    println!("\tor a hart is stuck taking exceptions, defaults to");
+    println!("\triscv_vm.crash. A summary is printed to stderr.");
+    println!();
+    println!("lockstep <file>:");
+    println!("\tRun the vm alongside a reference trace in spike's");
+    println!("\tcommit log format and stop at the first instruction");
+    println!("\tor trap that differs from it.");
+    println!();
+    println!("break [addr], b [addr]:");
+    println!("\tSet a breakpoint at addr, a symbol or a source line,");
+    println!("\tor list all breakpoints.");
+    println!();
+    println!("watch [addr] [r|w|rw]:");
+    println!("\tStop run and continue when a hart reads and/or writes addr");
+    println!("\t(as seen by the hart), defaults to w. Without an");
+    println!("\taddress list all watchpoints.");
+    println!();
+    println!("watch <addr> w <n>:");
+    println!("\tStop run and continue on the nth write to the physical");
+    println!("\taddress addr from now on, needs the store log.");
+    println!();
+    println!("storelog [on [capacity]|off]:");
+    println!("\tLog the last capacity (default 65536) stores harts");
+    println!("\tmake, or show how many stores are logged.");
+    println!();
+    println!("writes <addr> [size]:");
+    println!("\tList the logged stores to the size (default 1) bytes at");
+    println!("\tthe physical address addr, with the instret, hart, pc and");
+    println!("\tthe old and new value.");
+    println!();
+    println!("delete [addr], d [addr]:");
+    println!("\tRemove the breakpoint and watchpoints at addr, or all");
+    println!("\tof them if no address is given.");
+    println!();
+    println!("x/<n><fmt><size> [-v] <addr>:");
+    println!("\tExamine n values at addr, fmt is one of x (hex), d");
+    println!("\t(signed), u (unsigned), c (char) or i (instruction),");
+    println!("\tsize one of b, h, w or g. With -v addr is translated");
+    println!(
+        "\tby the focused hart. At most {} bytes are read.",
+        EXAMINE_LIMIT
+    );
+    println!();
+    println!("translate <vaddr> [r|w|x]:");
+    println!("\tWalk the page table of the focused hart for a read");
+    println!("\t(default), write or fetch of vaddr and show each step.");
+    println!();
+    println!("set reg <name|pc> <value>:");
+    println!("set csr <name|0xNNN> <value>:");
+    println!("set mem[/size] [-v] <addr> <value>:");
+    println!("\tSet a register or csr of the focused hart, or memory.");
+    println!();
+    println!("hart [id]:");
+    println!("\tFocus the given hart, or list all harts.");
+    println!();
+    println!("freeze <id>:");
+    println!("thaw <id>:");
+    println!("\tStop stepping the given hart, or resume doing so.");
+    println!();
+    println!("source <file>:");
+    println!("\tExecute the commands in file, one per line. The file");
+    println!("\tgiven with -x or .riscv_vm_init is sourced on startup.");
+    println!();
+    println!("gdb <port|socket path>:");
+    println!("\tWait for gdb to attach on the given localhost");
+    println!("\tport or unix socket and hand control to it");
+    println!("\tuntil it detaches.");
+    println!();
+    #[cfg(feature = "tui")]
+    {
+        println!("tui:");
+        println!("\tSwitch to a full screen view of the focused hart,");
+        println!("\tits registers, csrs, memory and the uart output.");
+        println!("\tKeys: s step, c continue, r run, b toggle a");
+        println!("\tbreakpoint at the cursor, j/k move the cursor, tab");
+        println!("\tfocus the next hart, [/] change the register the");
+        println!("\tmemory view follows, f show float registers, any");
+        println!("\tkey stops running, q returns to the prompt.");
+        println!();
     }
+    println!("help:");
+    println!("\tPrint this");
 }
@@ -0,0 +1,42 @@
+use std::fmt::Display;
+
+use crate::hart::privilege::PrivilegeMode;
+
+use super::{address::Address, paging::PageWalkFault, pmp::AccessMode, pmp::PmpFault};
+
+/// Why an access of a hart raised an access or page fault, taken with
+/// [`Memory::take_fault()`](super::Memory::take_fault()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultDiagnostic {
+    /// The address used by the hart, before translation
+    pub addr: Address,
+    pub mode: AccessMode,
+    /// The privilege the access was checked with, after MPRV
+    pub privilege: PrivilegeMode,
+    /// Whether MPRV made `privilege` differ from the mode of the hart
+    pub mprv: bool,
+    pub cause: FaultCause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultCause {
+    Page(PageWalkFault),
+    Pmp(PmpFault),
+}
+
+impl Display for FaultDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} of {:#x} in {:?} mode{}, ",
+            self.mode,
+            u64::from(self.addr),
+            self.privilege,
+            if self.mprv { " (MPRV)" } else { "" }
+        )?;
+        match &self.cause {
+            FaultCause::Page(fault) => write!(f, "page walk failed at {}", fault),
+            FaultCause::Pmp(fault) => write!(f, "{}", fault),
+        }
+    }
+}
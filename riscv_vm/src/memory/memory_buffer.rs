@@ -22,6 +22,12 @@ pub trait MemoryBuffer {
     fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError>;
 
     fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError>;
+
+    /// Read like a debugger, without the side effects a read by a hart can have, like popping a
+    /// fifo or claiming an interrupt. Buffers whose reads have none can keep the default.
+    fn peek_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        self.read_bytes(addr, size)
+    }
 }
 
 #[derive(Debug)]
@@ -2,6 +2,7 @@ use std::ops::{Range, RangeInclusive};
 
 use super::{address::Address, DeviceRegionId, MemoryError};
 
+/// The end of the range of a region is the first address past it, so regions can be adjacent
 #[derive(Debug)]
 pub enum MemoryRegion {
     Ram(RangeInclusive<Address>),
@@ -35,8 +36,14 @@ impl MemoryMap {
         Self(vec![MemoryRegion::Ram(ram)])
     }
 
+    pub(super) fn regions(&self) -> &[MemoryRegion] {
+        &self.0
+    }
+
     pub(super) fn find(&self, addr: Address) -> Option<&MemoryRegion> {
-        self.0.iter().find(|r| r.range().contains(&addr))
+        self.0
+            .iter()
+            .find(|r| *r.range().start() <= addr && addr < *r.range().end())
     }
 
     pub(super) fn fit(&self, range: Range<Address>) -> Result<&MemoryRegion, MemoryMapError> {
@@ -65,5 +72,5 @@ impl MemoryMap {
 }
 
 fn overlap<T: Ord>(a: RangeInclusive<T>, b: RangeInclusive<T>) -> bool {
-    a.start() <= b.end() && b.start() <= a.end()
+    a.start() < b.end() && b.start() < a.end()
 }
@@ -21,28 +21,37 @@ use crate::{
         privilege::{self, PrivilegeMode},
         Hart,
     },
-    vmstate::timer::MTimer,
+    trace::{MemAccess, MemAccessKind},
+    vmstate::{timer::MTimer, SnapshotError, SnapshotReader, SnapshotWriter},
 };
 
 use self::{
     address::{Address, VirtAddress},
+    fault::{FaultCause, FaultDiagnostic},
     memory_buffer::{MemoryBuffer, MemoryBufferError},
     memory_map::{MemoryMap, MemoryMapError, MemoryRegion},
     paging::{walk_page_table, AccessContext, AddressTranslationMode, PageError, Satp},
     pmp::{AccessMode, PmpCfg, PMP},
+    store_log::{Store, StoreLog},
+    watchpoint::{WatchHit, WatchKind, Watchpoint},
 };
 
 pub mod address;
+pub mod fault;
 pub mod memory_buffer;
 mod memory_map;
 pub mod paging;
 pub mod pmp;
+pub mod store_log;
 #[cfg(test)]
 mod tests;
+pub mod watchpoint;
 
 pub const KB: usize = 1024;
 pub const MB: usize = 1024 * KB;
 
+const SNAPSHOT_PAGE_SIZE: usize = 4 * KB;
+
 type DeviceRegionId = usize;
 
 pub struct Memory {
@@ -52,6 +61,15 @@ pub struct Memory {
     device_regions: IntMap<DeviceRegionId, Arc<RwLock<dyn MemoryBuffer>>>,
     reservations: IntMap<u64, Range<Address>>,
     next_region_id: DeviceRegionId,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    /// Why the last failed access of a hart failed
+    fault: Option<FaultDiagnostic>,
+    /// Loads and stores by harts since the last [`Memory::start_access_log()`], for tracing
+    access_log: Option<Vec<MemAccess>>,
+    store_log: Option<StoreLog>,
+    /// Don't log stores while set, for writes that didn't come from a running hart
+    store_log_paused: bool,
 }
 
 pub struct MainMemoryBuffer(Box<[u8]>);
@@ -59,7 +77,11 @@ pub struct MainMemoryBuffer(Box<[u8]>);
 pub struct MemoryWindow<'a> {
     mem: &'a mut Memory,
     hartid: u64,
+    pc: Address,
+    instret: u64,
     privilege: PrivilegeMode,
+    /// Whether MPRV changed `privilege` from the mode of the hart
+    mprv: bool,
     pmp: Option<&'a PMP>,
     paging: Satp,
     mxr: bool,
@@ -128,6 +150,12 @@ impl Memory {
             device_regions: IntMap::default(),
             reservations: IntMap::default(),
             next_region_id: 0,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            fault: None,
+            access_log: None,
+            store_log: None,
+            store_log_paused: false,
         }
     }
 
@@ -166,6 +194,28 @@ impl Memory {
         }
     }
 
+    /// Like [`Memory::read_bytes()`], but devices are read with
+    /// [`MemoryBuffer::peek_bytes()`], so the read has no side effects
+    pub fn peek_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryError> {
+        // A debugger may ask for any address, a range past the end of the address space is
+        // simply out of bounds
+        let Some(end) = u64::from(addr).checked_add(size as u64) else {
+            return Err(MemoryError::OutOfBoundsRead(addr));
+        };
+        match self.memory_map.fit(addr..end.into()) {
+            Ok(r) => match r {
+                MemoryRegion::Ram(r) => Ok(self.main_buffer.read_bytes(addr - *r.start(), size)?),
+                // Rom regions have no backing memory yet, a debugger should not take the vm down
+                MemoryRegion::Rom(_) => Err(MemoryError::OutOfBoundsRead(addr)),
+                MemoryRegion::IO(o, r) => self.device_regions[o]
+                    .read()?
+                    .peek_bytes(addr - *r.start(), size)
+                    .map_err(Into::into),
+            },
+            Err(_) => Err(MemoryError::OutOfBoundsRead(addr)),
+        }
+    }
+
     /// NOTE, does not do atomic checks, pmp checks or page table walks
     pub fn fetch(&self, addr: Address, privilege: PrivilegeMode) -> Result<u32, MemoryError> {
         match self.memory_map.fit(addr..(addr + 4u64)) {
@@ -216,14 +266,18 @@ impl Memory {
 
     pub fn window<'a>(&'a mut self, hart: &'a Hart) -> MemoryWindow {
         let (mxr, sum) = hart.get_csr().get_mxr_sum();
+        let privilege = if hart.get_csr().get_status().mprv {
+            hart.get_csr().get_status().mpp
+        } else {
+            hart.privilege()
+        };
         MemoryWindow {
             mem: self,
             hartid: hart.get_hart_id(),
-            privilege: if hart.get_csr().get_status().mprv {
-                hart.get_csr().get_status().mpp
-            } else {
-                hart.privilege()
-            },
+            pc: hart.get_pc(),
+            instret: hart.get_csr().get_instret(),
+            privilege,
+            mprv: privilege != hart.privilege(),
             pmp: hart.pmp_enable().then(|| &hart.get_csr().pmp),
             paging: hart.get_csr().get_satp(),
             mxr,
@@ -266,10 +320,229 @@ impl Memory {
     pub fn get_map(&self) -> &MemoryMap {
         &self.memory_map
     }
+
+    /// A line per region of the memory map with its range and kind
+    pub(crate) fn describe_map(&self) -> Vec<String> {
+        self.memory_map
+            .regions()
+            .iter()
+            .map(|region| {
+                let kind = match region {
+                    MemoryRegion::Ram(_) => "ram".to_string(),
+                    MemoryRegion::Rom(_) => "rom".to_string(),
+                    MemoryRegion::IO(id, _) => format!("device {id}"),
+                };
+                let range = region.range();
+                format!(
+                    "{:#018x}-{:#018x} {kind}",
+                    u64::from(*range.start()),
+                    u64::from(*range.end())
+                )
+            })
+            .collect()
+    }
+
+    /// The address of main memory and its contents
+    pub(crate) fn ram(&self) -> (Address, &[u8]) {
+        let start = *self.memory_map.regions()[0].range().start();
+        (start, &self.main_buffer.0)
+    }
+
+    /// The address and contents of each device region. Devices are read like a hart would, 8
+    /// bytes at a time, the bytes of reads that fail are left 0.
+    pub(crate) fn device_contents(&self) -> Vec<(Address, Vec<u8>)> {
+        self.memory_map
+            .regions()
+            .iter()
+            .filter_map(|region| {
+                let MemoryRegion::IO(id, range) = region else {
+                    return None;
+                };
+                let size = u64::from(*range.end()) - u64::from(*range.start());
+                let device = self.device_regions[id].read().ok()?;
+                let mut bytes = Vec::with_capacity(size as usize);
+                for offset in (0..size).step_by(8) {
+                    let len = 8.min(size - offset) as usize;
+                    let read = device.peek_bytes(offset.into(), len);
+                    bytes.extend(read.unwrap_or_else(|_| vec![0; len]));
+                }
+                Some((*range.start(), bytes))
+            })
+            .collect()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Remove all watchpoints on the given address
+    pub fn remove_watchpoint(&mut self, addr: Address) {
+        self.watchpoints.retain(|w| w.addr != addr);
+    }
+
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Get and clear the first watchpoint hit since the last call
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    /// Get and clear why the last access that failed a pmp check or page table walk failed
+    pub fn take_fault(&mut self) -> Option<FaultDiagnostic> {
+        self.fault.take()
+    }
+
+    /// Start recording the loads and stores harts make
+    pub(crate) fn start_access_log(&mut self) {
+        self.access_log = Some(Vec::new());
+    }
+
+    /// Stop recording accesses and get those made since the log was started
+    pub(crate) fn take_access_log(&mut self) -> Vec<MemAccess> {
+        self.access_log.take().unwrap_or_default()
+    }
+
+    /// Log the stores harts make, keeping the last `capacity` ones. Replaces an existing log.
+    pub fn enable_store_log(&mut self, capacity: usize) {
+        self.store_log = Some(StoreLog::new(capacity));
+    }
+
+    pub fn disable_store_log(&mut self) {
+        self.store_log = None;
+    }
+
+    pub fn get_store_log(&self) -> Option<&StoreLog> {
+        self.store_log.as_ref()
+    }
+
+    pub fn get_store_log_mut(&mut self) -> Option<&mut StoreLog> {
+        self.store_log.as_mut()
+    }
+
+    /// Stop or resume logging stores, returns whether logging was paused before
+    pub(crate) fn pause_store_log(&mut self, paused: bool) -> bool {
+        std::mem::replace(&mut self.store_log_paused, paused)
+    }
+
+    /// Write the size of main memory and the layout of the memory map, used to check a snapshot
+    /// belongs to this machine.
+    pub(crate) fn save_layout(&self, snapshot: &mut SnapshotWriter) {
+        snapshot.write_u64(self.main_buffer.size());
+        let regions = self.memory_map.regions();
+        snapshot.write_u64(regions.len() as u64);
+        for region in regions {
+            snapshot.write_u8(match region {
+                MemoryRegion::Ram(_) => 0,
+                MemoryRegion::Rom(_) => 1,
+                MemoryRegion::IO(..) => 2,
+            });
+            snapshot.write_address(*region.range().start());
+            snapshot.write_address(*region.range().end());
+        }
+    }
+
+    /// Save main memory and reservations, device memory is saved by the devices themselves.
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        let pages: Vec<_> = self
+            .main_buffer
+            .0
+            .chunks(SNAPSHOT_PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(|b| *b != 0))
+            .collect();
+        snapshot.write_u64(pages.len() as u64);
+        for (i, page) in pages {
+            snapshot.write_u64(i as u64);
+            snapshot.write_bytes(page);
+        }
+
+        let mut reservations: Vec<_> = self.reservations.iter().collect();
+        reservations.sort_by_key(|(hart, _)| **hart);
+        snapshot.write_u64(reservations.len() as u64);
+        for (hart, range) in reservations {
+            snapshot.write_u64(*hart);
+            snapshot.write_address(range.start);
+            snapshot.write_address(range.end);
+        }
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.main_buffer.0.fill(0);
+        for _ in 0..snapshot.read_u64()? {
+            let start = (snapshot.read_u64()? as usize)
+                .checked_mul(SNAPSHOT_PAGE_SIZE)
+                .ok_or(SnapshotError::Corrupt)?;
+            let page = snapshot.read_bytes()?;
+            self.main_buffer
+                .0
+                .get_mut(start..start + page.len())
+                .ok_or(SnapshotError::Corrupt)?
+                .copy_from_slice(page);
+        }
+
+        self.reservations.clear();
+        for _ in 0..snapshot.read_u64()? {
+            let hart = snapshot.read_u64()?;
+            let range = snapshot.read_address()?..snapshot.read_address()?;
+            self.reservations.insert(hart, range);
+        }
+        Ok(())
+    }
+
+    fn log_access(&mut self, addr: Address, size: usize, kind: MemAccessKind) {
+        if let Some(log) = &mut self.access_log {
+            log.push(MemAccess { addr, size, kind });
+        }
+    }
+
+    /// Read main memory without going through devices, reading those could have side effects
+    fn read_ram(&self, addr: Address, size: usize) -> Option<Vec<u8>> {
+        match self.memory_map.fit(addr..(addr + size as u64)) {
+            Ok(MemoryRegion::Ram(r)) => self.main_buffer.read_bytes(addr - *r.start(), size).ok(),
+            _ => None,
+        }
+    }
+
+    fn log_store(&mut self, store: Store) {
+        if self.store_log_paused {
+            return;
+        }
+        let Some(log) = &mut self.store_log else {
+            return;
+        };
+        if let Some(addr) = log.push(store) {
+            self.watch_hit.get_or_insert(WatchHit {
+                hart: store.hart,
+                addr,
+                access: WatchKind::Write,
+            });
+        }
+    }
+
+    fn check_watchpoints(&mut self, hart: u64, addr: Address, size: usize, access: WatchKind) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let range = addr..(addr + size as u64);
+        if let Some(w) = self
+            .watchpoints
+            .iter()
+            .find(|w| w.kind.matches(access) && range.contains(&w.addr))
+        {
+            self.watch_hit = Some(WatchHit {
+                hart,
+                addr: w.addr,
+                access,
+            });
+        }
+    }
 }
 
 impl MemoryWindow<'_> {
     pub fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryError> {
+        let virt_addr = addr;
         let addr = if self.paging.mode != AddressTranslationMode::Bare
             && self.privilege != PrivilegeMode::Machine
         {
@@ -283,25 +556,26 @@ impl MemoryWindow<'_> {
                     mxr: self.mxr,
                     sum: self.sum,
                 },
-            )
-            .map_err(|e| match e {
-                PageError::AccessFault => MemoryError::PmpDeniedWrite,
-                PageError::PageFault => MemoryError::PageFaultWrite,
-            }) {
+            ) {
                 Ok(a) => a,
-                Err(e) => {
+                Err(fault) => {
                     // let mut w = File::create("./mem.dump").unwrap();
                     // writeln!(&mut w, "{:?}", &self.mem);
-                    return Err(e);
+                    self.fault(virt_addr, AccessMode::Write, FaultCause::Page(fault));
+                    return Err(match fault.error() {
+                        PageError::AccessFault => MemoryError::PmpDeniedWrite,
+                        PageError::PageFault => MemoryError::PageFaultWrite,
+                    });
                 }
             }
         } else {
             addr
         };
-        if !self.pmp.map_or_else(
-            || true,
-            |pmp| pmp.check(addr, self.privilege, AccessMode::Write),
-        ) {
+        if let Some(Err(fault)) = self
+            .pmp
+            .map(|pmp| pmp.check_detailed(addr, self.privilege, AccessMode::Write))
+        {
+            self.fault(virt_addr, AccessMode::Write, FaultCause::Pmp(fault));
             return Err(MemoryError::PmpDeniedWrite);
         };
         // Remove all reservations that
@@ -310,44 +584,99 @@ impl MemoryWindow<'_> {
             let range = addr..(addr + bytes.len() as u64);
             v.start >= range.end || range.start >= v.end
         });
-        self.mem.write_bytes(bytes, addr)
+        let len = bytes.len().min(8);
+        let old = match self.mem.store_log {
+            Some(_) => Some(self.mem.read_ram(addr, len).unwrap_or_default()),
+            None => None,
+        };
+        self.mem.write_bytes(bytes, addr)?;
+        self.mem
+            .check_watchpoints(self.hartid, virt_addr, bytes.len(), WatchKind::Write);
+        let mut value = [0; 8];
+        value[..len].copy_from_slice(&bytes[..len]);
+        if let Some(old) = old {
+            let mut old_value = [0; 8];
+            old_value[..old.len()].copy_from_slice(&old);
+            self.mem.log_store(Store {
+                hart: self.hartid,
+                pc: self.pc,
+                instret: self.instret,
+                addr: virt_addr,
+                phys: addr,
+                size: bytes.len(),
+                old: u64::from_le_bytes(old_value),
+                new: u64::from_le_bytes(value),
+            });
+        }
+        self.mem.log_access(
+            virt_addr,
+            bytes.len(),
+            MemAccessKind::Store(u64::from_le_bytes(value)),
+        );
+        Ok(())
     }
 
     pub fn read_bytes(&mut self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryError> {
+        let virt_addr = addr;
+        let addr = self.translate_read(addr)?;
+        // Remove all reservations that
+        // contain the address we write to
+        self.mem.reservations.retain(|_, v| {
+            let range = addr..(addr + size as u64);
+            v.start >= range.end || range.start >= v.end
+        });
+        let bytes = self.mem.read_bytes(addr, size)?;
+        self.mem
+            .check_watchpoints(self.hartid, virt_addr, size, WatchKind::Read);
+        self.mem.log_access(virt_addr, size, MemAccessKind::Load);
+        Ok(bytes)
+    }
+
+    /// Read like [`MemoryWindow::read_bytes()`] for a debugger, devices are read with
+    /// [`MemoryBuffer::peek_bytes()`] and reservations, watchpoints and the access log are left
+    /// alone
+    pub fn peek_bytes(&mut self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryError> {
+        let addr = self.translate_read(addr)?;
+        self.mem.peek_bytes(addr, size)
+    }
+
+    /// The physical address of a read from `addr`, after the page table walk and pmp checks
+    fn translate_read(&mut self, addr: Address) -> Result<Address, MemoryError> {
+        let virt_addr = addr;
         let addr = if self.paging.mode != AddressTranslationMode::Bare
             && self.privilege != PrivilegeMode::Machine
         {
-            walk_page_table(
+            match walk_page_table(
                 VirtAddress::from_address(addr, self.paging.mode),
                 self.paging,
                 self,
                 AccessContext {
-                    mode: AccessMode::Write,
+                    mode: AccessMode::Read,
                     privilege: self.privilege,
                     mxr: self.mxr,
                     sum: self.sum,
                 },
-            )
-            .map_err(|e| match e {
-                PageError::AccessFault => MemoryError::PmpDeniedRead,
-                PageError::PageFault => MemoryError::PageFaultRead,
-            })?
+            ) {
+                Ok(a) => a,
+                Err(fault) => {
+                    self.fault(virt_addr, AccessMode::Read, FaultCause::Page(fault));
+                    return Err(match fault.error() {
+                        PageError::AccessFault => MemoryError::PmpDeniedRead,
+                        PageError::PageFault => MemoryError::PageFaultRead,
+                    });
+                }
+            }
         } else {
             addr
         };
-        if !self.pmp.map_or_else(
-            || true,
-            |pmp| pmp.check(addr, self.privilege, AccessMode::Read),
-        ) {
+        if let Some(Err(fault)) = self
+            .pmp
+            .map(|pmp| pmp.check_detailed(addr, self.privilege, AccessMode::Read))
+        {
+            self.fault(virt_addr, AccessMode::Read, FaultCause::Pmp(fault));
             return Err(MemoryError::PmpDeniedRead);
         }
-        // Remove all reservations that
-        // contain the address we write to
-        self.mem.reservations.retain(|_, v| {
-            let range = addr..(addr + size as u64);
-            v.start >= range.end || range.start >= v.end
-        });
-        self.mem.read_bytes(addr, size)
+        Ok(addr)
     }
 
     pub fn write_conditional(&mut self, bytes: &[u8], addr: Address) -> Result<bool, MemoryError> {
@@ -400,6 +729,7 @@ impl MemoryWindow<'_> {
     }
 
     pub fn fetch(&mut self, addr: Address) -> Result<u32, MemoryError> {
+        let virt_addr = addr;
         let addr = if self.paging.mode != AddressTranslationMode::Bare
             && self.privilege != PrivilegeMode::Machine
         {
@@ -413,26 +743,27 @@ impl MemoryWindow<'_> {
                     mxr: self.mxr,
                     sum: self.sum,
                 },
-            )
-            .map_err(|e| match e {
-                PageError::AccessFault => MemoryError::PmpDeniedFetch,
-                PageError::PageFault => MemoryError::PageFaultFetch,
-            }) {
+            ) {
                 Ok(a) => a,
-                Err(e) => {
+                Err(fault) => {
                     // let mut w = File::create("./mem.dump").unwrap();
                     // writeln!(&mut w, "{:?}", &self.mem);
-                    return Err(e);
+                    self.fault(virt_addr, AccessMode::Exec, FaultCause::Page(fault));
+                    return Err(match fault.error() {
+                        PageError::AccessFault => MemoryError::PmpDeniedFetch,
+                        PageError::PageFault => MemoryError::PageFaultFetch,
+                    });
                 }
             }
         } else {
             addr
         };
-        if !self.pmp.map_or_else(
-            || true,
-            |pmp| pmp.check(addr, self.privilege, AccessMode::Read),
-        ) {
-            return Err(MemoryError::PmpDeniedRead);
+        if let Some(Err(fault)) = self
+            .pmp
+            .map(|pmp| pmp.check_detailed(addr, self.privilege, AccessMode::Exec))
+        {
+            self.fault(virt_addr, AccessMode::Exec, FaultCause::Pmp(fault));
+            return Err(MemoryError::PmpDeniedFetch);
         }
         // Remove all reservations that
         // contain the address we write to
@@ -443,8 +774,15 @@ impl MemoryWindow<'_> {
         self.mem.fetch(addr, self.privilege)
     }
 
-    fn read_phys(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryError> {
-        self.mem.read_bytes(addr, size)
+    /// Remember why an access failed, for [`Memory::take_fault()`]
+    fn fault(&mut self, addr: Address, mode: AccessMode, cause: FaultCause) {
+        self.mem.fault = Some(FaultDiagnostic {
+            addr,
+            mode,
+            privilege: self.privilege,
+            mprv: self.mprv,
+            cause,
+        });
     }
 }
 
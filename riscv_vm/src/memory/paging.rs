@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use enumflags2::{bitflags, make_bitflags, BitFlag, BitFlags};
 
 use crate::{
@@ -7,12 +9,16 @@ use crate::{
 
 use super::{
     address::{Address, VirtAddress},
-    MemoryError, MemoryWindow,
+    Memory, MemoryError, MemoryWindow,
 };
 
 const PAGE_SIZE: u64 = 4096;
 const PTE_SIZE: u64 = 8;
 
+/// How a walk reads the pte at an address, [`Memory::read_bytes()`] for the walks harts do and
+/// [`Memory::peek_bytes()`] for inspecting the page table without device side effects
+type ReadPte<'a> = &'a dyn Fn(Address) -> Result<Vec<u8>, MemoryError>;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Satp {
     pub mode: AddressTranslationMode,
@@ -76,6 +82,57 @@ pub enum PageError {
     PageFault,
 }
 
+/// Why a page table walk failed, see [`walk_page_table()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageWalkFault {
+    /// The level of the pte that caused the fault, the root table has the highest level
+    pub level: usize,
+    pub pte_addr: Address,
+    /// The raw pte, 0 if it could not be read
+    pub pte: u64,
+    pub cause: PageFaultCause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultCause {
+    /// V is clear
+    Invalid,
+    /// The leaf lacks a flag the access needs
+    Missing(PteFlags),
+    /// A supervisor access to a user page while SUM is clear
+    UserPage,
+    /// A supervisor fetch from a user page, which SUM does not allow
+    UserPageFetch,
+    /// A read of an execute only page while MXR is clear
+    ExecuteOnly,
+    /// A superpage with ppn bits below its level set
+    MisalignedSuperpage,
+    /// The pte at level 0 points to another table
+    NoLeaf,
+    /// The pte could not be read, this is an access fault
+    PteAccess,
+}
+
+/// A pte read while walking a page table, see [`translate()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalkStep {
+    pub level: usize,
+    pub pte_addr: Address,
+    pub pte: u64,
+}
+
+/// A range of virtual memory mapped to contiguous physical memory by pages with the same flags,
+/// see [`mappings()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub virt: Address,
+    pub phys: Address,
+    pub size: u64,
+    /// The size of the pages, 4 KiB for leaves at level 0 and a superpage size otherwise
+    pub page_size: u64,
+    pub flags: BitFlags<PteFlags>,
+}
+
 pub struct AccessContext {
     pub mode: AccessMode,
     pub privilege: PrivilegeMode,
@@ -83,29 +140,119 @@ pub struct AccessContext {
     pub sum: bool,
 }
 
+/// Translate `virt`, on failure the returned [`PageWalkFault`] tells which pte caused it and why
 pub fn walk_page_table(
     virt: VirtAddress,
     satp: Satp,
     mem: &MemoryWindow,
     context: AccessContext,
-) -> Result<Address, PageError> {
+) -> Result<Address, PageWalkFault> {
     if context.privilege == PrivilegeMode::Machine {
         panic!("Why are you paging in M mode, you dummy");
     }
     let base = satp.ppn * PAGE_SIZE;
+    let root = satp.mode.levels() - 1;
+    walk_page_table_internal(
+        virt,
+        base,
+        root,
+        &|addr| mem.mem.read_bytes(addr, PTE_SIZE as usize),
+        satp.mode,
+        context,
+        None,
+    )
+}
 
-    match satp.mode {
-        m @ AddressTranslationMode::Sv39 => {
-            walk_page_table_internal(virt, base, 3, 2, mem, m, context)
-        }
-        m @ AddressTranslationMode::Sv48 => {
-            walk_page_table_internal(virt, base, 4, 3, mem, m, context)
-        }
-        m @ AddressTranslationMode::Sv57 => {
-            walk_page_table_internal(virt, base, 5, 4, mem, m, context)
-        }
-        AddressTranslationMode::Bare => {
-            unreachable!("We should not attempt to walk the page table it it is bare")
+/// Walk the page table rooted at `satp` like a hart making the access described by `context`
+/// would, returning every pte read along with the result. Addresses are not translated in bare
+/// mode. The privilege of `context` should be S or U mode, M mode accesses are not translated.
+/// Ptes are read without side effects, so a pte pointing at a device does not disturb it.
+pub fn translate(
+    virt: Address,
+    satp: Satp,
+    mem: &Memory,
+    context: AccessContext,
+) -> (Vec<WalkStep>, Result<Address, PageWalkFault>) {
+    if satp.mode == AddressTranslationMode::Bare {
+        return (vec![], Ok(virt));
+    }
+    let levels = satp.mode.levels();
+    let mut steps = Vec::with_capacity(levels);
+    let result = walk_page_table_internal(
+        VirtAddress::from_address(virt, satp.mode),
+        satp.ppn * PAGE_SIZE,
+        levels - 1,
+        &|addr| mem.peek_bytes(addr, PTE_SIZE as usize),
+        satp.mode,
+        context,
+        Some(&mut steps),
+    );
+    (steps, result)
+}
+
+/// All valid leaves of the page table rooted at `satp` in order of virtual address, with
+/// neighbouring pages that map contiguous physical memory with the same flags coalesced. Tables
+/// that can't be read are skipped, ptes are read without side effects.
+pub fn mappings(satp: Satp, mem: &Memory) -> Vec<Mapping> {
+    if satp.mode == AddressTranslationMode::Bare {
+        return vec![];
+    }
+    let levels = satp.mode.levels();
+    let mut result = vec![];
+    collect_mappings(
+        satp.ppn * PAGE_SIZE,
+        levels - 1,
+        0,
+        satp.mode,
+        mem,
+        &mut result,
+    );
+    result
+}
+
+fn collect_mappings(
+    base: u64,
+    level: usize,
+    virt: u64,
+    mode: AddressTranslationMode,
+    mem: &Memory,
+    result: &mut Vec<Mapping>,
+) {
+    let page_size = PAGE_SIZE << (9 * level);
+    for i in 0..(PAGE_SIZE / PTE_SIZE) {
+        let Ok(bytes) = mem.peek_bytes((base + i * PTE_SIZE).into(), PTE_SIZE as usize) else {
+            return;
+        };
+        let bits = u64::from_le_bytes(bytes.try_into().unwrap());
+        let virt = virt | (i * page_size);
+
+        match PteType::from_bytes(bits, mode) {
+            PteType::Branch(pte) | PteType::Leaf(pte) if !pte.flags().contains(PteFlags::V) => {}
+            PteType::Branch(pte) if level > 0 => {
+                collect_mappings(pte.ppn() * PAGE_SIZE, level - 1, virt, mode, mem, result)
+            }
+            PteType::Branch(_) => {}
+            PteType::Leaf(pte) => {
+                let virt = mode.sign_extend(virt);
+                let phys = pte.ppn() * PAGE_SIZE;
+                if let Some(last) = result.last_mut() {
+                    if u64::from(last.virt) + last.size == virt
+                        && u64::from(last.phys) + last.size == phys
+                        && last.page_size == page_size
+                        && last.flags == *pte.flags()
+                    {
+                        last.size += page_size;
+                        continue;
+                    }
+                }
+                result.push(Mapping {
+                    virt: virt.into(),
+                    phys: phys.into(),
+                    size: page_size,
+                    page_size,
+                    flags: *pte.flags(),
+                });
+            }
         }
     }
 }
@@ -113,39 +260,47 @@ pub fn walk_page_table(
 fn walk_page_table_internal(
     virt: VirtAddress,
     base: u64,
-    levels: usize,
     i: usize,
-    mem: &MemoryWindow,
+    read_pte: ReadPte,
     mode: AddressTranslationMode,
     context: AccessContext,
-) -> Result<Address, PageError> {
-    let pte = PteType::from_bytes(
-        u64::from_le_bytes(
-            mem.read_phys(
-                (base + (virt.vpn[i] as u64) * PTE_SIZE).into(),
-                PTE_SIZE as usize,
-            )?
-            .try_into()
-            .unwrap(),
-        ),
-        mode,
-    );
+    mut steps: Option<&mut Vec<WalkStep>>,
+) -> Result<Address, PageWalkFault> {
+    let pte_addr = (base + (virt.vpn[i] as u64) * PTE_SIZE).into();
+    let walk_fault = |pte, cause| PageWalkFault {
+        level: i,
+        pte_addr,
+        pte,
+        cause,
+    };
+    let bits = match read_pte(pte_addr) {
+        Ok(bytes) => u64::from_le_bytes(bytes.try_into().unwrap()),
+        Err(_) => return Err(walk_fault(0, PageFaultCause::PteAccess)),
+    };
+    if let Some(steps) = &mut steps {
+        steps.push(WalkStep {
+            level: i,
+            pte_addr,
+            pte: bits,
+        });
+    }
+    let pte = PteType::from_bytes(bits, mode);
+    let fault = |cause| Err(walk_fault(bits, cause));
 
     match pte {
-        PteType::Branch(pte) => {
-            if !pte.flags().contains(PteFlags::V) {
-                return Err(PageError::PageFault);
-            }
-            walk_page_table_internal(
-                virt,
-                pte.ppn() * PAGE_SIZE,
-                levels,
-                i - 1,
-                mem,
-                mode,
-                context,
-            )
+        PteType::Branch(pte) | PteType::Leaf(pte) if !pte.flags().contains(PteFlags::V) => {
+            fault(PageFaultCause::Invalid)
         }
+        PteType::Branch(_) if i == 0 => fault(PageFaultCause::NoLeaf),
+        PteType::Branch(pte) => walk_page_table_internal(
+            virt,
+            pte.ppn() * PAGE_SIZE,
+            i - 1,
+            read_pte,
+            mode,
+            context,
+            steps,
+        ),
         PteType::Leaf(pte) => {
             if (pte.flags().contains(PteFlags::U)
                 && (context.privilege == PrivilegeMode::User || context.sum))
@@ -157,34 +312,39 @@ fn walk_page_table_internal(
                         if !(pte.flags().contains(PteFlags::R)
                             || (pte.flags().contains(PteFlags::X) && context.mxr))
                         {
-                            return Err(PageError::PageFault);
+                            return fault(if pte.flags().contains(PteFlags::X) {
+                                PageFaultCause::ExecuteOnly
+                            } else {
+                                PageFaultCause::Missing(PteFlags::R)
+                            });
                         }
                     }
                     AccessMode::Write => {
-                        #[allow(clippy::if_same_then_else)] // Make intent mode clear
                         if !pte.flags().contains(PteFlags::W) {
-                            return Err(PageError::PageFault);
+                            return fault(PageFaultCause::Missing(PteFlags::W));
                         } else if !pte.flags().contains(PteFlags::D) {
-                            return Err(PageError::PageFault);
+                            return fault(PageFaultCause::Missing(PteFlags::D));
                         }
                     }
                     AccessMode::Exec => {
-                        if !pte.flags().contains(PteFlags::X)
-                            || (pte.flags().contains(PteFlags::U)
-                                && context.privilege == PrivilegeMode::Supervisor)
+                        if !pte.flags().contains(PteFlags::X) {
+                            return fault(PageFaultCause::Missing(PteFlags::X));
+                        }
+                        if pte.flags().contains(PteFlags::U)
+                            && context.privilege == PrivilegeMode::Supervisor
                         {
-                            return Err(PageError::PageFault);
+                            return fault(PageFaultCause::UserPageFetch);
                         }
                     }
                 }
                 if !pte.flags().contains(PteFlags::A) {
-                    return Err(PageError::PageFault);
+                    return fault(PageFaultCause::Missing(PteFlags::A));
                 }
                 if i != 0 {
                     let ppn = pte.ppn_bytes();
                     for j in ppn.iter().take(i) {
                         if *j != 0 {
-                            return Err(PageError::PageFault);
+                            return fault(PageFaultCause::MisalignedSuperpage);
                         }
                     }
                 }
@@ -192,17 +352,51 @@ fn walk_page_table_internal(
                 for j in 0..i {
                     ppn |= (virt.vpn[j] as u64) << (j * 9);
                 }
-                for j in i..levels {
+                for j in i..mode.levels() {
                     ppn |= (pte.ppn_bytes()[j] as u64) << (j * 9);
                 }
                 Ok(((virt.page_offset as u64) | (ppn << 12)).into())
+            } else if pte.flags().contains(PteFlags::U) {
+                fault(PageFaultCause::UserPage)
             } else {
-                Err(PageError::PageFault)
+                fault(PageFaultCause::Missing(PteFlags::U))
             }
         }
     }
 }
 
+impl PageWalkFault {
+    /// The error the walk failed with, only failing to read a pte is an access fault
+    pub fn error(&self) -> PageError {
+        match self.cause {
+            PageFaultCause::PteAccess => PageError::AccessFault,
+            _ => PageError::PageFault,
+        }
+    }
+}
+
+impl Display for PageWalkFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "level {} pte {:#018x} at {:#x}: ",
+            self.level,
+            self.pte,
+            u64::from(self.pte_addr)
+        )?;
+        match self.cause {
+            PageFaultCause::Invalid => write!(f, "V is clear"),
+            PageFaultCause::Missing(flag) => write!(f, "{:?} is clear", flag),
+            PageFaultCause::UserPage => write!(f, "U is set and SUM is clear"),
+            PageFaultCause::UserPageFetch => write!(f, "U is set, S mode can't execute it"),
+            PageFaultCause::ExecuteOnly => write!(f, "R is clear and MXR is clear"),
+            PageFaultCause::MisalignedSuperpage => write!(f, "misaligned superpage"),
+            PageFaultCause::NoLeaf => write!(f, "no leaf pte at level 0"),
+            PageFaultCause::PteAccess => write!(f, "the pte could not be read"),
+        }
+    }
+}
+
 impl Pte {
     pub fn flags(&self) -> &BitFlags<PteFlags> {
         match self {
@@ -350,6 +544,24 @@ impl Satp {
     }
 }
 
+impl AddressTranslationMode {
+    /// The number of levels of page tables, 0 in bare mode
+    pub fn levels(&self) -> usize {
+        match self {
+            AddressTranslationMode::Bare => 0,
+            AddressTranslationMode::Sv39 => 3,
+            AddressTranslationMode::Sv48 => 4,
+            AddressTranslationMode::Sv57 => 5,
+        }
+    }
+
+    /// Copy the top bit of a virtual address to the bits above it
+    fn sign_extend(&self, virt: u64) -> u64 {
+        let unused = 64 - 12 - 9 * self.levels() as u32;
+        (((virt << unused) as i64) >> unused) as u64
+    }
+}
+
 impl TryFrom<u64> for AddressTranslationMode {
     type Error = ();
 
@@ -1,13 +1,16 @@
 use std::{
     default,
-    fmt::Debug,
+    fmt::{Debug, Display},
     ops::{Range, RangeInclusive},
 };
 
 use enumflags2::{bitflags, BitFlags};
 
 use super::address::Address;
-use crate::hart::privilege::{self, PrivilegeMode};
+use crate::{
+    hart::privilege::{self, PrivilegeMode},
+    vmstate::{SnapshotError, SnapshotReader, SnapshotWriter},
+};
 
 pub struct PMP {
     pmpcfg: [PmpCfg; 64],
@@ -54,7 +57,7 @@ pub struct PmpCfg {
 
 #[repr(u8)]
 #[bitflags]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AccessMode {
     Read = 0b1 << 0,
     Write = 0b1 << 1,
@@ -71,6 +74,15 @@ pub enum AddressMatch {
     NAPOT = 3,
 }
 
+/// Why a pmp check failed, see [`PMP::check_detailed()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PmpFault {
+    pub addr: Address,
+    /// The index and config of the entry that denied the access, `None` if no entry matched
+    /// and the access was not from M mode
+    pub entry: Option<(usize, PmpCfg)>,
+}
+
 impl From<u8> for AddressMatch {
     fn from(value: u8) -> Self {
         match value {
@@ -146,18 +158,28 @@ impl PMP {
     }
 
     pub fn ranges(&self) -> Vec<(&PmpCfg, RangeInclusive<Address>)> {
+        self.indexed_ranges()
+            .into_iter()
+            .map(|(_, cfg, range)| (cfg, range))
+            .collect()
+    }
+
+    /// [`PMP::ranges()`] with the index of each entry
+    fn indexed_ranges(&self) -> Vec<(usize, &PmpCfg, RangeInclusive<Address>)> {
         let mut result = Vec::with_capacity(64);
         for (i, cfg) in self.pmpcfg.iter().enumerate() {
             match cfg.addr_match {
                 AddressMatch::OFF => (),
                 AddressMatch::TOR if i == 0 => {
-                    result.push((cfg, ((0u64.into())..=(self.pmpaddr[i] << 2).into())))
+                    result.push((i, cfg, ((0u64.into())..=(self.pmpaddr[i] << 2).into())))
                 }
                 AddressMatch::TOR if i > 0 => result.push((
+                    i,
                     cfg,
                     ((self.pmpaddr[i - 1] << 2).into()..=(self.pmpaddr[i] << 2).into()),
                 )),
                 AddressMatch::NA4 => result.push((
+                    i,
                     cfg,
                     ((self.pmpaddr[i] << 2).into()..=((self.pmpaddr[i] << 2) + 4).into()),
                 )),
@@ -174,7 +196,7 @@ impl PMP {
                     let high_mask = !low_mask; // set bottom size bits;
                     let low = ((self.pmpaddr[i] << 2) & low_mask).into();
                     let high = ((self.pmpaddr[i] << 2) | high_mask).into();
-                    result.push((cfg, (low..=high)));
+                    result.push((i, cfg, (low..=high)));
                 }
                 _ => unreachable!(),
             }
@@ -184,20 +206,44 @@ impl PMP {
     }
 
     pub fn check(&self, addr: Address, privilege: PrivilegeMode, mode: AccessMode) -> bool {
+        self.check_detailed(addr, privilege, mode).is_ok()
+    }
+
+    /// [`PMP::check()`], on failure the returned [`PmpFault`] tells which entry denied the access
+    pub fn check_detailed(
+        &self,
+        addr: Address,
+        privilege: PrivilegeMode,
+        mode: AccessMode,
+    ) -> Result<(), PmpFault> {
+        let denied = |i: usize, p: &PmpCfg| {
+            Err(PmpFault {
+                addr,
+                entry: Some((i, *p)),
+            })
+        };
         if privilege < PrivilegeMode::Machine {
-            for (p, r) in self.ranges() {
+            for (i, p, r) in self.indexed_ranges() {
                 if r.contains(&addr) {
-                    return p.rwx.contains(mode);
+                    return if p.rwx.contains(mode) {
+                        Ok(())
+                    } else {
+                        denied(i, p)
+                    };
                 }
             }
-            false
+            Err(PmpFault { addr, entry: None })
         } else {
-            for (p, r) in self.ranges() {
+            for (i, p, r) in self.indexed_ranges() {
                 if p.locked && r.contains(&addr) {
-                    return p.rwx.contains(mode);
+                    return if p.rwx.contains(mode) {
+                        Ok(())
+                    } else {
+                        denied(i, p)
+                    };
                 }
             }
-            true
+            Ok(())
         }
     }
 
@@ -217,6 +263,21 @@ impl PMP {
         self.pmpaddr[idx] = addr & 0x3FFFFFFFFFFFFF; // top 10 bits are WARL 0
     }
 
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        for (cfg, addr) in self.pmpcfg.iter().zip(self.pmpaddr) {
+            snapshot.write_u8(cfg.to_bits());
+            snapshot.write_u64(addr);
+        }
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        for (cfg, addr) in self.pmpcfg.iter_mut().zip(self.pmpaddr.iter_mut()) {
+            *cfg = PmpCfg::from_bits(snapshot.read_u8()?);
+            *addr = snapshot.read_u64()?;
+        }
+        Ok(())
+    }
+
     pub fn get_cfgs(&self) -> &[PmpCfg; 64] {
         &self.pmpcfg
     }
@@ -268,3 +329,23 @@ impl PmpCfg {
         bits
     }
 }
+
+impl Display for PmpFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pmp denied {:#x}: ", u64::from(self.addr))?;
+        let Some((i, cfg)) = self.entry else {
+            return write!(f, "no entry matched");
+        };
+        let flag = |mode, c| if cfg.rwx.contains(mode) { c } else { '-' };
+        write!(
+            f,
+            "pmp{}cfg {:?} {}{}{}{} matched",
+            i,
+            cfg.addr_match,
+            flag(AccessMode::Read, 'r'),
+            flag(AccessMode::Write, 'w'),
+            flag(AccessMode::Exec, 'x'),
+            if cfg.locked { " locked" } else { "" }
+        )
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+
+use super::address::Address;
+
+/// A store a hart made, values are little endian and only the first 8 bytes of larger stores
+/// are kept. The old value of stores to device memory is always 0, as reading it back could
+/// have side effects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Store {
+    pub hart: u64,
+    pub pc: Address,
+    /// The hart's `instret` when it made the store, the number of instructions it retired before
+    pub instret: u64,
+    /// The address used by the hart, so before translation
+    pub addr: Address,
+    /// The physical address that was written
+    pub phys: Address,
+    pub size: usize,
+    pub old: u64,
+    pub new: u64,
+}
+
+impl Store {
+    /// Whether the store wrote any of the `size` bytes at the physical address `addr`
+    pub fn overlaps(&self, addr: Address, size: u64) -> bool {
+        // Ranges that run past the end of the address space stop at its end
+        let start = u64::from(self.phys);
+        u64::from(addr) < start.saturating_add(self.size as u64)
+            && start < u64::from(addr).saturating_add(size)
+    }
+}
+
+/// Stop on the `count`th store to `addr` after the watch was added
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreWatch {
+    pub addr: Address,
+    pub count: u64,
+    /// Stores to `addr` since the watch was added
+    pub seen: u64,
+}
+
+/// The last stores harts made, oldest first. Once `capacity` stores are logged the oldest one
+/// is dropped for every new store.
+#[derive(Debug, Clone)]
+pub struct StoreLog {
+    stores: VecDeque<Store>,
+    capacity: usize,
+    dropped: u64,
+    watches: Vec<StoreWatch>,
+}
+
+impl StoreLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            stores: VecDeque::with_capacity(capacity.min(1 << 16)),
+            capacity,
+            dropped: 0,
+            watches: Vec::new(),
+        }
+    }
+
+    pub fn stores(&self) -> impl DoubleEndedIterator<Item = &Store> {
+        self.stores.iter()
+    }
+
+    /// The logged stores that wrote any of the `size` bytes at the physical address `addr`,
+    /// oldest first
+    pub fn writes_to(&self, addr: Address, size: u64) -> impl DoubleEndedIterator<Item = &Store> {
+        self.stores.iter().filter(move |s| s.overlaps(addr, size))
+    }
+
+    /// The number of stores that were dropped to make room for newer ones
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Forget all logged stores, watches are kept
+    pub fn clear(&mut self) {
+        self.stores.clear();
+        self.dropped = 0;
+    }
+
+    /// Stop the vm with a [`StopReason::Watchpoint`](crate::vmstate::StopReason) on the
+    /// `count`th store to the physical address `addr` from now on. Replaces any earlier watch
+    /// on `addr`.
+    pub fn add_watch(&mut self, addr: Address, count: u64) {
+        self.remove_watch(addr);
+        self.watches.push(StoreWatch {
+            addr,
+            count,
+            seen: 0,
+        });
+    }
+
+    pub fn remove_watch(&mut self, addr: Address) {
+        self.watches.retain(|w| w.addr != addr);
+    }
+
+    pub fn get_watches(&self) -> &[StoreWatch] {
+        &self.watches
+    }
+
+    /// Log a store, returns the address of a watch it completes. Completed watches are removed.
+    pub(super) fn push(&mut self, store: Store) -> Option<Address> {
+        let mut hit = None;
+        self.watches.retain_mut(|w| {
+            if !store.overlaps(w.addr, 1) {
+                return true;
+            }
+            w.seen += 1;
+            if w.seen < w.count {
+                return true;
+            }
+            hit = Some(w.addr);
+            false
+        });
+
+        if self.capacity > 0 {
+            if self.stores.len() == self.capacity {
+                self.stores.pop_front();
+                self.dropped += 1;
+            }
+            self.stores.push_back(store);
+        }
+        hit
+    }
+}
@@ -1,9 +1,17 @@
 use std::sync::mpsc;
 
+use enumflags2::make_bitflags;
+
 use crate::{
-    hart::{privilege::PrivilegeMode, Hart},
+    hart::{privilege::PrivilegeMode, CsrAddress, Hart},
     memory::{
-        pmp::{AddressMatch, PmpCfg, PMP},
+        fault::{FaultCause, FaultDiagnostic},
+        memory_map::MemoryRegion,
+        paging::{
+            mappings, translate, AccessContext, Mapping, PageFaultCause, PageWalkFault, PteFlags,
+            Satp, WalkStep,
+        },
+        pmp::{AccessMode, AddressMatch, PmpCfg, PmpFault, PMP},
         MemoryError,
     },
     vmstate::{timer::TimerRef, VMSettings},
@@ -415,6 +423,16 @@ fn read_oob() {
     assert!(matches!(result, Err(MemoryError::OutOfBoundsRead(_))));
 }
 
+#[test]
+fn peek_rom() {
+    let mut mem = Memory::new::<256>();
+    mem.memory_map
+        .add_region(MemoryRegion::Rom(0x1000u64.into()..=0x1FFFu64.into()))
+        .unwrap();
+    let result = mem.peek_bytes(0x1000u64.into(), 4);
+    assert!(matches!(result, Err(MemoryError::OutOfBoundsRead(_))));
+}
+
 #[test]
 fn write() {
     let mut mem = Memory::new::<256>();
@@ -455,6 +473,224 @@ fn write_pmp_denied() {
     assert!(matches!(result, Err(MemoryError::PmpDeniedWrite)));
 }
 
+#[test]
+fn write_pmp_fault() {
+    let mut pmp = PMP::default();
+    pmp.write_cfg_rv64(
+        0,
+        PmpCfg::new_configured(true, false, false, AddressMatch::TOR, false).to_bits() as u64,
+    );
+    pmp.write_addr_rv64(0, (0x80000010u64 >> 2));
+    let mut mem = Memory::new::<256>();
+    let mut hart = Hart::new(
+        0,
+        VMSettings {
+            pmp_enable: true,
+            ..Default::default()
+        },
+        TimerRef::dummy(),
+    );
+    hart.get_csr_mut().pmp = pmp;
+    hart.set_privilege(PrivilegeMode::User);
+
+    let result = mem
+        .window(&hart)
+        .write_bytes(&[37; 4], 0x80000004u64.into());
+    assert!(matches!(result, Err(MemoryError::PmpDeniedWrite)));
+    let cfg = hart.get_csr().pmp.get_cfgs()[0];
+    assert_eq!(
+        mem.take_fault(),
+        Some(FaultDiagnostic {
+            addr: 0x80000004u64.into(),
+            mode: AccessMode::Write,
+            privilege: PrivilegeMode::User,
+            mprv: false,
+            cause: FaultCause::Pmp(PmpFault {
+                addr: 0x80000004u64.into(),
+                entry: Some((0, cfg)),
+            }),
+        })
+    );
+
+    let result = mem.window(&hart).read_bytes(0x80000020u64.into(), 4);
+    assert!(matches!(result, Err(MemoryError::PmpDeniedRead)));
+    assert!(matches!(
+        mem.take_fault(),
+        Some(FaultDiagnostic {
+            cause: FaultCause::Pmp(PmpFault { entry: None, .. }),
+            ..
+        })
+    ));
+    assert_eq!(mem.take_fault(), None);
+}
+
+#[test]
+fn fetch_pmp() {
+    // Execute only below 0x80000010, read only above it
+    let exec = PmpCfg::new_configured(false, false, true, AddressMatch::TOR, false).to_bits();
+    let read = PmpCfg::new_configured(true, false, false, AddressMatch::TOR, false).to_bits();
+    let mut pmp = PMP::default();
+    pmp.write_cfg_rv64(0, exec as u64 | (read as u64) << 8);
+    pmp.write_addr_rv64(0, 0x80000010u64 >> 2);
+    pmp.write_addr_rv64(1, 0x80000020u64 >> 2);
+    let mut mem = Memory::new::<256>();
+    let mut hart = Hart::new(
+        0,
+        VMSettings {
+            pmp_enable: true,
+            ..Default::default()
+        },
+        TimerRef::dummy(),
+    );
+    hart.get_csr_mut().pmp = pmp;
+    hart.set_privilege(PrivilegeMode::User);
+
+    assert!(mem.window(&hart).fetch(0x80000004u64.into()).is_ok());
+    let result = mem.window(&hart).fetch(0x80000014u64.into());
+    assert!(matches!(result, Err(MemoryError::PmpDeniedFetch)));
+    assert!(matches!(
+        mem.take_fault(),
+        Some(FaultDiagnostic {
+            mode: AccessMode::Exec,
+            cause: FaultCause::Pmp(PmpFault {
+                entry: Some((1, _)),
+                ..
+            }),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn page_walk_fault() {
+    let mut mem = Memory::new::<{ 4 * 4096 }>();
+    // Map 0x1000 to 0x80003000 as readable but not writable, through tables at 0x80000000,
+    // 0x80001000 and 0x80002000
+    let ptes = [
+        (0x80000000u64, (0x80001u64 << 10) | 0x01),
+        (0x80001000, (0x80002 << 10) | 0x01),
+        (0x80002008, (0x80003 << 10) | 0x43),
+    ];
+    for (addr, pte) in ptes {
+        mem.write_bytes(&pte.to_le_bytes(), addr.into()).unwrap();
+    }
+    let mut hart = Hart::new(0, VMSettings::default(), TimerRef::dummy());
+    hart.get_csr_mut()
+        .write_csr(
+            CsrAddress::new(0x180),
+            (8 << 60) | 0x80000,
+            PrivilegeMode::Machine,
+            false,
+        )
+        .unwrap();
+    hart.set_privilege(PrivilegeMode::Supervisor);
+
+    // Loads are translated as reads, so a page that is not writable can be read
+    assert!(mem.window(&hart).read_bytes(0x1008u64.into(), 4).is_ok());
+    let result = mem.window(&hart).write_bytes(&[37; 4], 0x1008u64.into());
+    assert!(matches!(result, Err(MemoryError::PageFaultWrite)));
+    assert_eq!(
+        mem.take_fault(),
+        Some(FaultDiagnostic {
+            addr: 0x1008u64.into(),
+            mode: AccessMode::Write,
+            privilege: PrivilegeMode::Supervisor,
+            mprv: false,
+            cause: FaultCause::Page(PageWalkFault {
+                level: 0,
+                pte_addr: 0x80002008u64.into(),
+                pte: (0x80003 << 10) | 0x43,
+                cause: PageFaultCause::Missing(PteFlags::W),
+            }),
+        })
+    );
+
+    let result = mem.window(&hart).read_bytes(0x201000u64.into(), 4);
+    assert!(matches!(result, Err(MemoryError::PageFaultRead)));
+    assert!(matches!(
+        mem.take_fault(),
+        Some(FaultDiagnostic {
+            cause: FaultCause::Page(PageWalkFault {
+                level: 1,
+                cause: PageFaultCause::Invalid,
+                ..
+            }),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn page_table_mappings() {
+    let mut mem = Memory::new::<{ 4 * 4096 }>();
+    // Two 4 KiB pages at 0x1000 mapping 0x80003000 and on, a 2 MiB page at 0x200000 and a 1 GiB
+    // page at the top of the address space
+    let ptes = [
+        (0x80000000u64, (0x80001u64 << 10) | 0x01),
+        (0x80000ff8, (0x80000 << 10) | 0xcf),
+        (0x80001000, (0x80002 << 10) | 0x01),
+        (0x80001008, (0x80200 << 10) | 0xcf),
+        (0x80002008, (0x80003 << 10) | 0x43),
+        (0x80002010, (0x80004 << 10) | 0x43),
+    ];
+    for (addr, pte) in ptes {
+        mem.write_bytes(&pte.to_le_bytes(), addr.into()).unwrap();
+    }
+    let satp = Satp::from_bits((8 << 60) | 0x80000).unwrap();
+
+    let rwx = make_bitflags!(PteFlags::{ V | R | W | X | A | D });
+    assert_eq!(
+        mappings(satp, &mem),
+        vec![
+            Mapping {
+                virt: 0x1000u64.into(),
+                phys: 0x80003000u64.into(),
+                size: 0x2000,
+                page_size: 0x1000,
+                flags: make_bitflags!(PteFlags::{ V | R | A }),
+            },
+            Mapping {
+                virt: 0x200000u64.into(),
+                phys: 0x80200000u64.into(),
+                size: 0x200000,
+                page_size: 0x200000,
+                flags: rwx,
+            },
+            Mapping {
+                virt: 0xffffffffc0000000u64.into(),
+                phys: 0x80000000u64.into(),
+                size: 0x40000000,
+                page_size: 0x40000000,
+                flags: rwx,
+            },
+        ]
+    );
+
+    let context = AccessContext {
+        mode: AccessMode::Read,
+        privilege: PrivilegeMode::Supervisor,
+        mxr: false,
+        sum: false,
+    };
+    let (steps, result) = translate(0x200010u64.into(), satp, &mem, context);
+    assert_eq!(result, Ok(0x80200010u64.into()));
+    assert_eq!(
+        steps,
+        vec![
+            WalkStep {
+                level: 2,
+                pte_addr: 0x80000000u64.into(),
+                pte: (0x80001 << 10) | 0x01,
+            },
+            WalkStep {
+                level: 1,
+                pte_addr: 0x80001008u64.into(),
+                pte: (0x80200 << 10) | 0xcf,
+            },
+        ]
+    );
+}
+
 #[test]
 fn write_pmp() {
     let mut pmp = PMP::default();
@@ -0,0 +1,32 @@
+use super::address::Address;
+
+/// The kind of access a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Stop when a hart accesses `addr`, the address is the one used by the hart, so before
+/// translation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: Address,
+    pub kind: WatchKind,
+}
+
+/// A watchpoint that was triggered, `access` is the access that triggered it and thus never
+/// [`WatchKind::ReadWrite`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub hart: u64,
+    pub addr: Address,
+    pub access: WatchKind,
+}
+
+impl WatchKind {
+    pub(super) fn matches(&self, access: WatchKind) -> bool {
+        *self == WatchKind::ReadWrite || *self == access
+    }
+}
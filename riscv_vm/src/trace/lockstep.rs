@@ -0,0 +1,212 @@
+//! Checking the vm against a reference trace, see
+//! [`VMState::lockstep()`](crate::vmstate::VMState::lockstep()).
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    io::BufRead,
+};
+
+use crate::vmstate::StopReason;
+
+use super::{Commit, RegWrite, TraceEvent, TraceParseError, TraceReader};
+
+/// The number of matching events kept to show before a divergence
+const CONTEXT: usize = 8;
+
+#[derive(Debug)]
+pub enum LockstepError {
+    Parse(TraceParseError),
+    /// The vm did something other than the reference
+    Diverged(Box<Divergence>),
+    /// The vm stopped before the reference ended, as [`VMState::run_for()`] would
+    ///
+    /// [`VMState::run_for()`]: crate::vmstate::VMState::run_for()
+    Stopped(StopReason),
+}
+
+impl From<TraceParseError> for LockstepError {
+    fn from(value: TraceParseError) -> Self {
+        Self::Parse(value)
+    }
+}
+
+/// What differed at a [`Divergence`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    Pc,
+    /// The integer or float registers written, or the values written to them
+    RegWrite,
+    /// A csr write of the vm is not in the reference, csr writes the vm does not trace are not
+    /// checked.
+    CsrWrite,
+    /// Both trapped, but with a different cause, epc, tval or target
+    Trap,
+    /// One side trapped where the other retired an instruction
+    Kind,
+    /// The reference has no more events for the hart, but does for others
+    ReferenceEnded,
+}
+
+/// The first event where the vm and the reference differ.
+#[derive(Debug)]
+pub struct Divergence {
+    pub mismatch: Mismatch,
+    /// The reference event and its line, `None` if the reference ended
+    pub expected: Option<(usize, TraceEvent)>,
+    pub actual: TraceEvent,
+    /// The last events that matched, oldest first
+    pub context: Vec<TraceEvent>,
+}
+
+/// Compares the events of the vm with those of a reference, each hart is compared against the
+/// events of the same core in the reference.
+pub(crate) struct Lockstep<R> {
+    reference: TraceReader<R>,
+    ended: bool,
+    /// Reference events read ahead while looking for those of another hart
+    pending: HashMap<u64, VecDeque<(usize, TraceEvent)>>,
+    context: VecDeque<TraceEvent>,
+    matched: u64,
+}
+
+impl<R: BufRead> Lockstep<R> {
+    pub(crate) fn new(reference: R) -> Self {
+        Self {
+            reference: TraceReader::new(reference),
+            ended: false,
+            pending: HashMap::new(),
+            context: VecDeque::new(),
+            matched: 0,
+        }
+    }
+
+    /// The number of events that matched
+    pub(crate) fn matched(&self) -> u64 {
+        self.matched
+    }
+
+    /// Check the next event of the vm, returns false once the reference ended.
+    pub(crate) fn check(&mut self, actual: TraceEvent) -> Result<bool, LockstepError> {
+        let hart = event_hart(&actual);
+        let Some((line, expected)) = self.next_for(hart)? else {
+            if self.pending.values().all(VecDeque::is_empty) {
+                return Ok(false);
+            }
+            return Err(self.diverged(Mismatch::ReferenceEnded, None, actual));
+        };
+
+        if let Some(mismatch) = compare(&expected, &actual) {
+            return Err(self.diverged(mismatch, Some((line, expected)), actual));
+        }
+
+        if self.context.len() == CONTEXT {
+            self.context.pop_front();
+        }
+        self.context.push_back(actual);
+        self.matched += 1;
+        Ok(true)
+    }
+
+    fn next_for(&mut self, hart: u64) -> Result<Option<(usize, TraceEvent)>, TraceParseError> {
+        if let Some(event) = self.pending.get_mut(&hart).and_then(VecDeque::pop_front) {
+            return Ok(Some(event));
+        }
+        while !self.ended {
+            let Some(event) = self.reference.next().transpose()? else {
+                self.ended = true;
+                break;
+            };
+            let line = self.reference.line();
+            if event_hart(&event) == hart {
+                return Ok(Some((line, event)));
+            }
+            self.pending
+                .entry(event_hart(&event))
+                .or_default()
+                .push_back((line, event));
+        }
+        Ok(None)
+    }
+
+    fn diverged(
+        &mut self,
+        mismatch: Mismatch,
+        expected: Option<(usize, TraceEvent)>,
+        actual: TraceEvent,
+    ) -> LockstepError {
+        LockstepError::Diverged(Box::new(Divergence {
+            mismatch,
+            expected,
+            actual,
+            context: self.context.drain(..).collect(),
+        }))
+    }
+}
+
+fn event_hart(event: &TraceEvent) -> u64 {
+    match event {
+        TraceEvent::Commit(c) => c.hart,
+        TraceEvent::Trap(t) => t.hart,
+    }
+}
+
+fn compare(expected: &TraceEvent, actual: &TraceEvent) -> Option<Mismatch> {
+    match (expected, actual) {
+        (TraceEvent::Commit(expected), TraceEvent::Commit(actual)) => {
+            let registers = |c: &Commit| {
+                c.writes
+                    .iter()
+                    .filter(|w| !matches!(w, RegWrite::Csr(..)))
+                    .copied()
+                    .collect::<Vec<_>>()
+            };
+            if expected.pc != actual.pc {
+                Some(Mismatch::Pc)
+            } else if registers(expected) != registers(actual) {
+                Some(Mismatch::RegWrite)
+            } else if actual
+                .writes
+                .iter()
+                .any(|w| matches!(w, RegWrite::Csr(..)) && !expected.writes.contains(w))
+            {
+                Some(Mismatch::CsrWrite)
+            } else {
+                None
+            }
+        }
+        (TraceEvent::Trap(expected), TraceEvent::Trap(actual)) => (expected.cause != actual.cause
+            || expected.epc != actual.epc
+            || differs(expected.tval, actual.tval)
+            || differs(expected.target, actual.target))
+        .then_some(Mismatch::Trap),
+        _ => Some(Mismatch::Kind),
+    }
+}
+
+/// Both are known and not equal
+fn differs<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+    a.zip(b).is_some_and(|(a, b)| a != b)
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.expected {
+            Some((line, _)) => writeln!(
+                f,
+                "{:?} mismatch with line {line} of the reference, after:",
+                self.mismatch
+            )?,
+            None => writeln!(f, "{:?}, after:", self.mismatch)?,
+        }
+        for event in &self.context {
+            writeln!(f, "  {}", event.to_string().replace('\n', "\n  "))?;
+        }
+        if let Some((_, expected)) = &self.expected {
+            writeln!(f, "expected:")?;
+            writeln!(f, "  {}", expected.to_string().replace('\n', "\n  "))?;
+        }
+        writeln!(f, "actual:")?;
+        write!(f, "  {}", self.actual.to_string().replace('\n', "\n  "))
+    }
+}
@@ -0,0 +1,329 @@
+//! Execution traces in the format of Spike's `--log-commits`, one line per retired instruction
+//! with the registers, csrs and memory it wrote or read. Traps get their own lines in the format
+//! Spike uses for them with `-l`, extended with the mode that took the trap.
+//!
+//! ```text
+//! core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000
+//! core   0: 3 0x0000000080000004 (0x0102b503) x10 0x0000000000000005 mem 0x0000000080000010
+//! core   0: 3 0x0000000080000008 (0x4505) x10 0x0000000000000001
+//! core   0: 3 0x000000008000000a (0x00a2a023) mem 0x0000000080000000 0x00000001
+//! core   0: exception trap_illegal_instruction, epc 0x000000008000000e, target 3
+//! core   0:           tval 0x0000000000000000
+//! ```
+//!
+//! Access and page faults raised by a pmp check or page table walk get a third line saying why
+//! the access failed, see [`FaultDiagnostic`].
+//!
+//! Traces of the vm or Spike can be read back with a [`TraceReader`] and used as a reference to
+//! check the vm against with [`VMState::lockstep()`](crate::vmstate::VMState::lockstep()).
+
+mod lockstep;
+mod parse;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Display},
+    io::{self, Write},
+    mem,
+    rc::Rc,
+};
+
+use crate::{
+    hart::{privilege::PrivilegeMode, registers::IntRegister, CsrAddress},
+    memory::{address::Address, fault::FaultDiagnostic},
+};
+
+pub(crate) use lockstep::Lockstep;
+pub use lockstep::{Divergence, LockstepError, Mismatch};
+pub use parse::{TraceParseError, TraceReader};
+
+/// A line (or two for traps) of a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    Commit(Commit),
+    Trap(Trap),
+}
+
+/// An instruction that retired, instructions that raise an exception do not retire and only
+/// show up as the [`Trap`] they cause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub hart: u64,
+    /// The mode the instruction executed in
+    pub privilege: PrivilegeMode,
+    pub pc: Address,
+    /// The instruction bits, only the low 16 are used for compressed instructions
+    pub inst: u32,
+    pub compressed: bool,
+    /// Register and csr writes, in the order Spike prints them (by register number)
+    pub writes: Vec<RegWrite>,
+    /// Loads first, then stores
+    pub mem: Vec<MemAccess>,
+}
+
+/// A register written by an instruction with the value after the write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegWrite {
+    Int(IntRegister, u64),
+    /// The number of the float register and its raw (NaN boxed) bits, a plain number so traces
+    /// with float writes can be handled without the `float` feature.
+    Float(u8, u64),
+    /// Only explicit writes by csr instructions are traced, not side effects such as fflags
+    /// updates or mstatus changes on trap return.
+    Csr(CsrAddress, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    /// The address as seen by the hart, before translation
+    pub addr: Address,
+    /// The size in bytes, 0 for loads read from a trace as Spike does not log it
+    pub size: usize,
+    pub kind: MemAccessKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemAccessKind {
+    Load,
+    /// A store and the value stored, little endian
+    Store(u64),
+}
+
+/// A trap taken by a hart, for an exception `epc` is the pc of the instruction that raised it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub hart: u64,
+    /// The cause as written to the `cause` csr, with the top bit set for interrupts
+    pub cause: u64,
+    pub epc: Address,
+    /// Always set by the vm, Spike only logs it for causes that write it
+    pub tval: Option<u64>,
+    /// The mode the trap was taken into, always set by the vm but not logged by Spike
+    pub target: Option<PrivilegeMode>,
+    /// Why the access that raised an access or page fault failed, only known to the vm and not
+    /// read back from traces
+    pub fault: Option<FaultDiagnostic>,
+}
+
+const INTERRUPT_BIT: u64 = 1 << 63;
+
+/// The names Spike uses for exception causes
+const EXCEPTION_NAMES: &[(u64, &str)] = &[
+    (0, "trap_instruction_address_misaligned"),
+    (1, "trap_instruction_access_fault"),
+    (2, "trap_illegal_instruction"),
+    (3, "trap_breakpoint"),
+    (4, "trap_load_address_misaligned"),
+    (5, "trap_load_access_fault"),
+    (6, "trap_store_address_misaligned"),
+    (7, "trap_store_access_fault"),
+    (8, "trap_user_ecall"),
+    (9, "trap_supervisor_ecall"),
+    (11, "trap_machine_ecall"),
+    (12, "trap_instruction_page_fault"),
+    (13, "trap_load_page_fault"),
+    (15, "trap_store_page_fault"),
+];
+
+impl RegWrite {
+    /// The order Spike logs writes in, it keys them by register number and type
+    fn spike_key(&self) -> u64 {
+        match self {
+            RegWrite::Int(r, _) => (*r as u64) << 4,
+            RegWrite::Float(r, _) => ((*r as u64) << 4) | 1,
+            RegWrite::Csr(c, _) => ((u16::from(*c) as u64) << 4) | 4,
+        }
+    }
+}
+
+impl Commit {
+    pub(crate) fn new(hart: u64, privilege: PrivilegeMode, pc: Address, inst: u32) -> Self {
+        let compressed = inst & 0b11 != 0b11;
+        Self {
+            hart,
+            privilege,
+            pc,
+            inst: if compressed { inst & 0xFFFF } else { inst },
+            compressed,
+            writes: Vec::new(),
+            mem: Vec::new(),
+        }
+    }
+
+    /// Record a write, replacing an earlier write to the same register
+    pub(crate) fn write(&mut self, write: RegWrite) {
+        let key = write.spike_key();
+        match self.writes.binary_search_by_key(&key, RegWrite::spike_key) {
+            Ok(i) => self.writes[i] = write,
+            Err(i) => self.writes.insert(i, write),
+        }
+    }
+
+    pub(crate) fn set_mem(&mut self, mut accesses: Vec<MemAccess>) {
+        accesses.sort_by_key(|a| matches!(a.kind, MemAccessKind::Store(_)));
+        self.mem = accesses;
+    }
+}
+
+impl Trap {
+    pub fn is_interrupt(&self) -> bool {
+        self.cause & INTERRUPT_BIT != 0
+    }
+
+    /// The name Spike uses for the cause, Spike also calls interrupts exceptions in its log
+    pub fn name(&self) -> String {
+        let code = self.cause & !INTERRUPT_BIT;
+        if self.is_interrupt() {
+            return format!("interrupt #{code}");
+        }
+        EXCEPTION_NAMES
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map_or_else(|| format!("trap #{code}"), |(_, n)| n.to_string())
+    }
+
+    /// The inverse of [`Trap::name()`]
+    pub fn cause_from_name(name: &str) -> Option<u64> {
+        if let Some(code) = name.strip_prefix("interrupt #") {
+            return code.parse::<u64>().ok().map(|c| c | INTERRUPT_BIT);
+        }
+        if let Some(code) = name.strip_prefix("trap #") {
+            return code.parse().ok();
+        }
+        EXCEPTION_NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(c, _)| *c)
+    }
+}
+
+impl Display for Commit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "core {:>3}: {} 0x{:016x} ",
+            self.hart,
+            self.privilege as u8,
+            u64::from(self.pc)
+        )?;
+        if self.compressed {
+            write!(f, "(0x{:04x})", self.inst)?;
+        } else {
+            write!(f, "(0x{:08x})", self.inst)?;
+        }
+        for write in &self.writes {
+            match write {
+                RegWrite::Int(r, v) => write!(f, " x{:<2} 0x{v:016x}", *r as u8)?,
+                RegWrite::Float(r, v) => write!(f, " f{r:<2} 0x{v:016x}")?,
+                RegWrite::Csr(c, v) => write!(
+                    f,
+                    " c{}_{} 0x{v:016x}",
+                    u16::from(*c),
+                    c.name().as_deref().unwrap_or("unknown")
+                )?,
+            }
+        }
+        for access in &self.mem {
+            write!(f, " mem 0x{:016x}", u64::from(access.addr))?;
+            if let MemAccessKind::Store(v) = access.kind {
+                write!(f, " 0x{v:0width$x}", width = access.size * 2)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "core {:>3}: exception {}, epc 0x{:016x}",
+            self.hart,
+            self.name(),
+            u64::from(self.epc)
+        )?;
+        if let Some(target) = self.target {
+            write!(f, ", target {}", target as u8)?;
+        }
+        if let Some(tval) = self.tval {
+            write!(f, "\ncore {:>3}:           tval 0x{tval:016x}", self.hart)?;
+        }
+        if let Some(fault) = &self.fault {
+            write!(f, "\ncore {:>3}:           fault {fault}", self.hart)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceEvent::Commit(c) => Display::fmt(c, f),
+            TraceEvent::Trap(t) => Display::fmt(t, f),
+        }
+    }
+}
+
+/// The output of a trace, shared by all harts of a vm. Write errors are kept until the trace is
+/// stopped so a failing output does not stop the vm.
+#[derive(Default)]
+pub(crate) struct Tracer {
+    out: Option<Box<dyn Write>>,
+    error: Option<io::Error>,
+    /// Events kept until they are taken, for the lockstep checker
+    events: Option<Vec<TraceEvent>>,
+}
+
+pub(crate) type TraceSink = Rc<RefCell<Tracer>>;
+
+impl Tracer {
+    pub(crate) fn new(out: Box<dyn Write>) -> Self {
+        Self {
+            out: Some(out),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn emit(&mut self, event: TraceEvent) {
+        if self.error.is_none() {
+            if let Some(out) = &mut self.out {
+                if let Err(e) = writeln!(out, "{event}") {
+                    self.error = Some(e);
+                }
+            }
+        }
+        if let Some(events) = &mut self.events {
+            events.push(event);
+        }
+    }
+
+    pub(crate) fn has_output(&self) -> bool {
+        self.out.is_some()
+    }
+
+    /// Start or stop keeping the emitted events
+    pub(crate) fn set_buffered(&mut self, buffered: bool) {
+        self.events = buffered.then(Vec::new);
+    }
+
+    pub(crate) fn take_events(&mut self) -> Vec<TraceEvent> {
+        self.events.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Flush the output and report the first error writing to it
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        match (self.error.take(), &mut self.out) {
+            (Some(e), _) => Err(e),
+            (None, Some(out)) => out.flush(),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer").finish_non_exhaustive()
+    }
+}
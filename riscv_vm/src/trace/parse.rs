@@ -0,0 +1,218 @@
+//! Reading traces written by the vm or by Spike with `--log-commits`. Lines that are not part of
+//! a commit log, like the disassembly Spike prints with `-l` or output of the guest, are skipped.
+
+use std::io::{self, BufRead, Lines};
+
+use crate::{
+    hart::{privilege::PrivilegeMode, registers::IntRegister, CsrAddress},
+    memory::address::Address,
+};
+
+use super::{Commit, MemAccess, MemAccessKind, RegWrite, TraceEvent, Trap};
+
+#[derive(Debug)]
+pub enum TraceParseError {
+    Io(io::Error),
+    /// A line that looks like a trace line but could not be read, with its line number
+    InvalidLine(usize, String),
+}
+
+impl From<io::Error> for TraceParseError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// An iterator over the events in a trace.
+pub struct TraceReader<R> {
+    lines: Lines<R>,
+    /// The number of lines read
+    read: usize,
+    /// The line number of the first line of the last event
+    line: usize,
+    /// A line read past the end of a trap that had no tval line
+    peeked: Option<(usize, Line)>,
+}
+
+enum Line {
+    Commit(Commit),
+    Trap(Trap),
+    Tval { hart: u64, tval: u64 },
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            read: 0,
+            line: 0,
+            peeked: None,
+        }
+    }
+
+    /// The line number (from 1) at which the last event returned starts
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    fn next_line(&mut self) -> Result<Option<(usize, Line)>, TraceParseError> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+        for text in self.lines.by_ref() {
+            let text = text?;
+            self.read += 1;
+            match parse_line(&text) {
+                Ok(Some(line)) => return Ok(Some((self.read, line))),
+                Ok(None) => {}
+                Err(()) => return Err(TraceParseError::InvalidLine(self.read, text)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<TraceEvent, TraceParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, event) = match self.next_line() {
+            Ok(Some((line, Line::Commit(commit)))) => (line, TraceEvent::Commit(commit)),
+            Ok(Some((line, Line::Trap(mut trap)))) => {
+                match self.next_line() {
+                    Ok(Some((_, Line::Tval { hart, tval }))) if hart == trap.hart => {
+                        trap.tval = Some(tval);
+                    }
+                    Ok(next) => self.peeked = next,
+                    Err(e) => return Some(Err(e)),
+                }
+                (line, TraceEvent::Trap(trap))
+            }
+            Ok(Some((line, Line::Tval { .. }))) => {
+                return Some(Err(TraceParseError::InvalidLine(
+                    line,
+                    "tval without a trap".to_string(),
+                )))
+            }
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+        self.line = line;
+        Some(Ok(event))
+    }
+}
+
+/// Parse a line, `Ok(None)` if it is not part of the commit log
+fn parse_line(line: &str) -> Result<Option<Line>, ()> {
+    let Some((hart, rest)) = line
+        .strip_prefix("core")
+        .and_then(|rest| rest.split_once(':'))
+    else {
+        return Ok(None);
+    };
+    let hart = hart.trim().parse::<u64>().map_err(|_| ())?;
+    let mut tokens = rest.split_whitespace().peekable();
+
+    match tokens.next() {
+        Some("exception") => {
+            let rest = rest.trim_start().strip_prefix("exception").ok_or(())?;
+            let mut parts = rest.split(',').map(str::trim);
+            let cause = Trap::cause_from_name(parts.next().ok_or(())?).ok_or(())?;
+            let epc = parts
+                .next()
+                .and_then(|p| p.strip_prefix("epc "))
+                .ok_or(())?;
+            let target = match parts.next() {
+                Some(target) => {
+                    let target = target.strip_prefix("target ").ok_or(())?;
+                    Some(parse_privilege(target)?)
+                }
+                None => None,
+            };
+            Ok(Some(Line::Trap(Trap {
+                hart,
+                cause,
+                epc: parse_hex(epc)?.into(),
+                tval: None,
+                target,
+                fault: None,
+            })))
+        }
+        Some("tval") => {
+            let tval = parse_hex(tokens.next().ok_or(())?)?;
+            Ok(Some(Line::Tval { hart, tval }))
+        }
+        Some(privilege) if privilege.len() == 1 => {
+            let privilege = parse_privilege(privilege)?;
+            let pc = parse_hex(tokens.next().ok_or(())?)?;
+            let inst = tokens
+                .next()
+                .and_then(|i| i.strip_prefix('(')?.strip_suffix(')'))
+                .ok_or(())?;
+            let inst = parse_hex(inst)? as u32;
+            let mut commit = Commit::new(hart, privilege, pc.into(), inst);
+
+            let mut accesses = Vec::new();
+            while let Some(token) = tokens.next() {
+                if token == "mem" {
+                    let addr = parse_hex(tokens.next().ok_or(())?)?.into();
+                    let access = match tokens.next_if(|t| t.starts_with("0x")) {
+                        Some(value) => MemAccess {
+                            addr,
+                            size: (value.len() - 2) / 2,
+                            kind: MemAccessKind::Store(parse_hex(value)?),
+                        },
+                        None => MemAccess {
+                            addr,
+                            size: 0,
+                            kind: MemAccessKind::Load,
+                        },
+                    };
+                    accesses.push(access);
+                    continue;
+                }
+
+                let value = parse_hex(tokens.next().ok_or(())?)?;
+                let write = if let Some(csr) = token.strip_prefix('c') {
+                    let (addr, _) = csr.split_once('_').ok_or(())?;
+                    RegWrite::Csr(
+                        CsrAddress::from(addr.parse::<u16>().map_err(|_| ())?),
+                        value,
+                    )
+                } else if let Some(reg) = token.strip_prefix('x') {
+                    RegWrite::Int(IntRegister::from(parse_register(reg)? as u32), value)
+                } else if let Some(reg) = token.strip_prefix('f') {
+                    RegWrite::Float(parse_register(reg)?, value)
+                } else {
+                    return Err(());
+                };
+                commit.write(write);
+            }
+            commit.set_mem(accesses);
+            Ok(Some(Line::Commit(commit)))
+        }
+        // The disassembly and symbols printed by `-l`
+        _ => Ok(None),
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u64, ()> {
+    let digits = s.strip_prefix("0x").ok_or(())?;
+    u64::from_str_radix(digits, 16).map_err(|_| ())
+}
+
+fn parse_register(s: &str) -> Result<u8, ()> {
+    match s.parse::<u8>() {
+        Ok(r) if r < 32 => Ok(r),
+        _ => Err(()),
+    }
+}
+
+fn parse_privilege(s: &str) -> Result<PrivilegeMode, ()> {
+    match s {
+        "0" => Ok(PrivilegeMode::User),
+        "1" => Ok(PrivilegeMode::Supervisor),
+        "3" => Ok(PrivilegeMode::Machine),
+        _ => Err(()),
+    }
+}
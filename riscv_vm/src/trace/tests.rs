@@ -0,0 +1,165 @@
+use crate::{
+    hart::privilege::PrivilegeMode,
+    memory::{
+        fault::{FaultCause, FaultDiagnostic},
+        paging::{PageFaultCause, PageWalkFault},
+        pmp::AccessMode,
+    },
+    registers::IntRegister,
+    CsrAddress,
+};
+
+use super::{Commit, MemAccess, MemAccessKind, RegWrite, TraceEvent, TraceReader, Trap};
+
+#[test]
+fn commit_line() {
+    // Writes are ordered by register number like spike does, loads go before stores
+    // c.li a0, 1
+    let mut commit = Commit::new(
+        1,
+        PrivilegeMode::Supervisor,
+        0x80000000u64.into(),
+        0x13054505,
+    );
+    commit.write(RegWrite::Csr(CsrAddress::from(0x001u16), 0x1));
+    commit.write(RegWrite::Float(3, 0xffffffff3f800000));
+    commit.write(RegWrite::Int(IntRegister::X10, 0));
+    commit.write(RegWrite::Int(IntRegister::X10, 1));
+    commit.set_mem(vec![
+        MemAccess {
+            addr: 0x80001000u64.into(),
+            size: 2,
+            kind: MemAccessKind::Store(0xbeef),
+        },
+        MemAccess {
+            addr: 0x80002000u64.into(),
+            size: 8,
+            kind: MemAccessKind::Load,
+        },
+    ]);
+    assert_eq!(
+        commit.to_string(),
+        "core   1: 1 0x0000000080000000 (0x4505) c1_fflags 0x0000000000000001 \
+         f3  0xffffffff3f800000 x10 0x0000000000000001 mem 0x0000000080002000 \
+         mem 0x0000000080001000 0xbeef"
+    );
+}
+
+#[test]
+fn interrupt_line() {
+    let trap = Trap {
+        hart: 0,
+        cause: (1 << 63) | 7,
+        epc: 0x80000010u64.into(),
+        tval: Some(0),
+        target: Some(PrivilegeMode::Machine),
+        fault: None,
+    };
+    assert_eq!(
+        trap.to_string(),
+        "core   0: exception interrupt #7, epc 0x0000000080000010, target 3\n\
+         core   0:           tval 0x0000000000000000"
+    );
+}
+
+#[test]
+fn fault_line() {
+    let trap = Trap {
+        hart: 0,
+        cause: 15,
+        epc: 0x80000010u64.into(),
+        tval: Some(0x1000),
+        target: Some(PrivilegeMode::Supervisor),
+        fault: Some(FaultDiagnostic {
+            addr: 0x1000u64.into(),
+            mode: AccessMode::Write,
+            privilege: PrivilegeMode::User,
+            mprv: false,
+            cause: FaultCause::Page(PageWalkFault {
+                level: 2,
+                pte_addr: 0x80001000u64.into(),
+                pte: 0,
+                cause: PageFaultCause::Invalid,
+            }),
+        }),
+    };
+    let line = trap.to_string();
+    assert_eq!(
+        line,
+        "core   0: exception trap_store_page_fault, epc 0x0000000080000010, target 1\n\
+         core   0:           tval 0x0000000000001000\n\
+         core   0:           fault Write of 0x1000 in User mode, page walk failed at level 2 \
+         pte 0x0000000000000000 at 0x80001000: V is clear"
+    );
+
+    // The fault line is skipped when reading the trace back
+    let mut reader = TraceReader::new(line.as_bytes());
+    let Some(Ok(TraceEvent::Trap(read))) = reader.next() else {
+        panic!("expected a trap");
+    };
+    assert_eq!(read.tval, Some(0x1000));
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn read_spike_log() {
+    let log = "\
+bbl loader
+core   0: 0x0000000080000000 (0x00000297) auipc   t0, 0x0
+core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000
+core   0: >>>>  main
+core   0: 3 0x0000000080000004 (0x0002b503) x10 0x0000000000000000 mem 0x0000000080000000
+core   1: 3 0x0000000080000008 (0x30529073) c773_mtvec 0x0000000080000040
+core   0: exception trap_machine_ecall, epc 0x0000000080000008
+core   0: 3 0x0000000080000040 (0x00a2b023) mem 0x0000000080000000 0x0000000000000000
+core   0: exception interrupt #7, epc 0x0000000080000044, target 3
+core   0:           tval 0x0000000000000000
+";
+    let mut reader = TraceReader::new(log.as_bytes());
+    let events = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(events.len(), 6);
+    assert_eq!(reader.line(), 9);
+
+    let TraceEvent::Commit(load) = &events[1] else {
+        panic!("expected a commit")
+    };
+    assert_eq!(load.writes, [RegWrite::Int(IntRegister::X10, 0)]);
+    assert_eq!(load.mem[0].kind, MemAccessKind::Load);
+    let TraceEvent::Commit(csrw) = &events[2] else {
+        panic!("expected a commit")
+    };
+    assert_eq!(csrw.hart, 1);
+    assert_eq!(
+        csrw.writes,
+        [RegWrite::Csr(CsrAddress::from(0x305u16), 0x80000040)]
+    );
+    let TraceEvent::Trap(ecall) = &events[3] else {
+        panic!("expected a trap")
+    };
+    assert_eq!((ecall.cause, ecall.tval, ecall.target), (11, None, None));
+    let TraceEvent::Trap(interrupt) = &events[5] else {
+        panic!("expected a trap")
+    };
+    assert!(interrupt.is_interrupt());
+    assert_eq!(interrupt.tval, Some(0));
+    assert_eq!(interrupt.target, Some(PrivilegeMode::Machine));
+
+    // Everything but the noise survives a round trip
+    let written = events.iter().map(|e| format!("{e}\n")).collect::<String>();
+    let expected = log
+        .lines()
+        .filter(|l| l.starts_with("core") && !l.contains(": 0x") && !l.contains(">>>>"))
+        .map(|l| format!("{l}\n"))
+        .collect::<String>();
+    assert_eq!(written, expected);
+}
+
+#[test]
+fn read_invalid_line() {
+    let log = "core   0: 3 0x0000000080000000 (0x00000297) x5 nonsense\n";
+    let mut reader = TraceReader::new(log.as_bytes());
+    assert!(matches!(
+        reader.next(),
+        Some(Err(super::TraceParseError::InvalidLine(1, _)))
+    ));
+}
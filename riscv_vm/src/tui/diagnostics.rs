@@ -0,0 +1,71 @@
+//! Keeping what the vm prints to stderr off the screen while the [`Tui`](super::Tui) owns the
+//! terminal.
+
+use std::{
+    fs::File,
+    io::{self, Read},
+    os::fd::{FromRawFd, RawFd},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Stderr is pointed at a pipe until this is dropped, a thread collects what is written to it
+/// so the vm never blocks on a full pipe.
+#[derive(Debug)]
+pub(super) struct StderrCapture {
+    saved: RawFd,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl StderrCapture {
+    pub(super) fn start() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        // SAFETY: the fds are checked before use, the read end is owned by the File given to
+        // the thread, the write end is closed once stderr is a duplicate of it
+        unsafe {
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let [read, write] = fds;
+            let saved = libc::dup(libc::STDERR_FILENO);
+            if saved < 0 || libc::dup2(write, libc::STDERR_FILENO) < 0 {
+                let error = io::Error::last_os_error();
+                libc::close(read);
+                libc::close(write);
+                if saved >= 0 {
+                    libc::close(saved);
+                }
+                return Err(error);
+            }
+            libc::close(write);
+
+            let output = Arc::new(Mutex::new(Vec::new()));
+            let collected = output.clone();
+            let mut pipe = File::from_raw_fd(read);
+            // Ends once stderr is restored, which closes the last write end
+            thread::spawn(move || {
+                let mut buf = [0u8; 1024];
+                while let Ok(n @ 1..) = pipe.read(&mut buf) {
+                    collected.lock().unwrap().extend_from_slice(&buf[..n]);
+                }
+            });
+
+            Ok(Self { saved, output })
+        }
+    }
+
+    /// What was written to stderr since the last call
+    pub(super) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+}
+
+impl Drop for StderrCapture {
+    fn drop(&mut self) {
+        // SAFETY: saved is a duplicate of the original stderr owned by this capture
+        unsafe {
+            libc::dup2(self.saved, libc::STDERR_FILENO);
+            libc::close(self.saved);
+        }
+    }
+}
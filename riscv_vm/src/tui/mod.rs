@@ -0,0 +1,264 @@
+//! A full screen frontend for debugging a vm in the terminal, with panes for the disassembly
+//! around the pc, the registers, the most important csrs, memory and the output of the serial
+//! devices.
+
+#[cfg(unix)]
+mod diagnostics;
+#[cfg(test)]
+mod tests;
+mod view;
+
+use std::{collections::BTreeSet, io, time::Duration};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    DefaultTerminal,
+};
+
+use crate::{
+    devices::char_backend,
+    registers::IntRegister,
+    vmstate::{StopReason, VMState},
+    Address,
+};
+
+/// Steps between redraws and checks for a key press while running
+const RUN_CHUNK: u64 = 100_000;
+
+/// The amount of uart output and of messages kept, older output is dropped
+const OUTPUT_BUFFER: usize = 64 * 1024;
+
+/// The debugger state, it borrows the vm and the breakpoints so a line based frontend can share
+/// them.
+pub struct Tui<'a> {
+    vm: &'a mut VMState,
+    breakpoints: &'a mut BTreeSet<Address>,
+    focus: usize,
+    /// The line of the disassembly cursor relative to the pc
+    cursor: isize,
+    /// The addresses of the lines of the disassembly as last drawn, and the line of the pc
+    lines: Vec<Address>,
+    pc_line: usize,
+    /// The register whose value the memory pane shows
+    mem_reg: IntRegister,
+    /// Show the float instead of the integer registers
+    #[cfg(feature = "float")]
+    show_float: bool,
+    /// Register values before the last step or run, changes since are highlighted
+    prev_int: [i64; 32],
+    #[cfg(feature = "float")]
+    prev_float: [u64; 32],
+    uart: String,
+    /// What the vm printed to stderr while the tui ran
+    messages: String,
+    #[cfg(unix)]
+    stderr: Option<diagnostics::StderrCapture>,
+    status: String,
+    /// Running, and whether breakpoints are honoured
+    running: Option<bool>,
+    quit: bool,
+}
+
+impl<'a> Tui<'a> {
+    pub fn new(vm: &'a mut VMState, breakpoints: &'a mut BTreeSet<Address>, focus: usize) -> Self {
+        let mut tui = Self {
+            vm,
+            breakpoints,
+            focus,
+            cursor: 0,
+            lines: Vec::new(),
+            pc_line: 0,
+            mem_reg: IntRegister::X2,
+            #[cfg(feature = "float")]
+            show_float: false,
+            prev_int: [0; 32],
+            #[cfg(feature = "float")]
+            prev_float: [0; 32],
+            uart: String::new(),
+            messages: String::new(),
+            #[cfg(unix)]
+            stderr: None,
+            status: String::new(),
+            running: None,
+            quit: false,
+        };
+        tui.remember_registers();
+        tui
+    }
+
+    /// The hart the panes show
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    /// Take over the terminal until the user quits. The output of the serial devices is shown in
+    /// a pane instead of printed while it runs, see
+    /// [`capture_output()`](char_backend::capture_output()). On unix the diagnostics the vm
+    /// prints to stderr go to a pane of their own. An `ebreak` stops the vm while the tui runs.
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut terminal = ratatui::try_init()?;
+        char_backend::capture_output(true);
+        #[cfg(unix)]
+        {
+            self.stderr = diagnostics::StderrCapture::start().ok();
+        }
+        let halt = self.vm.set_halt_on_ebreak(true);
+        let result = self.event_loop(&mut terminal);
+        self.vm.set_halt_on_ebreak(halt);
+        char_backend::capture_output(false);
+        #[cfg(unix)]
+        {
+            self.stderr = None;
+        }
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        self.quit = false;
+        while !self.quit {
+            self.collect_output();
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if self.running.is_some() {
+                if event::poll(Duration::ZERO)? {
+                    if let Event::Key(key) = event::read()? {
+                        if key.kind == KeyEventKind::Press {
+                            self.running = None;
+                            self.status = "Interrupted".to_string();
+                        }
+                    }
+                }
+                self.run_chunk();
+            } else if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.handle_key(key.code);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn handle_key(&mut self, key: KeyCode) {
+        self.status.clear();
+        match key {
+            KeyCode::Char('s') => {
+                self.remember_registers();
+                self.cursor = 0;
+                match self.vm.step(false) {
+                    Ok(()) => match self.vm.step_watch_hit() {
+                        Some(hit) => self.status = describe_stop(hit),
+                        None => self.status = "Stepped".to_string(),
+                    },
+                    Err(e) => self.status = format!("Stepping errored at {:?}", e),
+                }
+            }
+            KeyCode::Char('c') => self.start_running(true),
+            KeyCode::Char('r') => self.start_running(false),
+            KeyCode::Char('b') => {
+                let line = self.pc_line as isize + self.cursor;
+                if let Some(addr) = usize::try_from(line).ok().and_then(|l| self.lines.get(l)) {
+                    if !self.breakpoints.remove(addr) {
+                        self.breakpoints.insert(*addr);
+                    }
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.cursor -= 1,
+            KeyCode::Down | KeyCode::Char('j') => self.cursor += 1,
+            KeyCode::Tab => {
+                self.focus = (self.focus + 1) % self.vm.hart_count();
+                self.cursor = 0;
+                self.remember_registers();
+            }
+            KeyCode::Char('[') => self.mem_reg = IntRegister::from((self.mem_reg as u32 + 31) % 32),
+            KeyCode::Char(']') => self.mem_reg = IntRegister::from((self.mem_reg as u32 + 1) % 32),
+            #[cfg(feature = "float")]
+            KeyCode::Char('f') => self.show_float = !self.show_float,
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            _ => {}
+        }
+    }
+
+    fn start_running(&mut self, breakpoints: bool) {
+        self.remember_registers();
+        self.cursor = 0;
+        self.running = Some(breakpoints);
+        self.status = "Running, press any key to stop".to_string();
+    }
+
+    /// Run one chunk, and stop running if the vm stopped for another reason than the budget.
+    pub(crate) fn run_chunk(&mut self) {
+        let Some(honour) = self.running else {
+            return;
+        };
+        let breakpoints = &*self.breakpoints;
+        let stop = self.vm.run_until(Some(RUN_CHUNK), |h| {
+            honour && breakpoints.contains(&h.get_pc())
+        });
+
+        if !matches!(
+            stop,
+            StopReason::BudgetExhausted { .. } | StopReason::Woken { .. }
+        ) {
+            self.running = None;
+            self.status = describe_stop(&stop);
+        }
+    }
+
+    fn remember_registers(&mut self) {
+        let Some(hart) = self.vm.get_hart(self.focus) else {
+            return;
+        };
+        for (i, value) in self.prev_int.iter_mut().enumerate() {
+            *value = hart.get_int_reg(IntRegister::from(i as u32));
+        }
+        #[cfg(feature = "float")]
+        for (i, value) in self.prev_float.iter_mut().enumerate() {
+            *value = view::float_bits(hart, i);
+        }
+    }
+
+    fn collect_output(&mut self) {
+        push_output(&mut self.uart, &char_backend::take_output());
+        #[cfg(unix)]
+        if let Some(stderr) = &self.stderr {
+            push_output(&mut self.messages, &stderr.take());
+        }
+    }
+}
+
+/// Append output to a pane's buffer, dropping the oldest output beyond [`OUTPUT_BUFFER`]
+fn push_output(buffer: &mut String, output: &[u8]) {
+    if output.is_empty() {
+        return;
+    }
+    buffer.push_str(&String::from_utf8_lossy(output));
+    if buffer.len() > OUTPUT_BUFFER {
+        let mut start = buffer.len() - OUTPUT_BUFFER;
+        while !buffer.is_char_boundary(start) {
+            start += 1;
+        }
+        buffer.drain(..start);
+    }
+}
+
+fn describe_stop(stop: &StopReason) -> String {
+    match stop {
+        StopReason::Breakpoint { hart, pc } => {
+            format!("Breakpoint hit, hart {} at {:#x}", hart, u64::from(*pc))
+        }
+        StopReason::Watchpoint {
+            hart,
+            pc,
+            addr,
+            access,
+        } => format!(
+            "Watchpoint hit, hart {} at {:#x}: {:?} of {:#x}",
+            hart,
+            u64::from(*pc),
+            access,
+            u64::from(*addr)
+        ),
+        stop => format!("Stopped: {:?}", stop),
+    }
+}
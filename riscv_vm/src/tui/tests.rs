@@ -0,0 +1,111 @@
+use std::collections::BTreeSet;
+
+use ratatui::{backend::TestBackend, crossterm::event::KeyCode, Terminal};
+
+use crate::{
+    vmstate::{VMSettings, VMState, VMStateBuilder},
+    Address, KB,
+};
+
+use super::Tui;
+
+fn vm_with(program: &[u32]) -> VMState {
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::new(VMSettings::default())
+        .set_hart_count(1)
+        .build()
+        .unwrap();
+    for (i, inst) in program.iter().enumerate() {
+        vm.write_memory(
+            0,
+            &inst.to_le_bytes(),
+            (0x80000000u64 + i as u64 * 4).into(),
+        )
+        .unwrap();
+    }
+    vm
+}
+
+/// The text on the screen, one string per row
+fn screen(tui: &mut Tui) -> Vec<String> {
+    let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+    terminal.draw(|frame| tui.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    buffer
+        .content
+        .chunks(buffer.area.width as usize)
+        .map(|row| row.iter().map(|c| c.symbol()).collect())
+        .collect()
+}
+
+#[test]
+fn step_shows_pc_and_registers() {
+    // addi a0, a0, 1 ; addi a0, a0, 1 ; jal x0, 0
+    let mut vm = vm_with(&[0x00150513, 0x00150513, 0x0000006f]);
+    let mut breakpoints = BTreeSet::new();
+    let mut tui = Tui::new(&mut vm, &mut breakpoints, 0);
+    tui.handle_key(KeyCode::Char('s'));
+
+    let screen = screen(&mut tui);
+    assert!(screen
+        .iter()
+        .any(|row| row.contains(" > 0x0000000080000004  addi       a0,a0,1")));
+    assert!(screen
+        .iter()
+        .any(|row| row.contains("a0 0x0000000000000001")));
+    assert!(screen.iter().any(|row| row.contains("privilege Machine")));
+}
+
+#[test]
+fn toggle_breakpoint_and_continue() {
+    // addi a0, a0, 1 ; addi a0, a0, 1 ; jal x0, -8
+    let mut vm = vm_with(&[0x00150513, 0x00150513, 0xff9ff06f]);
+    let mut breakpoints = BTreeSet::new();
+    let mut tui = Tui::new(&mut vm, &mut breakpoints, 0);
+    screen(&mut tui);
+    tui.handle_key(KeyCode::Char('j'));
+    tui.handle_key(KeyCode::Char('j'));
+    tui.handle_key(KeyCode::Char('b'));
+    assert!(tui.breakpoints.contains(&Address::from(0x80000008u64)));
+    assert!(screen(&mut tui)
+        .iter()
+        .any(|row| row.contains("*  0x0000000080000008")));
+
+    tui.handle_key(KeyCode::Char('c'));
+    tui.run_chunk();
+    assert!(tui.running.is_none());
+    assert_eq!(tui.status, "Breakpoint hit, hart 0 at 0x80000008");
+    assert_eq!(
+        tui.vm.get_hart(0).unwrap().get_pc(),
+        Address::from(0x80000008u64)
+    );
+
+    screen(&mut tui);
+    tui.handle_key(KeyCode::Char('b'));
+    assert!(tui.breakpoints.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn stderr_goes_to_messages() {
+    let mut vm = vm_with(&[0x0000006f]);
+    let mut breakpoints = BTreeSet::new();
+    let mut tui = Tui::new(&mut vm, &mut breakpoints, 0);
+    tui.stderr = Some(super::diagnostics::StderrCapture::start().unwrap());
+    let message = b"Exeption hit\n";
+    // SAFETY: the buffer outlives the call
+    unsafe { libc::write(libc::STDERR_FILENO, message.as_ptr().cast(), message.len()) };
+
+    // The pipe is read on a thread
+    for _ in 0..100 {
+        tui.collect_output();
+        if !tui.messages.is_empty() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    tui.stderr = None;
+    assert_eq!(tui.messages, "Exeption hit\n");
+    assert!(screen(&mut tui)
+        .iter()
+        .any(|row| row.contains("Exeption hit")));
+}
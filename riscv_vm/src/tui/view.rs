@@ -0,0 +1,339 @@
+//! Drawing the panes of the [`Tui`].
+
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    Frame,
+};
+#[cfg(feature = "float")]
+use softfloat_wrapper::Float;
+
+use crate::{
+    decode::{disassemble, Disassembly},
+    registers::IntRegister,
+    CsrAddress,
+};
+#[cfg(feature = "float")]
+use crate::{hart::Hart, registers::FloatRegister};
+
+use super::Tui;
+
+const MSTATUS: CsrAddress = CsrAddress::new(0x300);
+const MEPC: CsrAddress = CsrAddress::new(0x341);
+const MCAUSE: CsrAddress = CsrAddress::new(0x342);
+const SATP: CsrAddress = CsrAddress::new(0x180);
+
+const KEYS: &str = "s step  c continue  r run  b breakpoint  j/k move  tab hart  \
+                    [/] memory register  f float  q quit";
+
+const CHANGED: Style = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+
+impl Tui<'_> {
+    pub(crate) fn draw(&mut self, frame: &mut Frame) {
+        let [main, output, status] = Layout::vertical([
+            Constraint::Min(10),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [uart, messages] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(output);
+        let [code, right] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+        let [registers, csrs, memory] = Layout::vertical([
+            Constraint::Length(18),
+            Constraint::Length(10),
+            Constraint::Min(3),
+        ])
+        .areas(right);
+
+        self.draw_disassembly(frame, code);
+        self.draw_registers(frame, registers);
+        self.draw_csrs(frame, csrs);
+        self.draw_memory(frame, memory);
+        draw_output(frame, uart, " Uart ", &self.uart);
+        draw_output(frame, messages, " Messages ", &self.messages);
+
+        let status_line = if self.status.is_empty() {
+            Line::styled(KEYS, Style::new().add_modifier(Modifier::DIM))
+        } else {
+            Line::raw(self.status.as_str())
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+
+    /// The instructions around the pc and the line of the pc in them, starting about a third
+    /// of `rows` before the pc if that memory can be read.
+    fn listing(&mut self, rows: usize) -> (Vec<Disassembly>, usize) {
+        let pc = self.vm.get_hart(self.focus).unwrap().get_pc();
+        let before = rows / 3;
+
+        for back in (0..=before as u64).rev() {
+            let Some(start) = u64::from(pc).checked_sub(back * 4) else {
+                continue;
+            };
+            let Ok(bytes) = self.vm.read_memory(self.focus, start.into(), rows * 4) else {
+                continue;
+            };
+            let listing = disassemble(&bytes, start.into());
+            // Starting before the pc can decode the middle of an instruction
+            if let Some(line) = listing.iter().position(|i| i.pc == Some(pc)) {
+                let skip = line.saturating_sub(before);
+                let listing = listing.into_iter().skip(skip).take(rows).collect();
+                return (listing, line - skip);
+            }
+        }
+        (Vec::new(), 0)
+    }
+
+    fn draw_disassembly(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let (listing, pc_line) = self.listing(rows);
+        self.lines = listing.iter().filter_map(|i| i.pc).collect();
+        self.pc_line = pc_line;
+        self.cursor = self.cursor.clamp(
+            -(pc_line as isize),
+            self.lines.len() as isize - pc_line as isize - 1,
+        );
+
+        let lines: Vec<Line> = listing
+            .iter()
+            .enumerate()
+            .map(|(line, inst)| {
+                let addr = inst.pc.unwrap();
+                let breakpoint = if self.breakpoints.contains(&addr) {
+                    '*'
+                } else {
+                    ' '
+                };
+                let marker = if line == pc_line { '>' } else { ' ' };
+                let inst = inst.to_string();
+                let inst = match inst.split_once('\t') {
+                    Some((mnemonic, operands)) => format!("{mnemonic:<10} {operands}"),
+                    None => inst,
+                };
+                let text = format!("{breakpoint}{marker} {:#018x}  {inst}", u64::from(addr));
+
+                let mut style = Style::new();
+                if line == pc_line {
+                    style = style.fg(Color::Green).add_modifier(Modifier::BOLD);
+                } else if self.breakpoints.contains(&addr) {
+                    style = style.fg(Color::Red);
+                }
+                if line as isize == pc_line as isize + self.cursor {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                Line::styled(text, style)
+            })
+            .collect();
+
+        let title = format!(" Hart {} ", self.focus);
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+        let hart = self.vm.get_hart(self.focus).unwrap();
+
+        #[cfg(feature = "float")]
+        if self.show_float {
+            let cells = (0..32)
+                .map(|i| {
+                    let value = float_bits(hart, i);
+                    let name = FloatRegister::from(i as u32).abi_name();
+                    (name, value, value != self.prev_float[i])
+                })
+                .collect::<Vec<_>>();
+            frame.render_widget(
+                Paragraph::new(register_lines(&cells))
+                    .block(Block::bordered().title(" Float registers ")),
+                area,
+            );
+            return;
+        }
+
+        let cells = (0..32)
+            .map(|i| {
+                let reg = IntRegister::from(i as u32);
+                let value = hart.get_int_reg(reg);
+                (reg.abi_name(), value as u64, value != self.prev_int[i])
+            })
+            .collect::<Vec<_>>();
+        frame.render_widget(
+            Paragraph::new(register_lines(&cells)).block(Block::bordered().title(" Registers ")),
+            area,
+        );
+    }
+
+    fn draw_csrs(&self, frame: &mut Frame, area: Rect) {
+        let hart = self.vm.get_hart(self.focus).unwrap();
+        let csr = hart.get_csr();
+        let mstatus = csr.get_csr(MSTATUS);
+        let mcause = csr.get_csr(MCAUSE);
+        let satp = csr.get_csr(SATP);
+        let time = self.vm.time();
+
+        let bit = |n: u32| (mstatus >> n) & 1;
+        let lines = vec![
+            Line::raw(format!("privilege {:?}", hart.privilege())),
+            Line::raw(format!("mstatus   {mstatus:#018x}")),
+            Line::raw(format!(
+                "  MIE={} SIE={} MPIE={} SPIE={} MPP={} SPP={}",
+                bit(3),
+                bit(1),
+                bit(7),
+                bit(5),
+                (mstatus >> 11) & 0b11,
+                bit(8)
+            )),
+            Line::raw(format!(
+                "  SUM={} MXR={} MPRV={} FS={}",
+                bit(18),
+                bit(19),
+                bit(17),
+                (mstatus >> 13) & 0b11
+            )),
+            Line::raw(format!("mcause    {mcause:#018x} {}", cause_name(mcause))),
+            Line::raw(format!("mepc      {:#018x}", csr.get_csr(MEPC))),
+            Line::raw(format!(
+                "time      {time:#018x} {:.6}s",
+                time as f64 / self.vm.timebase_freq() as f64
+            )),
+            Line::raw(format!(
+                "satp      {satp:#018x} {} asid={:#x} ppn={:#x}",
+                match satp >> 60 {
+                    0 => "Bare",
+                    8 => "Sv39",
+                    9 => "Sv48",
+                    10 => "Sv57",
+                    _ => "?",
+                },
+                (satp >> 44) & 0xFFFF,
+                satp & 0xFFF_FFFF_FFFF
+            )),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Csrs ")),
+            area,
+        );
+    }
+
+    fn draw_memory(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let per_line = if area.width >= 90 { 16 } else { 8 };
+        let base = self
+            .vm
+            .get_hart(self.focus)
+            .unwrap()
+            .get_int_reg(self.mem_reg) as u64;
+
+        let lines = match self
+            .vm
+            .read_memory(self.focus, base.into(), rows * per_line)
+        {
+            Ok(bytes) => bytes
+                .chunks(per_line)
+                .enumerate()
+                .map(|(line, bytes)| {
+                    let hex: String = bytes.iter().map(|b| format!(" {b:02x}")).collect();
+                    let ascii: String = bytes
+                        .iter()
+                        .map(|b| {
+                            if b.is_ascii_graphic() {
+                                *b as char
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect();
+                    Line::raw(format!(
+                        "{:#018x}:{hex}  {ascii}",
+                        base.wrapping_add((line * per_line) as u64)
+                    ))
+                })
+                .collect(),
+            Err(e) => vec![Line::raw(format!("Can not read {base:#x}: {e:?}"))],
+        };
+
+        let title = format!(" Memory at {} ([/] to change) ", self.mem_reg.abi_name());
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+}
+
+/// The last lines of some output that fit the pane
+fn draw_output(frame: &mut Frame, area: Rect, title: &str, output: &str) {
+    let rows = area.height.saturating_sub(2) as usize;
+    let lines: Vec<&str> = output.lines().collect();
+    let lines: Vec<Line> = lines[lines.len().saturating_sub(rows)..]
+        .iter()
+        .map(|l| Line::raw(l.trim_end_matches('\r')))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+/// Two columns of registers, changed values highlighted
+fn register_lines(cells: &[(&str, u64, bool)]) -> Vec<Line<'static>> {
+    let half = cells.len() / 2;
+    (0..half)
+        .map(|row| {
+            let mut spans = Vec::new();
+            for (name, value, changed) in [cells[row], cells[row + half]] {
+                spans.push(Span::raw(format!("{name:>4} ")));
+                let value = format!("{value:#018x}  ");
+                spans.push(if changed {
+                    Span::styled(value, CHANGED)
+                } else {
+                    Span::raw(value)
+                });
+            }
+            Line::from(spans)
+        })
+        .collect()
+}
+
+fn cause_name(cause: u64) -> &'static str {
+    let interrupt = cause >> 63 == 1;
+    match (interrupt, cause & !(1 << 63)) {
+        (true, 1) => "supervisor software interrupt",
+        (true, 3) => "machine software interrupt",
+        (true, 5) => "supervisor timer interrupt",
+        (true, 7) => "machine timer interrupt",
+        (true, 9) => "supervisor external interrupt",
+        (true, 11) => "machine external interrupt",
+        (true, _) => "interrupt",
+        (false, 0) => "instruction address misaligned",
+        (false, 1) => "instruction access fault",
+        (false, 2) => "illegal instruction",
+        (false, 3) => "breakpoint",
+        (false, 4) => "load address misaligned",
+        (false, 5) => "load access fault",
+        (false, 6) => "store address misaligned",
+        (false, 7) => "store access fault",
+        (false, 8) => "ecall from U mode",
+        (false, 9) => "ecall from S mode",
+        (false, 11) => "ecall from M mode",
+        (false, 12) => "instruction page fault",
+        (false, 13) => "load page fault",
+        (false, 15) => "store page fault",
+        (false, _) => "exception",
+    }
+}
+
+/// The raw bits of a float register, NaN boxed singles included
+#[cfg(feature = "float")]
+pub(super) fn float_bits(hart: &Hart, register: usize) -> u64 {
+    hart.get_f64_reg(FloatRegister::from(register as u32))
+        .to_bits()
+}
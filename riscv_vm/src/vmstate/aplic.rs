@@ -0,0 +1,713 @@
+use std::{rc::Rc, sync::Mutex};
+
+use crate::{
+    devices::interrupt_line::InterruptLine,
+    memory::memory_buffer::{MemoryBuffer, MemoryBufferError},
+    Address,
+};
+
+use super::{
+    imsic::{FileRef, Imsic},
+    SnapshotError, SnapshotReader, SnapshotWriter,
+};
+
+const DOMAINCFG: u64 = 0x0000;
+const SOURCECFG_BASE: u64 = 0x0004;
+const MMSIADDRCFG: u64 = 0x1BC0;
+const MMSIADDRCFGH: u64 = 0x1BC4;
+const SMSIADDRCFG: u64 = 0x1BC8;
+const SMSIADDRCFGH: u64 = 0x1BCC;
+const SETIP_BASE: u64 = 0x1C00;
+const SETIPNUM: u64 = 0x1CDC;
+const IN_CLRIP_BASE: u64 = 0x1D00;
+const CLRIPNUM: u64 = 0x1DDC;
+const SETIE_BASE: u64 = 0x1E00;
+const SETIENUM: u64 = 0x1EDC;
+const CLRIE_BASE: u64 = 0x1F00;
+const CLRIENUM: u64 = 0x1FDC;
+const SETIPNUM_LE: u64 = 0x2000;
+const SETIPNUM_BE: u64 = 0x2004;
+const GENMSI: u64 = 0x3000;
+const TARGET_BASE: u64 = 0x3004;
+const IDC_BASE: u64 = 0x4000;
+const IDC_STRIDE: u64 = 0x20;
+
+/// The setip, in_clrip, setie and clrie arrays each have 32 words of source bits
+const BITS_SIZE: u64 = 0x80;
+
+const IDELIVERY: u64 = 0x00;
+const IFORCE: u64 = 0x04;
+const ITHRESHOLD: u64 = 0x08;
+const TOPI: u64 = 0x18;
+const CLAIMI: u64 = 0x1C;
+
+const DOMAINCFG_FIXED: u32 = 0x8000_0000;
+const DOMAINCFG_IE: u32 = 1 << 8;
+const DOMAINCFG_DM: u32 = 1 << 2;
+
+const SOURCECFG_D: u32 = 1 << 10;
+const INACTIVE: u32 = 0;
+const DETACHED: u32 = 1;
+const EDGE1: u32 = 4;
+const EDGE0: u32 = 5;
+const LEVEL1: u32 = 6;
+const LEVEL0: u32 = 7;
+
+const MSIADDRCFGH_L: u32 = 1 << 31;
+const MMSIADDRCFGH_MASK: u32 = 0x9F7F_FFFF;
+const SMSIADDRCFGH_MASK: u32 = 0x0070_0FFF;
+
+const GENMSI_BUSY: u32 = 1 << 12;
+const HART_SHIFT: u32 = 18;
+const EIID_MASK: u32 = 0x7FF;
+const IPRIO_MASK: u32 = 0xFF;
+
+/// The largest number of sources the register layout has room for, source 0 does not exist
+pub const MAX_SOURCES: u32 = 1023;
+
+const MACHINE: usize = 0;
+const SUPERVISOR: usize = 1;
+
+/// A riscv advanced platform level interrupt controller with a machine level root domain and a
+/// supervisor level child domain, sources are delegated to the child through their sourcecfg.
+/// A domain either signals the external interrupt of the harts through its interrupt delivery
+/// controls, or in msi mode forwards interrupts as msis to the interrupt files of the
+/// [imsic](super::imsic). Msis are written to memory on the next vm step.
+pub(crate) struct Aplic {
+    state: Rc<Mutex<AplicState>>,
+}
+
+/// The registers of one domain of an [`Aplic`]
+pub(crate) struct AplicDomain {
+    state: Rc<Mutex<AplicState>>,
+    domain: usize,
+}
+
+struct AplicState {
+    /// Indexed by source, index 0 is unused
+    sources: Vec<Source>,
+    domains: [Domain; 2],
+    mmsiaddrcfg: u32,
+    mmsiaddrcfgh: u32,
+    smsiaddrcfg: u32,
+    smsiaddrcfgh: u32,
+    /// Msis waiting to be written, as address and data
+    msis: Vec<(Address, u32)>,
+}
+
+#[derive(Default)]
+struct Source {
+    /// Delegated to the supervisor domain
+    delegated: bool,
+    /// The source mode in the domain the source belongs to
+    mode: u32,
+    pending: bool,
+    enabled: bool,
+    target: u32,
+    /// The level of the line before it is inverted by the source mode
+    level: bool,
+    line: Option<InterruptLine>,
+}
+
+struct Domain {
+    ie: bool,
+    msi: bool,
+    genmsi: u32,
+    idcs: Vec<Idc>,
+    /// The interrupt files whose external interrupt the idcs of this domain drive
+    files: Vec<FileRef>,
+}
+
+#[derive(Default)]
+struct Idc {
+    idelivery: bool,
+    iforce: bool,
+    ithreshold: u32,
+}
+
+impl Aplic {
+    /// The msi address registers start out pointing at the interrupt files of `imsic`, at
+    /// `m_files` and `s_files` with a page per hart.
+    pub(crate) fn new(sources: u32, imsic: &Imsic, m_files: Address, s_files: Address) -> Self {
+        assert!(sources <= MAX_SOURCES);
+        let harts = imsic.m_files().len();
+        let domain = |files: &[FileRef]| Domain {
+            ie: false,
+            msi: false,
+            genmsi: 0,
+            idcs: (0..harts).map(|_| Idc::default()).collect(),
+            files: files.to_vec(),
+        };
+        // Enough hart index bits to give every hart its own page
+        let lhxw = usize::BITS - harts.saturating_sub(1).leading_zeros();
+        let (m_files, s_files) = (u64::from(m_files) >> 12, u64::from(s_files) >> 12);
+        Self {
+            state: Rc::new(Mutex::new(AplicState {
+                sources: (0..=sources).map(|_| Source::default()).collect(),
+                domains: [domain(imsic.m_files()), domain(imsic.s_files())],
+                mmsiaddrcfg: m_files as u32,
+                mmsiaddrcfgh: lhxw << 12 | (m_files >> 32) as u32 & 0xFFF,
+                smsiaddrcfg: s_files as u32,
+                smsiaddrcfgh: (s_files >> 32) as u32 & 0xFFF,
+                msis: Vec::new(),
+            })),
+        }
+    }
+
+    /// The registers of the machine level domain
+    pub(crate) fn m_domain(&self) -> AplicDomain {
+        AplicDomain {
+            state: self.state.clone(),
+            domain: MACHINE,
+        }
+    }
+
+    /// The registers of the supervisor level domain
+    pub(crate) fn s_domain(&self) -> AplicDomain {
+        AplicDomain {
+            state: self.state.clone(),
+            domain: SUPERVISOR,
+        }
+    }
+
+    /// Connect `line` to `source`, returns false if there is no such source
+    pub(crate) fn connect(&self, source: u32, line: InterruptLine) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.sources.get_mut(source as usize) {
+            Some(slot) if source != 0 => {
+                slot.line = Some(line);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sample the lines of the sources and deliver pending interrupts
+    pub(crate) fn update(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.sample();
+        state.deliver();
+    }
+
+    /// Take the msis that were forwarded since the last call, the vm writes them to memory
+    pub(crate) fn take_msis(&self) -> Vec<(Address, u32)> {
+        let mut state = self.state.lock().unwrap();
+        for domain in state.domains.iter_mut() {
+            domain.genmsi &= !GENMSI_BUSY;
+        }
+        std::mem::take(&mut state.msis)
+    }
+
+    /// Whether any enabled source has a line connected, so an interrupt can still arrive while
+    /// all harts wait
+    pub(crate) fn is_armed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.sources.iter().skip(1).any(|source| {
+            source.line.is_some()
+                && source.enabled
+                && source.mode != INACTIVE
+                && state.domains[source.domain()].ie
+        })
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        let state = self.state.lock().unwrap();
+        for source in state.sources.iter().skip(1) {
+            snapshot.write_bool(source.delegated);
+            snapshot.write_u64(source.mode as u64);
+            snapshot.write_bool(source.pending);
+            snapshot.write_bool(source.enabled);
+            snapshot.write_u64(source.target as u64);
+            snapshot.write_bool(source.level);
+        }
+        for domain in &state.domains {
+            snapshot.write_bool(domain.ie);
+            snapshot.write_bool(domain.msi);
+            snapshot.write_u64(domain.genmsi as u64);
+            for idc in &domain.idcs {
+                snapshot.write_bool(idc.idelivery);
+                snapshot.write_bool(idc.iforce);
+                snapshot.write_u64(idc.ithreshold as u64);
+            }
+        }
+        snapshot.write_u64(state.mmsiaddrcfg as u64);
+        snapshot.write_u64(state.mmsiaddrcfgh as u64);
+        snapshot.write_u64(state.smsiaddrcfg as u64);
+        snapshot.write_u64(state.smsiaddrcfgh as u64);
+        snapshot.write_u64(state.msis.len() as u64);
+        for (addr, data) in &state.msis {
+            snapshot.write_address(*addr);
+            snapshot.write_u64(*data as u64);
+        }
+    }
+
+    /// The external interrupts driven by the domains are restored with the interrupt files
+    pub(crate) fn restore(&self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        let mut state = self.state.lock().unwrap();
+        for source in state.sources.iter_mut().skip(1) {
+            source.delegated = snapshot.read_bool()?;
+            source.mode = valid_mode(snapshot.read_u64()? as u32);
+            source.pending = snapshot.read_bool()?;
+            source.enabled = snapshot.read_bool()?;
+            source.target = snapshot.read_u64()? as u32;
+            source.level = snapshot.read_bool()?;
+        }
+        for domain in state.domains.iter_mut() {
+            domain.ie = snapshot.read_bool()?;
+            domain.msi = snapshot.read_bool()?;
+            domain.genmsi = snapshot.read_u64()? as u32;
+            for idc in domain.idcs.iter_mut() {
+                idc.idelivery = snapshot.read_bool()?;
+                idc.iforce = snapshot.read_bool()?;
+                idc.ithreshold = snapshot.read_u64()? as u32 & IPRIO_MASK;
+            }
+        }
+        state.mmsiaddrcfg = snapshot.read_u64()? as u32;
+        state.mmsiaddrcfgh = snapshot.read_u64()? as u32 & MMSIADDRCFGH_MASK;
+        state.smsiaddrcfg = snapshot.read_u64()? as u32;
+        state.smsiaddrcfgh = snapshot.read_u64()? as u32 & SMSIADDRCFGH_MASK;
+        state.msis.clear();
+        for _ in 0..snapshot.read_u64()? {
+            let addr = snapshot.read_address()?;
+            state.msis.push((addr, snapshot.read_u64()? as u32));
+        }
+        Ok(())
+    }
+}
+
+/// The reserved source modes act as inactive
+fn valid_mode(mode: u32) -> u32 {
+    match mode {
+        DETACHED | EDGE1 | EDGE0 | LEVEL1 | LEVEL0 => mode,
+        _ => INACTIVE,
+    }
+}
+
+impl Source {
+    fn domain(&self) -> usize {
+        if self.delegated {
+            SUPERVISOR
+        } else {
+            MACHINE
+        }
+    }
+
+    /// The level of the line after inverting it for the low and falling edge modes
+    fn rectified(&self) -> bool {
+        match self.mode {
+            EDGE1 | LEVEL1 => self.level,
+            EDGE0 | LEVEL0 => !self.level,
+            _ => false,
+        }
+    }
+
+    fn is_level(&self) -> bool {
+        matches!(self.mode, LEVEL1 | LEVEL0)
+    }
+
+    /// In direct mode the pending bit of a level triggered source follows the line, in msi mode
+    /// it is set when the line is raised and cleared when it is forwarded
+    fn sample(&mut self, msi: bool) {
+        let Some(line) = &self.line else {
+            return;
+        };
+        let was = self.rectified();
+        self.level = line.is_raised();
+        let now = self.rectified();
+        match self.mode {
+            EDGE1 | EDGE0 if now && !was => self.pending = true,
+            LEVEL1 | LEVEL0 if !msi => self.pending = now,
+            LEVEL1 | LEVEL0 if !now => self.pending = false,
+            LEVEL1 | LEVEL0 if !was => self.pending = true,
+            _ => {}
+        }
+    }
+
+    /// A level triggered source can only be made pending while its line is raised, and only in
+    /// msi mode
+    fn set_pending(&mut self, msi: bool) {
+        if !self.is_level() || (msi && self.rectified()) {
+            self.pending = self.mode != INACTIVE;
+        }
+    }
+
+    fn clear_pending(&mut self, msi: bool) {
+        if !self.is_level() || msi {
+            self.pending = false;
+        }
+    }
+
+    fn hart(&self) -> usize {
+        (self.target >> HART_SHIFT) as usize
+    }
+}
+
+impl AplicState {
+    fn sample(&mut self) {
+        for source in self.sources.iter_mut().skip(1) {
+            let msi = self.domains[source.domain()].msi;
+            source.sample(msi);
+        }
+    }
+
+    /// Whether `source` exists, belongs to `domain` and is not inactive
+    fn is_active(&self, domain: usize, source: usize) -> bool {
+        source != 0
+            && self
+                .sources
+                .get(source)
+                .is_some_and(|s| s.domain() == domain && s.mode != INACTIVE)
+    }
+
+    /// The source with the lowest priority number, then the lowest number, that is pending and
+    /// enabled for `hart` and below its threshold, as `topi`
+    fn topi(&self, domain: usize, hart: usize) -> u32 {
+        let threshold = self.domains[domain].idcs[hart].ithreshold;
+        (1..self.sources.len())
+            .filter(|s| self.is_active(domain, *s))
+            .map(|s| (s, &self.sources[s]))
+            .filter(|(_, s)| s.pending && s.enabled && s.hart() == hart)
+            .map(|(id, s)| (s.target & IPRIO_MASK, id as u32))
+            .filter(|(prio, _)| threshold == 0 || *prio < threshold)
+            .min()
+            .map_or(0, |(prio, id)| id << 16 | prio)
+    }
+
+    fn claimi(&mut self, domain: usize, hart: usize) -> u32 {
+        let topi = self.topi(domain, hart);
+        if topi == 0 {
+            self.domains[domain].idcs[hart].iforce = false;
+        } else {
+            // A level triggered source that is still raised becomes pending again when sampled
+            self.sources[(topi >> 16) as usize].pending = false;
+        }
+        topi
+    }
+
+    fn msi_address(&self, domain: usize, hart: usize) -> Address {
+        let high = self.mmsiaddrcfgh;
+        let lhxw = (high >> 12) & 0xF;
+        let hhxw = (high >> 16) & 0x7;
+        let hhxs = (high >> 24) & 0x1F;
+        let (low, high) = match domain {
+            MACHINE => (self.mmsiaddrcfg, high),
+            _ => (self.smsiaddrcfg, self.smsiaddrcfgh),
+        };
+        let lhxs = (high >> 20) & 0x7;
+        let ppn = ((high & 0xFFF) as u64) << 32 | low as u64;
+        let group = (hart as u64 >> lhxw) & ((1 << hhxw) - 1);
+        let index = hart as u64 & ((1 << lhxw) - 1);
+        ((ppn | group << (hhxs + 12) | index << lhxs) << 12).into()
+    }
+
+    /// Forward pending interrupts of domains in msi mode and update the external interrupts
+    /// driven by the others
+    fn deliver(&mut self) {
+        for domain in [MACHINE, SUPERVISOR] {
+            if self.domains[domain].msi {
+                for file in &self.domains[domain].files {
+                    file.lock().unwrap().set_external(false);
+                }
+                if !self.domains[domain].ie {
+                    continue;
+                }
+                for s in 1..self.sources.len() {
+                    if !self.is_active(domain, s) || !self.sources[s].pending {
+                        continue;
+                    }
+                    if self.sources[s].enabled {
+                        self.sources[s].pending = false;
+                        let target = self.sources[s].target;
+                        let addr = self.msi_address(domain, self.sources[s].hart());
+                        self.msis.push((addr, target & EIID_MASK));
+                    }
+                }
+            } else {
+                let ie = self.domains[domain].ie;
+                for hart in 0..self.domains[domain].idcs.len() {
+                    let idc = &self.domains[domain].idcs[hart];
+                    let raised =
+                        ie && idc.idelivery && (idc.iforce || self.topi(domain, hart) != 0);
+                    self.domains[domain].files[hart]
+                        .lock()
+                        .unwrap()
+                        .set_external(raised);
+                }
+            }
+        }
+    }
+
+    /// The sources of word `word` of a bit array that belong to `domain`
+    fn word_sources(&self, domain: usize, word: u64) -> impl Iterator<Item = usize> + '_ {
+        (word as usize * 32..word as usize * 32 + 32).filter(move |s| self.is_active(domain, *s))
+    }
+
+    fn read_bits(&self, domain: usize, word: u64, bit: impl Fn(&Source) -> bool) -> u32 {
+        self.word_sources(domain, word)
+            .filter(|s| bit(&self.sources[*s]))
+            .fold(0, |bits, s| bits | 1 << (s % 32))
+    }
+
+    fn write_bits(&mut self, domain: usize, word: u64, value: u32, f: impl Fn(&mut Source, bool)) {
+        let msi = self.domains[domain].msi;
+        let sources: Vec<usize> = self
+            .word_sources(domain, word)
+            .filter(|s| value & (1 << (s % 32)) != 0)
+            .collect();
+        for s in sources {
+            f(&mut self.sources[s], msi);
+        }
+    }
+
+    fn write_num(&mut self, domain: usize, source: u32, f: impl Fn(&mut Source, bool)) {
+        let msi = self.domains[domain].msi;
+        if self.is_active(domain, source as usize) {
+            f(&mut self.sources[source as usize], msi);
+        }
+    }
+
+    fn read_sourcecfg(&self, domain: usize, source: usize) -> u32 {
+        match self.sources.get(source) {
+            Some(s) if domain == MACHINE && s.delegated => SOURCECFG_D,
+            Some(s) if s.domain() == domain => s.mode,
+            _ => 0,
+        }
+    }
+
+    /// Only the machine level domain has a child to delegate to
+    fn write_sourcecfg(&mut self, domain: usize, source: usize, value: u32) {
+        let Some(s) = self.sources.get_mut(source) else {
+            return;
+        };
+        if s.domain() != domain && !(domain == MACHINE && s.delegated) {
+            return;
+        }
+        if domain == MACHINE && value & SOURCECFG_D != 0 {
+            s.delegated = true;
+            s.mode = INACTIVE;
+        } else {
+            s.delegated = domain == SUPERVISOR;
+            s.mode = valid_mode(value & 0x7);
+        }
+        let msi = self.domains[s.domain()].msi;
+        // The source starts over in its new mode
+        s.pending = s.is_level() && !msi && s.rectified();
+        if s.mode == INACTIVE {
+            s.enabled = false;
+            s.target = 0;
+        }
+    }
+
+    fn write_target(&mut self, domain: usize, source: usize, value: u32) {
+        if !self.is_active(domain, source) {
+            return;
+        }
+        let hart = value >> HART_SHIFT << HART_SHIFT;
+        self.sources[source].target = if self.domains[domain].msi {
+            // Guest interrupt files are not supported, the guest index is always 0
+            hart | value & EIID_MASK
+        } else {
+            // Priority 0 is not allowed and reads back as 1
+            hart | (value & IPRIO_MASK).max(1)
+        };
+    }
+
+    fn write_msiaddrcfg(&mut self, offset: u64, value: u32) {
+        if self.mmsiaddrcfgh & MSIADDRCFGH_L != 0 {
+            return;
+        }
+        match offset {
+            MMSIADDRCFG => self.mmsiaddrcfg = value,
+            MMSIADDRCFGH => self.mmsiaddrcfgh = value & MMSIADDRCFGH_MASK,
+            SMSIADDRCFG => self.smsiaddrcfg = value,
+            SMSIADDRCFGH => self.smsiaddrcfgh = value & SMSIADDRCFGH_MASK,
+            _ => {}
+        }
+    }
+
+    fn write_genmsi(&mut self, domain: usize, value: u32) {
+        if !self.domains[domain].msi || self.domains[domain].genmsi & GENMSI_BUSY != 0 {
+            return;
+        }
+        let genmsi = value >> HART_SHIFT << HART_SHIFT | value & EIID_MASK;
+        self.domains[domain].genmsi = genmsi | GENMSI_BUSY;
+        let addr = self.msi_address(domain, (genmsi >> HART_SHIFT) as usize);
+        self.msis.push((addr, genmsi & EIID_MASK));
+    }
+
+    /// Reading a claimi register claims the interrupt it shows
+    fn read(&mut self, domain: usize, offset: u64) -> u32 {
+        match self.claimi_register(domain, offset) {
+            Some(hart) => self.claimi(domain, hart),
+            None => self.peek(domain, offset),
+        }
+    }
+
+    /// The hart whose claimi register in `domain` is at `offset`
+    fn claimi_register(&self, domain: usize, offset: u64) -> Option<usize> {
+        let offset = offset.checked_sub(IDC_BASE)?;
+        let hart = (offset / IDC_STRIDE) as usize;
+        (offset % IDC_STRIDE == CLAIMI && hart < self.domains[domain].idcs.len()).then_some(hart)
+    }
+
+    /// The value a read returns without claiming anything, claimi shows `topi`. Reserved and
+    /// missing registers read as 0
+    fn peek(&self, domain: usize, offset: u64) -> u32 {
+        let msiaddrcfg = domain == MACHINE;
+        match offset {
+            DOMAINCFG => {
+                let domain = &self.domains[domain];
+                DOMAINCFG_FIXED
+                    | if domain.ie { DOMAINCFG_IE } else { 0 }
+                    | if domain.msi { DOMAINCFG_DM } else { 0 }
+            }
+            SOURCECFG_BASE..MMSIADDRCFG => {
+                self.read_sourcecfg(domain, ((offset - SOURCECFG_BASE) / 4 + 1) as usize)
+            }
+            MMSIADDRCFG if msiaddrcfg => self.mmsiaddrcfg,
+            MMSIADDRCFGH if msiaddrcfg => self.mmsiaddrcfgh,
+            SMSIADDRCFG if msiaddrcfg => self.smsiaddrcfg,
+            SMSIADDRCFGH if msiaddrcfg => self.smsiaddrcfgh,
+            _ if (SETIP_BASE..SETIP_BASE + BITS_SIZE).contains(&offset) => {
+                self.read_bits(domain, (offset - SETIP_BASE) / 4, |s| s.pending)
+            }
+            _ if (IN_CLRIP_BASE..IN_CLRIP_BASE + BITS_SIZE).contains(&offset) => {
+                self.read_bits(domain, (offset - IN_CLRIP_BASE) / 4, Source::rectified)
+            }
+            _ if (SETIE_BASE..SETIE_BASE + BITS_SIZE).contains(&offset) => {
+                self.read_bits(domain, (offset - SETIE_BASE) / 4, |s| s.enabled)
+            }
+            GENMSI => self.domains[domain].genmsi,
+            TARGET_BASE..IDC_BASE => {
+                let source = ((offset - TARGET_BASE) / 4 + 1) as usize;
+                if self.is_active(domain, source) {
+                    self.sources[source].target
+                } else {
+                    0
+                }
+            }
+            IDC_BASE.. => {
+                let hart = ((offset - IDC_BASE) / IDC_STRIDE) as usize;
+                let Some(idc) = self.domains[domain].idcs.get(hart) else {
+                    return 0;
+                };
+                match (offset - IDC_BASE) % IDC_STRIDE {
+                    IDELIVERY => idc.idelivery as u32,
+                    IFORCE => idc.iforce as u32,
+                    ITHRESHOLD => idc.ithreshold,
+                    TOPI | CLAIMI => self.topi(domain, hart),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, domain: usize, offset: u64, value: u32) {
+        match offset {
+            DOMAINCFG => {
+                self.domains[domain].ie = value & DOMAINCFG_IE != 0;
+                self.domains[domain].msi = value & DOMAINCFG_DM != 0;
+            }
+            SOURCECFG_BASE..MMSIADDRCFG => {
+                self.write_sourcecfg(domain, ((offset - SOURCECFG_BASE) / 4 + 1) as usize, value)
+            }
+            MMSIADDRCFG..=SMSIADDRCFGH if domain == MACHINE => self.write_msiaddrcfg(offset, value),
+            _ if (SETIP_BASE..SETIP_BASE + BITS_SIZE).contains(&offset) => self.write_bits(
+                domain,
+                (offset - SETIP_BASE) / 4,
+                value,
+                Source::set_pending,
+            ),
+            SETIPNUM | SETIPNUM_LE => self.write_num(domain, value, Source::set_pending),
+            SETIPNUM_BE => self.write_num(domain, value.swap_bytes(), Source::set_pending),
+            _ if (IN_CLRIP_BASE..IN_CLRIP_BASE + BITS_SIZE).contains(&offset) => self.write_bits(
+                domain,
+                (offset - IN_CLRIP_BASE) / 4,
+                value,
+                Source::clear_pending,
+            ),
+            CLRIPNUM => self.write_num(domain, value, Source::clear_pending),
+            _ if (SETIE_BASE..SETIE_BASE + BITS_SIZE).contains(&offset) => {
+                self.write_bits(domain, (offset - SETIE_BASE) / 4, value, |s, _| {
+                    s.enabled = true
+                })
+            }
+            SETIENUM => self.write_num(domain, value, |s, _| s.enabled = true),
+            _ if (CLRIE_BASE..CLRIE_BASE + BITS_SIZE).contains(&offset) => {
+                self.write_bits(domain, (offset - CLRIE_BASE) / 4, value, |s, _| {
+                    s.enabled = false
+                })
+            }
+            CLRIENUM => self.write_num(domain, value, |s, _| s.enabled = false),
+            GENMSI => self.write_genmsi(domain, value),
+            TARGET_BASE..IDC_BASE => {
+                self.write_target(domain, ((offset - TARGET_BASE) / 4 + 1) as usize, value)
+            }
+            IDC_BASE.. => {
+                let hart = ((offset - IDC_BASE) / IDC_STRIDE) as usize;
+                let Some(idc) = self.domains[domain].idcs.get_mut(hart) else {
+                    return;
+                };
+                match (offset - IDC_BASE) % IDC_STRIDE {
+                    IDELIVERY => idc.idelivery = value & 1 != 0,
+                    IFORCE => idc.iforce = value & 1 != 0,
+                    ITHRESHOLD => idc.ithreshold = value & IPRIO_MASK,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MemoryBuffer for AplicDomain {
+    fn size(&self) -> u64 {
+        let harts = self.state.lock().unwrap().domains[self.domain].idcs.len() as u64;
+        IDC_BASE + harts * IDC_STRIDE
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !bytes.len().is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedWrite(addr.into()));
+        }
+        let mut state = self.state.lock().unwrap();
+        for (offset, word) in (addr..).step_by(4).zip(bytes.chunks_exact(4)) {
+            state.write(
+                self.domain,
+                offset,
+                u32::from_le_bytes(word.try_into().unwrap()),
+            );
+        }
+        state.deliver();
+        Ok(())
+    }
+
+    fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !size.is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedRead(addr.into()));
+        }
+        let mut state = self.state.lock().unwrap();
+        let mut bytes = Vec::with_capacity(size);
+        for offset in (addr..addr + size as u64).step_by(4) {
+            bytes.extend(state.read(self.domain, offset).to_le_bytes());
+        }
+        // A claim lowers the interrupt
+        state.deliver();
+        Ok(bytes)
+    }
+
+    fn peek_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !size.is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedRead(addr.into()));
+        }
+        let state = self.state.lock().unwrap();
+        Ok((addr..addr + size as u64)
+            .step_by(4)
+            .flat_map(|offset| state.peek(self.domain, offset).to_le_bytes())
+            .collect())
+    }
+}
@@ -0,0 +1,175 @@
+//! Unwinding the stack of a hart, see [`VMState::backtrace()`](super::VMState::backtrace()).
+
+use std::fmt::Display;
+
+use elf_load::{
+    dwarf::frame::{CallFrameInfo, CfaRule, FrameRules, RegisterRule},
+    error::ElfParseError,
+    symbol::SymbolTable,
+    Elf,
+};
+
+use crate::{hart::privilege::PrivilegeMode, Address};
+
+/// The csrs of the trap vectors and the pcs traps were taken at
+pub(super) const MTVEC: u16 = 0x305;
+pub(super) const MEPC: u16 = 0x341;
+pub(super) const STVEC: u16 = 0x105;
+pub(super) const SEPC: u16 = 0x141;
+
+/// Frames after which unwinding stops, in case of a loop the checks below do not catch
+pub(super) const MAX_FRAMES: usize = 64;
+
+/// The largest distance between the stack pointer and a frame pointer that is still believed
+const MAX_FRAME_SIZE: u64 = 1 << 20;
+
+/// The dwarf numbers of the registers the unwinder uses
+pub(super) const RA: usize = 1;
+pub(super) const SP: usize = 2;
+pub(super) const FP: usize = 8;
+
+/// What the unwinder knows about the guest, from the elf it runs.
+#[derive(Debug, Clone, Default)]
+pub struct UnwindInfo {
+    /// Used to name the functions of frames and to find trap handlers
+    pub symbols: SymbolTable,
+    /// Used for functions without a frame pointer
+    pub frames: CallFrameInfo,
+}
+
+impl UnwindInfo {
+    pub fn from_elf(elf: &Elf) -> Result<Self, ElfParseError> {
+        Ok(Self {
+            symbols: elf.symbol_table()?,
+            frames: elf.call_frame_info()?,
+        })
+    }
+
+    /// The symbol `addr` is in and the offset of `addr` into it
+    pub(super) fn symbol(&self, addr: Address) -> Option<(&str, u64)> {
+        self.symbols
+            .by_address(elf_load::Address(addr.into()))
+            .map(|(s, offset)| (s.name.as_str(), offset))
+    }
+
+    /// Whether two addresses are in the same function, or equal if there are no symbols
+    pub(super) fn same_function(&self, a: Address, b: Address) -> bool {
+        match (self.symbol(a), self.symbol(b)) {
+            (Some((a, _)), Some((b, _))) => a == b,
+            _ => a == b,
+        }
+    }
+
+    pub(super) fn rules(&self, addr: Address) -> Option<FrameRules> {
+        self.frames.rules(elf_load::Address(addr.into()))
+    }
+}
+
+/// How a frame was found from the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoundBy {
+    /// The innermost frame, where the hart is
+    Pc,
+    /// The return address and frame pointer saved below the frame pointer
+    FramePointer,
+    CallFrameInfo,
+    /// The return address still in `ra`, for functions that did not set up a frame
+    ReturnAddress,
+    /// The frame before it was a trap handler, the frame is where the trap was taken as given by
+    /// the `mepc` or `sepc` of the mode
+    Trap(PrivilegeMode),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub pc: Address,
+    /// The stack pointer of the frame, if it is known
+    pub sp: Option<u64>,
+    /// The symbol the frame's function is in and the offset of the pc into it
+    pub symbol: Option<(String, u64)>,
+    pub found_by: FoundBy,
+}
+
+impl Frame {
+    /// The address that describes the frame's position in the code, which for callers is the
+    /// call and not the return address, as a call can be the last instruction of a function.
+    pub fn lookup_pc(&self) -> Address {
+        match self.found_by {
+            FoundBy::Pc | FoundBy::Trap(_) => self.pc,
+            _ => Address::from(u64::from(self.pc).wrapping_sub(1)),
+        }
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#018x}", u64::from(self.pc))?;
+        if let Some((name, offset)) = &self.symbol {
+            write!(f, " <{}+{:#x}>", name, offset)?;
+        }
+        if let FoundBy::Trap(mode) = self.found_by {
+            write!(f, " (trapped to {:?} mode)", mode)?;
+        }
+        Ok(())
+    }
+}
+
+/// The integer registers of a frame as far as they are known.
+pub(super) type Registers = [Option<u64>; 32];
+
+/// The canonical frame address of a frame, if its rule is understood and the register it is
+/// computed from is known
+pub(super) fn cfa(rules: &FrameRules, registers: &Registers) -> Option<u64> {
+    let CfaRule::RegisterOffset(register, offset) = rules.cfa else {
+        return None;
+    };
+    Some(
+        registers
+            .get(register as usize)
+            .copied()
+            .flatten()?
+            .wrapping_add_signed(offset),
+    )
+}
+
+/// Unwind a frame with its call frame information, returns the registers of the caller. The
+/// caller's pc is in the register the rules give for the return address.
+pub(super) fn unwind_cfi(
+    rules: &FrameRules,
+    registers: &Registers,
+    mut read: impl FnMut(u64) -> Option<u64>,
+) -> Option<Registers> {
+    let cfa = cfa(rules, registers)?;
+
+    let mut caller = [None; 32];
+    for (i, value) in caller.iter_mut().enumerate() {
+        *value = match rules.register(i as u16) {
+            RegisterRule::SameValue => registers[i],
+            RegisterRule::Offset(offset) => read(cfa.wrapping_add_signed(offset)),
+            RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add_signed(offset)),
+            RegisterRule::Register(other) => registers.get(other as usize).copied().flatten(),
+            RegisterRule::Undefined | RegisterRule::Unsupported => None,
+        };
+    }
+    caller[SP] = Some(cfa);
+    Some(caller)
+}
+
+/// Unwind a frame with the standard frame record, the return address at `fp - 8` and the
+/// caller's frame pointer at `fp - 16`. The frame pointer must point into the stack. The caller's
+/// pc is in its `ra`, the other registers saved by the frame are not known.
+pub(super) fn unwind_frame_pointer(
+    registers: &Registers,
+    mut read: impl FnMut(u64) -> Option<u64>,
+) -> Option<Registers> {
+    let (fp, sp) = (registers[FP]?, registers[SP]?);
+    if fp % 8 != 0 || fp <= sp || fp - sp > MAX_FRAME_SIZE {
+        return None;
+    }
+
+    let mut caller = [None; 32];
+    caller[RA] = Some(read(fp - 8)?);
+    caller[FP] = Some(read(fp - 16)?);
+    caller[SP] = Some(fp);
+    Some(caller)
+}
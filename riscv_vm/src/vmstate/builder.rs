@@ -2,11 +2,12 @@ use std::sync::mpsc::Sender;
 
 use nohash_hasher::IntMap;
 
-use super::VMSettings;
+use super::{aplic, imsic, plic::MAX_SOURCES, TimerLayout, VMSettings, VirtualTime};
 use crate::{
     devices::{
         async_device::{AsyncDevice, AsyncDeviceHolder},
         handled_device::{HandledDevice, HandledDeviceHolder},
+        interrupt_line::InterruptLine,
         Device, DeviceInitError,
     },
     memory::address::Address,
@@ -24,11 +25,30 @@ pub struct VMStateBuilder<const MEM_SIZE: usize> {
     settings: VMSettings,
     handled_devices: Vec<HandledDeviceHolder>,
     async_devices: Vec<AsyncDeviceHolder>,
+    interrupt_lines: Vec<(u32, InterruptLine)>,
 }
 
 #[derive(Debug)]
 pub enum VMInitError {
     DeviceInitError(DeviceInitError),
+    /// An interrupt line was requested for a source the plic or aplic does not have, or neither
+    /// is enabled
+    InvalidInterruptSource(u32),
+    /// More plic sources than [`MAX_PLIC_SOURCES`](super::MAX_PLIC_SOURCES)
+    TooManyPlicSources(u32),
+    /// More aplic sources than [`MAX_APLIC_SOURCES`](super::MAX_APLIC_SOURCES)
+    TooManyAplicSources(u32),
+    /// The number of imsic interrupt identities must be one less than a multiple of 64, and at
+    /// most [`MAX_IMSIC_IDS`](super::MAX_IMSIC_IDS)
+    InvalidImsicIds(u32),
+    /// Both the plic and the aia interrupt controllers are enabled, they drive the same external
+    /// interrupts
+    ConflictingInterruptControllers,
+    /// [`VirtualTime::instructions`](super::VirtualTime::instructions) or
+    /// [`VirtualTime::timebase_freq`](super::VirtualTime::timebase_freq) is zero
+    InvalidVirtualTime,
+    /// [`VMSettings::exception_storm_limit`] is zero, use `None` to turn it off
+    InvalidExceptionStormLimit,
 }
 
 impl<const MEM_SIZE: usize> VMStateBuilder<MEM_SIZE> {
@@ -53,6 +73,62 @@ impl<const MEM_SIZE: usize> VMStateBuilder<MEM_SIZE> {
         self
     }
 
+    /// Place the timer and software interrupt registers like a CLINT or ACLINT would, see
+    /// [`VMSettings::timer_layout`]. The registers are moved to where the layout usually has
+    /// them: a CLINT at `0x2000000` with the SSWI after it, or an ACLINT with its MSWI, MTIMER
+    /// and SSWI next to each other from `0x2000000`. The legacy layout goes back to the default
+    /// addresses. Only addresses still where the previous layout put them are moved, so an
+    /// address given in the [`VMSettings`] is kept whatever the order of the calls.
+    pub fn set_timer_layout(mut self, layout: TimerLayout) -> Self {
+        let (old_timer, old_m_swi, old_s_swi) = layout_addresses(self.settings.timer_layout);
+        let (timer, m_swi, s_swi) = layout_addresses(layout);
+        let settings = &mut self.settings;
+        settings.timer_layout = layout;
+        if settings.timer_addr == old_timer {
+            settings.timer_addr = timer;
+        }
+        if settings.m_mode_swi_addr == old_m_swi {
+            settings.m_mode_swi_addr = m_swi;
+        }
+        if settings.s_mode_swi_addr == old_s_swi {
+            settings.s_mode_swi_addr = s_swi;
+        }
+        self
+    }
+
+    /// Map a plic with the given number of interrupt sources, see [`VMSettings::plic_enable`].
+    pub fn enable_plic(mut self, sources: u32) -> Self {
+        self.settings.plic_enable = true;
+        self.settings.plic_sources = sources;
+        self
+    }
+
+    /// Map the aia interrupt controllers with the given number of aplic sources, see
+    /// [`VMSettings::aia_enable`].
+    pub fn enable_aia(mut self, sources: u32) -> Self {
+        self.settings.aia_enable = true;
+        self.settings.aplic_sources = sources;
+        self
+    }
+
+    /// The line of a plic or aplic source, give it to the device that raises the interrupt.
+    /// Requesting the same source again gives the same line.
+    pub fn interrupt_line(&mut self, source: u32) -> InterruptLine {
+        if let Some((_, line)) = self.interrupt_lines.iter().find(|(s, _)| *s == source) {
+            return line.clone();
+        }
+        let line = InterruptLine::new();
+        self.interrupt_lines.push((source, line.clone()));
+        line
+    }
+
+    /// Derive time from the number of executed steps instead of the host clock, see
+    /// [`VMSettings::virtual_time`].
+    pub fn enable_virtual_time(mut self, time: VirtualTime) -> Self {
+        self.settings.virtual_time = Some(time);
+        self
+    }
+
     /// Set the number of harts this vm has.
     ///
     /// NOTE: Will at some point be replaced with hart specific settings.
@@ -78,8 +154,18 @@ impl<const MEM_SIZE: usize> VMStateBuilder<MEM_SIZE> {
     // The device will be passed this base address so it can place its memory mapped registers
     // relative to this address
     pub fn add_sync_device<D: Device + HandledDevice + 'static>(mut self, addr: Address) -> Self {
-        let device = Box::new(D::new());
-        let dev = HandledDeviceHolder::new(device);
+        self.add_sync_device_with(addr, D::new())
+    }
+
+    /// Add a handled/sync device that was already created, for devices that have settings beyond
+    /// what [`Device::new()`] gives. The address specifies where the devices memory will be placed
+    /// in the vm's memory
+    pub fn add_sync_device_with<D: HandledDevice + 'static>(
+        mut self,
+        addr: Address,
+        device: D,
+    ) -> Self {
+        let dev = HandledDeviceHolder::new(Box::new(device), addr);
         self.handled_devices.push(dev.1);
         self
     }
@@ -97,7 +183,39 @@ impl<const MEM_SIZE: usize> VMStateBuilder<MEM_SIZE> {
 
     /// Build a vm from this builder, consumes the builder
     pub fn build(self) -> Result<VMState, VMInitError> {
-        let mut state = VMState::new::<MEM_SIZE>(self.hart_count, self.settings);
+        if self.settings.plic_enable && self.settings.plic_sources > MAX_SOURCES {
+            return Err(VMInitError::TooManyPlicSources(self.settings.plic_sources));
+        }
+        if self.settings.aia_enable {
+            if self.settings.plic_enable {
+                return Err(VMInitError::ConflictingInterruptControllers);
+            }
+            if self.settings.aplic_sources > aplic::MAX_SOURCES {
+                return Err(VMInitError::TooManyAplicSources(
+                    self.settings.aplic_sources,
+                ));
+            }
+            let ids = self.settings.imsic_ids;
+            if ids > imsic::MAX_IDS || !(ids + 1).is_multiple_of(64) {
+                return Err(VMInitError::InvalidImsicIds(ids));
+            }
+        }
+        if self
+            .settings
+            .virtual_time
+            .is_some_and(|time| time.instructions == 0 || time.timebase_freq == 0)
+        {
+            return Err(VMInitError::InvalidVirtualTime);
+        }
+        if self.settings.exception_storm_limit == Some(0) {
+            return Err(VMInitError::InvalidExceptionStormLimit);
+        }
+        let mut state = VMState::new::<MEM_SIZE>(self.hart_count, self.settings)?;
+        for (source, line) in self.interrupt_lines {
+            if !state.connect_interrupt(source, line) {
+                return Err(VMInitError::InvalidInterruptSource(source));
+            }
+        }
         for d in self.handled_devices {
             state.add_sync_device(d)?;
         }
@@ -113,3 +231,19 @@ impl From<DeviceInitError> for VMInitError {
         Self::DeviceInitError(value)
     }
 }
+
+/// Where `layout` usually has the timer, the machine and the supervisor software interrupts
+fn layout_addresses(layout: TimerLayout) -> (Address, Address, Address) {
+    match layout {
+        TimerLayout::Legacy => {
+            let settings = VMSettings::default();
+            (
+                settings.timer_addr,
+                settings.m_mode_swi_addr,
+                settings.s_mode_swi_addr,
+            )
+        }
+        TimerLayout::Clint => (0x2000000.into(), 0x2000000.into(), 0x2010000.into()),
+        TimerLayout::Aclint => (0x2004000.into(), 0x2000000.into(), 0x200C000.into()),
+    }
+}
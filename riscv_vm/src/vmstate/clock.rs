@@ -0,0 +1,92 @@
+use std::time::Instant;
+
+use super::{SnapshotError, SnapshotReader, SnapshotWriter, VirtualTime};
+
+/// The source of time for the timer and the harts' time csr. The clock is advanced once per vm
+/// step by [`Clock::tick()`] and every read in between sees the same time, so a recorded run can
+/// be replayed exactly by feeding the same times back through [`Clock::replay()`].
+pub(crate) struct Clock {
+    source: Source,
+    now: u64,
+}
+
+enum Source {
+    /// Microseconds of host time since `origin`, plus `offset`
+    Host { origin: Instant, offset: u64 },
+    /// Advances by a fixed amount every step, `remainder` is the fraction of a tick (in
+    /// `instructions`ths) that was not yet added to the time.
+    Virtual { time: VirtualTime, remainder: u64 },
+}
+
+impl Clock {
+    /// A virtual time with zero `instructions` is rejected by
+    /// [`VMStateBuilder::build()`](super::VMStateBuilder::build()).
+    pub(crate) fn new(virtual_time: Option<VirtualTime>) -> Self {
+        let source = match virtual_time {
+            Some(time) => Source::Virtual { time, remainder: 0 },
+            None => Source::Host {
+                origin: Instant::now(),
+                offset: 0,
+            },
+        };
+        Self { source, now: 0 }
+    }
+
+    /// The time in ticks of the timebase as of the last tick, microseconds for the host clock
+    pub(crate) fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Advance the clock for a new vm step, time never goes backwards between two ticks
+    pub(crate) fn tick(&mut self) -> u64 {
+        match &mut self.source {
+            Source::Host { origin, offset } => {
+                let host = *offset + origin.elapsed().as_micros() as u64;
+                self.now = self.now.max(host);
+            }
+            Source::Virtual { time, remainder } => {
+                let ticks = *remainder + time.ticks;
+                self.now += ticks / time.instructions;
+                *remainder = ticks % time.instructions;
+            }
+        }
+        self.now
+    }
+
+    /// Redo a tick that returned `now` while recording
+    pub(crate) fn replay(&mut self, now: u64) {
+        match self.source {
+            Source::Host { .. } => self.set(now),
+            Source::Virtual { .. } => {
+                self.tick();
+            }
+        }
+    }
+
+    /// Set the time, the clock keeps running from there
+    pub(crate) fn set(&mut self, now: u64) {
+        if let Source::Host { origin, offset } = &mut self.source {
+            *origin = Instant::now();
+            *offset = now;
+        }
+        self.now = now;
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        snapshot.write_u64(self.now);
+        if let Source::Virtual { remainder, .. } = self.source {
+            snapshot.write_u64(remainder);
+        }
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.set(snapshot.read_u64()?);
+        if let Source::Virtual { time, remainder } = &mut self.source {
+            *remainder = snapshot.read_u64()?;
+            if *remainder >= time.instructions {
+                return Err(SnapshotError::Corrupt);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! ELF core files of a vm, see [`VMState::write_core()`](super::VMState::write_core()). The notes
+//! use the layout of riscv64 linux core files, which is what gdb knows how to read.
+
+#[cfg(feature = "float")]
+use elf_load::writer::NT_PRFPREG;
+use elf_load::writer::{Note, NT_PRSTATUS};
+#[cfg(feature = "float")]
+use softfloat_wrapper::Float;
+
+#[cfg(feature = "float")]
+use crate::hart::{registers::FloatRegister, CsrAddress};
+use crate::hart::{registers::IntRegister, Hart};
+
+/// The size of `struct elf_prstatus` and the offsets of the pid and the registers in it
+const PRSTATUS_SIZE: usize = 376;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REGS: usize = 112;
+const PRSTATUS_FPVALID: usize = 368;
+
+/// The size of `struct __riscv_d_ext_state`, the registers and fcsr padded to 8 bytes
+#[cfg(feature = "float")]
+const FPREGSET_SIZE: usize = 264;
+#[cfg(feature = "float")]
+const FCSR: u16 = 0x003;
+
+/// `EF_RISCV_RVC` and `EF_RISCV_FLOAT_ABI_DOUBLE`, for when no kernel was loaded
+pub(super) const DEFAULT_FLAGS: u32 = if cfg!(feature = "float") { 0x5 } else { 0x1 };
+
+/// The integer registers of a hart, the pc takes the place of `x0` like it does in linux core
+/// files. Gdb shows the hart as the thread with lwp `pid`.
+pub(super) fn prstatus(pid: u32, hart: &Hart) -> Note {
+    let mut desc = vec![0; PRSTATUS_SIZE];
+    desc[PRSTATUS_PID..][..4].copy_from_slice(&pid.to_le_bytes());
+    for i in 0..32u32 {
+        let value = match i {
+            0 => hart.get_pc().into(),
+            i => hart.get_int_reg(IntRegister::from(i)) as u64,
+        };
+        desc[PRSTATUS_REGS + i as usize * 8..][..8].copy_from_slice(&value.to_le_bytes());
+    }
+    desc[PRSTATUS_FPVALID..][..4].copy_from_slice(&(cfg!(feature = "float") as u32).to_le_bytes());
+
+    Note {
+        name: "CORE".to_string(),
+        note_type: NT_PRSTATUS,
+        desc,
+    }
+}
+
+/// The floating point registers of a hart and `fcsr`
+#[cfg(feature = "float")]
+pub(super) fn fpregset(hart: &Hart) -> Note {
+    let mut desc = Vec::with_capacity(FPREGSET_SIZE);
+    for i in 0..32u32 {
+        let bits = hart.get_f64_reg(FloatRegister::from(i)).to_bits();
+        desc.extend(bits.to_le_bytes());
+    }
+    let fcsr = hart.get_csr().get_csr(CsrAddress::new(FCSR)) as u32;
+    desc.extend(fcsr.to_le_bytes());
+    desc.resize(FPREGSET_SIZE, 0);
+
+    Note {
+        name: "CORE".to_string(),
+        note_type: NT_PRFPREG,
+        desc,
+    }
+}
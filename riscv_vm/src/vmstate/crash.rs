@@ -0,0 +1,162 @@
+//! Post-mortem reports of a vm that failed to step, see
+//! [`VMState::set_crash_report()`](super::VMState::set_crash_report()).
+
+use std::fmt::Display;
+
+use crate::{
+    decode::Disassembly,
+    hart::privilege::PrivilegeMode,
+    memory::{fault::FaultDiagnostic, MemoryError},
+    registers::IntRegister,
+    Address, CsrAddress,
+};
+
+use super::Frame;
+
+/// The csrs that describe traps and translation, in the order they are reported
+pub(super) const TRAP_CSRS: [u16; 15] = [
+    0x300, 0x304, 0x344, 0x302, 0x303, 0x305, 0x341, 0x342, 0x343, 0x105, 0x141, 0x142, 0x143,
+    0x100, 0x180,
+];
+
+/// Bytes of memory shown before and after the pc and stack pointer
+pub(super) const MEMORY_BEFORE: u64 = 32;
+pub(super) const MEMORY_AFTER: u64 = 64;
+
+/// The state of a vm after stepping it failed.
+#[derive(Debug)]
+pub struct CrashReport {
+    /// The hart that failed
+    pub hart: usize,
+    pub error: String,
+    pub harts: Vec<HartReport>,
+    /// The regions of the memory map, described
+    pub memory_map: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct HartReport {
+    pub id: usize,
+    pub pc: Address,
+    pub privilege: PrivilegeMode,
+    /// The pc and raw bits of the last retired instructions, oldest first
+    pub recent: Vec<(Address, u32)>,
+    pub registers: [i64; 32],
+    pub csrs: Vec<(CsrAddress, u64)>,
+    /// Memory around the pc and the stack pointer as seen by the hart, with the address of the
+    /// first byte
+    pub pc_memory: Result<(Address, Vec<u8>), MemoryError>,
+    pub sp_memory: Result<(Address, Vec<u8>), MemoryError>,
+    pub backtrace: Vec<Frame>,
+    /// Why the last access or page fault the hart took was raised
+    pub last_fault: Option<FaultDiagnostic>,
+}
+
+impl CrashReport {
+    /// A few lines for the terminal, the full report is its [`Display`] output
+    pub fn summary(&self) -> String {
+        let mut summary = format!("hart {} crashed: {}\n", self.hart, self.error);
+        if let Some(hart) = self.harts.get(self.hart) {
+            summary += &format!(
+                "  pc {:#x} in {:?} mode\n",
+                u64::from(hart.pc),
+                hart.privilege
+            );
+            if let Some((pc, raw)) = hart.recent.last() {
+                summary += &format!("  last retired {}\n", instruction(*pc, *raw));
+            }
+            if let Some(fault) = &hart.last_fault {
+                summary += &format!("  last fault: {}\n", fault);
+            }
+        }
+        summary
+    }
+}
+
+fn instruction(pc: Address, raw: u32) -> String {
+    format!("{:#018x}: {}", u64::from(pc), Disassembly::decode(raw, pc))
+}
+
+fn hex_dump(
+    f: &mut std::fmt::Formatter<'_>,
+    memory: &Result<(Address, Vec<u8>), MemoryError>,
+) -> std::fmt::Result {
+    match memory {
+        Ok((start, bytes)) => {
+            for (line, bytes) in bytes.chunks(16).enumerate() {
+                write!(f, "  {:#018x}:", u64::from(*start) + line as u64 * 16)?;
+                for byte in bytes {
+                    write!(f, " {byte:02x}")?;
+                }
+                writeln!(f)?;
+            }
+            Ok(())
+        }
+        Err(e) => writeln!(f, "  unreadable: {e:?}"),
+    }
+}
+
+impl Display for CrashReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "hart {} crashed: {}", self.hart, self.error)?;
+
+        for hart in &self.harts {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "== hart {} at {:#x} in {:?} mode",
+                hart.id,
+                u64::from(hart.pc),
+                hart.privilege
+            )?;
+
+            writeln!(f, "last {} retired instructions:", hart.recent.len())?;
+            for (pc, raw) in &hart.recent {
+                writeln!(f, "  {}", instruction(*pc, *raw))?;
+            }
+
+            if let Some(fault) = &hart.last_fault {
+                writeln!(f, "last fault: {fault}")?;
+            }
+
+            writeln!(f, "backtrace:")?;
+            for (i, frame) in hart.backtrace.iter().enumerate() {
+                writeln!(f, "  #{i} {frame}")?;
+            }
+
+            writeln!(f, "registers:")?;
+            for (i, value) in hart.registers.iter().enumerate() {
+                write!(
+                    f,
+                    "  {:>4} {:#018x}",
+                    IntRegister::from(i as u32).abi_name(),
+                    *value as u64
+                )?;
+                if i % 4 == 3 {
+                    writeln!(f)?;
+                }
+            }
+
+            writeln!(f, "csrs:")?;
+            for (csr, value) in &hart.csrs {
+                writeln!(
+                    f,
+                    "  {:>8} {value:#018x}",
+                    csr.name().unwrap_or_else(|| csr.to_string())
+                )?;
+            }
+
+            writeln!(f, "memory around pc:")?;
+            hex_dump(f, &hart.pc_memory)?;
+            writeln!(f, "memory around sp:")?;
+            hex_dump(f, &hart.sp_memory)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "memory map:")?;
+        for region in &self.memory_map {
+            writeln!(f, "  {region}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Execution history for reverse debugging. While recording, the vm keeps a checkpoint (a
+//! [snapshot](super::VMState::snapshot())) every so often and logs every clock sample, input
+//! devices took from outside the vm and hart step since the oldest checkpoint. Going back means
+//! restoring the closest earlier checkpoint and deterministically replaying the log up to the
+//! wanted position.
+
+use std::collections::VecDeque;
+
+use super::SnapshotError;
+use crate::devices::DeviceError;
+
+#[derive(Debug, Clone)]
+pub(super) enum Event {
+    /// The devices and timer were updated with the clock at this time
+    Tick(u64),
+    /// The sync device at this index took input from outside the vm in the update of the tick
+    /// logged right before, see
+    /// [`HandledDevice::take_input()`](crate::devices::handled_device::HandledDevice::take_input())
+    Input(usize, Vec<u8>),
+    /// The hart executed one step
+    Step(usize),
+}
+
+pub(super) struct Checkpoint {
+    /// The position in the log this checkpoint was taken at, it holds the state before the event
+    /// at this position.
+    pub(super) pos: usize,
+    pub(super) snapshot: Vec<u8>,
+}
+
+pub(super) struct History {
+    interval: u64,
+    max_checkpoints: usize,
+    checkpoints: VecDeque<Checkpoint>,
+    log: Vec<Event>,
+    /// The position of the first event in the log
+    base: usize,
+    steps_since_checkpoint: u64,
+    /// The vm was changed from outside of a step, the next event needs a fresh checkpoint
+    dirty: bool,
+}
+
+#[derive(Debug)]
+pub enum ReverseError {
+    /// History is not being recorded, see
+    /// [`VMState::enable_history()`](super::VMState::enable_history()).
+    HistoryDisabled,
+    /// The start of the recorded history was reached before the target, the vm was moved to the
+    /// oldest recorded state.
+    HistoryStart,
+    /// A checkpoint could not be restored. The vm is left in whatever state the restore reached,
+    /// which the history no longer matches, so recording is turned off.
+    Snapshot(SnapshotError),
+    /// A device failed to replay its recorded input, recording is turned off as for
+    /// [`ReverseError::Snapshot`].
+    DeviceError(DeviceError),
+}
+
+impl History {
+    pub(super) fn new(interval: u64, max_checkpoints: usize) -> Self {
+        Self {
+            interval,
+            max_checkpoints: max_checkpoints.max(1),
+            checkpoints: VecDeque::new(),
+            log: Vec::new(),
+            base: 0,
+            steps_since_checkpoint: 0,
+            dirty: false,
+        }
+    }
+
+    /// The position of the next event
+    pub(super) fn pos(&self) -> usize {
+        self.base + self.log.len()
+    }
+
+    pub(super) fn wants_checkpoint(&self) -> bool {
+        self.dirty || self.checkpoints.is_empty() || self.steps_since_checkpoint >= self.interval
+    }
+
+    pub(super) fn checkpoint(&mut self, snapshot: Vec<u8>) {
+        let pos = self.pos();
+        if self.checkpoints.back().is_some_and(|c| c.pos == pos) {
+            self.checkpoints.pop_back();
+        }
+        self.checkpoints.push_back(Checkpoint { pos, snapshot });
+
+        if self.checkpoints.len() > self.max_checkpoints {
+            self.checkpoints.pop_front();
+            let start = self.checkpoints[0].pos;
+            self.log.drain(..start - self.base);
+            self.base = start;
+        }
+
+        self.steps_since_checkpoint = 0;
+        self.dirty = false;
+    }
+
+    pub(super) fn record(&mut self, event: Event) {
+        if let Event::Step(_) = event {
+            self.steps_since_checkpoint += 1;
+        }
+        self.log.push(event);
+    }
+
+    /// Force a checkpoint before the next event, used when the vm is changed in a way the log
+    /// can not replay.
+    pub(super) fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// The oldest position that can be returned to
+    pub(super) fn start(&self) -> usize {
+        self.checkpoints.front().map_or(self.pos(), |c| c.pos)
+    }
+
+    /// The latest checkpoint at or before `pos`
+    pub(super) fn checkpoint_before(&self, pos: usize) -> Option<&Checkpoint> {
+        self.checkpoints.iter().rev().find(|c| c.pos <= pos)
+    }
+
+    /// All checkpoints, oldest first
+    pub(super) fn checkpoints(&self) -> &VecDeque<Checkpoint> {
+        &self.checkpoints
+    }
+
+    pub(super) fn events(&self, from: usize, to: usize) -> &[Event] {
+        &self.log[from - self.base..to - self.base]
+    }
+
+    /// The position of the step `count` steps of `hart` back from the end, that is the position
+    /// of the event executing it.
+    pub(super) fn find_step_back(&self, hart: usize, count: u64) -> Option<usize> {
+        if count == 0 {
+            return Some(self.pos());
+        }
+        self.log
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, e)| matches!(e, Event::Step(h) if *h == hart))
+            .nth(count as usize - 1)
+            .map(|(i, _)| self.base + i)
+    }
+
+    /// Forget everything after `pos`, after going back to it.
+    pub(super) fn truncate(&mut self, pos: usize) {
+        self.log.truncate(pos - self.base);
+        while self.checkpoints.back().is_some_and(|c| c.pos > pos) {
+            self.checkpoints.pop_back();
+        }
+        self.steps_since_checkpoint = self
+            .events(self.checkpoints.back().map_or(self.base, |c| c.pos), pos)
+            .iter()
+            .filter(|e| matches!(e, Event::Step(_)))
+            .count() as u64;
+    }
+}
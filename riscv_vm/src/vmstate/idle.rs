@@ -0,0 +1,28 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+/// Wakes a vm that is sleeping because all its harts wait for an interrupt, for other threads
+/// that have something for the guest or its host, see
+/// [`VMState::idle_waker()`](super::VMState::idle_waker()).
+#[derive(Debug, Clone, Default)]
+pub struct IdleWaker(Arc<(Mutex<bool>, Condvar)>);
+
+impl IdleWaker {
+    pub fn wake(&self) {
+        let (woken, condvar) = &*self.0;
+        *woken.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    /// Sleep for at most `timeout`, returns true if woken before it ran out. A wake while not
+    /// sleeping ends the next sleep immediately.
+    pub(super) fn sleep(&self, timeout: Duration) -> bool {
+        let (woken, condvar) = &*self.0;
+        let (mut woken, _) = condvar
+            .wait_timeout_while(woken.lock().unwrap(), timeout, |woken| !*woken)
+            .unwrap();
+        std::mem::take(&mut *woken)
+    }
+}
@@ -0,0 +1,315 @@
+use std::{rc::Rc, sync::Mutex};
+
+use enumflags2::BitFlags;
+
+use crate::{
+    memory::memory_buffer::{MemoryBuffer, MemoryBufferError},
+    trap::InterruptInternal,
+    Address,
+};
+
+use super::{SnapshotError, SnapshotReader, SnapshotWriter};
+
+/// Every interrupt file has a page of its own, harts are this far apart
+pub(crate) const FILE_STRIDE: u64 = 0x1000;
+
+/// The largest interrupt identity the eip and eie registers have room for
+pub const MAX_IDS: u32 = 2047;
+
+const SETEIPNUM_LE: u64 = 0x0;
+const SETEIPNUM_BE: u64 = 0x4;
+
+const EIDELIVERY: u64 = 0x70;
+const EITHRESHOLD: u64 = 0x72;
+const EIP_BASE: u64 = 0x80;
+const EIE_BASE: u64 = 0xC0;
+const EIE_END: u64 = 0xFF;
+
+/// Interrupts from the file are delivered to the hart
+const DELIVER_IMSIC: u32 = 1;
+/// The external interrupt is driven by an aplic domain in direct delivery mode instead
+const DELIVER_APLIC: u32 = 0x4000_0000;
+
+pub(crate) type FileRef = Rc<Mutex<InterruptFile>>;
+
+/// An interrupt file of an incoming msi controller, it receives msis through its
+/// [page](ImsicPages) and drives the external interrupt bit of one privilege mode of its hart.
+/// Its registers are accessed by the hart through `*iselect` and `*ireg`, pending interrupts are
+/// claimed through `*topei`.
+pub(crate) struct InterruptFile {
+    ids: u32,
+    eidelivery: u32,
+    eithreshold: u32,
+    /// Bit `n % 64` of word `n / 64` is interrupt identity `n`, identity 0 does not exist
+    eip: Vec<u64>,
+    eie: Vec<u64>,
+    /// Raised by an aplic domain in direct delivery mode
+    external: bool,
+    interrupt: InterruptInternal,
+    mip: Rc<Mutex<BitFlags<InterruptInternal>>>,
+}
+
+/// The machine and supervisor level interrupt files of every hart
+pub(crate) struct Imsic {
+    m_files: Vec<FileRef>,
+    s_files: Vec<FileRef>,
+}
+
+/// The msi pages of the interrupt files of one privilege mode, the page of hart `n` is at offset
+/// `n * 0x1000`. Writing an identity to `seteipnum_le` at offset 0 of a page makes it pending,
+/// `seteipnum_be` at offset 4 takes it big endian.
+pub(crate) struct ImsicPages {
+    files: Vec<FileRef>,
+}
+
+impl InterruptFile {
+    /// Interrupts are delivered from the aplic until the guest sets `eidelivery`, so guests that
+    /// only use the aplic in direct mode do not have to know about the imsic.
+    fn new(
+        ids: u32,
+        interrupt: InterruptInternal,
+        mip: Rc<Mutex<BitFlags<InterruptInternal>>>,
+    ) -> Self {
+        let words = (ids as usize + 1).div_ceil(64);
+        Self {
+            ids,
+            eidelivery: DELIVER_APLIC,
+            eithreshold: 0,
+            eip: vec![0; words],
+            eie: vec![0; words],
+            external: false,
+            interrupt,
+            mip,
+        }
+    }
+
+    pub(crate) fn set_pending(&mut self, id: u32) {
+        if id != 0 && id <= self.ids {
+            self.eip[id as usize / 64] |= 1 << (id % 64);
+            self.update();
+        }
+    }
+
+    /// Set by an aplic domain in direct delivery mode
+    pub(crate) fn set_external(&mut self, raised: bool) {
+        if self.external != raised {
+            self.external = raised;
+            self.update();
+        }
+    }
+
+    /// The lowest pending and enabled identity below the threshold, 0 if there is none
+    fn top(&self) -> u32 {
+        let limit = if self.eithreshold == 0 {
+            self.ids + 1
+        } else {
+            self.eithreshold.min(self.ids + 1)
+        };
+        self.eip
+            .iter()
+            .zip(&self.eie)
+            .enumerate()
+            .find_map(|(word, (eip, eie))| {
+                let bits = eip & eie;
+                (bits != 0).then(|| word as u32 * 64 + bits.trailing_zeros())
+            })
+            .filter(|id| *id < limit)
+            .unwrap_or(0)
+    }
+
+    /// The value of `*topei`, the top identity in both the identity and the priority field
+    pub(crate) fn topei(&self) -> u64 {
+        let id = self.top() as u64;
+        id << 16 | id
+    }
+
+    /// Clear the pending bit of the top identity, returns `*topei` from before the claim
+    pub(crate) fn claim(&mut self) -> u64 {
+        let id = self.top();
+        if id != 0 {
+            self.eip[id as usize / 64] &= !(1 << (id % 64));
+            self.update();
+        }
+        (id as u64) << 16 | id as u64
+    }
+
+    /// Read a register selected through `*iselect`, missing registers and the odd eip and eie
+    /// registers, which do not exist on rv64, read as 0
+    pub(crate) fn read_indirect(&self, select: u64) -> u64 {
+        match select {
+            EIDELIVERY => self.eidelivery as u64,
+            EITHRESHOLD => self.eithreshold as u64,
+            i @ EIP_BASE..EIE_BASE if i % 2 == 0 => self
+                .eip
+                .get((i - EIP_BASE) as usize / 2)
+                .copied()
+                .unwrap_or(0),
+            i @ EIE_BASE..=EIE_END if i % 2 == 0 => self
+                .eie
+                .get((i - EIE_BASE) as usize / 2)
+                .copied()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn write_indirect(&mut self, select: u64, value: u64) {
+        match select {
+            EIDELIVERY => {
+                if [0, DELIVER_IMSIC, DELIVER_APLIC].contains(&(value as u32)) {
+                    self.eidelivery = value as u32;
+                }
+            }
+            EITHRESHOLD => self.eithreshold = value as u32 & MAX_IDS,
+            i @ EIP_BASE..EIE_BASE if i % 2 == 0 => {
+                let word = (i - EIP_BASE) as usize / 2;
+                let valid = self.valid(word);
+                if let Some(eip) = self.eip.get_mut(word) {
+                    *eip = value & valid;
+                }
+            }
+            i @ EIE_BASE..=EIE_END if i % 2 == 0 => {
+                let word = (i - EIE_BASE) as usize / 2;
+                let valid = self.valid(word);
+                if let Some(eie) = self.eie.get_mut(word) {
+                    *eie = value & valid;
+                }
+            }
+            _ => return,
+        }
+        self.update();
+    }
+
+    /// The bits of word `word` that are implemented identities
+    fn valid(&self, word: usize) -> u64 {
+        (0..64)
+            .map(|bit| word as u32 * 64 + bit)
+            .filter(|id| *id != 0 && *id <= self.ids)
+            .fold(0, |mask, id| mask | 1 << (id % 64))
+    }
+
+    fn update(&self) {
+        let raised = match self.eidelivery {
+            DELIVER_IMSIC => self.top() != 0,
+            DELIVER_APLIC => self.external,
+            _ => false,
+        };
+        let mut mip = self.mip.lock().unwrap();
+        if raised {
+            *mip |= self.interrupt;
+        } else {
+            *mip &= !self.interrupt;
+        }
+    }
+
+    fn save(&self, snapshot: &mut SnapshotWriter) {
+        snapshot.write_u64(self.eidelivery as u64);
+        snapshot.write_u64(self.eithreshold as u64);
+        for (eip, eie) in self.eip.iter().zip(&self.eie) {
+            snapshot.write_u64(*eip);
+            snapshot.write_u64(*eie);
+        }
+        snapshot.write_bool(self.external);
+    }
+
+    /// The external interrupt bit is restored with the hart's mip
+    fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.eidelivery = snapshot.read_u64()? as u32;
+        self.eithreshold = snapshot.read_u64()? as u32 & MAX_IDS;
+        for word in 0..self.eip.len() {
+            let valid = self.valid(word);
+            self.eip[word] = snapshot.read_u64()? & valid;
+            self.eie[word] = snapshot.read_u64()? & valid;
+        }
+        self.external = snapshot.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Imsic {
+    /// Create the files of a hart for each of the given mip bits
+    pub(crate) fn new(ids: u32, mips: &[Rc<Mutex<BitFlags<InterruptInternal>>>]) -> Self {
+        assert!(ids <= MAX_IDS);
+        let files = |interrupt| {
+            mips.iter()
+                .map(|mip| Rc::new(Mutex::new(InterruptFile::new(ids, interrupt, mip.clone()))))
+                .collect()
+        };
+        Self {
+            m_files: files(InterruptInternal::MachineExternal),
+            s_files: files(InterruptInternal::SupervisorExternal),
+        }
+    }
+
+    /// The machine and supervisor level files of a hart
+    pub(crate) fn files(&self, hartid: usize) -> (FileRef, FileRef) {
+        (self.m_files[hartid].clone(), self.s_files[hartid].clone())
+    }
+
+    pub(crate) fn m_files(&self) -> &[FileRef] {
+        &self.m_files
+    }
+
+    pub(crate) fn s_files(&self) -> &[FileRef] {
+        &self.s_files
+    }
+
+    pub(crate) fn m_pages(&self) -> ImsicPages {
+        ImsicPages {
+            files: self.m_files.clone(),
+        }
+    }
+
+    pub(crate) fn s_pages(&self) -> ImsicPages {
+        ImsicPages {
+            files: self.s_files.clone(),
+        }
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        for file in self.m_files.iter().chain(&self.s_files) {
+            file.lock().unwrap().save(snapshot);
+        }
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        for file in self.m_files.iter().chain(&self.s_files) {
+            file.lock().unwrap().restore(snapshot)?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryBuffer for ImsicPages {
+    fn size(&self) -> u64 {
+        self.files.len() as u64 * FILE_STRIDE
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !bytes.len().is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedWrite(addr.into()));
+        }
+        for (offset, word) in (addr..).step_by(4).zip(bytes.chunks_exact(4)) {
+            let word: [u8; 4] = word.try_into().unwrap();
+            let id = match offset % FILE_STRIDE {
+                SETEIPNUM_LE => u32::from_le_bytes(word),
+                SETEIPNUM_BE => u32::from_be_bytes(word),
+                _ => continue,
+            };
+            if let Some(file) = self.files.get((offset / FILE_STRIDE) as usize) {
+                file.lock().unwrap().set_pending(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// The pages are write only
+    fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !size.is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedRead(addr.into()));
+        }
+        Ok(vec![0; size])
+    }
+}
@@ -1,23 +1,43 @@
 //! The vmstate is the main  way to interact with the vm, is is created via a [`VMStateBuilder`]
 //! and can than be interacted with directly.
 
+mod aplic;
+mod backtrace;
 mod builder;
+mod clock;
+mod core_file;
+mod crash;
+mod history;
+mod idle;
+pub(crate) mod imsic;
+mod plic;
+mod shutdown;
+mod snapshot;
 mod swi_controller;
+#[cfg(test)]
+mod tests;
 pub(crate) mod timer;
 
 use std::{
     any::Any,
+    cell::RefCell,
     collections::HashMap,
     fmt::Debug,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
     rc::Rc,
     sync::{
         mpsc::{self, Receiver, Sender},
         Arc, RwLock,
     },
+    time::{Duration, Instant},
 };
 
 use elf_load::{
-    data::{Bitness, Endianess, ProgramType, ASI},
+    data::{Bitness, Endianess, ObjectType, ProgramFlags, ProgramType, ASI},
+    dwarf::frame::CfaRule,
+    writer::{ElfWriter, Segment},
     ByteRanges, Elf,
 };
 use swi_controller::SwiController;
@@ -27,21 +47,64 @@ use crate::{
     devices::{
         async_device::{AsyncDevice, AsyncDeviceHolder},
         handled_device::{HandledDevice, HandledDeviceHolder},
+        interrupt_line::InterruptLine,
         Device, DeviceError, DeviceInitError,
     },
     execute::{execute_rv64, ExecuteError},
-    hart::{self, privilege::PrivilegeMode, trap::InterruptTarget, Hart},
-    memory::{self, address::Address, pmp::PMP, Memory, MemoryError},
+    hart::{
+        self,
+        privilege::PrivilegeMode,
+        registers::IntRegister,
+        trap::{Exception, InterruptTarget},
+        CsrAddress, Hart,
+    },
+    memory::{
+        self,
+        address::Address,
+        paging::{self, AccessContext, Mapping, PageWalkFault, WalkStep},
+        pmp::{AccessMode, PMP},
+        store_log::StoreLog,
+        watchpoint::{WatchHit, Watchpoint},
+        Memory, MemoryError,
+    },
+    trace::{Lockstep, LockstepError, TraceSink, Tracer},
 };
 
-use self::timer::{MTimer, TimerRef};
+use self::{
+    aplic::Aplic,
+    backtrace::{unwind_cfi, unwind_frame_pointer, Registers, FP, RA, SP},
+    history::{Checkpoint, Event, History},
+    imsic::Imsic,
+    plic::Plic,
+    shutdown::ShutdownController,
+    timer::{MTimer, TimerRef},
+};
+pub use crate::memory::watchpoint::WatchKind;
+pub use aplic::MAX_SOURCES as MAX_APLIC_SOURCES;
+pub use backtrace::{FoundBy, Frame, UnwindInfo};
 pub use builder::{VMInitError, VMStateBuilder};
+pub use crash::{CrashReport, HartReport};
+pub use history::ReverseError;
+pub use idle::IdleWaker;
+pub use imsic::MAX_IDS as MAX_IMSIC_IDS;
+pub use plic::MAX_SOURCES as MAX_PLIC_SOURCES;
+pub use shutdown::ShutdownRequest;
+pub use snapshot::{SnapshotError, SnapshotReader, SnapshotWriter};
+pub use timer::TimerLayout;
 
 #[derive(Debug, Clone, Copy)]
 pub struct VMSettings {
     pub pmp_enable: bool,
     pub virt_mem_enable: bool,
 
+    /// Where the timer and the software interrupt controllers place their registers. With
+    /// [`TimerLayout::Clint`] the clint is at `timer_addr` and also holds the machine mode
+    /// software interrupts, with [`TimerLayout::Aclint`] the MTIMER is at `timer_addr` and the
+    /// MSWI and SSWI are at `m_mode_swi_addr` and `s_mode_swi_addr`. The default addresses only
+    /// leave room for the legacy layout, [`VMStateBuilder::set_timer_layout`] also moves them.
+    /// Overlapping registers make [`VMStateBuilder::build`] fail with
+    /// [`DeviceInitError::MemoryOverlap`].
+    pub timer_layout: TimerLayout,
     pub timer_addr: Address,
 
     pub m_mode_swi_enable: bool,
@@ -49,6 +112,71 @@ pub struct VMSettings {
 
     pub s_mode_swi_enable: bool,
     pub s_mode_swi_addr: Address,
+
+    /// Map a [sifive test finisher](ShutdownRequest) compatible device the guest can use to
+    /// power off the vm.
+    pub shutdown_enable: bool,
+    pub shutdown_addr: Address,
+
+    /// Map a riscv PLIC with `plic_sources` interrupt sources, numbered from 1, that devices
+    /// raise through the [`InterruptLine`](crate::devices::interrupt_line::InterruptLine)s of
+    /// [`VMStateBuilder::interrupt_line()`]. Context `2n` of the plic is machine mode on hart
+    /// `n` and context `2n + 1` supervisor mode, `plic_contexts` defaults to both for every hart.
+    pub plic_enable: bool,
+    pub plic_addr: Address,
+    pub plic_sources: u32,
+    pub plic_contexts: Option<u32>,
+
+    /// Map the riscv AIA interrupt controllers instead of a plic. The APLIC has `aplic_sources`
+    /// sources that devices raise through the lines of [`VMStateBuilder::interrupt_line()`], its
+    /// machine level domain is at `aplic_m_addr` and its supervisor level domain at
+    /// `aplic_s_addr`. Every hart gets a machine and a supervisor level IMSIC interrupt file with
+    /// `imsic_ids` interrupt identities, those of hart `n` at `imsic_m_addr + n * 0x1000` and
+    /// `imsic_s_addr + n * 0x1000`. A domain in msi mode forwards its interrupts to them.
+    pub aia_enable: bool,
+    pub aplic_m_addr: Address,
+    pub aplic_s_addr: Address,
+    pub aplic_sources: u32,
+    pub imsic_m_addr: Address,
+    pub imsic_s_addr: Address,
+    pub imsic_ids: u32,
+
+    /// Stop the vm with [`VMError::MBreak`] when a hart is about to execute an `ebreak`, instead of
+    /// raising a breakpoint exception in the guest.
+    pub halt_on_ebreak: bool,
+
+    /// Stop the vm with [`VMError::ExceptionStorm`] when a hart takes this many exceptions, other
+    /// than ecalls and breakpoints, with only a few instructions retired between each of them.
+    /// This catches guests stuck faulting in their trap handler.
+    pub exception_storm_limit: Option<u64>,
+
+    /// Derive `mtime` (and the `time` csr) from the number of vm steps instead of the host clock,
+    /// which makes runs of the same guest reproducible. When not set time is in microseconds of
+    /// host time.
+    pub virtual_time: Option<VirtualTime>,
+}
+
+/// Settings for [`VMSettings::virtual_time`], time advances by `ticks` every `instructions` vm
+/// steps, in each of which every hart executes at most one instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualTime {
+    /// The frequency of `mtime` in Hz, the guest should be told the same through its device tree.
+    /// Must not be zero
+    pub timebase_freq: u64,
+    pub ticks: u64,
+    /// Must not be zero
+    pub instructions: u64,
+}
+
+impl Default for VirtualTime {
+    /// A 1 MHz timebase advancing one tick per instruction, as if the harts ran at 1 MHz.
+    fn default() -> Self {
+        Self {
+            timebase_freq: 1_000_000,
+            ticks: 1,
+            instructions: 1,
+        }
+    }
 }
 
 impl Default for VMSettings {
@@ -57,6 +185,7 @@ impl Default for VMSettings {
             pmp_enable: false,
             virt_mem_enable: false,
 
+            timer_layout: TimerLayout::Legacy,
             timer_addr: 0x1000.into(),
 
             m_mode_swi_enable: false,
@@ -64,10 +193,46 @@ impl Default for VMSettings {
 
             s_mode_swi_enable: false,
             s_mode_swi_addr: 0x3000.into(),
+
+            shutdown_enable: false,
+            shutdown_addr: 0x100000.into(),
+
+            plic_enable: false,
+            plic_addr: 0xC000000.into(),
+            plic_sources: 64,
+            plic_contexts: None,
+
+            aia_enable: false,
+            aplic_m_addr: 0xC000000.into(),
+            aplic_s_addr: 0xD000000.into(),
+            aplic_sources: 64,
+            imsic_m_addr: 0x24000000.into(),
+            imsic_s_addr: 0x28000000.into(),
+            imsic_ids: 255,
+
+            halt_on_ebreak: false,
+            exception_storm_limit: None,
+
+            virtual_time: None,
         }
     }
 }
 
+/// How long the host sleeps at most at a time while all harts wait for an interrupt, devices are
+/// updated in between.
+const MAX_IDLE_SLEEP: Duration = Duration::from_millis(10);
+
+/// Counters for how the vm spent its time, see [`VMState::stats()`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VMStats {
+    /// Steps taken, in each of which every hart executes at most one instruction
+    pub steps: u64,
+    /// Host time spent sleeping while all harts waited for an interrupt
+    pub idle_time: Duration,
+    /// The number of times the host went to sleep
+    pub idle_sleeps: u64,
+}
+
 /// An actual instance of a riscv vm, with memory, devices and harts
 pub struct VMState {
     harts: Vec<Hart>,
@@ -75,6 +240,23 @@ pub struct VMState {
     sync_devices: Vec<HandledDeviceHolder>,
     // async_devices: HashMap<usize, Box<dyn AsyncDevice>>,
     timer: Arc<RwLock<MTimer>>,
+    shutdown: Option<Arc<RwLock<ShutdownController>>>,
+    plic: Option<Arc<RwLock<Plic>>>,
+    aplic: Option<Aplic>,
+    imsic: Option<Imsic>,
+    /// Frozen harts are skipped when stepping the whole vm
+    frozen: Vec<bool>,
+    history: Option<History>,
+    /// The watchpoint hit during the last [`VMState::step()`] or [`VMState::step_hart()`]
+    step_watch_hit: Option<StopReason>,
+    tracer: Option<TraceSink>,
+    /// Where to write a crash report when stepping fails
+    crash_report: Option<PathBuf>,
+    unwind_info: UnwindInfo,
+    /// The elf flags of the loaded kernel, which core files are written with
+    kernel_flags: u32,
+    waker: IdleWaker,
+    stats: VMStats,
     next_dev_id: usize,
     settings: VMSettings,
 }
@@ -96,10 +278,84 @@ pub enum VMError {
     DeviceError(DeviceError),
     ExecureError(ExecuteError),
     MBreak,
+    /// The hart took too many exceptions in quick succession, the last of which is given, see
+    /// [`VMSettings::exception_storm_limit`].
+    ExceptionStorm(Exception),
+}
+
+/// Why [`VMState::run_for()`] or [`VMState::run_until()`] returned. Every reason carries the hart
+/// it concerns and the pc of that hart.
+#[derive(Debug)]
+pub enum StopReason {
+    /// The step budget ran out, this is reported for hart 0 at its current pc.
+    BudgetExhausted { hart: usize, pc: Address },
+    /// All harts were idle and an [`IdleWaker`] woke the vm, reported for hart 0 at its current pc.
+    Woken { hart: usize, pc: Address },
+    /// The stop condition matched for this hart after it stepped, pc is where it stopped.
+    Breakpoint { hart: usize, pc: Address },
+    /// The hart accessed a watched address, pc is that of the accessing instruction.
+    Watchpoint {
+        hart: usize,
+        pc: Address,
+        addr: Address,
+        access: WatchKind,
+    },
+    /// The hart is about to execute an `ebreak` and [`VMSettings::halt_on_ebreak`] is set, the
+    /// pc points at the `ebreak` which has not been executed.
+    EBreak { hart: usize, pc: Address },
+    /// All harts are waiting for an interrupt and no timer is armed to wake them, reported for
+    /// the last hart to start waiting.
+    AllHartsIdle { hart: usize, pc: Address },
+    /// The guest wrote a request to the shutdown device, pc is that of the store.
+    Shutdown {
+        hart: usize,
+        pc: Address,
+        request: ShutdownRequest,
+    },
+    /// Stepping the hart failed fatally, pc is where the failing step started. A device that
+    /// failed to update is reported for hart 0 at its current pc.
+    Error {
+        hart: usize,
+        pc: Address,
+        error: VMError,
+    },
+}
+
+impl StopReason {
+    /// The hart this stop concerns.
+    pub fn hart(&self) -> usize {
+        match self {
+            StopReason::BudgetExhausted { hart, .. }
+            | StopReason::Woken { hart, .. }
+            | StopReason::Breakpoint { hart, .. }
+            | StopReason::Watchpoint { hart, .. }
+            | StopReason::EBreak { hart, .. }
+            | StopReason::AllHartsIdle { hart, .. }
+            | StopReason::Shutdown { hart, .. }
+            | StopReason::Error { hart, .. } => *hart,
+        }
+    }
+
+    /// The pc of the hart this stop concerns.
+    pub fn pc(&self) -> Address {
+        match self {
+            StopReason::BudgetExhausted { pc, .. }
+            | StopReason::Woken { pc, .. }
+            | StopReason::Breakpoint { pc, .. }
+            | StopReason::Watchpoint { pc, .. }
+            | StopReason::EBreak { pc, .. }
+            | StopReason::AllHartsIdle { pc, .. }
+            | StopReason::Shutdown { pc, .. }
+            | StopReason::Error { pc, .. } => *pc,
+        }
+    }
 }
 
 impl VMState {
-    fn new<const MEM_SIZE: usize>(hart_count: u64, settings: VMSettings) -> Self {
+    fn new<const MEM_SIZE: usize>(
+        hart_count: u64,
+        settings: VMSettings,
+    ) -> Result<Self, DeviceInitError> {
         let mut mem = Memory::new::<MEM_SIZE>();
         // let timer = MTimer::new(
         //     hart_count as usize,
@@ -108,7 +364,8 @@ impl VMState {
         // let timer: DeviceData = Arc::new(RwLock::new(Box::new(timer)));
         // mem.add_timer(0x1000.into(), 0x1040.into(), timer.clone());
 
-        let mut timer = MTimer::new(hart_count as usize);
+        let mut timer = MTimer::new(hart_count as usize, settings.virtual_time)
+            .with_layout(settings.timer_layout);
 
         let mut harts = Vec::new();
         for i in 0..hart_count {
@@ -117,29 +374,98 @@ impl VMState {
             harts.push(hart);
         }
 
-        let timer = mem.add_device_memory(settings.timer_addr, timer).unwrap();
+        // The clint has the machine mode software interrupts built in
+        let clint = settings.timer_layout == TimerLayout::Clint;
+        if clint {
+            timer.set_msip(SwiController::new(
+                &harts,
+                PrivilegeMode::Machine,
+                TimerLayout::Clint,
+            ));
+        }
+
+        let timer = mem.add_device_memory(settings.timer_addr, timer)?;
 
         if settings.s_mode_swi_enable {
-            let s_swi = SwiController::new(&harts, PrivilegeMode::Supervisor);
-            mem.add_device_memory(settings.s_mode_swi_addr, s_swi)
-                .unwrap();
+            let s_swi =
+                SwiController::new(&harts, PrivilegeMode::Supervisor, settings.timer_layout);
+            mem.add_device_memory(settings.s_mode_swi_addr, s_swi)?;
         }
 
-        if settings.m_mode_swi_enable {
-            let m_swi = SwiController::new(&harts, PrivilegeMode::Machine);
-            mem.add_device_memory(settings.m_mode_swi_addr, m_swi)
-                .unwrap();
+        if settings.m_mode_swi_enable && !clint {
+            let m_swi = SwiController::new(&harts, PrivilegeMode::Machine, settings.timer_layout);
+            mem.add_device_memory(settings.m_mode_swi_addr, m_swi)?;
         }
 
-        Self {
+        let shutdown = settings
+            .shutdown_enable
+            .then(|| mem.add_device_memory(settings.shutdown_addr, ShutdownController::new()))
+            .transpose()?;
+
+        let plic = settings
+            .plic_enable
+            .then(|| {
+                let contexts = settings.plic_contexts.unwrap_or(hart_count as u32 * 2);
+                let mut plic = Plic::new(settings.plic_sources, contexts);
+                for hart in &harts {
+                    plic.add_interrupt_bits(hart.get_hart_id() as usize, hart.get_mip_ref());
+                }
+                mem.add_device_memory(settings.plic_addr, plic)
+            })
+            .transpose()?;
+
+        let imsic = settings
+            .aia_enable
+            .then(|| {
+                let mips: Vec<_> = harts.iter().map(Hart::get_mip_ref).collect();
+                let imsic = Imsic::new(settings.imsic_ids, &mips);
+                for (i, hart) in harts.iter_mut().enumerate() {
+                    let (m_file, s_file) = imsic.files(i);
+                    hart.set_interrupt_files(m_file, s_file);
+                }
+                mem.add_device_memory(settings.imsic_m_addr, imsic.m_pages())?;
+                mem.add_device_memory(settings.imsic_s_addr, imsic.s_pages())?;
+                Ok::<_, DeviceInitError>(imsic)
+            })
+            .transpose()?;
+
+        let aplic = imsic
+            .as_ref()
+            .map(|imsic| {
+                let aplic = Aplic::new(
+                    settings.aplic_sources,
+                    imsic,
+                    settings.imsic_m_addr,
+                    settings.imsic_s_addr,
+                );
+                mem.add_device_memory(settings.aplic_m_addr, aplic.m_domain())?;
+                mem.add_device_memory(settings.aplic_s_addr, aplic.s_domain())?;
+                Ok::<_, DeviceInitError>(aplic)
+            })
+            .transpose()?;
+
+        Ok(Self {
             harts,
             mem,
             sync_devices: Vec::new(),
             // async_devices: HashMap::new(),
             timer,
+            shutdown,
+            plic,
+            aplic,
+            imsic,
+            frozen: vec![false; hart_count as usize],
+            history: None,
+            step_watch_hit: None,
+            tracer: None,
+            crash_report: None,
+            unwind_info: UnwindInfo::default(),
+            kernel_flags: core_file::DEFAULT_FLAGS,
+            waker: IdleWaker::default(),
+            stats: VMStats::default(),
             next_dev_id: 0,
             settings,
-        }
+        })
     }
 
     /// Load a kernel from an elf file and place it at 0x80000000 (bottom of memory)
@@ -161,11 +487,24 @@ impl VMState {
             )));
         }
         let addr = load_elf_phys(elf, &mut self.mem)?;
+        self.kernel_flags = elf.header.flags;
+        self.invalidate_history();
         Ok(())
     }
 
+    /// Connect a line to a source of the plic or aplic, returns false if neither is enabled or
+    /// it does not have the source
+    fn connect_interrupt(&mut self, source: u32, line: InterruptLine) -> bool {
+        if let Some(aplic) = &self.aplic {
+            return aplic.connect(source, line);
+        }
+        self.plic
+            .as_ref()
+            .is_some_and(|p| p.write().unwrap().connect(source, line))
+    }
+
     fn add_sync_device(&mut self, mut dev: HandledDeviceHolder) -> Result<(), DeviceInitError> {
-        dev.init_device(&mut self.mem);
+        dev.init_device(&mut self.mem, self.waker.clone())?;
         self.sync_devices.push(dev);
         Ok(())
         // let mut memory = DeviceMemory::new(mem_size, addr);
@@ -195,7 +534,8 @@ impl VMState {
         // Ok(())
     }
 
-    /// Advance all cores one cycle and, if verbose, print the instruction that was executed
+    /// Advance all cores one cycle and, if verbose, print the instruction that was executed. A
+    /// watchpoint hit is reported by [`VMState::step_watch_hit()`] afterwards.
     pub fn step(&mut self, verbose: bool) -> Result<(), VMError> {
         // TODO
         // for dev in &mut self.sync_devices {
@@ -210,36 +550,139 @@ impl VMState {
         //     )?;
         // }
 
-        for dev in &mut self.sync_devices {
-            dev.update().unwrap();
+        self.update_devices()?;
+        self.step_watch_hit = None;
+
+        for i in 0..self.harts.len() {
+            if !self.frozen[i] {
+                if let Some(history) = &mut self.history {
+                    history.record(Event::Step(i));
+                }
+                self.step_one(i, verbose)?;
+            }
         }
 
-        self.timer.read().unwrap().generate_interrupts();
+        Ok(())
+    }
 
-        for hart in &mut self.harts {
-            hart.step(&mut self.mem, verbose)?;
+    /// Advance a single hart one cycle, devices and the timer are updated as in [`VMState::step`]
+    /// but the other harts are left untouched. This also steps frozen harts.
+    pub fn step_hart(&mut self, hart: usize, verbose: bool) -> Result<(), VMError> {
+        self.update_devices()?;
+        self.step_watch_hit = None;
+
+        if let Some(history) = &mut self.history {
+            history.record(Event::Step(hart));
         }
+        self.step_one(hart, verbose)
+    }
+
+    /// Step a hart and take the watchpoint it hit, if any
+    fn step_one(&mut self, hart: usize, verbose: bool) -> Result<(), VMError> {
+        let pc = self.harts[hart].get_pc();
+        if let Err(error) = self.harts[hart].step(&mut self.mem, verbose) {
+            return Err(self.crashed(hart, error));
+        }
+        if let Some(hit) = self.mem.take_watch_hit() {
+            self.step_watch_hit.get_or_insert(StopReason::Watchpoint {
+                hart,
+                pc,
+                addr: hit.addr,
+                access: hit.access,
+            });
+        }
+        Ok(())
+    }
+
+    /// The watchpoint the last [`VMState::step()`] or [`VMState::step_hart()`] hit, the first one
+    /// if several harts hit one
+    pub fn step_watch_hit(&self) -> Option<&StopReason> {
+        self.step_watch_hit.as_ref()
+    }
+
+    /// Start of every vm step, sample the clock and update devices and the timer. When recording
+    /// history this is also where checkpoints are taken.
+    fn update_devices(&mut self) -> Result<(), VMError> {
+        if self.history.as_ref().is_some_and(History::wants_checkpoint) {
+            let snapshot = self.snapshot();
+            if let Some(history) = &mut self.history {
+                history.checkpoint(snapshot);
+            }
+        }
+
+        self.stats.steps += 1;
+        let now = self.timer.read().unwrap().tick();
+        if let Some(history) = &mut self.history {
+            history.record(Event::Tick(now));
+        }
+
+        self.tick_devices()
+    }
+
+    fn tick_devices(&mut self) -> Result<(), VMError> {
+        for (i, dev) in self.sync_devices.iter_mut().enumerate() {
+            dev.update()?;
+            let input = dev.take_input();
+            if let (Some(history), false) = (&mut self.history, input.is_empty()) {
+                history.record(Event::Input(i, input));
+            }
+        }
+        self.tick_interrupts();
+        Ok(())
+    }
 
+    /// Redo [`VMState::tick_devices()`] while replaying history, devices get the input they took
+    /// when it first ran
+    fn replay_tick_devices(&mut self, inputs: &[(usize, &[u8])]) -> Result<(), DeviceError> {
+        for (i, dev) in self.sync_devices.iter_mut().enumerate() {
+            let input = inputs.iter().find(|(dev, _)| *dev == i).map_or(&[][..], |i| i.1);
+            dev.replay(input)?;
+        }
+        self.tick_interrupts();
         Ok(())
     }
 
+    fn tick_interrupts(&mut self) {
+        self.timer.read().unwrap().generate_interrupts();
+        if let Some(plic) = &self.plic {
+            plic.read().unwrap().update();
+        }
+        if let Some(aplic) = &self.aplic {
+            aplic.update();
+            // Msis are plain writes, one that does not reach a device is lost
+            for (addr, data) in aplic.take_msis() {
+                let _ = self.mem.write_bytes(&data.to_le_bytes(), addr);
+            }
+        }
+    }
+
     /// Step a specific hart until its pc hits the given address or it has made 10000 steps,
     /// whichever happens first
     pub fn step_hart_until(&mut self, hart: usize, target: Address) -> Result<(), VMError> {
-        self.harts[hart].step_until(&mut self.mem, target, 10000)
+        // These steps bypass the vm, so they can not be replayed
+        self.invalidate_history();
+        let result = self.harts[hart].step_until(&mut self.mem, target, 10000);
+        // Watchpoints only stop the run functions
+        self.mem.take_watch_hit();
+        result
     }
 
     /// Step all harts until its pc hits the given address or it has made 10000 steps,
     /// whichever happens first
     pub fn step_all_until(&mut self, target: Address) -> Result<(), VMError> {
         for _ in 0..10000 {
-            for dev in &mut self.sync_devices {
-                dev.update().unwrap();
-            }
+            self.update_devices()?;
 
-            for hart in &mut self.harts {
-                if hart.get_pc() != target {
-                    hart.step(&mut self.mem, false)?;
+            for (i, (hart, frozen)) in self.harts.iter_mut().zip(&self.frozen).enumerate() {
+                if !frozen && hart.get_pc() != target {
+                    if let Some(history) = &mut self.history {
+                        history.record(Event::Step(i));
+                    }
+                    if let Err(error) = hart.step(&mut self.mem, false) {
+                        return Err(self.crashed(i, error));
+                    }
+                    // Watchpoints only stop the run functions
+                    self.mem.take_watch_hit();
                 }
             }
 
@@ -275,8 +718,8 @@ impl VMState {
             // }
         }
 
-        for hart in &self.harts {
-            if hart.get_pc() != target {
+        for (hart, frozen) in self.harts.iter().zip(&self.frozen) {
+            if !frozen && hart.get_pc() != target {
                 return Err(VMError::StepUntilLimit);
             }
         }
@@ -284,13 +727,216 @@ impl VMState {
         Ok(())
     }
 
-    /// Run the vm until it errors or forever, whichever happens first.
-    pub fn run(&mut self) -> Result<(), VMError> {
+    /// Run the vm until it errors or the guest requests a shutdown, which is returned. While all
+    /// harts wait for an interrupt the host sleeps until the next timer deadline, watchpoints are
+    /// ignored.
+    pub fn run(&mut self) -> Result<ShutdownRequest, VMError> {
         loop {
-            self.step(false)?
+            match self.run_bounded(None, |_| false) {
+                StopReason::Shutdown { request, .. } => return Ok(request),
+                StopReason::EBreak { .. } => return Err(VMError::MBreak),
+                StopReason::Error { error, .. } => return Err(error),
+                // Without an armed timer only an interrupt from a device can wake the harts
+                StopReason::AllHartsIdle { .. } => {
+                    self.idle();
+                }
+                StopReason::BudgetExhausted { .. }
+                | StopReason::Woken { .. }
+                | StopReason::Breakpoint { .. }
+                | StopReason::Watchpoint { .. } => {}
+            }
+        }
+    }
+
+    /// Run the vm for at most `budget` steps, in each of which every hart executes at most one
+    /// instruction, or until it stops for another reason.
+    pub fn run_for(&mut self, budget: u64) -> StopReason {
+        self.run_bounded(Some(budget), |_| false)
+    }
+
+    /// Run the vm until `condition` returns true for a hart, it is checked for every hart after
+    /// it steps. If `budget` is given the vm runs for at most that many steps, see
+    /// [`VMState::run_for()`].
+    pub fn run_until<F: FnMut(&Hart) -> bool>(
+        &mut self,
+        budget: Option<u64>,
+        condition: F,
+    ) -> StopReason {
+        self.run_bounded(budget, condition)
+    }
+
+    fn run_bounded<F: FnMut(&Hart) -> bool>(
+        &mut self,
+        budget: Option<u64>,
+        mut condition: F,
+    ) -> StopReason {
+        let mut steps = 0;
+        let mut last_idle = 0;
+        loop {
+            if budget.is_some_and(|b| steps >= b) {
+                return StopReason::BudgetExhausted {
+                    hart: 0,
+                    pc: self.hart_pc(0),
+                };
+            }
+            steps += 1;
+
+            if let Err(error) = self.update_devices() {
+                return StopReason::Error {
+                    hart: 0,
+                    pc: self.hart_pc(0),
+                    error,
+                };
+            }
+
+            for (i, hart) in self.harts.iter_mut().enumerate() {
+                if self.frozen[i] {
+                    continue;
+                }
+
+                let pc = hart.get_pc();
+                let was_idle = hart.is_waiting_for_interrupt();
+
+                if let Some(history) = &mut self.history {
+                    history.record(Event::Step(i));
+                }
+                match hart.step(&mut self.mem, false) {
+                    Ok(_) => {}
+                    Err(VMError::MBreak) => return StopReason::EBreak { hart: i, pc },
+                    Err(error) => {
+                        let error = self.crashed(i, error);
+                        return StopReason::Error { hart: i, pc, error };
+                    }
+                }
+
+                if let Some(request) = self
+                    .shutdown
+                    .as_ref()
+                    .and_then(|s| s.write().unwrap().take_request())
+                {
+                    return StopReason::Shutdown {
+                        hart: i,
+                        pc,
+                        request,
+                    };
+                }
+
+                if let Some(hit) = self.mem.take_watch_hit() {
+                    return StopReason::Watchpoint {
+                        hart: i,
+                        pc,
+                        addr: hit.addr,
+                        access: hit.access,
+                    };
+                }
+
+                if !was_idle && hart.is_waiting_for_interrupt() {
+                    last_idle = i;
+                }
+
+                if condition(hart) {
+                    return StopReason::Breakpoint {
+                        hart: i,
+                        pc: hart.get_pc(),
+                    };
+                }
+            }
+
+            let timer_armed = self
+                .timer
+                .read()
+                .unwrap()
+                .get_cmps()
+                .iter()
+                .any(Option::is_some);
+            let plic_armed = self
+                .plic
+                .as_ref()
+                .is_some_and(|p| p.read().unwrap().is_armed())
+                || self.aplic.as_ref().is_some_and(Aplic::is_armed);
+            if !timer_armed && !plic_armed && self.all_idle() {
+                return StopReason::AllHartsIdle {
+                    hart: last_idle,
+                    pc: self.hart_pc(last_idle),
+                };
+            }
+
+            // After a wake the devices are updated before the harts could sleep again, so they
+            // see what woke the vm
+            if self.idle() {
+                return StopReason::Woken {
+                    hart: 0,
+                    pc: self.hart_pc(0),
+                };
+            }
         }
     }
 
+    fn all_idle(&self) -> bool {
+        self.harts
+            .iter()
+            .zip(&self.frozen)
+            .all(|(h, frozen)| *frozen || h.is_waiting_for_interrupt())
+    }
+
+    /// If all harts wait for an interrupt, sleep the host until just after the next timer
+    /// deadline, for at most [`MAX_IDLE_SLEEP`] or until woken by an [`IdleWaker`]. Returns true if
+    /// it was woken. With virtual time, time only passes while stepping so this never sleeps.
+    fn idle(&mut self) -> bool {
+        if self.settings.virtual_time.is_some() || !self.all_idle() {
+            return false;
+        }
+
+        let timer = self.timer.read().unwrap();
+        let now = timer.get_time_micros();
+        let sleep = timer
+            .get_cmps()
+            .iter()
+            .flatten()
+            .filter(|cmp| **cmp >= now)
+            .min()
+            .map_or(MAX_IDLE_SLEEP, |cmp| {
+                Duration::from_micros(cmp - now + 1).min(MAX_IDLE_SLEEP)
+            });
+        drop(timer);
+
+        let start = Instant::now();
+        let woken = self.waker.sleep(sleep);
+        self.stats.idle_time += start.elapsed();
+        self.stats.idle_sleeps += 1;
+        woken
+    }
+
+    /// A handle to wake the vm from sleeping in the `run` functions while all harts wait for an
+    /// interrupt, [`VMState::run_for()`] and [`VMState::run_until()`] then return
+    /// [`StopReason::Woken`].
+    pub fn idle_waker(&self) -> IdleWaker {
+        self.waker.clone()
+    }
+
+    pub fn stats(&self) -> VMStats {
+        self.stats
+    }
+
+    /// The frequency of `mtime` in Hz, that of [`VMSettings::virtual_time`] if set, otherwise
+    /// 1 MHz as the host clock counts microseconds
+    pub fn timebase_freq(&self) -> u64 {
+        self.settings
+            .virtual_time
+            .map_or(1_000_000, |time| time.timebase_freq)
+    }
+
+    /// The current value of `mtime`, in ticks of [`VMState::timebase_freq()`]
+    pub fn time(&self) -> u64 {
+        self.timer.read().unwrap().get_time_micros()
+    }
+
+    fn hart_pc(&self, hart: usize) -> Address {
+        self.harts
+            .get(hart)
+            .map_or(0u64.into(), |h: &Hart| h.get_pc())
+    }
+
     #[cfg(test)]
     pub(crate) fn mem(&self) -> &Memory {
         &self.mem
@@ -311,10 +957,755 @@ impl VMState {
         println!("{:#?}", self.mem.get_map());
     }
 
-    /// Get an immutable refierence to a specific hart, if it exists.
+    /// Get an immutable reference to a specific hart, if it exists.
     pub fn get_hart(&self, hart: usize) -> Option<&Hart> {
         self.harts.get(hart)
     }
+
+    /// Get a mutable reference to a specific hart, if it exists.
+    pub fn get_hart_mut(&mut self, hart: usize) -> Option<&mut Hart> {
+        self.invalidate_history();
+        self.harts.get_mut(hart)
+    }
+
+    /// The number of harts in this vm.
+    pub fn hart_count(&self) -> usize {
+        self.harts.len()
+    }
+
+    /// Read memory as seen by the given hart, that is with address translation and pmp checks
+    /// applied according to its current privilege and csrs. Does not trigger watchpoints, and
+    /// devices are read without side effects, see
+    /// [`MemoryBuffer::peek_bytes()`](crate::memory::memory_buffer::MemoryBuffer::peek_bytes()).
+    pub fn read_memory(
+        &mut self,
+        hart: usize,
+        addr: Address,
+        size: usize,
+    ) -> Result<Vec<u8>, MemoryError> {
+        let bytes = self.mem.window(&self.harts[hart]).peek_bytes(addr, size);
+        self.mem.take_watch_hit();
+        self.mem.take_fault();
+        bytes
+    }
+
+    /// Write memory as seen by the given hart, that is with address translation and pmp checks
+    /// applied according to its current privilege and csrs. Does not trigger watchpoints and is
+    /// not logged in the store log.
+    pub fn write_memory(
+        &mut self,
+        hart: usize,
+        bytes: &[u8],
+        addr: Address,
+    ) -> Result<(), MemoryError> {
+        let paused = self.mem.pause_store_log(true);
+        let result = self.mem.window(&self.harts[hart]).write_bytes(bytes, addr);
+        self.mem.pause_store_log(paused);
+        self.mem.take_watch_hit();
+        self.mem.take_fault();
+        self.invalidate_history();
+        result
+    }
+
+    /// Read physical memory, bypassing translation and pmp. Devices are read without side
+    /// effects, see
+    /// [`MemoryBuffer::peek_bytes()`](crate::memory::memory_buffer::MemoryBuffer::peek_bytes()).
+    pub fn read_phys(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryError> {
+        self.mem.peek_bytes(addr, size)
+    }
+
+    /// Write physical memory, bypassing translation and pmp.
+    pub fn write_phys(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryError> {
+        self.invalidate_history();
+        self.mem.write_bytes(bytes, addr)
+    }
+
+    /// The mappings of the page table the given hart uses, see [`paging::mappings()`]
+    pub fn page_mappings(&self, hart: usize) -> Vec<Mapping> {
+        paging::mappings(self.harts[hart].get_csr().get_satp(), &self.mem)
+    }
+
+    /// Walk the page table of the given hart for an access to `addr`, see
+    /// [`paging::translate()`]. The walk uses the privilege of the hart after MPRV, or S mode
+    /// if that is M mode.
+    pub fn translate(
+        &self,
+        hart: usize,
+        addr: Address,
+        mode: AccessMode,
+    ) -> (Vec<WalkStep>, Result<Address, PageWalkFault>) {
+        let h = &self.harts[hart];
+        let status = h.get_csr().get_status();
+        let privilege = if status.mprv {
+            status.mpp
+        } else {
+            h.privilege()
+        };
+        let (mxr, sum) = h.get_csr().get_mxr_sum();
+        let context = AccessContext {
+            mode,
+            privilege: privilege.min(PrivilegeMode::Supervisor),
+            mxr,
+            sum,
+        };
+        paging::translate(addr, h.get_csr().get_satp(), &self.mem, context)
+    }
+
+    /// Freeze or thaw a hart, frozen harts are not stepped by [`VMState::step()`] or the `run`
+    /// functions.
+    pub fn set_hart_frozen(&mut self, hart: usize, frozen: bool) {
+        self.frozen[hart] = frozen;
+    }
+
+    pub fn is_hart_frozen(&self, hart: usize) -> bool {
+        self.frozen[hart]
+    }
+
+    /// Change [`VMSettings::halt_on_ebreak`] of a running vm, debugger frontends turn it on while
+    /// they are attached. Returns the previous setting.
+    pub fn set_halt_on_ebreak(&mut self, halt: bool) -> bool {
+        for hart in &mut self.harts {
+            hart.set_halt_on_ebreak(halt);
+        }
+        std::mem::replace(&mut self.settings.halt_on_ebreak, halt)
+    }
+
+    /// Stop the `run` functions when a hart makes an access of the given kind to `addr`
+    pub fn add_watchpoint(&mut self, addr: Address, kind: WatchKind) {
+        self.mem.add_watchpoint(Watchpoint { addr, kind });
+    }
+
+    /// Remove all watchpoints on `addr`
+    pub fn remove_watchpoint(&mut self, addr: Address) {
+        self.mem.remove_watchpoint(addr);
+    }
+
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        self.mem.get_watchpoints()
+    }
+
+    /// Log the last `capacity` stores harts make, with the hart, pc, instret and old and new
+    /// value, see [`StoreLog`]. Replaces an existing log. Stores undone by reverse execution
+    /// stay in the log.
+    pub fn enable_store_log(&mut self, capacity: usize) {
+        self.mem.enable_store_log(capacity);
+    }
+
+    pub fn disable_store_log(&mut self) {
+        self.mem.disable_store_log();
+    }
+
+    pub fn store_log(&self) -> Option<&StoreLog> {
+        self.mem.get_store_log()
+    }
+
+    pub fn store_log_mut(&mut self) -> Option<&mut StoreLog> {
+        self.mem.get_store_log_mut()
+    }
+
+    /// Write a line in Spike's commit log format to `out` for every instruction a hart retires
+    /// and every trap it takes, see [`crate::trace`]. This replaces any running trace.
+    pub fn start_trace<W: Write + 'static>(&mut self, out: W) {
+        self.tracer = Some(Rc::new(RefCell::new(Tracer::new(Box::new(out)))));
+        self.set_hart_tracers(self.tracer.clone());
+    }
+
+    /// Stop tracing, flush the output and report the first error writing to it
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        self.set_hart_tracers(None);
+        match self.tracer.take() {
+            Some(tracer) => tracer.borrow_mut().finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    /// Run the vm in lockstep with `reference`, a trace in Spike's commit log format, until the
+    /// reference ends. Stops at the first retired instruction or trap that differs from the
+    /// reference in pc, register writes, csr writes or trap cause, see
+    /// [`Divergence`](crate::trace::Divergence). Returns the number of events that matched.
+    pub fn lockstep<R: BufRead>(&mut self, reference: R) -> Result<u64, LockstepError> {
+        let tracer = self.tracer.get_or_insert_with(Default::default).clone();
+        tracer.borrow_mut().set_buffered(true);
+        self.set_hart_tracers(Some(tracer.clone()));
+
+        let result = self.run_lockstep(Lockstep::new(reference), &tracer);
+
+        tracer.borrow_mut().set_buffered(false);
+        if !tracer.borrow().has_output() {
+            self.tracer = None;
+            self.set_hart_tracers(None);
+        }
+        result
+    }
+
+    fn run_lockstep<R: BufRead>(
+        &mut self,
+        mut checker: Lockstep<R>,
+        tracer: &TraceSink,
+    ) -> Result<u64, LockstepError> {
+        loop {
+            let stop = self.run_for(1);
+            for event in tracer.borrow_mut().take_events() {
+                if !checker.check(event)? {
+                    return Ok(checker.matched());
+                }
+            }
+            if !matches!(
+                stop,
+                StopReason::BudgetExhausted { .. } | StopReason::Woken { .. }
+            ) {
+                return Err(LockstepError::Stopped(stop));
+            }
+        }
+    }
+
+    fn set_hart_tracers(&mut self, tracer: Option<TraceSink>) {
+        for hart in &mut self.harts {
+            hart.set_tracer(tracer.clone());
+        }
+    }
+
+    /// Write a [`CrashReport`] to the file at `path`, and a summary of it to stderr, whenever
+    /// stepping fails with an error other than [`VMError::MBreak`], or stop doing so if `None`.
+    pub fn set_crash_report(&mut self, path: Option<PathBuf>) {
+        self.crash_report = path;
+    }
+
+    /// Describe the state of the vm after `hart` failed with `error`.
+    pub fn crash_report(&mut self, hart: usize, error: &VMError) -> CrashReport {
+        let mut harts = Vec::new();
+        for i in 0..self.harts.len() {
+            let pc = self.harts[i].get_pc();
+            let sp = Address::from(self.harts[i].get_int_reg(IntRegister::X2) as u64);
+            let pc_memory = self.memory_around(i, pc);
+            let sp_memory = self.memory_around(i, sp);
+            let backtrace = self.backtrace(i);
+
+            let h = &self.harts[i];
+            harts.push(HartReport {
+                id: i,
+                pc,
+                privilege: h.privilege(),
+                recent: h.recent_instructions().collect(),
+                registers: std::array::from_fn(|r| h.get_int_reg(IntRegister::from(r as u32))),
+                csrs: crash::TRAP_CSRS
+                    .iter()
+                    .map(|a| CsrAddress::new(*a))
+                    .map(|a| (a, h.get_csr().get_csr(a)))
+                    .collect(),
+                pc_memory,
+                sp_memory,
+                backtrace,
+                last_fault: h.last_fault().copied(),
+            });
+        }
+
+        CrashReport {
+            hart,
+            error: format!("{error:?}"),
+            harts,
+            memory_map: self.mem.describe_map(),
+        }
+    }
+
+    /// Use the symbols and call frame information of the guest in [`VMState::backtrace()`] and
+    /// crash reports, without them frames are only found through frame pointers.
+    pub fn set_unwind_info(&mut self, info: UnwindInfo) {
+        self.unwind_info = info;
+    }
+
+    /// The canonical frame address of the function `hart` is in, the stack pointer before the
+    /// function was called, as given by the call frame information set with
+    /// [`VMState::set_unwind_info()`]. `None` without call frame information for the pc.
+    pub fn cfa(&self, hart: usize) -> Option<u64> {
+        let h = self.harts.get(hart)?;
+        let rules = self.unwind_info.rules(h.get_pc())?;
+        let registers: Registers =
+            std::array::from_fn(|r| Some(h.get_int_reg(IntRegister::from(r as u32)) as u64));
+        backtrace::cfa(&rules, &registers)
+    }
+
+    /// The call stack of `hart`, innermost frame first. Functions are unwound through the frame
+    /// pointer chain if they keep a frame pointer and with call frame information otherwise,
+    /// trap handlers are unwound to where the trap was taken. Memory is read as seen by the hart
+    /// in its current mode.
+    pub fn backtrace(&mut self, hart: usize) -> Vec<Frame> {
+        let h = &self.harts[hart];
+        let mut registers: Registers =
+            std::array::from_fn(|r| Some(h.get_int_reg(IntRegister::from(r as u32)) as u64));
+        let csr = |addr| h.get_csr().get_csr(CsrAddress::new(addr));
+        let (mtvec, stvec) = (csr(backtrace::MTVEC) & !3, csr(backtrace::STVEC) & !3);
+        // Each trap vector is unwound through once, as a nested trap overwrites the epc
+        let mut traps = vec![
+            (mtvec, csr(backtrace::MEPC), PrivilegeMode::Machine),
+            (stvec, csr(backtrace::SEPC), PrivilegeMode::Supervisor),
+        ];
+
+        let mut frames = vec![];
+        let mut pc = h.get_pc();
+        let mut found_by = FoundBy::Pc;
+        loop {
+            let mut frame = Frame {
+                pc,
+                sp: registers[SP],
+                symbol: None,
+                found_by,
+            };
+            let lookup = frame.lookup_pc();
+            frame.symbol = self.unwind_info.symbol(lookup).map(|(name, offset)| {
+                let offset = offset + (u64::from(pc) - u64::from(lookup));
+                (name.to_string(), offset)
+            });
+            frames.push(frame);
+            if frames.len() == backtrace::MAX_FRAMES {
+                break;
+            }
+
+            let trap = traps
+                .iter()
+                .position(|(vector, ..)| self.unwind_info.same_function(lookup, (*vector).into()));
+            let unwound = self.unwind(hart, lookup, &registers, frames.len() == 1);
+            let (caller_pc, caller, caller_found_by) = match (trap, unwound) {
+                // Registers the handler saved are restored if it can be unwound, otherwise the
+                // registers are assumed to be as they were when the trap was taken
+                (Some(i), unwound) => {
+                    let (_, epc, mode) = traps.remove(i);
+                    let caller = unwound.map_or(registers, |(_, caller, _)| caller);
+                    (Address::from(epc), caller, FoundBy::Trap(mode))
+                }
+                (None, Some(unwound)) => unwound,
+                (None, None) => break,
+            };
+
+            // The stack grows down, so a caller's frame can not be below that of its callee
+            let sp_went_down = matches!(
+                (registers[SP], caller[SP]),
+                (Some(sp), Some(caller_sp)) if caller_sp < sp
+            );
+            let same_frame = caller_pc == pc && caller[SP] == registers[SP];
+            let is_trap = matches!(caller_found_by, FoundBy::Trap(_));
+            if u64::from(caller_pc) == 0 || (!is_trap && (sp_went_down || same_frame)) {
+                break;
+            }
+
+            pc = caller_pc;
+            registers = caller;
+            found_by = caller_found_by;
+        }
+        frames
+    }
+
+    /// Find the caller of the frame at `pc`, its pc, registers and how it was found.
+    fn unwind(
+        &mut self,
+        hart: usize,
+        pc: Address,
+        registers: &Registers,
+        innermost: bool,
+    ) -> Option<(Address, Registers, FoundBy)> {
+        let rules = self.unwind_info.rules(pc);
+        let mut read = |addr: u64| {
+            let bytes = self.read_memory(hart, addr.into(), 8).ok()?;
+            Some(u64::from_le_bytes(bytes.try_into().ok()?))
+        };
+
+        // A function keeps a frame pointer if its cfa is computed from it, functions without
+        // call frame information are assumed to keep one
+        let keeps_fp = rules.as_ref().is_none_or(
+            |rules| matches!(rules.cfa, CfaRule::RegisterOffset(reg, _) if reg as usize == FP),
+        );
+        if keeps_fp {
+            if let Some(caller) = unwind_frame_pointer(registers, &mut read) {
+                return Some((caller[RA]?.into(), caller, FoundBy::FramePointer));
+            }
+        }
+
+        if let Some(rules) = rules {
+            let caller = unwind_cfi(&rules, registers, &mut read)?;
+            let pc = caller
+                .get(rules.return_address as usize)
+                .copied()
+                .flatten()?;
+            return Some((pc.into(), caller, FoundBy::CallFrameInfo));
+        }
+
+        // A function without a frame that has not called anything still has its return address
+        // in ra
+        if innermost {
+            return Some((registers[RA]?.into(), *registers, FoundBy::ReturnAddress));
+        }
+        None
+    }
+
+    /// Memory around `addr` as seen by `hart`, only from `addr` on if the memory before it can
+    /// not be read.
+    fn memory_around(
+        &mut self,
+        hart: usize,
+        addr: Address,
+    ) -> Result<(Address, Vec<u8>), MemoryError> {
+        let start = Address::from(u64::from(addr).saturating_sub(crash::MEMORY_BEFORE));
+        let len = (crash::MEMORY_BEFORE + crash::MEMORY_AFTER) as usize;
+        self.read_memory(hart, start, len)
+            .map(|bytes| (start, bytes))
+            .or_else(|_| {
+                self.read_memory(hart, addr, crash::MEMORY_AFTER as usize)
+                    .map(|bytes| (addr, bytes))
+            })
+    }
+
+    /// Report a failed step if crash reports are enabled, passes the error on.
+    fn crashed(&mut self, hart: usize, error: VMError) -> VMError {
+        let Some(path) = self.crash_report.clone() else {
+            return error;
+        };
+        if matches!(error, VMError::MBreak) {
+            return error;
+        }
+
+        let report = self.crash_report(hart, &error);
+        eprint!("{}", report.summary());
+        match fs::write(&path, report.to_string()) {
+            Ok(()) => eprintln!("  crash report written to {}", path.display()),
+            Err(e) => eprintln!("  failed to write crash report to {}: {e}", path.display()),
+        }
+        error
+    }
+
+    /// Serialize the state of all harts, main memory, the timer and devices. Debugger state like
+    /// watchpoints and frozen harts is not part of a snapshot.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut snapshot = SnapshotWriter::new();
+        snapshot.write_raw(snapshot::MAGIC);
+        snapshot.write_u64(snapshot::VERSION);
+        snapshot.write_bytes(&self.snapshot_config());
+
+        for hart in &self.harts {
+            hart.save(&mut snapshot);
+        }
+        self.mem.save(&mut snapshot);
+        self.timer.read().unwrap().save(&mut snapshot);
+        if let Some(plic) = &self.plic {
+            plic.read().unwrap().save(&mut snapshot);
+        }
+        if let (Some(aplic), Some(imsic)) = (&self.aplic, &self.imsic) {
+            aplic.save(&mut snapshot);
+            imsic.save(&mut snapshot);
+        }
+        for dev in &self.sync_devices {
+            let mut dev_snapshot = SnapshotWriter::new();
+            dev.save(&mut dev_snapshot);
+            snapshot.write_bytes(&dev_snapshot.into_bytes());
+        }
+
+        snapshot.into_bytes()
+    }
+
+    /// Restore a snapshot taken by [`VMState::snapshot()`], the vm must have been built with the
+    /// same configuration as the one the snapshot was taken of. On error the vm may be left
+    /// partially restored.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        self.invalidate_history();
+        self.load_snapshot(bytes)
+    }
+
+    fn load_snapshot(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let magic = bytes
+            .get(..snapshot::MAGIC.len())
+            .ok_or(SnapshotError::InvalidMagic)?;
+        if magic != snapshot::MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let mut snapshot = SnapshotReader::new(&bytes[snapshot::MAGIC.len()..]);
+        let version = snapshot.read_u64()?;
+        if version != snapshot::VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if snapshot.read_bytes()? != self.snapshot_config() {
+            return Err(SnapshotError::ConfigMismatch);
+        }
+
+        for hart in &mut self.harts {
+            hart.restore(&mut snapshot)?;
+        }
+        self.mem.restore(&mut snapshot)?;
+        self.timer.write().unwrap().restore(&mut snapshot)?;
+        if let Some(plic) = &self.plic {
+            plic.write().unwrap().restore(&mut snapshot)?;
+        }
+        if let (Some(aplic), Some(imsic)) = (&self.aplic, &mut self.imsic) {
+            aplic.restore(&mut snapshot)?;
+            imsic.restore(&mut snapshot)?;
+        }
+        for dev in &mut self.sync_devices {
+            let mut dev_snapshot = SnapshotReader::new(snapshot.read_bytes()?);
+            dev.restore(&mut dev_snapshot)?;
+        }
+
+        if snapshot.is_empty() {
+            Ok(())
+        } else {
+            Err(SnapshotError::Corrupt)
+        }
+    }
+
+    /// Write a [snapshot](VMState::snapshot()) to the file at `path`
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        fs::write(path, self.snapshot())?;
+        Ok(())
+    }
+
+    /// [Restore](VMState::restore()) a snapshot from the file at `path`
+    pub fn restore_snapshot<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SnapshotError> {
+        self.restore(&fs::read(path)?)
+    }
+
+    /// An elf core file of the vm with a segment for main memory, one for each device region if
+    /// `devices` and notes with the registers of each hart. Memory is at its physical address,
+    /// so with paging on only identity mapped memory is where the guest sees it.
+    pub fn core_file(&self, devices: bool) -> ElfWriter {
+        let mut core = ElfWriter::new(ObjectType::Core, ASI::RISCV).set_flags(self.kernel_flags);
+        for (i, hart) in self.harts.iter().enumerate() {
+            core = core.add_note(core_file::prstatus(i as u32 + 1, hart));
+            #[cfg(feature = "float")]
+            {
+                core = core.add_note(core_file::fpregset(hart));
+            }
+        }
+
+        let (ram, bytes) = self.mem.ram();
+        core = core.add_segment(Segment {
+            addr: elf_load::Address(ram.into()),
+            flags: ProgramFlags::Read | ProgramFlags::Write | ProgramFlags::Exec,
+            data: bytes.to_vec(),
+        });
+        if devices {
+            for (addr, data) in self.mem.device_contents() {
+                core = core.add_segment(Segment {
+                    addr: elf_load::Address(addr.into()),
+                    flags: ProgramFlags::Read | ProgramFlags::Write,
+                    data,
+                });
+            }
+        }
+        core
+    }
+
+    /// Write a [core file](VMState::core_file()) to `path`, which gdb can open together with the
+    /// kernel's elf with `gdb kernel.elf <path>`.
+    pub fn write_core<P: AsRef<Path>>(&self, path: P, devices: bool) -> io::Result<()> {
+        let file = fs::File::create(path)?;
+        self.core_file(devices).write(io::BufWriter::new(file))
+    }
+
+    /// Start recording execution so the vm can be stepped backwards with
+    /// [`VMState::reverse_step()`] and [`VMState::reverse_continue()`]. A checkpoint is taken every
+    /// `interval` steps and at most `max_checkpoints` are kept, which bounds both memory use and
+    /// how far back the vm can go. Any existing history is discarded.
+    ///
+    /// Devices are updated again while replaying, they take the input recorded when the update
+    /// first ran and their output is suppressed, so it is not sent out again.
+    pub fn enable_history(&mut self, interval: u64, max_checkpoints: usize) {
+        self.history = Some(History::new(interval, max_checkpoints));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history_enabled(&self) -> bool {
+        self.history.is_some()
+    }
+
+    /// Undo the last `count` steps of `hart`, together with whatever the other harts did since.
+    /// If the history can not be replayed recording is turned off, the vm is then left where
+    /// the replay stopped.
+    pub fn reverse_step(&mut self, hart: usize, count: u64) -> Result<(), ReverseError> {
+        let mut history = self.history.take().ok_or(ReverseError::HistoryDisabled)?;
+        match history.find_step_back(hart, count) {
+            Some(pos) => self.goto(&mut history, pos)?,
+            None => {
+                let start = history.start();
+                self.goto(&mut history, start)?;
+                self.history = Some(history);
+                return Err(ReverseError::HistoryStart);
+            }
+        }
+        self.history = Some(history);
+        Ok(())
+    }
+
+    /// Go back to the last time `condition` held for a hart after it stepped or a watchpoint was
+    /// hit, the mirror image of [`VMState::run_until()`]. The returned reason is either
+    /// [`StopReason::Breakpoint`] or [`StopReason::Watchpoint`]. If replaying the history fails
+    /// recording is turned off, as for [`VMState::reverse_step()`].
+    pub fn reverse_continue<F: FnMut(&Hart) -> bool>(
+        &mut self,
+        mut condition: F,
+    ) -> Result<StopReason, ReverseError> {
+        let mut history = self.history.take().ok_or(ReverseError::HistoryDisabled)?;
+        let end = history.pos();
+
+        // Replay one checkpoint interval at a time starting at the most recent, the last stop
+        // in the first interval that has one is where to go.
+        let mut found = None;
+        let mut segment_end = end;
+        for checkpoint in history.checkpoints().iter().rev() {
+            if checkpoint.pos >= end {
+                continue;
+            }
+            let mut on_step = |vm: &Self, pos, hart, pc, hit: Option<WatchHit>| {
+                if pos >= end {
+                    return;
+                }
+                if let Some(hit) = hit {
+                    let reason = StopReason::Watchpoint {
+                        hart,
+                        pc,
+                        addr: hit.addr,
+                        access: hit.access,
+                    };
+                    found = Some((pos, reason));
+                } else if condition(&vm.harts[hart]) {
+                    let pc = vm.harts[hart].get_pc();
+                    found = Some((pos, StopReason::Breakpoint { hart, pc }));
+                }
+            };
+            self.replay(&history, checkpoint, segment_end, &mut on_step)?;
+            if found.is_some() {
+                break;
+            }
+            segment_end = checkpoint.pos;
+        }
+
+        let result = match found {
+            Some((pos, reason)) => {
+                self.goto(&mut history, pos)?;
+                Ok(reason)
+            }
+            None => {
+                let start = history.start();
+                self.goto(&mut history, start)?;
+                Err(ReverseError::HistoryStart)
+            }
+        };
+        self.history = Some(history);
+        result
+    }
+
+    fn invalidate_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.invalidate();
+        }
+    }
+
+    /// Move the vm to position `pos` in the history and forget everything after it
+    fn goto(&mut self, history: &mut History, pos: usize) -> Result<(), ReverseError> {
+        if let Some(checkpoint) = history.checkpoint_before(pos) {
+            self.replay(history, checkpoint, pos, |_, _, _, _, _| {})?;
+            history.truncate(pos);
+        }
+        Ok(())
+    }
+
+    /// Restore `checkpoint` and replay the log up to `to`, `on_step` is called after every step
+    /// with the position after it, the hart, the pc it stepped at and the watchpoint it hit.
+    /// Callers take the history out of the vm for this, so it is dropped when replaying fails.
+    fn replay<F: FnMut(&Self, usize, usize, Address, Option<WatchHit>)>(
+        &mut self,
+        history: &History,
+        checkpoint: &Checkpoint,
+        to: usize,
+        mut on_step: F,
+    ) -> Result<(), ReverseError> {
+        self.load_snapshot(&checkpoint.snapshot)
+            .map_err(ReverseError::Snapshot)?;
+        // Replayed instructions were traced and logged when they first ran
+        self.set_hart_tracers(None);
+        let paused = self.mem.pause_store_log(true);
+
+        let mut result = Ok(());
+        let events = history.events(checkpoint.pos, to);
+        for (i, event) in events.iter().enumerate() {
+            match *event {
+                Event::Tick(now) => {
+                    self.timer.read().unwrap().replay_tick(now);
+                    // The input of a tick is logged right after it
+                    let inputs: Vec<_> = events[i + 1..]
+                        .iter()
+                        .map_while(|e| match e {
+                            Event::Input(dev, input) => Some((*dev, input.as_slice())),
+                            _ => None,
+                        })
+                        .collect();
+                    if let Err(error) = self.replay_tick_devices(&inputs) {
+                        result = Err(ReverseError::DeviceError(error));
+                        break;
+                    }
+                }
+                Event::Input(..) => {}
+                Event::Step(hart) => {
+                    let pc = self.harts[hart].get_pc();
+                    // Errors and shutdown requests were already reported when this first ran
+                    let _ = self.harts[hart].step(&mut self.mem, false);
+                    if let Some(shutdown) = &self.shutdown {
+                        shutdown.write().unwrap().take_request();
+                    }
+                    let hit = self.mem.take_watch_hit();
+                    on_step(self, checkpoint.pos + i + 1, hart, pc, hit);
+                }
+            }
+        }
+
+        self.mem.pause_store_log(paused);
+        self.set_hart_tracers(self.tracer.clone());
+        result
+    }
+
+    /// Everything a snapshot depends on but does not restore, a snapshot can only be restored
+    /// into a vm for which this is equal.
+    fn snapshot_config(&self) -> Vec<u8> {
+        let mut config = SnapshotWriter::new();
+        config.write_u64(self.harts.len() as u64);
+        self.mem.save_layout(&mut config);
+
+        config.write_bool(self.settings.pmp_enable);
+        config.write_bool(self.settings.virt_mem_enable);
+        config.write_u8(self.settings.timer_layout as u8);
+        config.write_address(self.settings.timer_addr);
+        config.write_bool(self.settings.m_mode_swi_enable);
+        config.write_address(self.settings.m_mode_swi_addr);
+        config.write_bool(self.settings.s_mode_swi_enable);
+        config.write_address(self.settings.s_mode_swi_addr);
+        config.write_bool(self.settings.shutdown_enable);
+        config.write_address(self.settings.shutdown_addr);
+        config.write_bool(self.settings.plic_enable);
+        config.write_address(self.settings.plic_addr);
+        config.write_u64(self.settings.plic_sources as u64);
+        config.write_u64(self.settings.plic_contexts.map_or(0, |c| c as u64 + 1));
+        config.write_bool(self.settings.aia_enable);
+        config.write_address(self.settings.aplic_m_addr);
+        config.write_address(self.settings.aplic_s_addr);
+        config.write_u64(self.settings.aplic_sources as u64);
+        config.write_address(self.settings.imsic_m_addr);
+        config.write_address(self.settings.imsic_s_addr);
+        config.write_u64(self.settings.imsic_ids as u64);
+
+        config.write_bool(self.settings.virtual_time.is_some());
+        if let Some(time) = self.settings.virtual_time {
+            config.write_u64(time.timebase_freq);
+            config.write_u64(time.ticks);
+            config.write_u64(time.instructions);
+        }
+
+        config.write_bool(cfg!(feature = "float"));
+        config.write_u64(self.sync_devices.len() as u64);
+        config.into_bytes()
+    }
 }
 
 impl Debug for VMState {
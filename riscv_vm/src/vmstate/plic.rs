@@ -0,0 +1,360 @@
+use std::{rc::Rc, sync::Mutex};
+
+use enumflags2::BitFlags;
+
+use crate::{
+    devices::interrupt_line::InterruptLine,
+    memory::memory_buffer::{MemoryBuffer, MemoryBufferError},
+    trap::InterruptInternal,
+    Address,
+};
+
+use super::{SnapshotError, SnapshotReader, SnapshotWriter};
+
+const PENDING_BASE: u64 = 0x1000;
+const ENABLE_BASE: u64 = 0x2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT_BASE: u64 = 0x200000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+/// Priorities and thresholds are 3 bits, like on the sifive and qemu plics
+const PRIORITY_MASK: u32 = 0x7;
+
+/// The largest number of sources the register layout has room for, source 0 does not exist
+pub const MAX_SOURCES: u32 = 1023;
+
+/// A riscv platform level interrupt controller. Sources are level triggered, a source is pending
+/// while its line is raised unless it is claimed by a context. Context `2n` is the machine mode
+/// of hart `n`, context `2n + 1` its supervisor mode, each drives the matching external interrupt
+/// bit of its hart's mip.
+pub(crate) struct Plic {
+    state: Mutex<PlicState>,
+}
+
+struct PlicState {
+    /// Indexed by source, index 0 is unused
+    priority: Vec<u32>,
+    pending: Vec<bool>,
+    claimed: Vec<bool>,
+    lines: Vec<Option<InterruptLine>>,
+    contexts: Vec<Context>,
+}
+
+struct Context {
+    /// Bit `n % 32` of word `n / 32` enables source `n`
+    enable: Vec<u32>,
+    threshold: u32,
+    interrupt: InterruptInternal,
+    mip: Option<Rc<Mutex<BitFlags<InterruptInternal>>>>,
+}
+
+impl Plic {
+    pub(crate) fn new(sources: u32, contexts: u32) -> Self {
+        assert!(sources <= MAX_SOURCES);
+        let sources = sources as usize + 1;
+        let words = sources.div_ceil(32);
+        Self {
+            state: Mutex::new(PlicState {
+                priority: vec![0; sources],
+                pending: vec![false; sources],
+                claimed: vec![false; sources],
+                lines: vec![None; sources],
+                contexts: (0..contexts)
+                    .map(|c| Context {
+                        enable: vec![0; words],
+                        threshold: 0,
+                        interrupt: if c % 2 == 0 {
+                            InterruptInternal::MachineExternal
+                        } else {
+                            InterruptInternal::SupervisorExternal
+                        },
+                        mip: None,
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Give the contexts of a hart the bits they drive
+    pub(crate) fn add_interrupt_bits(
+        &mut self,
+        hartid: usize,
+        bits: Rc<Mutex<BitFlags<InterruptInternal>>>,
+    ) {
+        let state = self.state.get_mut().unwrap();
+        for context in state.contexts.iter_mut().skip(hartid * 2).take(2) {
+            context.mip = Some(bits.clone());
+        }
+    }
+
+    /// Connect `line` to `source`, returns false if there is no such source
+    pub(crate) fn connect(&mut self, source: u32, line: InterruptLine) -> bool {
+        let state = self.state.get_mut().unwrap();
+        match state.lines.get_mut(source as usize) {
+            Some(slot) if source != 0 => {
+                *slot = Some(line);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sample the lines of the sources and update the external interrupt bits of the harts
+    pub(crate) fn update(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.sample();
+        state.notify();
+    }
+
+    /// Whether any enabled source has a line connected, so an interrupt can still arrive while
+    /// all harts wait
+    pub(crate) fn is_armed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        (1..state.lines.len()).any(|source| {
+            state.lines[source].is_some()
+                && state.priority[source] != 0
+                && state.contexts.iter().any(|c| c.is_enabled(source))
+        })
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        let state = self.state.lock().unwrap();
+        for source in 1..state.priority.len() {
+            snapshot.write_u64(state.priority[source] as u64);
+            snapshot.write_bool(state.pending[source]);
+            snapshot.write_bool(state.claimed[source]);
+        }
+        for context in &state.contexts {
+            for word in &context.enable {
+                snapshot.write_u64(*word as u64);
+            }
+            snapshot.write_u64(context.threshold as u64);
+        }
+    }
+
+    /// Pending external interrupts are restored with the harts' mip
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        let state = self.state.get_mut().unwrap();
+        for source in 1..state.priority.len() {
+            state.priority[source] = snapshot.read_u64()? as u32 & PRIORITY_MASK;
+            state.pending[source] = snapshot.read_bool()?;
+            state.claimed[source] = snapshot.read_bool()?;
+        }
+        for context in state.contexts.iter_mut() {
+            for word in context.enable.iter_mut() {
+                *word = snapshot.read_u64()? as u32;
+            }
+            context.threshold = snapshot.read_u64()? as u32 & PRIORITY_MASK;
+        }
+        Ok(())
+    }
+}
+
+impl PlicState {
+    fn sample(&mut self) {
+        for source in 1..self.lines.len() {
+            if let Some(line) = &self.lines[source] {
+                if !self.claimed[source] {
+                    self.pending[source] = line.is_raised();
+                }
+            }
+        }
+    }
+
+    /// The highest priority pending source enabled for `context`, ties go to the lowest source
+    fn best(&self, context: usize) -> Option<usize> {
+        let context = &self.contexts[context];
+        (1..self.pending.len())
+            .filter(|s| self.pending[*s] && self.priority[*s] != 0 && context.is_enabled(*s))
+            .fold(None, |best: Option<usize>, s| match best {
+                Some(b) if self.priority[b] >= self.priority[s] => Some(b),
+                _ => Some(s),
+            })
+    }
+
+    fn notify(&self) {
+        for (i, context) in self.contexts.iter().enumerate() {
+            let Some(mip) = &context.mip else {
+                continue;
+            };
+            let raised = self
+                .best(i)
+                .is_some_and(|s| self.priority[s] > context.threshold);
+            let mut mip = mip.lock().unwrap();
+            if raised {
+                *mip |= context.interrupt;
+            } else {
+                *mip &= !context.interrupt;
+            }
+        }
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        let Some(source) = self.best(context) else {
+            return 0;
+        };
+        self.pending[source] = false;
+        self.claimed[source] = true;
+        source as u32
+    }
+
+    fn complete(&mut self, context: usize, source: u32) {
+        let source = source as usize;
+        // Completions of sources the context has not enabled are ignored
+        if source < self.claimed.len() && self.contexts[context].is_enabled(source) {
+            self.claimed[source] = false;
+            self.sample();
+        }
+    }
+
+    /// Reading a claim register claims the interrupt it shows
+    fn read(&mut self, offset: u64) -> u32 {
+        match self.claim_register(offset) {
+            Some(context) => self.claim(context),
+            None => self.peek(offset),
+        }
+    }
+
+    /// The context whose claim register is at `offset`
+    fn claim_register(&self, offset: u64) -> Option<usize> {
+        let offset = offset.checked_sub(CONTEXT_BASE)?;
+        let context = (offset / CONTEXT_STRIDE) as usize;
+        (offset % CONTEXT_STRIDE == 4 && context < self.contexts.len()).then_some(context)
+    }
+
+    /// The value a read returns without claiming anything, reserved and missing registers read
+    /// as 0
+    fn peek(&self, offset: u64) -> u32 {
+        match offset {
+            _ if offset < PENDING_BASE => self
+                .priority
+                .get((offset / 4) as usize)
+                .copied()
+                .unwrap_or(0),
+            _ if offset < ENABLE_BASE => {
+                let word = ((offset - PENDING_BASE) / 4) as usize;
+                self.pending
+                    .iter()
+                    .enumerate()
+                    .skip(word * 32)
+                    .take(32)
+                    .filter(|(_, pending)| **pending)
+                    .fold(0, |bits, (s, _)| bits | 1 << (s % 32))
+            }
+            _ if offset < CONTEXT_BASE => {
+                let offset = offset - ENABLE_BASE;
+                self.contexts
+                    .get((offset / ENABLE_STRIDE) as usize)
+                    .and_then(|c| c.enable.get((offset % ENABLE_STRIDE / 4) as usize))
+                    .copied()
+                    .unwrap_or(0)
+            }
+            _ => {
+                let offset = offset - CONTEXT_BASE;
+                let context = (offset / CONTEXT_STRIDE) as usize;
+                if context >= self.contexts.len() {
+                    return 0;
+                }
+                match offset % CONTEXT_STRIDE {
+                    0 => self.contexts[context].threshold,
+                    4 => self.best(context).map_or(0, |s| s as u32),
+                    _ => 0,
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u64, value: u32) {
+        match offset {
+            _ if offset < PENDING_BASE => {
+                if let Some(priority) = self.priority.get_mut((offset / 4) as usize) {
+                    *priority = value & PRIORITY_MASK;
+                }
+            }
+            // The pending bits are read only
+            _ if offset < ENABLE_BASE => {}
+            _ if offset < CONTEXT_BASE => {
+                let offset = offset - ENABLE_BASE;
+                let sources = self.priority.len();
+                let Some(context) = self.contexts.get_mut((offset / ENABLE_STRIDE) as usize) else {
+                    return;
+                };
+                let word = (offset % ENABLE_STRIDE / 4) as usize;
+                if let Some(enable) = context.enable.get_mut(word) {
+                    // Source 0 and sources past the last one are hardwired to 0
+                    let valid = (0..32)
+                        .map(|bit| word * 32 + bit)
+                        .filter(|s| *s != 0 && *s < sources)
+                        .fold(0u32, |mask, s| mask | 1 << (s % 32));
+                    *enable = value & valid;
+                }
+            }
+            _ => {
+                let offset = offset - CONTEXT_BASE;
+                let context = (offset / CONTEXT_STRIDE) as usize;
+                if context >= self.contexts.len() {
+                    return;
+                }
+                match offset % CONTEXT_STRIDE {
+                    0 => self.contexts[context].threshold = value & PRIORITY_MASK,
+                    4 => self.complete(context, value),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl Context {
+    fn is_enabled(&self, source: usize) -> bool {
+        self.enable
+            .get(source / 32)
+            .is_some_and(|w| w & (1 << (source % 32)) != 0)
+    }
+}
+
+impl MemoryBuffer for Plic {
+    fn size(&self) -> u64 {
+        CONTEXT_BASE + self.state.lock().unwrap().contexts.len() as u64 * CONTEXT_STRIDE
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !bytes.len().is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedWrite(addr.into()));
+        }
+        let state = self.state.get_mut().unwrap();
+        for (offset, word) in (addr..).step_by(4).zip(bytes.chunks_exact(4)) {
+            state.write(offset, u32::from_le_bytes(word.try_into().unwrap()));
+        }
+        state.notify();
+        Ok(())
+    }
+
+    fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !size.is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedRead(addr.into()));
+        }
+        let mut state = self.state.lock().unwrap();
+        let mut bytes = Vec::with_capacity(size);
+        for offset in (addr..addr + size as u64).step_by(4) {
+            bytes.extend(state.read(offset).to_le_bytes());
+        }
+        // A claim lowers the interrupt
+        state.notify();
+        Ok(bytes)
+    }
+
+    fn peek_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        let addr = u64::from(addr);
+        if addr % 4 != 0 || !size.is_multiple_of(4) {
+            return Err(MemoryBufferError::UnalignedRead(addr.into()));
+        }
+        let state = self.state.lock().unwrap();
+        Ok((addr..addr + size as u64)
+            .step_by(4)
+            .flat_map(|offset| state.peek(offset).to_le_bytes())
+            .collect())
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{
+    memory::memory_buffer::{MemoryBuffer, MemoryBufferError},
+    Address,
+};
+
+const FINISHER_PASS: u16 = 0x5555;
+const FINISHER_FAIL: u16 = 0x3333;
+const FINISHER_RESET: u16 = 0x7777;
+
+/// What the guest asked for when writing to the shutdown device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownRequest {
+    /// Power off with a successful exit status.
+    Pass,
+    /// Power off with the given failure code.
+    Fail(u16),
+    /// Reset the machine, it is up to the embedder to act on this.
+    Reset,
+}
+
+/// A shutdown device compatible with the sifive test finisher (as found in qemu's virt machine),
+/// a 32 bit write of `0x5555` powers off, `0x3333 | (code << 16)` powers off with a failure code
+/// and `0x7777` requests a reset. Other values are ignored.
+pub(super) struct ShutdownController {
+    request: Option<ShutdownRequest>,
+}
+
+impl ShutdownController {
+    pub(super) fn new() -> Self {
+        Self { request: None }
+    }
+
+    pub(super) fn take_request(&mut self) -> Option<ShutdownRequest> {
+        self.request.take()
+    }
+}
+
+impl MemoryBuffer for ShutdownController {
+    fn size(&self) -> u64 {
+        4
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError> {
+        if <Address as Into<u64>>::into(addr) != 0 {
+            return Err(MemoryBufferError::UnalignedWrite(addr));
+        }
+        let mut num_bytes = [0u8; 4];
+        let len = bytes.len().min(4);
+        num_bytes[0..len].copy_from_slice(&bytes[0..len]);
+        let value = u32::from_le_bytes(num_bytes);
+
+        self.request = match value as u16 {
+            FINISHER_PASS => Some(ShutdownRequest::Pass),
+            FINISHER_FAIL => Some(ShutdownRequest::Fail((value >> 16) as u16)),
+            FINISHER_RESET => Some(ShutdownRequest::Reset),
+            _ => self.request,
+        };
+        Ok(())
+    }
+
+    fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
+        Ok(vec![0u8; size])
+    }
+}
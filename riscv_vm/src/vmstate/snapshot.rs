@@ -0,0 +1,138 @@
+//! The snapshot format is a magic, a version, a block describing the machine configuration and
+//! then the state of the harts, memory, timer and devices in that order. Integers are stored as
+//! LEB128 varints, which keeps mostly empty csrs and registers small, and main memory only
+//! stores pages that are not all zero.
+
+use std::io;
+
+use crate::Address;
+
+pub(super) const MAGIC: &[u8; 8] = b"RVVMSNAP";
+pub(super) const VERSION: u64 = 3;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The data is not a snapshot
+    InvalidMagic,
+    UnsupportedVersion(u64),
+    /// The snapshot was taken of a vm with a different number of harts, memory size, settings
+    /// or devices.
+    ConfigMismatch,
+    /// The snapshot ended early or contained a value that can not be restored
+    Corrupt,
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Serializes state into a snapshot, passed to devices through
+/// [`DeviceObject::save()`](crate::devices::DeviceObject::save).
+#[derive(Default)]
+pub struct SnapshotWriter {
+    buf: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn write_u64(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_address(&mut self, value: Address) {
+        self.write_u64(value.into());
+    }
+
+    pub(super) fn write_raw(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Write a length prefixed byte string
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Deserializes state written by a [`SnapshotWriter`], passed to devices through
+/// [`DeviceObject::restore()`](crate::devices::DeviceObject::restore).
+pub struct SnapshotReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        let (byte, rest) = self.buf.split_first().ok_or(SnapshotError::Corrupt)?;
+        self.buf = rest;
+        Ok(*byte)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Corrupt),
+        }
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SnapshotError::Corrupt)
+    }
+
+    pub fn read_address(&mut self) -> Result<Address, SnapshotError> {
+        Ok(self.read_u64()?.into())
+    }
+
+    /// Read a length prefixed byte string
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.read_u64()? as usize;
+        if len > self.buf.len() {
+            return Err(SnapshotError::Corrupt);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
@@ -10,59 +10,82 @@ use crate::{
     Address,
 };
 
-use super::VMState;
+use super::{timer::TimerLayout, VMState};
 
+/// The pending software interrupts of one privilege mode, bit 0 of the register of a hart is its
+/// pending bit. The registers of an ACLINT SSWI can only set the bit and read as 0.
 pub struct SwiController {
     mode: PrivilegeMode,
     interrupts: IntMap<usize, Rc<Mutex<BitFlags<InterruptInternal>>>>,
+    /// The size of the register of each hart
+    stride: u64,
+    set_only: bool,
     // hart_count: usize,
 }
 
 impl SwiController {
-    pub(super) fn new(harts: &Vec<Hart>, mode: PrivilegeMode) -> Self {
+    pub(super) fn new(harts: &[Hart], mode: PrivilegeMode, layout: TimerLayout) -> Self {
         let interrupts = harts
             .iter()
             .map(|h| (h.get_hart_id() as usize, h.get_mip_ref()))
             .collect::<IntMap<usize, Rc<Mutex<BitFlags<InterruptInternal>>>>>();
 
-        Self { mode, interrupts }
+        let legacy = layout == TimerLayout::Legacy;
+        Self {
+            mode,
+            interrupts,
+            stride: if legacy { 8 } else { 4 },
+            set_only: !legacy && mode == PrivilegeMode::Supervisor,
+        }
+    }
+
+    fn interrupt(&self) -> InterruptInternal {
+        match self.mode {
+            PrivilegeMode::User => unreachable!(),
+            PrivilegeMode::Supervisor => InterruptInternal::SupervisorSoftware,
+            PrivilegeMode::Machine => InterruptInternal::MachineSoftware,
+        }
     }
 }
 
 impl MemoryBuffer for SwiController {
     fn size(&self) -> u64 {
         // (self.hart_count as u64) * 8
-        (self.interrupts.len() as u64) * 8
+        if self.stride == 8 {
+            (self.interrupts.len() as u64) * 8
+        } else {
+            // The MSWI and SSWI of an ACLINT take 16 KiB
+            0x4000
+        }
     }
 
     fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError> {
-        dbg!(bytes);
-        dbg!(addr);
         let addr = <Address as Into<u64>>::into(addr);
-        if addr % 8 != 0 {
+        let offset = addr % self.stride;
+        if offset + bytes.len() as u64 > self.stride {
             return Err(MemoryBufferError::UnalignedWrite(addr.into()));
         }
-        let hartid = addr / 8;
+        let hartid = addr / self.stride;
 
-        let bits = self
-            .interrupts
-            .get(&(hartid as usize))
-            .ok_or(MemoryBufferError::OutOfBoundsWrite(addr.into()))?;
+        let Some(bits) = self.interrupts.get(&(hartid as usize)) else {
+            // Registers past the last hart of an ACLINT device are reserved
+            if self.stride == 8 {
+                return Err(MemoryBufferError::OutOfBoundsWrite(addr.into()));
+            }
+            return Ok(());
+        };
+
+        // Only the lowest byte holds the pending bit
+        if offset != 0 {
+            return Ok(());
+        }
 
         let mut bits = bits.lock().unwrap();
 
         if bytes[0] % 2 == 1 {
-            match self.mode {
-                PrivilegeMode::User => unreachable!(),
-                PrivilegeMode::Supervisor => *bits |= InterruptInternal::SupervisorSoftware,
-                PrivilegeMode::Machine => *bits |= InterruptInternal::MachineSoftware,
-            }
-        } else {
-            match self.mode {
-                PrivilegeMode::User => unreachable!(),
-                PrivilegeMode::Supervisor => *bits &= !InterruptInternal::SupervisorSoftware,
-                PrivilegeMode::Machine => *bits &= !InterruptInternal::MachineSoftware,
-            }
+            *bits |= self.interrupt();
+        } else if !self.set_only {
+            *bits &= !self.interrupt();
         }
 
         Ok(())
@@ -70,24 +93,23 @@ impl MemoryBuffer for SwiController {
 
     fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
         let addr = <Address as Into<u64>>::into(addr);
-        if addr % 8 != 0 {
+        let offset = addr % self.stride;
+        if offset + size as u64 > self.stride {
             return Err(MemoryBufferError::UnalignedWrite(addr.into()));
         }
-        let hartid = addr / 8;
-
-        let bits = self
-            .interrupts
-            .get(&(hartid as usize))
-            .ok_or(MemoryBufferError::OutOfBoundsWrite(addr.into()))?;
-
-        let bits = bits.lock().unwrap();
+        let hartid = addr / self.stride;
 
         let mut bytes = vec![0u8; size];
-        bytes[0] = match self.mode {
-            PrivilegeMode::User => unreachable!(),
-            PrivilegeMode::Supervisor => bits.contains(InterruptInternal::SupervisorSoftware),
-            PrivilegeMode::Machine => bits.contains(InterruptInternal::MachineSoftware),
-        } as u8;
+        let Some(bits) = self.interrupts.get(&(hartid as usize)) else {
+            if self.stride == 8 {
+                return Err(MemoryBufferError::OutOfBoundsWrite(addr.into()));
+            }
+            return Ok(bytes);
+        };
+
+        if offset == 0 && !self.set_only {
+            bytes[0] = bits.lock().unwrap().contains(self.interrupt()) as u8;
+        }
 
         Ok(bytes)
     }
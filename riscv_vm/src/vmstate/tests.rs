@@ -0,0 +1,1291 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    net::{Ipv4Addr, TcpStream},
+    rc::Rc,
+};
+
+use elf_load::{
+    data::{ObjectType, ProgramType, SymbolBinding, SymbolType},
+    dwarf::{frame::CallFrameInfo, DwarfSections},
+    symbol::{Symbol, SymbolTable},
+    Elf,
+};
+
+use crate::{
+    devices::{
+        char_backend::{ChannelBackend, SocketBackend},
+        handled_device::HandledDevice,
+        ns16550a::Ns16550a,
+        Device, DeviceError, DeviceInitError, DeviceMemHandle, DeviceObject,
+    },
+    hart::privilege::PrivilegeMode,
+    memory::pmp::AccessMode,
+    registers::IntRegister,
+    trace::{LockstepError, Mismatch, TraceEvent},
+    trap::Exception,
+    Address, CsrAddress, KB,
+};
+
+use super::{
+    FoundBy, ReverseError, ShutdownRequest, SnapshotError, SnapshotReader, StopReason, TimerLayout,
+    UnwindInfo, VMError, VMInitError, VMSettings, VMState, VMStateBuilder, VirtualTime, WatchKind,
+};
+
+fn vm_with(settings: VMSettings, program: &[u32]) -> VMState {
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::new(settings)
+        .set_hart_count(1)
+        .build()
+        .unwrap();
+    for (i, inst) in program.iter().enumerate() {
+        vm.write_memory(
+            0,
+            &inst.to_le_bytes(),
+            (0x80000000u64 + i as u64 * 4).into(),
+        )
+        .unwrap();
+    }
+    vm
+}
+
+fn addr(a: u64) -> Address {
+    a.into()
+}
+
+/// A device that fails to update after `ok_updates` updates, and to be restored from a
+/// snapshot or replayed if `fail_restore` or `fail_replay` is set
+#[derive(Debug)]
+struct FailingDevice {
+    ok_updates: u64,
+    fail_restore: bool,
+    fail_replay: bool,
+}
+
+impl DeviceObject for FailingDevice {
+    fn init(&mut self, mem: DeviceMemHandle) -> Result<(), DeviceInitError> {
+        Ok(())
+    }
+
+    fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        if self.fail_restore {
+            return Err(SnapshotError::Corrupt);
+        }
+        Ok(())
+    }
+}
+
+impl HandledDevice for FailingDevice {
+    fn update(&mut self) -> Result<(), DeviceError> {
+        match self.ok_updates.checked_sub(1) {
+            Some(left) => {
+                self.ok_updates = left;
+                Ok(())
+            }
+            None => Err(io::Error::other("update failed").into()),
+        }
+    }
+
+    fn replay(&mut self, input: &[u8]) -> Result<(), DeviceError> {
+        if self.fail_replay {
+            return Err(io::Error::other("replay failed").into());
+        }
+        self.update()
+    }
+}
+
+#[test]
+fn run_for_budget() {
+    // jal x0, 0
+    let mut vm = vm_with(VMSettings::default(), &[0x0000006f]);
+    let stop = vm.run_for(100);
+    assert!(matches!(stop, StopReason::BudgetExhausted { hart: 0, .. }));
+    assert_eq!(stop.pc(), addr(0x80000000));
+}
+
+#[test]
+fn run_until_breakpoint() {
+    // addi a0, a0, 1 ; addi a0, a0, 1 ; jal x0, -8
+    let mut vm = vm_with(VMSettings::default(), &[0x00150513, 0x00150513, 0xff9ff06f]);
+    let stop = vm.run_until(Some(100), |h| h.get_pc() == addr(0x80000008));
+    assert!(matches!(stop, StopReason::Breakpoint { hart: 0, .. }));
+    assert_eq!(stop.pc(), addr(0x80000008));
+}
+
+#[test]
+fn run_halts_on_ebreak() {
+    // addi a0, a0, 1 ; ebreak
+    let mut vm = vm_with(
+        VMSettings {
+            halt_on_ebreak: true,
+            ..Default::default()
+        },
+        &[0x00150513, 0x00100073],
+    );
+    let stop = vm.run_for(100);
+    assert!(matches!(stop, StopReason::EBreak { hart: 0, .. }));
+    assert_eq!(stop.pc(), addr(0x80000004));
+}
+
+#[test]
+fn run_until_shutdown() {
+    // lui t0, 0x100 ; lui t1, 0x5 ; addi t1, t1, 0x555 ; sw t1, 0(t0) ; jal x0, 0
+    let mut vm = vm_with(
+        VMSettings {
+            shutdown_enable: true,
+            ..Default::default()
+        },
+        &[0x001002b7, 0x00005337, 0x55530313, 0x0062a023, 0x0000006f],
+    );
+    let stop = vm.run_for(100);
+    assert!(matches!(
+        stop,
+        StopReason::Shutdown {
+            hart: 0,
+            request: ShutdownRequest::Pass,
+            ..
+        }
+    ));
+    assert_eq!(stop.pc(), addr(0x8000000c));
+
+    let mut vm = vm_with(
+        VMSettings {
+            shutdown_enable: true,
+            ..Default::default()
+        },
+        &[0x001002b7, 0x00005337, 0x55530313, 0x0062a023, 0x0000006f],
+    );
+    assert_eq!(vm.run().unwrap(), ShutdownRequest::Pass);
+    assert_eq!(vm.get_hart(0).unwrap().get_pc(), addr(0x80000010));
+}
+
+#[test]
+fn run_until_idle() {
+    // wfi
+    let mut vm = vm_with(VMSettings::default(), &[0x10500073]);
+    let stop = vm.run_for(100);
+    assert!(matches!(stop, StopReason::AllHartsIdle { hart: 0, .. }));
+}
+
+#[test]
+fn run_stops_on_device_error() {
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .add_sync_device_with(
+            addr(0x10000000),
+            FailingDevice {
+                ok_updates: 5,
+                fail_restore: false,
+                fail_replay: false,
+            },
+        )
+        .build()
+        .unwrap();
+    // jal x0, 0
+    vm.write_memory(0, &0x0000006fu32.to_le_bytes(), addr(0x80000000))
+        .unwrap();
+
+    let stop = vm.run_for(100);
+    assert!(matches!(
+        stop,
+        StopReason::Error {
+            hart: 0,
+            error: VMError::DeviceError(DeviceError::UpdateError(_)),
+            ..
+        }
+    ));
+    assert_eq!(vm.stats().steps, 6);
+    assert!(matches!(vm.step(false), Err(VMError::DeviceError(_))));
+}
+
+#[test]
+fn run_until_watchpoint() {
+    // auipc t0, 0 ; addi a0, a0, 1 ; sw a0, 0x100(t0) ; jal x0, -8
+    let mut vm = vm_with(
+        VMSettings::default(),
+        &[0x00000297, 0x00150513, 0x10a2a023, 0xff9ff06f],
+    );
+    vm.add_watchpoint(addr(0x80000100), WatchKind::Read);
+    assert!(matches!(
+        vm.run_for(100),
+        StopReason::BudgetExhausted { .. }
+    ));
+
+    vm.add_watchpoint(addr(0x80000100), WatchKind::Write);
+    let stop = vm.run_for(100);
+    assert!(matches!(
+        stop,
+        StopReason::Watchpoint {
+            hart: 0,
+            access: WatchKind::Write,
+            ..
+        }
+    ));
+    assert_eq!(stop.pc(), addr(0x80000008));
+
+    vm.remove_watchpoint(addr(0x80000100));
+    assert!(vm.get_watchpoints().is_empty());
+    assert!(matches!(
+        vm.run_for(100),
+        StopReason::BudgetExhausted { .. }
+    ));
+}
+
+#[test]
+fn step_takes_watchpoint() {
+    // auipc t0, 0 ; addi a0, a0, 1 ; sw a0, 0x100(t0) ; jal x0, -8
+    let mut vm = vm_with(
+        VMSettings::default(),
+        &[0x00000297, 0x00150513, 0x10a2a023, 0xff9ff06f],
+    );
+    vm.add_watchpoint(addr(0x80000100), WatchKind::Write);
+    for _ in 0..2 {
+        vm.step(false).unwrap();
+        assert!(vm.step_watch_hit().is_none());
+    }
+    vm.step(false).unwrap();
+    let hit = vm.step_watch_hit().unwrap();
+    assert!(matches!(hit, StopReason::Watchpoint { hart: 0, .. }));
+    assert_eq!(hit.pc(), addr(0x80000008));
+
+    // The hit was taken by the step, it is not reported again
+    vm.step_hart(0, false).unwrap();
+    assert!(vm.step_watch_hit().is_none());
+    let stop = vm.run_until(Some(100), |h| h.get_pc() == addr(0x80000008));
+    assert!(matches!(stop, StopReason::Breakpoint { .. }));
+}
+
+#[test]
+fn store_log() {
+    // auipc t0, 0 ; addi a0, a0, 1 ; sw a0, 0x100(t0) ; jal x0, -8
+    let mut vm = vm_with(
+        VMSettings::default(),
+        &[0x00000297, 0x00150513, 0x10a2a023, 0xff9ff06f],
+    );
+    vm.enable_store_log(2);
+    vm.store_log_mut().unwrap().add_watch(addr(0x80000102), 3);
+    let stop = vm.run_for(100);
+    assert!(matches!(
+        stop,
+        StopReason::Watchpoint {
+            hart: 0,
+            access: WatchKind::Write,
+            ..
+        }
+    ));
+    assert_eq!(stop.pc(), addr(0x80000008));
+
+    // Writes by the debugger are not logged
+    vm.write_memory(0, &[0xff], addr(0x80000100)).unwrap();
+
+    let log = vm.store_log().unwrap();
+    assert_eq!(log.dropped(), 1);
+    assert!(log.get_watches().is_empty());
+    let stores: Vec<_> = log.writes_to(addr(0x80000102), 1).collect();
+    assert_eq!(stores.len(), 2);
+    assert_eq!(stores[0].instret, 5);
+    assert_eq!(stores[1].instret, 8);
+    assert_eq!(stores[1].pc, addr(0x80000008));
+    assert_eq!(stores[1].phys, addr(0x80000100));
+    assert_eq!(stores[1].size, 4);
+    assert_eq!((stores[1].old, stores[1].new), (2, 3));
+    assert_eq!(log.writes_to(addr(0x80000104), 4).count(), 0);
+    assert_eq!(log.writes_to(addr(0x80000000), u64::MAX).count(), 2);
+    assert_eq!(
+        log.writes_to(addr(0xffffffff00000000), 0x200000000).count(),
+        0
+    );
+}
+
+#[test]
+fn store_watch_without_log_capacity() {
+    // auipc t0, 0 ; addi a0, a0, 1 ; sw a0, 0x100(t0) ; jal x0, -8
+    let mut vm = vm_with(
+        VMSettings::default(),
+        &[0x00000297, 0x00150513, 0x10a2a023, 0xff9ff06f],
+    );
+    vm.enable_store_log(0);
+    vm.store_log_mut().unwrap().add_watch(addr(0x80000100), 2);
+    let stop = vm.run_for(100);
+    assert!(matches!(stop, StopReason::Watchpoint { hart: 0, .. }));
+    assert_eq!(vm.get_hart(0).unwrap().get_int_reg(IntRegister::X10), 2);
+    assert_eq!(vm.store_log().unwrap().stores().count(), 0);
+}
+
+#[test]
+fn frozen_hart_not_stepped() {
+    // nop ; jal x0, 0
+    let mut vm = vm_with(VMSettings::default(), &[0x00000013, 0x0000006f]);
+    vm.set_hart_frozen(0, true);
+    vm.step(false).unwrap();
+    assert_eq!(vm.get_hart(0).unwrap().get_pc(), addr(0x80000000));
+
+    vm.set_hart_frozen(0, false);
+    vm.step(false).unwrap();
+    assert_eq!(vm.get_hart(0).unwrap().get_pc(), addr(0x80000004));
+}
+
+#[test]
+fn snapshot_roundtrip() {
+    // addi a0, a0, 1 ; csrw mscratch, a0 ; addi a0, a0, 1 ; jal x0, 0
+    let mut vm = vm_with(
+        VMSettings::default(),
+        &[0x00150513, 0x34051073, 0x00150513, 0x0000006f],
+    );
+    vm.step(false).unwrap();
+    vm.step(false).unwrap();
+    let snapshot = vm.snapshot();
+
+    vm.step(false).unwrap();
+    vm.write_phys(&[0xAA; 4], addr(0x80000800)).unwrap();
+    vm.get_hart_mut(0)
+        .unwrap()
+        .get_csr_mut()
+        .pmp
+        .write_addr_rv64(0, 0x1234);
+
+    vm.restore(&snapshot).unwrap();
+    let hart = vm.get_hart(0).unwrap();
+    assert_eq!(hart.get_pc(), addr(0x80000008));
+    assert_eq!(hart.get_int_reg(IntRegister::X10), 1);
+    assert_eq!(hart.get_csr().get_csr(CsrAddress::from(0x340u16)), 1);
+    assert_eq!(hart.get_csr().get_csr(CsrAddress::from(0x3B0u16)), 0);
+    assert_eq!(vm.read_phys(addr(0x80000800), 4).unwrap(), [0; 4]);
+    assert_eq!(
+        vm.read_phys(addr(0x80000000), 4).unwrap(),
+        0x00150513u32.to_le_bytes()
+    );
+}
+
+#[test]
+fn snapshot_config_mismatch() {
+    let vm = vm_with(VMSettings::default(), &[0x0000006f]);
+    let mut other = VMStateBuilder::<{ 4 * KB }>::new(VMSettings::default())
+        .set_hart_count(2)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        other.restore(&vm.snapshot()),
+        Err(SnapshotError::ConfigMismatch)
+    ));
+    assert!(matches!(
+        other.restore(b"not a snapshot"),
+        Err(SnapshotError::InvalidMagic)
+    ));
+}
+
+#[test]
+fn reverse_step() {
+    // addi a0, a0, 1 ; jal x0, -4
+    let mut vm = vm_with(VMSettings::default(), &[0x00150513, 0xffdff06f]);
+    vm.enable_history(3, 4);
+    for _ in 0..20 {
+        vm.step(false).unwrap();
+    }
+    assert_eq!(vm.get_hart(0).unwrap().get_int_reg(IntRegister::X10), 10);
+
+    vm.reverse_step(0, 5).unwrap();
+    let hart = vm.get_hart(0).unwrap();
+    assert_eq!(hart.get_int_reg(IntRegister::X10), 8);
+    assert_eq!(hart.get_pc(), addr(0x80000004));
+
+    // Only four checkpoints three steps apart are kept
+    assert!(matches!(
+        vm.reverse_step(0, 100),
+        Err(ReverseError::HistoryStart)
+    ));
+    assert!(vm.get_hart(0).unwrap().get_int_reg(IntRegister::X10) > 0);
+
+    // Going forward again records new history
+    vm.step(false).unwrap();
+    vm.reverse_step(0, 1).unwrap();
+}
+
+#[test]
+fn reverse_step_restore_error() {
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .add_sync_device_with(
+            addr(0x10000000),
+            FailingDevice {
+                ok_updates: u64::MAX,
+                fail_restore: true,
+                fail_replay: false,
+            },
+        )
+        .build()
+        .unwrap();
+    // jal x0, 0
+    vm.write_memory(0, &0x0000006fu32.to_le_bytes(), addr(0x80000000))
+        .unwrap();
+    vm.enable_history(3, 4);
+    for _ in 0..10 {
+        vm.step(false).unwrap();
+    }
+
+    assert!(matches!(
+        vm.reverse_step(0, 2),
+        Err(ReverseError::Snapshot(SnapshotError::Corrupt))
+    ));
+    assert!(!vm.history_enabled());
+    assert!(matches!(
+        vm.reverse_continue(|_| true),
+        Err(ReverseError::HistoryDisabled)
+    ));
+    vm.step(false).unwrap();
+}
+
+#[test]
+fn reverse_continue_replay_error() {
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .add_sync_device_with(
+            addr(0x10000000),
+            FailingDevice {
+                ok_updates: u64::MAX,
+                fail_restore: false,
+                fail_replay: true,
+            },
+        )
+        .build()
+        .unwrap();
+    // jal x0, 0
+    vm.write_memory(0, &0x0000006fu32.to_le_bytes(), addr(0x80000000))
+        .unwrap();
+    vm.enable_history(3, 4);
+    for _ in 0..10 {
+        vm.step(false).unwrap();
+    }
+
+    assert!(matches!(
+        vm.reverse_continue(|_| true),
+        Err(ReverseError::DeviceError(DeviceError::UpdateError(_)))
+    ));
+    assert!(!vm.history_enabled());
+    vm.step(false).unwrap();
+}
+
+#[test]
+fn reverse_continue() {
+    // auipc t0, 0 ; addi a0, a0, 1 ; sw a0, 0x100(t0) ; addi a1, a1, 1 ; jal x0, -12
+    let mut vm = vm_with(
+        VMSettings::default(),
+        &[0x00000297, 0x00150513, 0x10a2a023, 0x00158593, 0xff5ff06f],
+    );
+    vm.enable_history(4, 16);
+    vm.run_for(30);
+
+    let stop = vm
+        .reverse_continue(|h| h.get_pc() == addr(0x80000004))
+        .unwrap();
+    assert!(matches!(stop, StopReason::Breakpoint { hart: 0, .. }));
+    let a0 = vm.get_hart(0).unwrap().get_int_reg(IntRegister::X10);
+
+    vm.add_watchpoint(addr(0x80000100), WatchKind::Write);
+    let stop = vm.reverse_continue(|_| false).unwrap();
+    assert!(matches!(stop, StopReason::Watchpoint { pc, .. } if pc == addr(0x80000008)));
+    assert_eq!(
+        vm.read_phys(addr(0x80000100), 4).unwrap(),
+        (a0 as u32).to_le_bytes()
+    );
+
+    vm.remove_watchpoint(addr(0x80000100));
+    assert!(matches!(
+        vm.reverse_continue(|_| false),
+        Err(ReverseError::HistoryStart)
+    ));
+    assert_eq!(vm.get_hart(0).unwrap().get_pc(), addr(0x80000000));
+}
+
+#[test]
+fn virtual_time_is_deterministic() {
+    let mut program = [0u32; 18];
+    program[..10].copy_from_slice(&[
+        0x00000297, // auipc t0, 0
+        0x04028293, // addi t0, t0, 0x40
+        0x30529073, // csrw mtvec, t0
+        0x08000313, // li t1, 0x80
+        0x30431073, // csrw mie, t1
+        0x30046073, // csrsi mstatus, 8
+        0x000013b7, // lui t2, 0x1
+        0x03200e13, // li t3, 50
+        0x01c3b423, // sd t3, 8(t2)
+        0x0000006f, // jal x0, 0
+    ]);
+    program[16] = 0xc0102573; // csrr a0, time
+    program[17] = 0x0000006f; // jal x0, 0
+
+    let run = || {
+        let mut vm = VMStateBuilder::<{ 4 * KB }>::new(VMSettings::default())
+            .set_hart_count(1)
+            .enable_virtual_time(VirtualTime {
+                timebase_freq: 10_000_000,
+                ticks: 1,
+                instructions: 2,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(vm.timebase_freq(), 10_000_000);
+        for (i, inst) in program.iter().enumerate() {
+            vm.write_phys(&inst.to_le_bytes(), addr(0x80000000 + i as u64 * 4))
+                .unwrap();
+        }
+        let mut steps = 0;
+        while vm.get_hart(0).unwrap().get_pc() != addr(0x80000044) {
+            vm.step(false).unwrap();
+            steps += 1;
+        }
+        vm.step(false).unwrap();
+        let time = vm.get_hart(0).unwrap().get_int_reg(IntRegister::X10);
+        // The time csr reads mtime as of the start of the step
+        assert_eq!(vm.time(), time as u64);
+        (steps, time)
+    };
+
+    let (steps, time) = run();
+    // Time passes at half a tick per step, so the interrupt is taken after about 100 steps
+    assert!((100..110).contains(&steps));
+    assert_eq!(time, (steps + 1) / 2);
+    assert_eq!(run(), (steps, time));
+
+    for (timebase_freq, instructions) in [(1_000_000, 0), (0, 1)] {
+        let zero = VMStateBuilder::<{ 4 * KB }>::new(VMSettings::default())
+            .enable_virtual_time(VirtualTime {
+                timebase_freq,
+                ticks: 1,
+                instructions,
+            })
+            .build();
+        assert!(matches!(zero, Err(VMInitError::InvalidVirtualTime)));
+    }
+}
+
+#[test]
+fn idle_sleeps_until_timer() {
+    let mut program = [0u32; 17];
+    program[..13].copy_from_slice(&[
+        0x00000297, // auipc t0, 0
+        0x04028293, // addi t0, t0, 0x40
+        0x30529073, // csrw mtvec, t0
+        0x08000313, // li t1, 0x80
+        0x30431073, // csrw mie, t1
+        0x30046073, // csrsi mstatus, 8
+        0x000013b7, // lui t2, 0x1
+        0x0003be03, // ld t3, 0(t2)
+        0x00005eb7, // lui t4, 0x5
+        0x01de0e33, // add t3, t3, t4
+        0x01c3b423, // sd t3, 8(t2)
+        0x10500073, // wfi
+        0x0000006f, // jal x0, 0
+    ]);
+    program[16] = 0x0000006f; // jal x0, 0
+    let mut vm = vm_with(VMSettings::default(), &program);
+
+    // The timer fires about 20ms after it is set, spinning until then would take thousands of
+    // steps
+    let stop = vm.run_until(Some(10_000), |h| h.get_pc() == addr(0x80000040));
+    assert!(matches!(stop, StopReason::Breakpoint { hart: 0, .. }));
+    let stats = vm.stats();
+    assert!(stats.steps < 100);
+    // Sleeps never end before their deadline, so the host slept for most of the 20ms, in at
+    // least two sleeps of at most 10ms
+    assert!(stats.idle_time.as_millis() >= 15);
+    assert!(stats.idle_sleeps >= 2);
+}
+
+#[test]
+fn idle_waker() {
+    // Arm the timer far in the future and wait: lui t2, 0x1 ; lui t3, 0x10000 ; sd t3, 8(t2) ; wfi
+    let mut vm = vm_with(
+        VMSettings::default(),
+        &[0x000013b7, 0x10000e37, 0x01c3b423, 0x10500073],
+    );
+    vm.run_for(4);
+    assert!(vm.get_hart(0).unwrap().is_waiting_for_interrupt());
+
+    let waker = vm.idle_waker();
+    let wake = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        waker.wake();
+    });
+    let stop = vm.run_for(100_000);
+    assert!(matches!(stop, StopReason::Woken { hart: 0, .. }));
+    wake.join().unwrap();
+}
+
+#[test]
+fn serial_input_wakes() {
+    let backend = SocketBackend::bind_tcp(0).unwrap();
+    let port = backend.port().unwrap();
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::new(VMSettings::default())
+        .set_hart_count(1)
+        .add_sync_device_with(addr(0x10000000), Ns16550a::new().with_backend(backend))
+        .build()
+        .unwrap();
+    // lui t2, 0x1 ; lui t3, 0x10000 ; sd t3, 8(t2) ; wfi
+    for (i, inst) in [0x000013b7u32, 0x10000e37, 0x01c3b423, 0x10500073]
+        .iter()
+        .enumerate()
+    {
+        vm.write_phys(&inst.to_le_bytes(), addr(0x80000000 + i as u64 * 4))
+            .unwrap();
+    }
+    vm.run_for(4);
+    assert!(vm.get_hart(0).unwrap().is_waiting_for_interrupt());
+
+    let client = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        client.write_all(b"a").unwrap();
+        client
+    });
+    // Without a wake this runs into the budget after sleeping for 10s
+    let stop = vm.run_for(1000);
+    assert!(matches!(stop, StopReason::Woken { hart: 0, .. }));
+    client.join().unwrap();
+}
+
+#[test]
+fn serial_input_replayed() {
+    let (backend, input, output) = ChannelBackend::new();
+    let mut vm = VMStateBuilder::<{ 4 * KB }>::new(VMSettings::default())
+        .set_hart_count(1)
+        .add_sync_device_with(addr(0x10000000), Ns16550a::new().with_backend(backend))
+        .build()
+        .unwrap();
+    // lui t0, 0x10000 ; lbu t1, 5(t0) ; andi t1, t1, 1 ; beqz t1, -8 ; lbu a0, 0(t0) ;
+    // sb a0, 0(t0) ; add a1, a1, a0 ; jal x0, -24
+    let program = [
+        0x100002b7u32,
+        0x0052c303,
+        0x00137313,
+        0xfe030ce3,
+        0x0002c503,
+        0x00a28023,
+        0x00a585b3,
+        0xfe9ff06f,
+    ];
+    for (i, inst) in program.iter().enumerate() {
+        vm.write_phys(&inst.to_le_bytes(), addr(0x80000000 + i as u64 * 4))
+            .unwrap();
+    }
+    vm.enable_history(100, 4);
+    input.send(b'a').unwrap();
+    input.send(b'b').unwrap();
+    vm.run_for(200);
+    assert_eq!(output.try_iter().collect::<Vec<_>>(), b"ab");
+
+    // Going back replays from before the input arrived, the echo is not sent again
+    vm.reverse_step(0, 180).unwrap();
+    let hart = vm.get_hart(0).unwrap();
+    assert_eq!(hart.get_int_reg(IntRegister::X11), (b'a' + b'b') as i64);
+    assert!(output.try_recv().is_err());
+}
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// auipc t0, 0 ; addi a0, a0, 1 ; sw a0, 0x100(t0) ; lw a1, 0x100(t0) ; csrw mscratch, a0 ;
+// ecall
+const TRACED_PROGRAM: [u32; 6] = [
+    0x00000297, 0x00150513, 0x10a2a023, 0x1002a583, 0x34051073, 0x00000073,
+];
+
+fn trace_of(steps: usize) -> String {
+    let mut vm = vm_with(VMSettings::default(), &TRACED_PROGRAM);
+    let out = SharedBuf::default();
+    vm.start_trace(out.clone());
+    for _ in 0..steps {
+        vm.step(false).unwrap();
+    }
+    vm.stop_trace().unwrap();
+    vm.step(false).unwrap();
+    String::from_utf8(out.0.take()).unwrap()
+}
+
+#[test]
+fn trace_commit_log() {
+    let trace = trace_of(6);
+    let expected = [
+        "core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000",
+        "core   0: 3 0x0000000080000004 (0x00150513) x10 0x0000000000000001",
+        "core   0: 3 0x0000000080000008 (0x10a2a023) mem 0x0000000080000100 0x00000001",
+        "core   0: 3 0x000000008000000c (0x1002a583) x11 0x0000000000000001 \
+         mem 0x0000000080000100",
+        "core   0: 3 0x0000000080000010 (0x34051073) c832_mscratch 0x0000000000000001",
+        "core   0: exception trap_machine_ecall, epc 0x0000000080000014, target 3",
+        "core   0:           tval 0x0000000000000000",
+    ];
+    assert_eq!(trace.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn lockstep_matches() {
+    let reference = trace_of(6);
+    let mut vm = vm_with(VMSettings::default(), &TRACED_PROGRAM);
+    assert_eq!(vm.lockstep(reference.as_bytes()).unwrap(), 6);
+    assert!(!vm.tracing());
+}
+
+#[test]
+fn lockstep_divergence() {
+    let reference = trace_of(6).replace("x11 0x0000000000000001", "x11 0x0000000000000002");
+    let mut vm = vm_with(VMSettings::default(), &TRACED_PROGRAM);
+    let Err(LockstepError::Diverged(divergence)) = vm.lockstep(reference.as_bytes()) else {
+        panic!("expected a divergence");
+    };
+    assert_eq!(divergence.mismatch, Mismatch::RegWrite);
+    assert_eq!(divergence.expected.as_ref().map(|(line, _)| *line), Some(4));
+    assert_eq!(divergence.context.len(), 3);
+    let TraceEvent::Commit(actual) = &divergence.actual else {
+        panic!("expected a commit");
+    };
+    assert_eq!(actual.pc, addr(0x8000000c));
+}
+
+#[test]
+fn exception_storm_stops_and_reports() {
+    // addi a0, a0, 1 ; lw a0, 0(x0), which faults into mtvec 0 and keeps faulting there
+    let mut vm = vm_with(
+        VMSettings {
+            exception_storm_limit: Some(10),
+            ..Default::default()
+        },
+        &[0x00150513, 0x00002503],
+    );
+    let path = std::env::temp_dir().join(format!("riscv_vm_crash_{}", std::process::id()));
+    vm.set_crash_report(Some(path.clone()));
+
+    let StopReason::Error { hart: 0, error, .. } = vm.run_for(100) else {
+        panic!("expected an error");
+    };
+    assert!(matches!(
+        error,
+        VMError::ExceptionStorm(Exception::InstructionAccessFault)
+    ));
+
+    let report = vm.crash_report(0, &error);
+    assert_eq!(report.harts[0].recent, [(addr(0x80000000), 0x00150513)]);
+    assert_eq!(report.harts[0].registers[10], 1);
+    assert!(report.harts[0].pc_memory.is_err());
+    assert_eq!(report.memory_map.len(), 2);
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(written, report.to_string());
+    assert!(written.contains("0x0000000080000000: addi"));
+    assert!(written.contains("mcause 0x0000000000000001"));
+
+    let zero = VMStateBuilder::<{ 4 * KB }>::new(VMSettings {
+        exception_storm_limit: Some(0),
+        ..Default::default()
+    })
+    .build();
+    assert!(matches!(zero, Err(VMInitError::InvalidExceptionStormLimit)));
+}
+
+/// `.debug_frame` for `outer` at 0x80000020..0x80000038, which keeps its frame pointer in s0,
+/// `middle` at 0x80000038..0x80000050, which does not, and the frameless `leaf` at
+/// 0x80000050..0x80000056
+const DEBUG_FRAME: &[u8] = &[
+    0x14, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x04, 0x00, 0x08, 0x00, 0x01, 0x78, 0x01, 0x0c,
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x20, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x42, 0x0e, 0x10, 0x44, 0x81, 0x01, 0x88, 0x02, 0x42, 0x0c, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+    0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x0e, 0x20, 0x44, 0x81, 0x01, 0x88, 0x02,
+    0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+    0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+fn unwind_info() -> UnwindInfo {
+    let symbol = |name: &str, value: u64, size| Symbol {
+        name: name.to_string(),
+        sym_type: SymbolType::Func,
+        binding: SymbolBinding::Global,
+        other: 0,
+        section: 1,
+        value: elf_load::Address(value),
+        size,
+    };
+    UnwindInfo {
+        symbols: SymbolTable::new(vec![
+            symbol("_start", 0x80000000, 0x20),
+            symbol("outer", 0x80000020, 0x18),
+            symbol("middle", 0x80000038, 0x18),
+            symbol("leaf", 0x80000050, 0x6),
+            symbol("handler", 0x80000058, 0x8),
+        ]),
+        frames: CallFrameInfo::parse(&DwarfSections {
+            debug_frame: DEBUG_FRAME,
+            ..Default::default()
+        })
+        .unwrap(),
+    }
+}
+
+#[test]
+fn backtrace() {
+    // `_start` called `outer`, which called `middle`, which called `leaf`, which trapped into
+    // `handler` with an ecall
+    let mut vm = vm_with(VMSettings::default(), &[]);
+    vm.set_unwind_info(unwind_info());
+    let mut write = |a: u64, value: u64| vm.write_memory(0, &value.to_le_bytes(), addr(a)).unwrap();
+    // The frame of `middle`, the return address and the s0 of `outer`
+    write(0x80000fb8, 0x80000030);
+    write(0x80000fb0, 0x80000fd0);
+    // The frame record of `outer`, the return address and the s0 of `_start`
+    write(0x80000fc8, 0x8000001e);
+    write(0x80000fc0, 0);
+
+    let hart = vm.get_hart_mut(0).unwrap();
+    hart.set_pc(addr(0x80000058));
+    hart.set_int_reg(IntRegister::X1, 0x80000048);
+    hart.set_int_reg(IntRegister::X2, 0x80000fa0);
+    hart.set_int_reg(IntRegister::X8, 0);
+    let csr = hart.get_csr_mut();
+    for (a, value) in [(0x305u16, 0x80000058), (0x341, 0x80000050)] {
+        csr.write_csr(CsrAddress::from(a), value, PrivilegeMode::Machine, false)
+            .unwrap();
+    }
+
+    let frames = vm.backtrace(0);
+    let found: Vec<_> = frames
+        .iter()
+        .map(|f| (u64::from(f.pc), f.symbol.clone().unwrap(), f.found_by))
+        .collect();
+    let symbol = |name: &str, offset| (name.to_string(), offset);
+    assert_eq!(
+        found,
+        [
+            (0x80000058, symbol("handler", 0), FoundBy::Pc),
+            (
+                0x80000050,
+                symbol("leaf", 0),
+                FoundBy::Trap(PrivilegeMode::Machine)
+            ),
+            (0x80000048, symbol("middle", 0x10), FoundBy::CallFrameInfo),
+            (0x80000030, symbol("outer", 0x10), FoundBy::CallFrameInfo),
+            (0x8000001e, symbol("_start", 0x1e), FoundBy::FramePointer),
+        ]
+    );
+    assert_eq!(frames[3].sp, Some(0x80000fc0));
+    assert_eq!(
+        frames[1].to_string(),
+        "0x0000000080000050 <leaf+0x0> (trapped to Machine mode)"
+    );
+}
+
+#[test]
+fn backtrace_without_unwind_info() {
+    // Without symbols or call frame information the frame pointer chain is still followed
+    let mut vm = vm_with(VMSettings::default(), &[]);
+    vm.write_memory(0, &0x80000100u64.to_le_bytes(), addr(0x80000fe8))
+        .unwrap();
+    vm.write_memory(0, &0u64.to_le_bytes(), addr(0x80000fe0))
+        .unwrap();
+    let hart = vm.get_hart_mut(0).unwrap();
+    hart.set_int_reg(IntRegister::X2, 0x80000fd0);
+    hart.set_int_reg(IntRegister::X8, 0x80000ff0);
+
+    let frames = vm.backtrace(0);
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].pc, addr(0x80000000));
+    assert_eq!(frames[1].pc, addr(0x80000100));
+    assert_eq!(frames[1].found_by, FoundBy::FramePointer);
+    assert!(frames[1].symbol.is_none());
+}
+
+#[test]
+fn core_file() {
+    // addi a0, a0, 1
+    let mut vm = vm_with(VMSettings::default(), &[0x00150513]);
+    vm.step(false).unwrap();
+
+    let elf = Elf::from_bytes(vm.core_file(false).to_bytes()).unwrap();
+    assert_eq!(elf.header.obj_type, ObjectType::Core);
+    let [notes, ram] = &elf.program_headers[..] else {
+        panic!("expected a note and a ram segment");
+    };
+    assert_eq!(notes.program_type, ProgramType::Note);
+    assert_eq!(ram.program_type, ProgramType::Load);
+    assert_eq!(ram.seg_v_addr, elf_load::Address(0x80000000));
+    assert_eq!(ram.seg_f_size, elf_load::Address(4 * KB as u64));
+    let offset = ram.seg_offset as usize;
+    assert_eq!(elf.bytes[offset..offset + 4], 0x00150513u32.to_le_bytes());
+
+    // The registers of the prstatus note, after its 20 byte header, start with the pc
+    let prstatus = notes.seg_offset as usize + 20;
+    let register = |i: usize| {
+        let at = prstatus + 112 + i * 8;
+        u64::from_le_bytes(elf.bytes[at..at + 8].try_into().unwrap())
+    };
+    assert_eq!(elf.bytes[prstatus + 32], 1);
+    assert_eq!(register(0), 0x80000004);
+    assert_eq!(register(10), 1);
+
+    let path = std::env::temp_dir().join(format!("riscv_vm_core_{}", std::process::id()));
+    vm.write_core(&path, true).unwrap();
+    let written = Elf::from_bytes(std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // The timer's registers follow ram
+    assert_eq!(written.program_headers.len(), 3);
+    assert_eq!(written.program_headers[2].seg_f_size, elf_load::Address(16));
+}
+
+#[test]
+fn plic_claim_complete() {
+    const PLIC: u64 = 0xC000000;
+    let mut builder = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .enable_plic(8);
+    let line = builder.interrupt_line(3);
+    let other = builder.interrupt_line(5);
+    let mut vm = builder.build().unwrap();
+    // jal x0, 0
+    vm.write_memory(0, &0x0000006fu32.to_le_bytes(), addr(0x80000000))
+        .unwrap();
+
+    let write = |vm: &mut VMState, offset: u64, value: u32| {
+        vm.write_memory(0, &value.to_le_bytes(), addr(PLIC + offset))
+            .unwrap();
+    };
+    let read = |vm: &mut VMState, offset: u64| {
+        let bytes = vm.read_memory(0, addr(PLIC + offset), 4).unwrap();
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    };
+    // Reads by harts claim, debugger reads with `read` only show what would be claimed
+    let claim = |vm: &mut VMState| {
+        let bytes = vm.mem.read_bytes(addr(PLIC + 0x201004), 4).unwrap();
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    };
+    let mip = |vm: &VMState| {
+        let csr = vm.get_hart(0).unwrap().get_csr();
+        csr.get_csr(CsrAddress::new(0x344))
+    };
+
+    // Sources 3 and 5 go to the supervisor context, 5 has the higher priority
+    write(&mut vm, 3 * 4, 1);
+    write(&mut vm, 5 * 4, 2);
+    write(&mut vm, 0x2080, 0xffff_ffff);
+    assert_eq!(read(&mut vm, 0x2080), 0b1_1111_1110);
+
+    line.raise();
+    other.raise();
+    vm.run_for(1);
+    assert_eq!(read(&mut vm, 0x1000), 0b10_1000);
+    assert_eq!(mip(&vm), 1 << 9);
+
+    // Raising the threshold to the highest priority masks everything
+    write(&mut vm, 0x201000, 2);
+    assert_eq!(mip(&vm), 0);
+    write(&mut vm, 0x201000, 0);
+    assert_eq!(mip(&vm), 1 << 9);
+
+    assert_eq!(read(&mut vm, 0x201004), 5);
+    assert_eq!(read(&mut vm, 0x201004), 5);
+    assert_eq!(claim(&mut vm), 5);
+    assert_eq!(read(&mut vm, 0x201004), 3);
+    assert_eq!(claim(&mut vm), 3);
+    assert_eq!(claim(&mut vm), 0);
+    assert_eq!(mip(&vm), 0);
+
+    // A completed source that is still raised is pending again
+    other.lower();
+    write(&mut vm, 0x201004, 5);
+    write(&mut vm, 0x201004, 3);
+    assert_eq!(read(&mut vm, 0x1000), 0b1000);
+    assert_eq!(mip(&vm), 1 << 9);
+    line.lower();
+    vm.run_for(1);
+    assert_eq!(read(&mut vm, 0x1000), 0);
+    assert_eq!(mip(&vm), 0);
+
+    let mut builder = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .enable_plic(8);
+    builder.interrupt_line(9);
+    assert!(matches!(
+        builder.build(),
+        Err(VMInitError::InvalidInterruptSource(9))
+    ));
+}
+
+#[test]
+fn page_table_inspection_does_not_claim() {
+    const PLIC: u64 = 0xC000000;
+    let mut builder = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .enable_plic(8);
+    let line = builder.interrupt_line(5);
+    let mut vm = builder.build().unwrap();
+    vm.write_memory(0, &1u32.to_le_bytes(), addr(PLIC + 5 * 4))
+        .unwrap();
+    vm.write_memory(0, &0xffu32.to_le_bytes(), addr(PLIC + 0x2080))
+        .unwrap();
+    line.raise();
+    vm.run_for(1);
+
+    // A page table rooted at the supervisor context, the root pte is read from its claim
+    // register
+    let satp = (8 << 60) | ((PLIC + 0x201000) >> 12);
+    vm.get_hart_mut(0)
+        .unwrap()
+        .get_csr_mut()
+        .write_csr(CsrAddress::new(0x180), satp, PrivilegeMode::Machine, false)
+        .unwrap();
+    let (steps, _) = vm.translate(0, addr(0x1000), AccessMode::Read);
+    assert_eq!(steps[0].pte, 5 << 32);
+    vm.page_mappings(0);
+
+    let bytes = vm.mem.read_bytes(addr(PLIC + 0x201004), 4).unwrap();
+    assert_eq!(u32::from_le_bytes(bytes.try_into().unwrap()), 5);
+}
+
+#[test]
+fn aia_direct_and_msi_delivery() {
+    const APLIC_M: u64 = 0xC000000;
+    const APLIC_S: u64 = 0xD000000;
+    const IMSIC_M: u64 = 0x24000000;
+    let mut builder = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .enable_aia(8);
+    let line = builder.interrupt_line(2);
+    let mut vm = builder.build().unwrap();
+    // jal x0, 0
+    vm.write_memory(0, &0x0000006fu32.to_le_bytes(), addr(0x80000000))
+        .unwrap();
+
+    let write = |vm: &mut VMState, at: u64, value: u32| {
+        vm.write_memory(0, &value.to_le_bytes(), addr(at)).unwrap();
+    };
+    let read = |vm: &mut VMState, at: u64| {
+        let bytes = vm.read_memory(0, addr(at), 4).unwrap();
+        u32::from_le_bytes(bytes.try_into().unwrap())
+    };
+    let csr = |vm: &VMState, csr: u16| {
+        let holder = vm.get_hart(0).unwrap().get_csr();
+        holder.get_csr(CsrAddress::new(csr))
+    };
+    let write_csr = |vm: &mut VMState, csr: u16, value: u64| {
+        let holder = vm.get_hart_mut(0).unwrap().get_csr_mut();
+        holder
+            .write_csr(CsrAddress::new(csr), value, PrivilegeMode::Machine, false)
+            .unwrap();
+    };
+
+    // Source 2 is level triggered in the machine domain, delivered directly with priority 3
+    write(&mut vm, APLIC_M + 0x8, 6);
+    write(&mut vm, APLIC_M + 0x3008, 3);
+    write(&mut vm, APLIC_M + 0x1EDC, 2);
+    write(&mut vm, APLIC_M + 0x4000, 1);
+    write(&mut vm, APLIC_M, 0x100);
+    assert_eq!(read(&mut vm, APLIC_M), 0x8000_0100);
+
+    line.raise();
+    vm.run_for(1);
+    assert_eq!(csr(&vm, 0x344), 1 << 11);
+    assert_eq!(read(&mut vm, APLIC_M + 0x4018), 2 << 16 | 3);
+    assert_eq!(csr(&vm, 0xFB0), 0);
+    write_csr(&mut vm, 0x304, 1 << 11);
+    assert_eq!(csr(&vm, 0xFB0), 11 << 16 | 1);
+
+    // A threshold at the priority of the source masks it
+    write(&mut vm, APLIC_M + 0x4008, 3);
+    assert_eq!(csr(&vm, 0x344), 0);
+    write(&mut vm, APLIC_M + 0x4008, 0);
+    // Debugger reads of claimi only show what would be claimed, reads by harts claim
+    assert_eq!(read(&mut vm, APLIC_M + 0x401C), 2 << 16 | 3);
+    assert_eq!(csr(&vm, 0x344), 1 << 11);
+    let bytes = vm.mem.read_bytes(addr(APLIC_M + 0x401C), 4).unwrap();
+    assert_eq!(u32::from_le_bytes(bytes.try_into().unwrap()), 2 << 16 | 3);
+    assert_eq!(csr(&vm, 0x344), 0);
+    line.lower();
+    vm.run_for(1);
+    assert_eq!(csr(&vm, 0x344), 0);
+
+    // Delegated to the supervisor domain in msi mode the rising edge is forwarded as identity 5
+    // to the supervisor interrupt file
+    write(&mut vm, APLIC_M + 0x8, 1 << 10);
+    assert_eq!(read(&mut vm, APLIC_M + 0x8), 1 << 10);
+    assert_eq!(read(&mut vm, APLIC_S + 0x8), 0);
+    write(&mut vm, APLIC_S + 0x8, 4);
+    write(&mut vm, APLIC_S + 0x3008, 5);
+    write(&mut vm, APLIC_S + 0x1EDC, 2);
+    write(&mut vm, APLIC_S, 0x104);
+    write_csr(&mut vm, 0x150, 0x70);
+    write_csr(&mut vm, 0x151, 1);
+    write_csr(&mut vm, 0x150, 0xC0);
+    write_csr(&mut vm, 0x151, 1 << 5);
+
+    line.raise();
+    vm.run_for(1);
+    assert_eq!(csr(&vm, 0x344), 1 << 9);
+    write_csr(&mut vm, 0x150, 0x80);
+    assert_eq!(csr(&vm, 0x151), 1 << 5);
+    assert_eq!(csr(&vm, 0x15C), 5 << 16 | 5);
+    write_csr(&mut vm, 0x15C, 0);
+    assert_eq!(csr(&vm, 0x15C), 0);
+    assert_eq!(csr(&vm, 0x344), 0);
+    vm.run_for(1);
+    assert_eq!(csr(&vm, 0x344), 0);
+
+    // An msi written to the machine level file directly, identities below the threshold only
+    write_csr(&mut vm, 0x350, 0x70);
+    write_csr(&mut vm, 0x351, 1);
+    write_csr(&mut vm, 0x350, 0xC0);
+    write_csr(&mut vm, 0x351, 1 << 7 | 1 << 9);
+    write(&mut vm, IMSIC_M, 9);
+    write(&mut vm, IMSIC_M + 4, 7u32.swap_bytes());
+    assert_eq!(csr(&vm, 0x35C), 7 << 16 | 7);
+    write_csr(&mut vm, 0x350, 0x72);
+    write_csr(&mut vm, 0x351, 7);
+    assert_eq!(csr(&vm, 0x35C), 0);
+    assert_eq!(csr(&vm, 0x344), 0);
+    write_csr(&mut vm, 0x351, 0);
+    assert_eq!(csr(&vm, 0x344), 1 << 11);
+
+    let builder = VMStateBuilder::<{ 4 * KB }>::default()
+        .set_hart_count(1)
+        .enable_plic(8)
+        .enable_aia(8);
+    assert!(matches!(
+        builder.build(),
+        Err(VMInitError::ConflictingInterruptControllers)
+    ));
+}
+
+#[test]
+fn clint_and_aclint_layouts() {
+    const CLINT: u64 = 0x2000000;
+    let mut vm = vm_with(
+        VMSettings {
+            timer_layout: TimerLayout::Clint,
+            timer_addr: addr(CLINT),
+            virtual_time: Some(VirtualTime::default()),
+            ..Default::default()
+        },
+        // jal x0, 0
+        &[0x0000006f],
+    );
+    let write = |vm: &mut VMState, at: u64, bytes: &[u8]| {
+        vm.write_memory(0, bytes, addr(at)).unwrap();
+    };
+    let read = |vm: &mut VMState, at: u64, size: usize| {
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&vm.read_memory(0, addr(at), size).unwrap());
+        u64::from_le_bytes(bytes)
+    };
+    let mip = |vm: &VMState| {
+        let csr = vm.get_hart(0).unwrap().get_csr();
+        csr.get_csr(CsrAddress::new(0x344))
+    };
+
+    write(&mut vm, CLINT, &1u32.to_le_bytes());
+    assert_eq!(read(&mut vm, CLINT, 4), 1);
+    assert_eq!(mip(&vm), 1 << 3);
+    write(&mut vm, CLINT, &0u32.to_le_bytes());
+    assert_eq!(mip(&vm), 0);
+
+    // The 64 bit registers are read and written in 32 bit halves
+    write(&mut vm, CLINT + 0xBFF8, &0x1_0000_0005u64.to_le_bytes());
+    assert_eq!(read(&mut vm, CLINT + 0xBFFC, 4), 1);
+    assert_eq!(read(&mut vm, CLINT + 0xBFF8, 4), 5);
+    write(&mut vm, CLINT + 0x4004, &u32::MAX.to_le_bytes());
+    write(&mut vm, CLINT + 0x4000, &0x10u32.to_le_bytes());
+    assert_eq!(read(&mut vm, CLINT + 0x4000, 8), 0xFFFF_FFFF_0000_0010);
+    assert_eq!(mip(&vm), 0);
+    write(&mut vm, CLINT + 0x4004, &0u32.to_le_bytes());
+    assert_eq!(mip(&vm), 1 << 7);
+    assert!(vm.read_memory(0, addr(CLINT + 0x4004), 8).is_err());
+
+    let mut vm = vm_with(
+        VMSettings {
+            timer_layout: TimerLayout::Aclint,
+            m_mode_swi_enable: true,
+            m_mode_swi_addr: addr(CLINT),
+            timer_addr: addr(CLINT + 0x4000),
+            s_mode_swi_enable: true,
+            s_mode_swi_addr: addr(CLINT + 0xC000),
+            ..Default::default()
+        },
+        &[0x0000006f],
+    );
+    write(&mut vm, CLINT + 0xBFF8, &0x1234u64.to_le_bytes());
+    assert_eq!(read(&mut vm, CLINT + 0xBFF8, 8), 0x1234);
+    write(&mut vm, CLINT + 0x4000, &0x10u64.to_le_bytes());
+    assert_eq!(read(&mut vm, CLINT + 0x4000, 8), 0x10);
+    write(&mut vm, CLINT, &1u32.to_le_bytes());
+    assert_eq!(mip(&vm) & 1 << 3, 1 << 3);
+
+    // Setting the supervisor software interrupt is all the SSWI does
+    write(&mut vm, CLINT + 0xC000, &1u32.to_le_bytes());
+    assert_eq!(mip(&vm) & 1 << 1, 1 << 1);
+    assert_eq!(read(&mut vm, CLINT + 0xC000, 4), 0);
+    write(&mut vm, CLINT + 0xC000, &0u32.to_le_bytes());
+    assert_eq!(mip(&vm) & 1 << 1, 1 << 1);
+}
+
+#[test]
+fn timer_layout_addresses() {
+    for (layout, sswi) in [
+        (TimerLayout::Clint, 0x2010000),
+        (TimerLayout::Aclint, 0x200C000),
+    ] {
+        let settings = VMSettings {
+            m_mode_swi_enable: true,
+            s_mode_swi_enable: true,
+            ..Default::default()
+        };
+        let mut vm = VMStateBuilder::<{ 4 * KB }>::new(settings)
+            .set_hart_count(1)
+            .set_timer_layout(layout)
+            .build()
+            .unwrap();
+        vm.write_memory(0, &1u32.to_le_bytes(), addr(0x2000000))
+            .unwrap();
+        vm.write_memory(0, &1u32.to_le_bytes(), addr(sswi)).unwrap();
+        let csr = vm.get_hart(0).unwrap().get_csr();
+        assert_eq!(csr.get_csr(CsrAddress::new(0x344)), 1 << 3 | 1 << 1);
+
+        // An address given in the settings stays, also when the layout is set more than once
+        let custom = VMSettings {
+            s_mode_swi_addr: addr(0x3000000),
+            ..settings
+        };
+        let mut vm = VMStateBuilder::<{ 4 * KB }>::new(custom)
+            .set_hart_count(1)
+            .set_timer_layout(TimerLayout::Aclint)
+            .set_timer_layout(layout)
+            .build()
+            .unwrap();
+        vm.write_memory(0, &1u32.to_le_bytes(), addr(0x2000000))
+            .unwrap();
+        vm.write_memory(0, &1u32.to_le_bytes(), addr(0x3000000))
+            .unwrap();
+        let csr = vm.get_hart(0).unwrap().get_csr();
+        assert_eq!(csr.get_csr(CsrAddress::new(0x344)), 1 << 3 | 1 << 1);
+
+        // Setting only the layout leaves the registers where they overlap
+        let settings = VMSettings {
+            timer_layout: layout,
+            ..settings
+        };
+        let vm = VMStateBuilder::<{ 4 * KB }>::new(settings)
+            .set_hart_count(1)
+            .build();
+        assert!(matches!(
+            vm,
+            Err(VMInitError::DeviceInitError(DeviceInitError::MemoryOverlap))
+        ));
+    }
+}
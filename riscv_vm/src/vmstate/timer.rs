@@ -1,8 +1,4 @@
-use std::{
-    rc::Rc,
-    sync::Mutex,
-    time::{Duration, Instant},
-};
+use std::{rc::Rc, sync::Mutex};
 
 use enumflags2::BitFlags;
 use nohash_hasher::IntMap;
@@ -17,67 +13,153 @@ use crate::{
     Address,
 };
 
-pub struct TimerRef(Rc<Mutex<Instant>>);
+use super::{
+    clock::Clock, swi_controller::SwiController, SnapshotError, SnapshotReader, SnapshotWriter,
+    VirtualTime,
+};
+
+/// Where the registers of the timer and the software interrupt controllers are, see
+/// [`VMSettings::timer_layout`](super::VMSettings::timer_layout)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimerLayout {
+    /// `mtime` at offset 0 followed by the `mtimecmp` of every hart, the software interrupt
+    /// controllers have an 8 byte register per hart
+    #[default]
+    Legacy,
+    /// A SiFive CLINT, the `msip` of hart `n` at `4n`, its `mtimecmp` at `0x4000 + 8n` and
+    /// `mtime` at `0xBFF8`. It takes the place of the machine mode software interrupt
+    /// controller.
+    Clint,
+    /// An ACLINT MTIMER with the `mtimecmp` of hart `n` at `8n` and `mtime` at `0x7FF8`, and
+    /// MSWI and SSWI software interrupt controllers with a 4 byte register per hart
+    Aclint,
+}
+
+/// The msip registers of a clint come before its timer registers
+const CLINT_MTIMECMP: u64 = 0x4000;
+const CLINT_MTIME: u64 = 0xBFF8;
+const CLINT_SIZE: u64 = 0x10000;
+const ACLINT_MTIME: u64 = 0x7FF8;
+const ACLINT_MTIMER_SIZE: u64 = 0x8000;
+
+pub struct TimerRef(Rc<Mutex<Clock>>);
 
 impl TimerRef {
     pub fn get_time(&self) -> u64 {
-        self.0.lock().unwrap().elapsed().as_micros() as u64
+        self.0.lock().unwrap().now()
     }
 
     #[cfg(test)]
     pub(crate) fn dummy() -> Self {
-        Self(Rc::new(Mutex::new(Instant::now())))
+        Self(Rc::new(Mutex::new(Clock::new(None))))
     }
 }
 
 pub struct MTimer {
-    time: Rc<Mutex<Instant>>,
+    time: Rc<Mutex<Clock>>,
     time_cmp: Vec<Option<u64>>,
     interrupts: IntMap<usize, Rc<Mutex<BitFlags<InterruptInternal>>>>,
     hart_count: usize,
+    layout: TimerLayout,
+    /// The msip registers of a clint
+    msip: Option<SwiController>,
 }
 
 impl MemoryBuffer for MTimer {
     fn size(&self) -> u64 {
-        (self.hart_count as u64 + 1) * 8
+        match self.layout {
+            TimerLayout::Legacy => (self.hart_count as u64 + 1) * 8,
+            TimerLayout::Clint => CLINT_SIZE,
+            TimerLayout::Aclint => ACLINT_MTIMER_SIZE,
+        }
     }
 
+    /// The 64 bit registers can be written in parts, like the two halves a 32 bit guest writes
     fn write_bytes(&mut self, bytes: &[u8], addr: Address) -> Result<(), MemoryBufferError> {
         let addr = <Address as Into<u64>>::into(addr);
-        if addr % 8 != 0 {
+        if let Some(msip) = self.msip.as_mut().filter(|_| addr < CLINT_MTIMECMP) {
+            return msip.write_bytes(bytes, addr.into());
+        }
+        let (base, offset) = (addr - addr % 8, (addr % 8) as usize);
+        if offset + bytes.len() > 8 {
             return Err(MemoryBufferError::UnalignedWrite(addr.into()));
         }
-        let mut num_bytes = [0u8; 8];
-        num_bytes[0..bytes.len()].copy_from_slice(bytes);
+        let Some(register) = self.register(base) else {
+            return Ok(());
+        };
+        let mut num_bytes = self.read_register(register).to_le_bytes();
+        num_bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
         let micros = u64::from_le_bytes(num_bytes);
-        if addr == 0 {
-            self.set_time_micros(micros);
-        } else {
-            self.set_cmp_micros(micros, (addr / 8) - 1);
+        match register {
+            Register::Time => self.set_time_micros(micros),
+            Register::Cmp(hartid) => self.set_cmp_micros(micros, hartid),
         }
         Ok(())
     }
 
     fn read_bytes(&self, addr: Address, size: usize) -> Result<Vec<u8>, MemoryBufferError> {
         let addr = <Address as Into<u64>>::into(addr);
-        if addr % 8 != 0 {
-            return Err(MemoryBufferError::UnalignedRead(addr.into()));
+        if let Some(msip) = self.msip.as_ref().filter(|_| addr < CLINT_MTIMECMP) {
+            return msip.read_bytes(addr.into(), size);
         }
-        if addr == 0 {
-            Ok(self.get_time_micros().to_le_bytes()[0..size].to_vec())
-        } else {
-            Ok(self.get_cmp_micros((addr / 8) - 1).to_le_bytes()[0..size].to_vec())
+        let (base, offset) = (addr - addr % 8, (addr % 8) as usize);
+        if offset + size > 8 {
+            return Err(MemoryBufferError::UnalignedRead(addr.into()));
         }
+        // Reserved offsets read as 0
+        let value = self.register(base).map_or(0, |r| self.read_register(r));
+        Ok(value.to_le_bytes()[offset..offset + size].to_vec())
     }
 }
 
+/// A 64 bit register of the timer
+#[derive(Clone, Copy)]
+enum Register {
+    Time,
+    Cmp(u64),
+}
+
 impl MTimer {
-    pub fn new(hart_count: usize) -> Self {
+    pub fn new(hart_count: usize, virtual_time: Option<VirtualTime>) -> Self {
         Self {
-            time: Rc::new(Mutex::new(Instant::now())),
+            time: Rc::new(Mutex::new(Clock::new(virtual_time))),
             time_cmp: vec![None; hart_count],
             interrupts: IntMap::default(),
             hart_count,
+            layout: TimerLayout::Legacy,
+            msip: None,
+        }
+    }
+
+    /// Place the registers as in `layout`, a clint also needs [`MTimer::set_msip()`]
+    pub fn with_layout(mut self, layout: TimerLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Give a clint the machine mode software interrupt controller it maps below its timer
+    pub(crate) fn set_msip(&mut self, msip: SwiController) {
+        self.msip = Some(msip);
+    }
+
+    /// The register at the 8 byte aligned `offset`, if there is one
+    fn register(&self, offset: u64) -> Option<Register> {
+        let (time, cmps) = match self.layout {
+            TimerLayout::Legacy => (0, 8),
+            TimerLayout::Clint => (CLINT_MTIME, CLINT_MTIMECMP),
+            TimerLayout::Aclint => (ACLINT_MTIME, 0),
+        };
+        if offset == time {
+            return Some(Register::Time);
+        }
+        let hartid = offset.checked_sub(cmps)? / 8;
+        (hartid < self.hart_count as u64).then_some(Register::Cmp(hartid))
+    }
+
+    fn read_register(&self, register: Register) -> u64 {
+        match register {
+            Register::Time => self.get_time_micros(),
+            Register::Cmp(hartid) => self.get_cmp_micros(hartid),
         }
     }
 
@@ -90,11 +172,37 @@ impl MTimer {
     }
 
     pub fn get_time_micros(&self) -> u64 {
-        self.time.lock().unwrap().elapsed().as_micros() as u64
+        self.time.lock().unwrap().now()
     }
 
     pub fn set_time_micros(&mut self, micros: u64) {
-        *self.time.lock().unwrap() = Instant::now() - Duration::from_micros(micros);
+        self.time.lock().unwrap().set(micros);
+    }
+
+    /// Advance the clock for a new vm step, see [`Clock::tick()`]
+    pub(crate) fn tick(&self) -> u64 {
+        self.time.lock().unwrap().tick()
+    }
+
+    pub(crate) fn replay_tick(&self, now: u64) {
+        self.time.lock().unwrap().replay(now);
+    }
+
+    pub(crate) fn save(&self, snapshot: &mut SnapshotWriter) {
+        self.time.lock().unwrap().save(snapshot);
+        for cmp in &self.time_cmp {
+            snapshot.write_u64(cmp.unwrap_or(0));
+        }
+    }
+
+    /// Restores time and the compare values, pending timer interrupts are restored with the
+    /// harts' mip.
+    pub(crate) fn restore(&mut self, snapshot: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.time.lock().unwrap().restore(snapshot)?;
+        for cmp in self.time_cmp.iter_mut() {
+            *cmp = Some(snapshot.read_u64()?).filter(|c| *c != 0);
+        }
+        Ok(())
     }
 
     pub fn get_cmp_micros(&self, hartid: u64) -> u64 {
@@ -105,14 +213,13 @@ impl MTimer {
     }
 
     pub fn set_cmp_micros(&mut self, micros: u64, hartid: u64) {
-        eprintln!("Set cmp to {micros} for H{hartid}");
         if micros == 0 {
             self.time_cmp[hartid as usize] = None;
         } else {
             self.time_cmp[hartid as usize] = Some(micros);
         }
 
-        if (micros as u128) < self.time.lock().unwrap().elapsed().as_micros() {
+        if micros < self.get_time_micros() {
             self.interrupts
                 .get(&(hartid as usize))
                 .as_mut()
@@ -127,7 +234,7 @@ impl MTimer {
 
     pub fn generate_interrupts(&self) {
         for (i, t) in self.time_cmp.iter().enumerate() {
-            if t.is_some_and(|t| (t as u128) < self.time.lock().unwrap().elapsed().as_micros()) {
+            if t.is_some_and(|t| t < self.get_time_micros()) {
                 self.interrupts
                     .get(&i)
                     .as_mut()